@@ -299,7 +299,7 @@ describe("ApiStorageService", () => {
 
         const result = await service.getRuns()
 
-        expect(mockFetch).toHaveBeenCalledWith("http://localhost:3001/api/test-runs")
+        expect(mockFetch).toHaveBeenCalledWith("http://localhost:3001/api/test-runs?include=logs")
         expect(result).toEqual(expectedRuns)
       })
 
@@ -0,0 +1,619 @@
+use crate::auth::Principal;
+use crate::db::traced;
+use crate::error::ApiError;
+use crate::notifications::failure_reason;
+use crate::rbac::Action;
+use crate::run_query::push_visible_runs;
+use crate::state::AppState;
+use chrono::{DateTime, Datelike, Duration, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+use sparktest_core::TestRun;
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+/// How far back analytics look when the query gives no `from`
+const DEFAULT_WINDOW_DAYS: i64 = 30;
+/// The most buckets one response may have per group
+const MAX_BUCKETS: i64 = 1000;
+/// Failure reasons listed per summary or bucket, most frequent first
+const TOP_FAILURE_REASONS: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnalyticsGroup {
+    #[default]
+    Definition,
+    Suite,
+    Executor,
+}
+
+/// Width of the time buckets; weeks start on Monday, all in UTC
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BucketSize {
+    Hour,
+    #[default]
+    Day,
+    Week,
+}
+
+impl BucketSize {
+    fn width(self) -> Duration {
+        match self {
+            BucketSize::Hour => Duration::hours(1),
+            BucketSize::Day => Duration::days(1),
+            BucketSize::Week => Duration::weeks(1),
+        }
+    }
+
+    /// The `date_trunc` field with the same buckets
+    fn unit(self) -> &'static str {
+        match self {
+            BucketSize::Hour => "hour",
+            BucketSize::Day => "day",
+            BucketSize::Week => "week",
+        }
+    }
+
+    /// Start of the bucket a moment falls in
+    pub fn start(self, at: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            BucketSize::Hour | BucketSize::Day => at
+                .duration_trunc(self.width())
+                .expect("buckets are far from the time limits"),
+            BucketSize::Week => {
+                let day = BucketSize::Day.start(at);
+                day - Duration::days(day.weekday().num_days_from_monday().into())
+            }
+        }
+    }
+}
+
+/// `GET /api/analytics/runs` parameters. Runs are counted in the bucket they were
+/// created in; `from` is inclusive and `to` exclusive.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AnalyticsQuery {
+    #[serde(default)]
+    pub group_by: AnalyticsGroup,
+    #[serde(default)]
+    pub bucket: BucketSize,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub definition_id: Option<Uuid>,
+    pub suite_id: Option<Uuid>,
+    pub executor_id: Option<String>,
+}
+
+impl AnalyticsQuery {
+    fn matches(&self, run: &TestRun, from: DateTime<Utc>, to: DateTime<Utc>) -> bool {
+        run.created_at >= from
+            && run.created_at < to
+            && self
+                .definition_id
+                .is_none_or(|id| run.definition_id == Some(id))
+            && self.suite_id.is_none_or(|id| run.suite_id == Some(id))
+            && self
+                .executor_id
+                .as_deref()
+                .is_none_or(|id| run.executor_id.as_deref() == Some(id))
+    }
+
+    /// The column runs are grouped by
+    fn group_column(&self) -> &'static str {
+        match self.group_by {
+            AnalyticsGroup::Definition => "test_definition_id::TEXT",
+            AnalyticsGroup::Suite => "suite_id::TEXT",
+            AnalyticsGroup::Executor => "executor_id",
+        }
+    }
+
+    /// What a run is grouped under; runs without one are left out
+    fn group_of(&self, run: &TestRun) -> Option<String> {
+        match self.group_by {
+            AnalyticsGroup::Definition => run.definition_id.map(|id| id.to_string()),
+            AnalyticsGroup::Suite => run.suite_id.map(|id| id.to_string()),
+            AnalyticsGroup::Executor => run.executor_id.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FailureReason {
+    pub reason: String,
+    pub count: usize,
+}
+
+/// Aggregates over a set of runs. Pass rate and durations only cover finished runs.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunStats {
+    pub runs: usize,
+    pub passed: usize,
+    pub failed: usize,
+    pub pass_rate: Option<f64>,
+    pub mean_duration_seconds: Option<f64>,
+    pub p50_duration_seconds: Option<i32>,
+    pub p95_duration_seconds: Option<i32>,
+    /// The last line failed runs logged, counted
+    pub failure_reasons: Vec<FailureReason>,
+}
+
+impl RunStats {
+    pub fn of(runs: &[&TestRun]) -> Self {
+        let passed = runs.iter().filter(|run| run.status == "succeeded").count();
+        let failed: Vec<&TestRun> = runs
+            .iter()
+            .copied()
+            .filter(|run| run.status == "failed")
+            .collect();
+        let finished = passed + failed.len();
+
+        let mut durations: Vec<i32> = runs
+            .iter()
+            .filter(|run| matches!(run.status.as_str(), "succeeded" | "failed"))
+            .filter_map(|run| run.duration)
+            .collect();
+        durations.sort_unstable();
+        let mean = (!durations.is_empty())
+            .then(|| durations.iter().map(|&d| f64::from(d)).sum::<f64>() / durations.len() as f64);
+
+        let mut reasons: HashMap<String, usize> = HashMap::new();
+        for run in &failed {
+            let reason = failure_reason(run).unwrap_or_else(|| "unknown".to_string());
+            *reasons.entry(reason).or_default() += 1;
+        }
+        let failure_reasons = reasons
+            .into_iter()
+            .map(|(reason, count)| FailureReason { reason, count })
+            .collect();
+
+        Self {
+            runs: runs.len(),
+            passed,
+            failed: failed.len(),
+            pass_rate: (finished > 0).then(|| passed as f64 / finished as f64),
+            mean_duration_seconds: mean,
+            p50_duration_seconds: percentile(&durations, 50),
+            p95_duration_seconds: percentile(&durations, 95),
+            failure_reasons: most_common(failure_reasons),
+        }
+    }
+}
+
+/// The most common failure reasons, most frequent first
+fn most_common(mut reasons: Vec<FailureReason>) -> Vec<FailureReason> {
+    reasons.sort_by(|a, b| b.count.cmp(&a.count).then(a.reason.cmp(&b.reason)));
+    reasons.truncate(TOP_FAILURE_REASONS);
+    reasons
+}
+
+/// Nearest-rank percentile of sorted values
+fn percentile(sorted: &[i32], percent: usize) -> Option<i32> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+    Some(sorted[rank - 1])
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BucketStats {
+    pub start: DateTime<Utc>,
+    #[serde(flatten)]
+    pub stats: RunStats,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GroupStats {
+    /// Definition, suite or executor id
+    pub id: String,
+    /// Name of the definition, suite or executor, if it still exists
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub summary: RunStats,
+    /// Buckets with at least one run, oldest first
+    pub buckets: Vec<BucketStats>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RunAnalytics {
+    pub group_by: AnalyticsGroup,
+    pub bucket: BucketSize,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    /// Most runs first
+    pub groups: Vec<GroupStats>,
+}
+
+/// Analytics over the runs the principal may view
+pub async fn run_analytics(
+    state: &AppState,
+    principal: &Principal,
+    query: &AnalyticsQuery,
+) -> Result<RunAnalytics, ApiError> {
+    let to = query.to.unwrap_or_else(Utc::now);
+    let from = query
+        .from
+        .unwrap_or(to - Duration::days(DEFAULT_WINDOW_DAYS));
+    if from >= to {
+        return Err(ApiError::validation("from must be before to"));
+    }
+    if (to - from).num_seconds() / query.bucket.width().num_seconds() >= MAX_BUCKETS {
+        return Err(ApiError::validation(format!(
+            "The range spans more than {MAX_BUCKETS} buckets; use larger buckets or a shorter range"
+        )));
+    }
+
+    let stats = match &state.db {
+        Some(db) => stored_stats(db, principal, query, from, to).await?,
+        None => held_stats(state, principal, query, from, to).await,
+    };
+    let mut groups = Vec::with_capacity(stats.len());
+    for (id, (summary, buckets)) in stats {
+        groups.push(GroupStats {
+            name: group_name(state, query.group_by, &id).await,
+            summary,
+            buckets: buckets
+                .into_iter()
+                .map(|(start, stats)| BucketStats { start, stats })
+                .collect(),
+            id,
+        });
+    }
+    groups.sort_by(|a, b| b.summary.runs.cmp(&a.summary.runs).then(a.id.cmp(&b.id)));
+
+    Ok(RunAnalytics {
+        group_by: query.group_by,
+        bucket: query.bucket,
+        from,
+        to,
+        groups,
+    })
+}
+
+/// Each group's summary and buckets
+type GroupedStats = HashMap<String, (RunStats, BTreeMap<DateTime<Utc>, RunStats>)>;
+
+/// Aggregates over the runs the server holds
+async fn held_stats(
+    state: &AppState,
+    principal: &Principal,
+    query: &AnalyticsQuery,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> GroupedStats {
+    let runs: Vec<TestRun> = state
+        .store
+        .list_runs()
+        .await
+        .into_iter()
+        .filter(|run| query.matches(run, from, to))
+        .filter(|run| principal.can(Action::View, &run.into()))
+        .collect();
+
+    let mut grouped: HashMap<String, Vec<&TestRun>> = HashMap::new();
+    for run in &runs {
+        if let Some(group) = query.group_of(run) {
+            grouped.entry(group).or_default().push(run);
+        }
+    }
+    grouped
+        .into_iter()
+        .map(|(id, runs)| {
+            let mut buckets: BTreeMap<DateTime<Utc>, Vec<&TestRun>> = BTreeMap::new();
+            for run in &runs {
+                buckets
+                    .entry(query.bucket.start(run.created_at))
+                    .or_default()
+                    .push(run);
+            }
+            let buckets = buckets
+                .into_iter()
+                .map(|(start, runs)| (start, RunStats::of(&runs)))
+                .collect();
+            (id, (RunStats::of(&runs), buckets))
+        })
+        .collect()
+}
+
+/// One group's summary, with no bucket, or one of its buckets
+#[derive(sqlx::FromRow)]
+struct StatsRow {
+    group_id: String,
+    bucket: Option<DateTime<Utc>>,
+    runs: i64,
+    passed: i64,
+    failed: i64,
+    duration_sum: Option<i64>,
+    durations: i64,
+    p50: Option<i32>,
+    p95: Option<i32>,
+}
+
+#[derive(sqlx::FromRow)]
+struct ReasonRow {
+    group_id: String,
+    bucket: Option<DateTime<Utc>>,
+    reason: String,
+    count: i64,
+}
+
+/// The same aggregates, computed by PostgreSQL over `test_runs`. Percentiles
+/// are nearest-rank like [`percentile`], and reasons are the last non-blank
+/// line like [`failure_reason`].
+async fn stored_stats(
+    db: &PgPool,
+    principal: &Principal,
+    query: &AnalyticsQuery,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<GroupedStats, sqlx::Error> {
+    let mut sql = counted_runs(principal, query, from, to);
+    sql.push(
+        " SELECT group_id, bucket, COUNT(*) AS runs, \
+         COUNT(*) FILTER (WHERE status = 'succeeded') AS passed, \
+         COUNT(*) FILTER (WHERE status = 'failed') AS failed, \
+         SUM(duration) FILTER (WHERE finished) AS duration_sum, \
+         COUNT(duration) FILTER (WHERE finished) AS durations, \
+         percentile_disc(0.5) WITHIN GROUP (ORDER BY duration) FILTER (WHERE finished) AS p50, \
+         percentile_disc(0.95) WITHIN GROUP (ORDER BY duration) FILTER (WHERE finished) AS p95 \
+         FROM runs GROUP BY GROUPING SETS ((group_id), (group_id, bucket))",
+    );
+    let statement = sql.sql().to_string();
+    let rows: Vec<StatsRow> = traced(&statement, |_| sql.build_query_as().fetch_all(db)).await?;
+
+    let mut sql = counted_runs(principal, query, from, to);
+    sql.push(
+        " SELECT group_id, bucket, reason, COUNT(*) AS count FROM runs WHERE reason IS NOT NULL \
+         GROUP BY GROUPING SETS ((group_id, reason), (group_id, bucket, reason))",
+    );
+    let statement = sql.sql().to_string();
+    let reason_rows: Vec<ReasonRow> =
+        traced(&statement, |_| sql.build_query_as().fetch_all(db)).await?;
+    let mut reasons: HashMap<(String, Option<DateTime<Utc>>), Vec<FailureReason>> = HashMap::new();
+    for row in reason_rows {
+        reasons
+            .entry((row.group_id, row.bucket))
+            .or_default()
+            .push(FailureReason {
+                reason: row.reason,
+                count: row.count as usize,
+            });
+    }
+
+    let mut grouped = GroupedStats::new();
+    for row in rows {
+        let failure_reasons = reasons
+            .remove(&(row.group_id.clone(), row.bucket))
+            .unwrap_or_default();
+        let finished = row.passed + row.failed;
+        let stats = RunStats {
+            runs: row.runs as usize,
+            passed: row.passed as usize,
+            failed: row.failed as usize,
+            pass_rate: (finished > 0).then(|| row.passed as f64 / finished as f64),
+            mean_duration_seconds: row
+                .duration_sum
+                .filter(|_| row.durations > 0)
+                .map(|sum| sum as f64 / row.durations as f64),
+            p50_duration_seconds: row.p50,
+            p95_duration_seconds: row.p95,
+            failure_reasons: most_common(failure_reasons),
+        };
+        let (summary, buckets) = grouped
+            .entry(row.group_id)
+            .or_insert_with(|| (RunStats::of(&[]), BTreeMap::new()));
+        match row.bucket {
+            Some(start) => {
+                buckets.insert(start, stats);
+            }
+            None => *summary = stats,
+        }
+    }
+    Ok(grouped)
+}
+
+/// `runs`: the runs the analytics count, with their group, bucket and, for
+/// failed runs, failure reason
+fn counted_runs(
+    principal: &Principal,
+    query: &AnalyticsQuery,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> QueryBuilder<'static, Postgres> {
+    let group = query.group_column();
+    let mut sql = QueryBuilder::new(format!(
+        "WITH runs AS (SELECT {group} AS group_id, \
+         date_trunc('{unit}', created_at AT TIME ZONE 'UTC') AT TIME ZONE 'UTC' AS bucket, \
+         status, duration, status IN ('succeeded', 'failed') AS finished, \
+         CASE WHEN status = 'failed' THEN COALESCE((SELECT btrim(line, E' \\t\\n\\r\\f') \
+         FROM unnest(logs) WITH ORDINALITY AS log(line, n) \
+         WHERE btrim(line, E' \\t\\n\\r\\f') <> '' ORDER BY n DESC LIMIT 1), 'unknown') \
+         END AS reason \
+         FROM test_runs WHERE {group} IS NOT NULL AND created_at >= ",
+        unit = query.bucket.unit(),
+    ));
+    sql.push_bind(from).push(" AND created_at < ").push_bind(to);
+    if let Some(id) = query.definition_id {
+        sql.push(" AND test_definition_id = ").push_bind(id);
+    }
+    if let Some(id) = query.suite_id {
+        sql.push(" AND suite_id = ").push_bind(id);
+    }
+    if let Some(id) = &query.executor_id {
+        sql.push(" AND executor_id = ").push_bind(id.clone());
+    }
+    push_visible_runs(&mut sql, principal);
+    sql.push(")");
+    sql
+}
+
+async fn group_name(state: &AppState, group_by: AnalyticsGroup, id: &str) -> Option<String> {
+    match group_by {
+        AnalyticsGroup::Definition => {
+            let id = id.parse().ok()?;
+            state.store.get_definition(id).await.map(|d| d.name)
+        }
+        AnalyticsGroup::Suite => {
+            let id = id.parse().ok()?;
+            state.store.get_suite(id).await.map(|s| s.name)
+        }
+        AnalyticsGroup::Executor => state.store.get_executor(id).await.map(|e| e.name),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use crate::store::Store;
+    use sparktest_core::Role;
+
+    fn run(status: &str, duration: Option<i32>, last_line: &str) -> TestRun {
+        let mut run = TestRun::new("Unit".to_string(), "node:20".to_string(), vec![]);
+        run.status = status.to_string();
+        run.duration = duration;
+        run.logs = Some(vec!["starting".to_string(), last_line.to_string()]);
+        run
+    }
+
+    #[test]
+    fn test_run_stats() {
+        let runs = [
+            run("succeeded", Some(10), "ok"),
+            run("succeeded", Some(20), "ok"),
+            run("failed", Some(30), "Timeout waiting for db"),
+            run("failed", Some(40), "Timeout waiting for db"),
+            run("failed", Some(100), "AssertionError"),
+            run("running", None, ""),
+        ];
+        let refs: Vec<&TestRun> = runs.iter().collect();
+        let stats = RunStats::of(&refs);
+
+        assert_eq!(stats.runs, 6);
+        assert_eq!(stats.passed, 2);
+        assert_eq!(stats.failed, 3);
+        assert_eq!(stats.pass_rate, Some(0.4));
+        assert_eq!(stats.mean_duration_seconds, Some(40.0));
+        assert_eq!(stats.p50_duration_seconds, Some(30));
+        assert_eq!(stats.p95_duration_seconds, Some(100));
+        assert_eq!(
+            stats.failure_reasons,
+            vec![
+                FailureReason {
+                    reason: "Timeout waiting for db".to_string(),
+                    count: 2
+                },
+                FailureReason {
+                    reason: "AssertionError".to_string(),
+                    count: 1
+                },
+            ]
+        );
+
+        let empty = RunStats::of(&[]);
+        assert_eq!(empty.pass_rate, None);
+        assert_eq!(empty.p95_duration_seconds, None);
+    }
+
+    #[test]
+    fn test_bucket_start() {
+        let at: DateTime<Utc> = "2026-10-15T13:45:12Z".parse().unwrap();
+        assert_eq!(
+            BucketSize::Hour.start(at),
+            "2026-10-15T13:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        assert_eq!(
+            BucketSize::Day.start(at),
+            "2026-10-15T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+        // A Thursday, so the week started on Monday the 12th
+        assert_eq!(
+            BucketSize::Week.start(at),
+            "2026-10-12T00:00:00Z".parse::<DateTime<Utc>>().unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stored_analytics_match_held_analytics() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        // Other tests share the database, so runs are grouped under these executors
+        let tag = Uuid::new_v4().simple().to_string();
+        let (team, other_team) = (Uuid::new_v4(), Uuid::new_v4());
+        let store = Store::load(db.clone()).await.unwrap();
+        let start: DateTime<Utc> = "2026-10-12T08:30:00Z".parse().unwrap();
+        let runs = [
+            ("a", "succeeded", Some(10), "ok", None, 0),
+            ("a", "succeeded", Some(20), "ok", Some(team), 1),
+            (
+                "a",
+                "failed",
+                Some(30),
+                "  Timeout waiting for db\t",
+                None,
+                26,
+            ),
+            (
+                "a",
+                "failed",
+                Some(40),
+                "Timeout waiting for db",
+                Some(other_team),
+                30,
+            ),
+            ("a", "failed", None, "", None, 200),
+            ("a", "running", Some(5), "", Some(team), 200),
+            ("b", "failed", Some(7), "AssertionError", Some(team), 3),
+            ("b", "pending", None, "", None, 170),
+        ];
+        for (executor, status, duration, last_line, team_id, hours) in runs {
+            let mut run = run(status, duration, last_line);
+            run.executor_id = Some(format!("{tag}-{executor}"));
+            run.team_id = team_id;
+            run.created_at = start + Duration::hours(hours);
+            store.insert_run(run).await.unwrap();
+        }
+
+        let held = AppState {
+            store: store.clone(),
+            ..AppState::default()
+        };
+        let stored = AppState {
+            db: Some(db),
+            ..held.clone()
+        };
+        let member = Principal {
+            is_admin: false,
+            roles: HashMap::from([(team, Role::Viewer)]),
+            ..Principal::anonymous()
+        };
+        for principal in [Principal::anonymous(), member] {
+            for bucket in [BucketSize::Hour, BucketSize::Day, BucketSize::Week] {
+                for executor_id in [None, Some(format!("{tag}-b"))] {
+                    let query = AnalyticsQuery {
+                        group_by: AnalyticsGroup::Executor,
+                        bucket,
+                        from: Some(start),
+                        to: Some(start + Duration::days(30)),
+                        executor_id,
+                        ..Default::default()
+                    };
+                    let ours = |analytics: RunAnalytics| {
+                        let groups: Vec<GroupStats> = analytics
+                            .groups
+                            .into_iter()
+                            .filter(|group| group.id.starts_with(&tag))
+                            .collect();
+                        serde_json::to_value(groups).unwrap()
+                    };
+                    let expected = ours(run_analytics(&held, &principal, &query).await.unwrap());
+                    assert_ne!(expected, serde_json::json!([]));
+                    let actual = ours(run_analytics(&stored, &principal, &query).await.unwrap());
+                    assert_eq!(actual, expected, "{query:?}");
+                }
+            }
+        }
+    }
+}
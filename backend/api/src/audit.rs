@@ -0,0 +1,753 @@
+use crate::auth::Principal;
+use crate::db::traced;
+use crate::state::AppState;
+use axum::{
+    body::{to_bytes, Body, HttpBody},
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    http::{HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sparktest_core::{AuditEvent, AuditOutcome};
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use std::collections::VecDeque;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Largest response body inspected for the created resource's id or the error message
+const MAX_INSPECTED_BODY: usize = 1024 * 1024;
+
+/// How long audit events are kept, and whose `X-Forwarded-For` is believed
+#[derive(Debug, Clone)]
+pub struct AuditConfig {
+    pub retention: Option<chrono::Duration>,
+    pub max_events: Option<usize>,
+    /// Reverse proxies allowed to report the client address in `X-Forwarded-For`
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            retention: Some(chrono::Duration::days(90)),
+            max_events: Some(100_000),
+            trusted_proxies: Vec::new(),
+        }
+    }
+}
+
+impl AuditConfig {
+    /// `SPARKTEST_AUDIT_RETENTION_DAYS` and `SPARKTEST_AUDIT_MAX_EVENTS`; `0` keeps events forever.
+    /// `SPARKTEST_TRUSTED_PROXIES` is a comma-separated list of proxy IP addresses.
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Some(days) = env_number("SPARKTEST_AUDIT_RETENTION_DAYS") {
+            config.retention = (days > 0).then(|| chrono::Duration::days(days as i64));
+        }
+        if let Some(max) = env_number("SPARKTEST_AUDIT_MAX_EVENTS") {
+            config.max_events = (max > 0).then_some(max as usize);
+        }
+        if let Ok(proxies) = std::env::var("SPARKTEST_TRUSTED_PROXIES") {
+            for proxy in proxies.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                match proxy.parse() {
+                    Ok(ip) => config.trusted_proxies.push(ip),
+                    Err(_) => warn!("Ignoring trusted proxy {:?}: not an IP address", proxy),
+                }
+            }
+        }
+        config
+    }
+}
+
+fn env_number(name: &str) -> Option<u64> {
+    let value = std::env::var(name).ok()?;
+    match value.parse() {
+        Ok(number) => Some(number),
+        Err(_) => {
+            warn!("Ignoring {}={:?}: expected a number", name, value);
+            None
+        }
+    }
+}
+
+/// Filters for `GET /api/audit`
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub actor: Option<String>,
+    pub action: Option<String>,
+    pub target_type: Option<String>,
+    pub target_id: Option<String>,
+    pub outcome: Option<AuditOutcome>,
+    pub since: Option<DateTime<Utc>>,
+    pub until: Option<DateTime<Utc>>,
+    pub limit: Option<usize>,
+}
+
+impl AuditQuery {
+    fn matches(&self, event: &AuditEvent) -> bool {
+        fn eq(filter: &Option<String>, value: &str) -> bool {
+            filter.as_deref().is_none_or(|f| f == value)
+        }
+
+        eq(&self.actor, &event.actor)
+            && eq(&self.action, &event.action)
+            && eq(&self.target_type, &event.target_type)
+            && eq(
+                &self.target_id,
+                event.target_id.as_deref().unwrap_or_default(),
+            )
+            && self.outcome.is_none_or(|o| o == event.outcome)
+            && self.since.is_none_or(|since| event.timestamp >= since)
+            && self.until.is_none_or(|until| event.timestamp < until)
+    }
+}
+
+/// How often retention is applied to the `audit_log` table; pruning on every
+/// request would scan the table's index each time
+const DATABASE_PRUNE_INTERVAL: chrono::Duration = chrono::Duration::minutes(1);
+
+#[derive(sqlx::FromRow)]
+struct AuditRow {
+    id: Uuid,
+    timestamp: DateTime<Utc>,
+    actor: String,
+    user_id: Option<Uuid>,
+    token_id: Option<Uuid>,
+    action: String,
+    target_type: String,
+    target_id: Option<String>,
+    outcome: String,
+    status_code: i32,
+    message: Option<String>,
+    method: String,
+    path: String,
+    remote_addr: Option<String>,
+    user_agent: Option<String>,
+    request_id: String,
+}
+
+/// Append-only audit trail; events are only ever removed by retention. With a
+/// database events go to the `audit_log` table, otherwise they are kept in memory.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    events: Arc<RwLock<VecDeque<AuditEvent>>>,
+    config: AuditConfig,
+    db: Option<PgPool>,
+    last_pruned: Arc<Mutex<Option<DateTime<Utc>>>>,
+}
+
+impl AuditLog {
+    pub fn new(config: AuditConfig) -> Self {
+        Self {
+            config,
+            ..Default::default()
+        }
+    }
+
+    /// An audit log kept in the database's `audit_log` table
+    pub fn with_database(config: AuditConfig, db: PgPool) -> Self {
+        Self {
+            config,
+            db: Some(db),
+            ..Default::default()
+        }
+    }
+
+    pub fn config(&self) -> &AuditConfig {
+        &self.config
+    }
+
+    /// Append an event and drop whatever has fallen out of retention
+    pub async fn record(&self, event: AuditEvent) {
+        // Also emitted as a log line so events can be shipped to external storage
+        info!(
+            target: "sparktest::audit",
+            actor = %event.actor,
+            action = %event.action,
+            target_id = event.target_id.as_deref().unwrap_or_default(),
+            outcome = ?event.outcome,
+            status = event.status_code,
+            request_id = %event.request_id,
+            "audit"
+        );
+
+        if let Some(db) = &self.db {
+            if let Err(e) = insert_event(db, &event).await {
+                warn!("Failed to store audit event {}: {}", event.id, e);
+            }
+            self.prune_database(db, Utc::now()).await;
+            return;
+        }
+
+        let mut events = self.events.write().await;
+        events.push_back(event);
+        self.apply_retention(&mut events, Utc::now());
+    }
+
+    fn apply_retention(&self, events: &mut VecDeque<AuditEvent>, now: DateTime<Utc>) {
+        if let Some(retention) = self.config.retention {
+            let cutoff = now - retention;
+            while events.front().is_some_and(|e| e.timestamp < cutoff) {
+                events.pop_front();
+            }
+        }
+        if let Some(max_events) = self.config.max_events {
+            let excess = events.len().saturating_sub(max_events);
+            events.drain(..excess);
+        }
+    }
+
+    async fn prune_database(&self, db: &PgPool, now: DateTime<Utc>) {
+        {
+            let mut last_pruned = self.last_pruned.lock().unwrap();
+            if last_pruned.is_some_and(|at| now - at < DATABASE_PRUNE_INTERVAL) {
+                return;
+            }
+            *last_pruned = Some(now);
+        }
+
+        if let Some(retention) = self.config.retention {
+            let pruned = traced("DELETE FROM audit_log WHERE timestamp < $1", |sql| {
+                sqlx::query(sql).bind(now - retention).execute(db)
+            })
+            .await;
+            if let Err(e) = pruned {
+                warn!("Failed to apply audit log retention: {}", e);
+            }
+        }
+        if let Some(max_events) = self.config.max_events {
+            let pruned = traced(
+                "DELETE FROM audit_log WHERE id IN \
+                 (SELECT id FROM audit_log ORDER BY timestamp DESC OFFSET $1)",
+                |sql| sqlx::query(sql).bind(max_events as i64).execute(db),
+            )
+            .await;
+            if let Err(e) = pruned {
+                warn!("Failed to apply the audit log size limit: {}", e);
+            }
+        }
+    }
+
+    /// Matching events, newest first
+    pub async fn query(&self, query: &AuditQuery) -> Result<Vec<AuditEvent>, sqlx::Error> {
+        let limit = query.limit.unwrap_or(100).min(1000);
+        if let Some(db) = &self.db {
+            return query_events(db, query, limit).await;
+        }
+        Ok(self
+            .events
+            .read()
+            .await
+            .iter()
+            .rev()
+            .filter(|event| query.matches(event))
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+}
+
+async fn insert_event(db: &PgPool, event: &AuditEvent) -> Result<(), sqlx::Error> {
+    traced(
+        "INSERT INTO audit_log (id, timestamp, actor, user_id, token_id, action, target_type, \
+         target_id, outcome, status_code, message, method, path, remote_addr, user_agent, \
+         request_id) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)",
+        |sql| {
+            sqlx::query(sql)
+                .bind(event.id)
+                .bind(event.timestamp)
+                .bind(&event.actor)
+                .bind(event.user_id)
+                .bind(event.token_id)
+                .bind(&event.action)
+                .bind(&event.target_type)
+                .bind(&event.target_id)
+                .bind(outcome_name(event.outcome))
+                .bind(i32::from(event.status_code))
+                .bind(&event.message)
+                .bind(&event.method)
+                .bind(&event.path)
+                .bind(&event.remote_addr)
+                .bind(&event.user_agent)
+                .bind(&event.request_id)
+                .execute(db)
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+async fn query_events(
+    db: &PgPool,
+    query: &AuditQuery,
+    limit: usize,
+) -> Result<Vec<AuditEvent>, sqlx::Error> {
+    let mut sql = QueryBuilder::<Postgres>::new(
+        "SELECT id, timestamp, actor, user_id, token_id, action, target_type, target_id, \
+         outcome, status_code, message, method, path, remote_addr, user_agent, request_id \
+         FROM audit_log WHERE TRUE",
+    );
+    for (column, filter) in [
+        ("actor", &query.actor),
+        ("action", &query.action),
+        ("target_type", &query.target_type),
+        ("target_id", &query.target_id),
+    ] {
+        if let Some(value) = filter {
+            sql.push(format_args!(" AND {column} = ")).push_bind(value);
+        }
+    }
+    if let Some(outcome) = query.outcome {
+        sql.push(" AND outcome = ").push_bind(outcome_name(outcome));
+    }
+    if let Some(since) = query.since {
+        sql.push(" AND timestamp >= ").push_bind(since);
+    }
+    if let Some(until) = query.until {
+        sql.push(" AND timestamp < ").push_bind(until);
+    }
+    sql.push(" ORDER BY timestamp DESC LIMIT ")
+        .push_bind(limit as i64);
+
+    let statement = sql.sql().to_string();
+    let rows: Vec<AuditRow> = traced(&statement, |_| sql.build_query_as().fetch_all(db)).await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| AuditEvent {
+            id: row.id,
+            timestamp: row.timestamp,
+            actor: row.actor,
+            user_id: row.user_id,
+            token_id: row.token_id,
+            action: row.action,
+            target_type: row.target_type,
+            target_id: row.target_id,
+            outcome: match row.outcome.as_str() {
+                "success" => AuditOutcome::Success,
+                "denied" => AuditOutcome::Denied,
+                _ => AuditOutcome::Failed,
+            },
+            status_code: row.status_code as u16,
+            message: row.message,
+            method: row.method,
+            path: row.path,
+            remote_addr: row.remote_addr,
+            user_agent: row.user_agent,
+            request_id: row.request_id,
+        })
+        .collect())
+}
+
+fn outcome_name(outcome: AuditOutcome) -> &'static str {
+    match outcome {
+        AuditOutcome::Success => "success",
+        AuditOutcome::Denied => "denied",
+        AuditOutcome::Failed => "failed",
+    }
+}
+
+/// Record every mutating request in the audit log. Runs outside authentication so
+/// rejected requests are recorded too; the principal comes back on the response.
+pub async fn record_audit(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return next.run(request).await;
+    }
+
+    let method = request.method().clone();
+    let path = request.uri().path().to_string();
+    let template = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched| matched.as_str().to_string())
+        .unwrap_or_else(|| path.clone());
+    let headers = request.headers().clone();
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+
+    let response = next.run(request).await;
+
+    let (parts, body) = response.into_parts();
+    let (body, inspected) = inspect_body(body).await;
+
+    let (target_type, mut target_id, action) = describe(&method, &template, &path);
+    if target_id.is_none() && parts.status.is_success() {
+        // Creates don't have the id in the path, so take it from the created resource
+        target_id = ["id", "name"]
+            .iter()
+            .find_map(|key| inspected[key].as_str().map(str::to_string));
+    }
+
+    let principal = parts.extensions.get::<Principal>();
+    let event = AuditEvent {
+        id: Uuid::new_v4(),
+        timestamp: Utc::now(),
+        actor: principal.map_or_else(|| "unauthenticated".to_string(), |p| p.name.clone()),
+        user_id: principal.and_then(|p| p.user_id),
+        token_id: principal.and_then(|p| p.token_id),
+        action,
+        target_type,
+        target_id,
+        outcome: outcome(parts.status),
+        status_code: parts.status.as_u16(),
+        message: (!parts.status.is_success())
+            .then(|| inspected["message"].as_str().map(str::to_string))
+            .flatten(),
+        method: method.to_string(),
+        path,
+        remote_addr: client_addr(peer, &headers, &state.audit.config().trusted_proxies),
+        user_agent: header(&headers, "user-agent"),
+        request_id: header(&headers, "x-request-id").unwrap_or_else(|| Uuid::new_v4().to_string()),
+    };
+    state.audit.record(event).await;
+
+    Response::from_parts(parts, body)
+}
+
+/// Parse a response body that is known to be small as JSON, handing back an
+/// equivalent body. Streamed or larger bodies pass through untouched and are
+/// not inspected.
+async fn inspect_body(body: Body) -> (Body, serde_json::Value) {
+    let small = body
+        .size_hint()
+        .upper()
+        .is_some_and(|size| size <= MAX_INSPECTED_BODY as u64);
+    if !small {
+        return (body, serde_json::Value::Null);
+    }
+    match to_bytes(body, MAX_INSPECTED_BODY).await {
+        Ok(bytes) => {
+            let inspected = serde_json::from_slice(&bytes).unwrap_or_default();
+            (Body::from(bytes), inspected)
+        }
+        Err(error) => {
+            warn!(
+                "Failed to read a response body for the audit log: {}",
+                error
+            );
+            (Body::empty(), serde_json::Value::Null)
+        }
+    }
+}
+
+/// The connecting peer, unless it is a trusted proxy: then the nearest address in
+/// `X-Forwarded-For` that isn't one of the trusted proxies itself. Clients can put
+/// anything in the header, so it is never read from untrusted peers.
+fn client_addr(peer: Option<IpAddr>, headers: &HeaderMap, trusted: &[IpAddr]) -> Option<String> {
+    let peer = peer?;
+    if !trusted.contains(&peer) {
+        return Some(peer.to_string());
+    }
+    let Some(forwarded) = header(headers, "x-forwarded-for") else {
+        return Some(peer.to_string());
+    };
+    let hops: Vec<&str> = forwarded.split(',').map(str::trim).collect();
+    hops.iter()
+        .rev()
+        .find(|hop| hop.parse().map_or(true, |ip| !trusted.contains(&ip)))
+        .or(hops.first())
+        .map(|hop| hop.to_string())
+}
+
+fn header(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+fn outcome(status: StatusCode) -> AuditOutcome {
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => AuditOutcome::Denied,
+        status if status.is_success() || status.is_redirection() => AuditOutcome::Success,
+        _ => AuditOutcome::Failed,
+    }
+}
+
+/// Derive the target type, target id and action from the matched route, e.g.
+/// `POST /api/test-definitions/:id/run` is `test_definition.run` on that definition
+pub fn describe(method: &Method, template: &str, path: &str) -> (String, Option<String>, String) {
+    let template: Vec<&str> = template.trim_start_matches("/api/").split('/').collect();
+    let path: Vec<&str> = path.trim_start_matches("/api/").split('/').collect();
+
+    // `/api/k8s/jobs/:job_name` is about jobs, not about `k8s`
+    let skip = usize::from(template.first() == Some(&"k8s"));
+    let target_type = match template.get(skip).copied().unwrap_or_default() {
+        "runs" | "test-runs" => "test_run".to_string(),
+        collection => collection
+            .strip_suffix('s')
+            .unwrap_or(collection)
+            .replace('-', "_"),
+    };
+
+    let first_param = template.iter().position(|segment| segment.starts_with(':'));
+    let target_id = first_param
+        .and_then(|i| path.get(i))
+        .map(|id| id.to_string());
+    let sub_resource: Vec<&str> = first_param
+        .map(|i| &template[i + 1..])
+        .unwrap_or_default()
+        .iter()
+        .filter(|segment| !segment.starts_with(':'))
+        .copied()
+        .collect();
+
+    let verb = match *method {
+        Method::POST if sub_resource.is_empty() => "create",
+        Method::POST => "",
+        Method::PUT | Method::PATCH => "update",
+        Method::DELETE => "delete",
+        _ => "",
+    };
+    let action = std::iter::once(target_type.as_str())
+        .chain(sub_resource)
+        .chain((!verb.is_empty()).then_some(verb))
+        .collect::<Vec<_>>()
+        .join(".");
+
+    (target_type, target_id, action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    fn event(actor: &str, action: &str, timestamp: DateTime<Utc>) -> AuditEvent {
+        AuditEvent {
+            id: Uuid::new_v4(),
+            timestamp,
+            actor: actor.to_string(),
+            user_id: None,
+            token_id: None,
+            action: action.to_string(),
+            target_type: "job".to_string(),
+            target_id: Some("test-run-1".to_string()),
+            outcome: AuditOutcome::Success,
+            status_code: 200,
+            message: None,
+            method: "DELETE".to_string(),
+            path: "/api/k8s/jobs/test-run-1".to_string(),
+            remote_addr: None,
+            user_agent: None,
+            request_id: Uuid::new_v4().to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_large_bodies_pass_through() {
+        let large = vec![b'x'; MAX_INSPECTED_BODY + 1];
+        let (body, inspected) = inspect_body(Body::from(large.clone())).await;
+        assert!(inspected.is_null());
+        assert_eq!(to_bytes(body, usize::MAX).await.unwrap(), large);
+
+        let (body, inspected) = inspect_body(Body::from(r#"{"id":"abc"}"#)).await;
+        assert_eq!(inspected["id"], "abc");
+        assert_eq!(
+            to_bytes(body, usize::MAX).await.unwrap(),
+            r#"{"id":"abc"}"#.as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_forwarded_for_is_only_trusted_from_proxies() {
+        let proxy: IpAddr = "10.0.0.2".parse().unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            "1.2.3.4, 198.51.100.1, 10.0.0.3".parse().unwrap(),
+        );
+        let trusted = ["10.0.0.2".parse().unwrap(), "10.0.0.3".parse().unwrap()];
+
+        // A client can't claim another address by sending the header itself
+        assert_eq!(
+            client_addr(Some(client), &headers, &trusted).as_deref(),
+            Some("203.0.113.7")
+        );
+        // Behind the proxies, the last hop they didn't add is the client
+        assert_eq!(
+            client_addr(Some(proxy), &headers, &trusted).as_deref(),
+            Some("198.51.100.1")
+        );
+        assert_eq!(
+            client_addr(Some(proxy), &HeaderMap::new(), &trusted).as_deref(),
+            Some("10.0.0.2")
+        );
+        assert_eq!(client_addr(None, &headers, &trusted), None);
+    }
+
+    #[test]
+    fn test_describe_routes() {
+        let cases = [
+            (
+                Method::POST,
+                "/api/test-runs",
+                "/api/test-runs",
+                "test_run",
+                None,
+                "test_run.create",
+            ),
+            (
+                Method::DELETE,
+                "/api/k8s/jobs/:job_name",
+                "/api/k8s/jobs/test-run-1",
+                "job",
+                Some("test-run-1"),
+                "job.delete",
+            ),
+            (
+                Method::POST,
+                "/api/test-definitions/:id/run",
+                "/api/test-definitions/abc/run",
+                "test_definition",
+                Some("abc"),
+                "test_definition.run",
+            ),
+            (
+                Method::PUT,
+                "/api/teams/:id/members/:user_id",
+                "/api/teams/t1/members/u1",
+                "team",
+                Some("t1"),
+                "team.members.update",
+            ),
+            (
+                Method::DELETE,
+                "/api/execution-targets/:name",
+                "/api/execution-targets/staging",
+                "execution_target",
+                Some("staging"),
+                "execution_target.delete",
+            ),
+        ];
+
+        for (method, template, path, target_type, target_id, action) in cases {
+            let described = describe(&method, template, path);
+            assert_eq!(
+                described,
+                (
+                    target_type.to_string(),
+                    target_id.map(str::to_string),
+                    action.to_string()
+                ),
+                "{method} {template}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_filters_newest_first() {
+        let log = AuditLog::default();
+        let now = Utc::now();
+        log.record(event(
+            "alice",
+            "job.delete",
+            now - chrono::Duration::minutes(2),
+        ))
+        .await;
+        log.record(event(
+            "bob",
+            "job.delete",
+            now - chrono::Duration::minutes(1),
+        ))
+        .await;
+        log.record(event("alice", "test_run.create", now)).await;
+
+        let all = log.query(&AuditQuery::default()).await.unwrap();
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[0].action, "test_run.create");
+
+        let query = AuditQuery {
+            actor: Some("alice".to_string()),
+            action: Some("job.delete".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(log.query(&query).await.unwrap().len(), 1);
+
+        let query = AuditQuery {
+            since: Some(now - chrono::Duration::seconds(90)),
+            limit: Some(1),
+            ..Default::default()
+        };
+        let recent = log.query(&query).await.unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].action, "test_run.create");
+    }
+
+    #[tokio::test]
+    async fn test_retention_drops_old_events() {
+        let log = AuditLog::new(AuditConfig {
+            retention: Some(chrono::Duration::days(1)),
+            max_events: Some(2),
+            ..Default::default()
+        });
+        let now = Utc::now();
+        log.record(event("old", "job.delete", now - chrono::Duration::days(2)))
+            .await;
+        assert!(log.query(&AuditQuery::default()).await.unwrap().is_empty());
+
+        for actor in ["a", "b", "c"] {
+            log.record(event(actor, "job.delete", now)).await;
+        }
+        let actors: Vec<String> = log
+            .query(&AuditQuery::default())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|e| e.actor)
+            .collect();
+        assert_eq!(actors, vec!["c", "b"]);
+    }
+
+    #[tokio::test]
+    async fn test_events_are_stored_in_the_database() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        let log = AuditLog::with_database(AuditConfig::default(), db.clone());
+        let actor = format!("auditor-{}", Uuid::new_v4());
+        let now = Utc::now();
+        log.record(event(
+            &actor,
+            "job.delete",
+            now - chrono::Duration::seconds(1),
+        ))
+        .await;
+        let mut denied = event(&actor, "test_run.create", now);
+        denied.outcome = AuditOutcome::Denied;
+        denied.status_code = 403;
+        log.record(denied).await;
+
+        // Another server instance sees the same events
+        let restarted = AuditLog::with_database(AuditConfig::default(), db);
+        let query = AuditQuery {
+            actor: Some(actor.clone()),
+            ..Default::default()
+        };
+        let events = restarted.query(&query).await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].action, "test_run.create");
+        assert_eq!(events[0].outcome, AuditOutcome::Denied);
+        assert_eq!(events[0].status_code, 403);
+
+        let query = AuditQuery {
+            actor: Some(actor),
+            outcome: Some(AuditOutcome::Success),
+            ..Default::default()
+        };
+        let events = restarted.query(&query).await.unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].target_id.as_deref(), Some("test-run-1"));
+    }
+}
@@ -0,0 +1,593 @@
+use crate::db::traced;
+use crate::error::{ApiError, ErrorType};
+use crate::state::AppState;
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Request, State},
+    http::{header, request::Parts, Method},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use sparktest_core::{ApiToken, Role, TokenScope};
+use sqlx::PgPool;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::warn;
+use uuid::Uuid;
+
+/// Prefix of every SparkTest token secret, so leaked tokens are easy to spot
+pub const TOKEN_PREFIX: &str = "spk_";
+
+/// The caller a request was authenticated as, available to handlers as an extractor
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub token_id: Option<Uuid>,
+    pub user_id: Option<Uuid>,
+    pub name: String,
+    pub scope: TokenScope,
+    pub is_admin: bool,
+    /// The caller's role in each team they belong to
+    pub roles: HashMap<Uuid, Role>,
+}
+
+impl Principal {
+    /// Used for every request when authentication is disabled
+    pub fn anonymous() -> Self {
+        Self {
+            token_id: None,
+            user_id: None,
+            name: "anonymous".to_string(),
+            scope: TokenScope::Admin,
+            is_admin: true,
+            roles: HashMap::new(),
+        }
+    }
+
+    /// Webhook deliveries are authenticated by their signature, not a token.
+    /// The rules they trigger were authorized when they were created.
+    pub fn webhook() -> Self {
+        Self {
+            token_id: None,
+            user_id: None,
+            name: "webhook".to_string(),
+            scope: TokenScope::Run,
+            is_admin: false,
+            roles: HashMap::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for Principal {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<Principal>()
+            .cloned()
+            .ok_or_else(|| ApiError::unauthorized("Request was not authenticated"))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    pub enabled: bool,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+impl AuthConfig {
+    /// Authentication is on unless `SPARKTEST_AUTH_DISABLED=true`
+    pub fn from_env() -> Self {
+        let disabled = std::env::var("SPARKTEST_AUTH_DISABLED")
+            .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+            .unwrap_or(false);
+        Self { enabled: !disabled }
+    }
+}
+
+struct StoredToken {
+    token: ApiToken,
+    secret_hash: String,
+}
+
+/// How stale `last_used_at` may get in the database, so authenticating
+/// doesn't write a row on every request
+const LAST_USED_PRECISION: chrono::Duration = chrono::Duration::minutes(1);
+
+#[derive(sqlx::FromRow)]
+struct TokenRow {
+    id: Uuid,
+    name: String,
+    user_id: Option<Uuid>,
+    scope: String,
+    prefix: String,
+    secret_hash: String,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    last_used_at: Option<DateTime<Utc>>,
+    revoked_at: Option<DateTime<Utc>>,
+}
+
+/// Token table keyed by id; secrets are only kept as SHA-256 hashes. With a
+/// database the `api_tokens` table is the source of truth and this is its cache.
+#[derive(Clone, Default)]
+pub struct TokenStore {
+    tokens: Arc<RwLock<HashMap<Uuid, StoredToken>>>,
+    db: Option<PgPool>,
+}
+
+impl TokenStore {
+    /// Every token stored in the database, which changes are written to from then on
+    pub async fn load(db: PgPool) -> Result<Self, sqlx::Error> {
+        let rows: Vec<TokenRow> = traced(
+            "SELECT id, name, user_id, scope, prefix, secret_hash, created_at, expires_at, \
+             last_used_at, revoked_at FROM api_tokens",
+            |sql| sqlx::query_as(sql).fetch_all(&db),
+        )
+        .await?;
+
+        let mut tokens = HashMap::new();
+        for row in rows {
+            let Some(scope) = parse_scope(&row.scope) else {
+                warn!(
+                    "Ignoring token {} with unknown scope '{}'",
+                    row.id, row.scope
+                );
+                continue;
+            };
+            let token = ApiToken {
+                id: row.id,
+                name: row.name,
+                user_id: row.user_id,
+                scope,
+                prefix: row.prefix,
+                created_at: row.created_at,
+                expires_at: row.expires_at,
+                last_used_at: row.last_used_at,
+                revoked_at: row.revoked_at,
+            };
+            let secret_hash = row.secret_hash;
+            tokens.insert(token.id, StoredToken { token, secret_hash });
+        }
+        Ok(Self {
+            tokens: Arc::new(RwLock::new(tokens)),
+            db: Some(db),
+        })
+    }
+
+    /// Create a token and return it together with its secret, which is not stored
+    pub async fn create(
+        &self,
+        name: String,
+        user_id: Option<Uuid>,
+        scope: TokenScope,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> Result<(ApiToken, String), sqlx::Error> {
+        let secret = generate_secret();
+        let token = self
+            .insert(name, user_id, scope, expires_at, &secret)
+            .await?;
+        Ok((token, secret))
+    }
+
+    /// Register a token with a caller-provided secret, e.g. the bootstrap admin
+    /// token. A secret that is already registered keeps its existing token.
+    pub async fn insert(
+        &self,
+        name: String,
+        user_id: Option<Uuid>,
+        scope: TokenScope,
+        expires_at: Option<DateTime<Utc>>,
+        secret: &str,
+    ) -> Result<ApiToken, sqlx::Error> {
+        let secret_hash = hash_secret(secret);
+        let mut tokens = self.tokens.write().await;
+        if let Some(existing) = tokens
+            .values()
+            .find(|stored| constant_time_eq(&stored.secret_hash, &secret_hash))
+        {
+            return Ok(existing.token.clone());
+        }
+
+        let token = ApiToken {
+            id: Uuid::new_v4(),
+            name,
+            user_id,
+            scope,
+            prefix: secret.chars().take(TOKEN_PREFIX.len() + 6).collect(),
+            created_at: Utc::now(),
+            expires_at,
+            last_used_at: None,
+            revoked_at: None,
+        };
+        if let Some(db) = &self.db {
+            traced(
+                "INSERT INTO api_tokens \
+                 (id, name, user_id, scope, prefix, secret_hash, created_at, expires_at) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                |sql| {
+                    sqlx::query(sql)
+                        .bind(token.id)
+                        .bind(&token.name)
+                        .bind(token.user_id)
+                        .bind(scope_name(token.scope))
+                        .bind(&token.prefix)
+                        .bind(&secret_hash)
+                        .bind(token.created_at)
+                        .bind(token.expires_at)
+                        .execute(db)
+                },
+            )
+            .await?;
+        }
+        let stored = StoredToken {
+            token: token.clone(),
+            secret_hash,
+        };
+        tokens.insert(token.id, stored);
+        Ok(token)
+    }
+
+    /// List tokens, oldest first
+    pub async fn list(&self) -> Vec<ApiToken> {
+        let mut tokens: Vec<ApiToken> = self
+            .tokens
+            .read()
+            .await
+            .values()
+            .map(|stored| stored.token.clone())
+            .collect();
+        tokens.sort_by_key(|token| token.created_at);
+        tokens
+    }
+
+    /// Mark a token as revoked; returns `None` if it doesn't exist
+    pub async fn revoke(&self, id: Uuid) -> Result<Option<ApiToken>, sqlx::Error> {
+        let mut tokens = self.tokens.write().await;
+        let Some(stored) = tokens.get_mut(&id) else {
+            return Ok(None);
+        };
+        if stored.token.revoked_at.is_none() {
+            let now = Utc::now();
+            if let Some(db) = &self.db {
+                traced(
+                    "UPDATE api_tokens SET revoked_at = $2 WHERE id = $1",
+                    |sql| sqlx::query(sql).bind(id).bind(now).execute(db),
+                )
+                .await?;
+            }
+            stored.token.revoked_at = Some(now);
+        }
+        Ok(Some(stored.token.clone()))
+    }
+
+    /// Find the live token matching a presented secret and record its use
+    pub async fn authenticate(&self, secret: &str) -> Option<ApiToken> {
+        let secret_hash = hash_secret(secret);
+        let now = Utc::now();
+
+        let mut tokens = self.tokens.write().await;
+        let stored = tokens
+            .values_mut()
+            .find(|stored| constant_time_eq(&stored.secret_hash, &secret_hash))?;
+
+        let token = &mut stored.token;
+        if !token.is_live(now) {
+            return None;
+        }
+        let stale = token
+            .last_used_at
+            .is_none_or(|at| now - at >= LAST_USED_PRECISION);
+        if let (Some(db), true) = (&self.db, stale) {
+            let recorded = traced(
+                "UPDATE api_tokens SET last_used_at = $2 WHERE id = $1",
+                |sql| sqlx::query(sql).bind(token.id).bind(now).execute(db),
+            )
+            .await;
+            if let Err(e) = recorded {
+                warn!("Failed to record the use of token {}: {}", token.id, e);
+            }
+        }
+        token.last_used_at = Some(now);
+        Some(token.clone())
+    }
+}
+
+fn generate_secret() -> String {
+    format!(
+        "{TOKEN_PREFIX}{}{}",
+        Uuid::new_v4().simple(),
+        Uuid::new_v4().simple()
+    )
+}
+
+pub fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+pub(crate) fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+/// The scope a request needs: reads need `read`, token, target, user, team and
+/// admission policy management need `admin`, and every other mutation (launching, deleting runs
+/// and jobs, editing definitions) needs `run`
+pub fn required_scope(method: &Method, path: &str) -> TokenScope {
+    let admin_mutations = [
+        "/api/execution-targets",
+        "/api/users",
+        "/api/teams",
+        "/api/admission-policy",
+    ];
+
+    if path.starts_with("/api/tokens") {
+        TokenScope::Admin
+    } else if matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS) {
+        TokenScope::Read
+    } else if admin_mutations
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+    {
+        TokenScope::Admin
+    } else {
+        TokenScope::Run
+    }
+}
+
+fn bearer_token(request: &Request) -> Option<&str> {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+fn unauthorized(message: &str) -> Response {
+    let mut response = ApiError::unauthorized(message).into_response();
+    response.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        header::HeaderValue::from_static("Bearer"),
+    );
+    response
+}
+
+/// Authenticate the bearer token, check its scope against the route and make
+/// the resulting [`Principal`] available to handlers. The principal is also
+/// attached to the response so outer layers such as the audit log can see it.
+pub async fn require_auth(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    // Liveness probes must keep working without credentials
+    if !state.auth.enabled || request.uri().path() == "/api/health" {
+        return run_as(Principal::anonymous(), request, next).await;
+    }
+    if request.uri().path().starts_with("/api/webhooks/") {
+        return run_as(Principal::webhook(), request, next).await;
+    }
+
+    let Some(secret) = bearer_token(&request) else {
+        return unauthorized("Missing bearer token");
+    };
+
+    // SparkTest tokens are recognisable by their prefix; anything else is an OIDC JWT
+    let principal = match &state.oidc {
+        Some(oidc) if !secret.starts_with(TOKEN_PREFIX) => {
+            match oidc.authenticate(&state.store, secret).await {
+                Ok(principal) => principal,
+                Err(e) if e.error_type == ErrorType::Unauthorized => {
+                    return unauthorized(&e.message)
+                }
+                Err(e) => return e.into_response(),
+            }
+        }
+        _ => match token_principal(&state, secret).await {
+            Some(principal) => principal,
+            None => return unauthorized("Invalid, expired or revoked token"),
+        },
+    };
+
+    let required = required_scope(request.method(), request.uri().path());
+    if principal.scope < required {
+        let mut response = ApiError::forbidden(format!(
+            "'{}' does not have the '{}' scope",
+            principal.name,
+            scope_name(required)
+        ))
+        .with_details(serde_json::json!({
+            "actor": principal.name,
+            "token_id": principal.token_id,
+            "token_scope": principal.scope,
+            "required_scope": required,
+            "method": request.method().as_str(),
+            "path": request.uri().path(),
+        }))
+        .into_response();
+        response.extensions_mut().insert(principal);
+        return response;
+    }
+
+    run_as(principal, request, next).await
+}
+
+async fn run_as(principal: Principal, mut request: Request, next: Next) -> Response {
+    request.extensions_mut().insert(principal.clone());
+    let mut response = next.run(request).await;
+    response.extensions_mut().insert(principal);
+    response
+}
+
+/// Resolve a SparkTest API token to the principal it acts as
+async fn token_principal(state: &AppState, secret: &str) -> Option<Principal> {
+    let token = state.tokens.authenticate(secret).await?;
+
+    match token.user_id {
+        Some(user_id) => {
+            let user = state.store.get_user(user_id).await?;
+            Some(Principal {
+                token_id: Some(token.id),
+                user_id: Some(user.id),
+                name: user.name,
+                scope: token.scope,
+                is_admin: user.is_admin,
+                roles: state.store.roles_of(user.id).await,
+            })
+        }
+        // Service tokens act instance-wide, limited only by their scope
+        None => Some(Principal {
+            token_id: Some(token.id),
+            user_id: None,
+            name: token.name,
+            scope: token.scope,
+            is_admin: true,
+            roles: HashMap::new(),
+        }),
+    }
+}
+
+fn parse_scope(scope: &str) -> Option<TokenScope> {
+    match scope {
+        "read" => Some(TokenScope::Read),
+        "run" => Some(TokenScope::Run),
+        "admin" => Some(TokenScope::Admin),
+        _ => None,
+    }
+}
+
+fn scope_name(scope: TokenScope) -> &'static str {
+    match scope {
+        TokenScope::Read => "read",
+        TokenScope::Run => "run",
+        TokenScope::Admin => "admin",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+
+    #[tokio::test]
+    async fn test_token_lifecycle() {
+        let store = TokenStore::default();
+        let (token, secret) = store
+            .create("ci".to_string(), None, TokenScope::Run, None)
+            .await
+            .unwrap();
+        assert!(secret.starts_with(TOKEN_PREFIX));
+        assert!(secret.starts_with(&token.prefix));
+
+        let authenticated = store.authenticate(&secret).await.unwrap();
+        assert_eq!(authenticated.id, token.id);
+        assert!(authenticated.last_used_at.is_some());
+        assert!(store.authenticate("spk_wrong").await.is_none());
+
+        store.revoke(token.id).await.unwrap().unwrap();
+        assert!(store.authenticate(&secret).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_token_is_rejected() {
+        let store = TokenStore::default();
+        let expired = Utc::now() - chrono::Duration::minutes(1);
+        let (_, secret) = store
+            .create("old".to_string(), None, TokenScope::Read, Some(expired))
+            .await
+            .unwrap();
+        assert!(store.authenticate(&secret).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_tokens_survive_a_restart() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        let store = TokenStore::load(db.clone()).await.unwrap();
+        let (token, secret) = store
+            .create(
+                "ci".to_string(),
+                Some(Uuid::new_v4()),
+                TokenScope::Run,
+                None,
+            )
+            .await
+            .unwrap();
+        let (revoked, revoked_secret) = store
+            .create("old".to_string(), None, TokenScope::Admin, None)
+            .await
+            .unwrap();
+        store.revoke(revoked.id).await.unwrap();
+        store.authenticate(&secret).await.unwrap();
+
+        let restarted = TokenStore::load(db).await.unwrap();
+        let authenticated = restarted.authenticate(&secret).await.unwrap();
+        assert_eq!(authenticated.id, token.id);
+        assert_eq!(authenticated.scope, TokenScope::Run);
+        assert!(restarted.authenticate(&revoked_secret).await.is_none());
+
+        // Registering a known secret again keeps its token
+        let again = restarted
+            .insert("ci".to_string(), None, TokenScope::Run, None, &secret)
+            .await
+            .unwrap();
+        assert_eq!(again.id, token.id);
+    }
+
+    #[test]
+    fn test_secrets_are_hashed() {
+        let hash = hash_secret("spk_example");
+        assert_eq!(hash.len(), 64);
+        assert_ne!(hash, "spk_example");
+        assert_eq!(hash, hash_secret("spk_example"));
+    }
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(
+            required_scope(&Method::GET, "/api/test-runs"),
+            TokenScope::Read
+        );
+        assert_eq!(
+            required_scope(&Method::POST, "/api/test-runs"),
+            TokenScope::Run
+        );
+        assert_eq!(
+            required_scope(&Method::DELETE, "/api/k8s/jobs/test-job"),
+            TokenScope::Run
+        );
+        assert_eq!(
+            required_scope(&Method::POST, "/api/execution-targets"),
+            TokenScope::Admin
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "/api/execution-targets"),
+            TokenScope::Read
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "/api/tokens"),
+            TokenScope::Admin
+        );
+        assert_eq!(
+            required_scope(&Method::PUT, "/api/teams/x/members/y"),
+            TokenScope::Admin
+        );
+    }
+
+    #[test]
+    fn test_scope_ordering() {
+        assert!(TokenScope::Admin > TokenScope::Run);
+        assert!(TokenScope::Run > TokenScope::Read);
+    }
+}
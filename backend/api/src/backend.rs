@@ -0,0 +1,263 @@
+use crate::fake::FakeBackend;
+use crate::job_metadata::job_metadata;
+use crate::k8s::{JobLogs, KubernetesClient};
+use crate::local::{ContainerRuntime, LocalBackend};
+use crate::metrics::observe_k8s;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use sparktest_core::{ExecutionTarget, GitSource, ResourceLimits, SecretRef, TestRun};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Everything a backend needs to launch the workload for a test run
+#[derive(Debug, Clone, Default)]
+pub struct JobSpec {
+    pub name: String,
+    pub image: String,
+    pub commands: Vec<String>,
+    pub env: BTreeMap<String, String>,
+    pub resources: Option<ResourceLimits>,
+    pub privileged: bool,
+    pub secrets: Vec<SecretRef>,
+    pub source: Option<GitSource>,
+    /// Image that clones `source` into the workspace
+    pub git_image: String,
+    /// Uploaded test files by name, mounted read-only into the container
+    pub files: BTreeMap<String, Vec<u8>>,
+    /// Set on the Job and its pod next to SparkTest's own `app` and `component`
+    pub labels: BTreeMap<String, String>,
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl JobSpec {
+    /// Build the job for a run; job names follow the `test-run-<uuid>` convention.
+    /// File contents live in the store, so the caller attaches them.
+    pub fn for_run(run: &TestRun, git_image: &str) -> Self {
+        let env = run
+            .variables
+            .as_ref()
+            .and_then(|v| v.as_object())
+            .map(|vars| {
+                vars.iter()
+                    .map(|(key, value)| {
+                        let value = match value {
+                            serde_json::Value::String(s) => s.clone(),
+                            other => other.to_string(),
+                        };
+                        (key.clone(), value)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let (labels, annotations) = job_metadata(run);
+
+        Self {
+            name: format!("test-run-{}", run.id),
+            image: run.image.clone(),
+            commands: run.commands.clone(),
+            env,
+            resources: run.resources.clone(),
+            privileged: run.privileged,
+            secrets: run.secrets.clone(),
+            source: run.source.clone(),
+            git_image: git_image.to_string(),
+            files: BTreeMap::new(),
+            labels,
+            annotations,
+        }
+    }
+}
+
+/// One key of a Secret, and the team the Secret is labelled for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecretValue {
+    pub value: String,
+    /// The Secret's `sparktest.dev/team` label
+    pub team: Option<String>,
+}
+
+/// Returned (possibly wrapped in context) when a backend has no job with the given name
+#[derive(Debug)]
+pub struct JobNotFound(pub String);
+
+impl std::fmt::Display for JobNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Job '{}' not found", self.0)
+    }
+}
+
+impl std::error::Error for JobNotFound {}
+
+/// The operations SparkTest needs from whatever actually runs test workloads.
+///
+/// Job status is reported as `pending`, `running`, `completed` or `failed`.
+#[async_trait]
+pub trait ExecutionBackend: Send + Sync {
+    /// Short identifier reported by the health endpoint
+    fn name(&self) -> &'static str;
+
+    async fn create_job(&self, target: &ExecutionTarget, spec: &JobSpec) -> Result<()>;
+
+    async fn get_job_status(&self, target: &ExecutionTarget, job_name: &str) -> Result<String>;
+
+    async fn get_job_logs(&self, target: &ExecutionTarget, job_name: &str) -> Result<JobLogs>;
+
+    async fn delete_job(&self, target: &ExecutionTarget, job_name: &str) -> Result<()>;
+
+    async fn health_check(&self, target: &ExecutionTarget) -> Result<bool>;
+
+    /// Drop anything cached for a target that was deleted or is being re-registered
+    async fn forget_target(&self, _name: &str) {}
+
+    /// Read one key of a Secret on the target, `None` if the secret or key is
+    /// missing. Used to check references before launching and to redact logs.
+    async fn read_secret(
+        &self,
+        _target: &ExecutionTarget,
+        _name: &str,
+        _key: &str,
+    ) -> Result<Option<SecretValue>> {
+        Err(anyhow::anyhow!(
+            "the {} backend does not support Kubernetes secrets",
+            self.name()
+        ))
+    }
+}
+
+/// Runs tests as Kubernetes Jobs on the cluster behind each target
+#[derive(Default)]
+pub struct KubernetesBackend {
+    /// One client per target name, so connections and auth are reused
+    clients: RwLock<HashMap<String, Arc<KubernetesClient>>>,
+}
+
+impl KubernetesBackend {
+    async fn client(&self, target: &ExecutionTarget) -> Result<Arc<KubernetesClient>> {
+        if let Some(client) = self.clients.read().await.get(&target.name) {
+            return Ok(client.clone());
+        }
+
+        let mut clients = self.clients.write().await;
+        if let Some(client) = clients.get(&target.name) {
+            return Ok(client.clone());
+        }
+        let client = Arc::new(
+            KubernetesClient::for_target(target)
+                .await
+                .context("Kubernetes client unavailable")?,
+        );
+        clients.insert(target.name.clone(), client.clone());
+        Ok(client)
+    }
+}
+
+#[async_trait]
+impl ExecutionBackend for KubernetesBackend {
+    fn name(&self) -> &'static str {
+        "kubernetes"
+    }
+
+    async fn create_job(&self, target: &ExecutionTarget, spec: &JobSpec) -> Result<()> {
+        observe_k8s("create_job", async {
+            self.client(target).await?.create_job(spec).await
+        })
+        .await
+    }
+
+    async fn get_job_status(&self, target: &ExecutionTarget, job_name: &str) -> Result<String> {
+        observe_k8s("get_job_status", async {
+            self.client(target).await?.get_job_status(job_name).await
+        })
+        .await
+    }
+
+    async fn get_job_logs(&self, target: &ExecutionTarget, job_name: &str) -> Result<JobLogs> {
+        observe_k8s("get_job_logs", async {
+            self.client(target).await?.get_job_logs(job_name).await
+        })
+        .await
+    }
+
+    async fn delete_job(&self, target: &ExecutionTarget, job_name: &str) -> Result<()> {
+        observe_k8s("delete_job", async {
+            self.client(target).await?.delete_job(job_name).await
+        })
+        .await
+    }
+
+    async fn health_check(&self, target: &ExecutionTarget) -> Result<bool> {
+        observe_k8s("health_check", async {
+            self.client(target).await?.health_check().await
+        })
+        .await
+    }
+
+    async fn forget_target(&self, name: &str) {
+        self.clients.write().await.remove(name);
+    }
+
+    async fn read_secret(
+        &self,
+        target: &ExecutionTarget,
+        name: &str,
+        key: &str,
+    ) -> Result<Option<SecretValue>> {
+        observe_k8s("read_secret", async {
+            self.client(target).await?.read_secret(name, key).await
+        })
+        .await
+    }
+}
+
+/// Pick the execution backend from `SPARKTEST_EXECUTION_BACKEND`
+/// (`kubernetes`, `docker`, `podman` or `fake`; defaults to `kubernetes`)
+pub fn backend_from_env() -> Result<Arc<dyn ExecutionBackend>> {
+    let kind = std::env::var("SPARKTEST_EXECUTION_BACKEND").unwrap_or_default();
+    backend_from_name(&kind)
+}
+
+pub fn backend_from_name(kind: &str) -> Result<Arc<dyn ExecutionBackend>> {
+    match kind.trim().to_ascii_lowercase().as_str() {
+        "" | "kubernetes" | "k8s" => Ok(Arc::new(KubernetesBackend::default())),
+        "docker" => Ok(Arc::new(LocalBackend::new(ContainerRuntime::Docker))),
+        "podman" => Ok(Arc::new(LocalBackend::new(ContainerRuntime::Podman))),
+        "fake" => Ok(Arc::new(FakeBackend::default())),
+        other => Err(anyhow::anyhow!("Unknown execution backend '{other}'")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backend_from_name() {
+        assert_eq!(backend_from_name("").unwrap().name(), "kubernetes");
+        assert_eq!(
+            backend_from_name("Kubernetes").unwrap().name(),
+            "kubernetes"
+        );
+        assert_eq!(backend_from_name("docker").unwrap().name(), "docker");
+        assert_eq!(backend_from_name("podman").unwrap().name(), "podman");
+        assert_eq!(backend_from_name("fake").unwrap().name(), "fake");
+        assert!(backend_from_name("nomad").is_err());
+    }
+
+    #[test]
+    fn test_job_spec_for_run() {
+        let mut run = TestRun::new(
+            "Spec Run".to_string(),
+            "node:18-alpine".to_string(),
+            vec!["npm".to_string(), "test".to_string()],
+        );
+        run.variables = Some(serde_json::json!({ "NODE_ENV": "test", "WORKERS": 4 }));
+
+        let spec = JobSpec::for_run(&run, "alpine/git:2.45.2");
+        assert_eq!(spec.name, format!("test-run-{}", run.id));
+        assert_eq!(spec.git_image, "alpine/git:2.45.2");
+        assert_eq!(spec.commands, run.commands);
+        assert_eq!(spec.env["NODE_ENV"], "test");
+        assert_eq!(spec.env["WORKERS"], "4");
+    }
+}
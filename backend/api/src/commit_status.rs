@@ -0,0 +1,737 @@
+use crate::db::traced;
+use crate::encryption::storage_key;
+use crate::error::ApiError;
+use crate::policy::glob_match;
+use crate::state::AppState;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use sparktest_core::{RunTrigger, StatusReporter, TestRun};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+const GITHUB_API_URL: &str = "https://api.github.com";
+const GITLAB_API_URL: &str = "https://gitlab.com/api/v4";
+
+/// How commit statuses are delivered
+#[derive(Debug, Clone)]
+pub struct StatusReportingConfig {
+    /// Base URL of the SparkTest UI, so statuses can link to their run
+    pub public_url: Option<String>,
+    pub max_attempts: u32,
+    /// Delay before the first retry; it doubles with every further attempt
+    pub retry_delay: Duration,
+    pub http: reqwest::Client,
+    pub deliveries: DeliveryLocks,
+}
+
+type DeliveryLock = Arc<tokio::sync::Mutex<()>>;
+
+/// One lock per (run, reporter), so a run's updates reach the Git host one at a time
+#[derive(Debug, Clone, Default)]
+pub struct DeliveryLocks(Arc<Mutex<HashMap<(Uuid, Uuid), DeliveryLock>>>);
+
+impl DeliveryLocks {
+    fn acquire(&self, key: (Uuid, Uuid)) -> DeliveryLock {
+        self.0.lock().unwrap().entry(key).or_default().clone()
+    }
+
+    /// Forget the lock once no other delivery for the pair is waiting on it
+    fn release(&self, key: (Uuid, Uuid), lock: DeliveryLock) {
+        let mut locks = self.0.lock().unwrap();
+        drop(lock);
+        if locks
+            .get(&key)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(&key);
+        }
+    }
+}
+
+impl Default for StatusReportingConfig {
+    fn default() -> Self {
+        Self {
+            public_url: None,
+            max_attempts: 4,
+            retry_delay: Duration::from_secs(2),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("static client configuration"),
+            deliveries: DeliveryLocks::default(),
+        }
+    }
+}
+
+impl StatusReportingConfig {
+    /// Reads `SPARKTEST_PUBLIC_URL`
+    pub fn from_env() -> Self {
+        Self {
+            public_url: std::env::var("SPARKTEST_PUBLIC_URL")
+                .ok()
+                .map(|url| url.trim_end_matches('/').to_string())
+                .filter(|url| !url.is_empty()),
+            ..Default::default()
+        }
+    }
+}
+
+/// A run's status as a commit status
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommitState {
+    Pending,
+    Running,
+    Success,
+    Failure,
+}
+
+impl CommitState {
+    pub fn of(run: &TestRun) -> Self {
+        match run.status.as_str() {
+            "running" => CommitState::Running,
+            "succeeded" => CommitState::Success,
+            "failed" => CommitState::Failure,
+            _ => CommitState::Pending,
+        }
+    }
+
+    /// GitHub has no separate running state
+    fn github(self) -> &'static str {
+        match self {
+            CommitState::Pending | CommitState::Running => "pending",
+            CommitState::Success => "success",
+            CommitState::Failure => "failure",
+        }
+    }
+
+    fn gitlab(self) -> &'static str {
+        match self {
+            CommitState::Pending => "pending",
+            CommitState::Running => "running",
+            CommitState::Success => "success",
+            CommitState::Failure => "failed",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            CommitState::Pending => "Queued in SparkTest",
+            CommitState::Running => "Running in SparkTest",
+            CommitState::Success => "Tests passed",
+            CommitState::Failure => "Tests failed",
+        }
+    }
+}
+
+/// One commit status request
+#[derive(Debug, Clone)]
+pub struct StatusUpdate {
+    pub url: String,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: serde_json::Value,
+}
+
+/// The API call that sets `state` on the trigger's commit
+pub fn build_update(
+    reporter: &StatusReporter,
+    run: &TestRun,
+    trigger: &RunTrigger,
+    state: CommitState,
+    public_url: Option<&str>,
+) -> StatusUpdate {
+    let target_url = public_url.map(|url| format!("{url}/runs/{}", run.id));
+    // Statuses of different runs of one commit would overwrite each other under a shared context
+    let context = format!("{}/{}", reporter.context, run.name);
+
+    if reporter.provider == "gitlab" {
+        let api_url = reporter.api_url.as_deref().unwrap_or(GITLAB_API_URL);
+        StatusUpdate {
+            url: format!(
+                "{}/projects/{}/statuses/{}",
+                api_url.trim_end_matches('/'),
+                trigger.repository.replace('/', "%2F"),
+                trigger.commit_sha
+            ),
+            headers: vec![("PRIVATE-TOKEN", reporter.token.clone())],
+            body: serde_json::json!({
+                "state": state.gitlab(),
+                "name": context,
+                "description": state.description(),
+                "target_url": target_url,
+            }),
+        }
+    } else {
+        let api_url = reporter.api_url.as_deref().unwrap_or(GITHUB_API_URL);
+        StatusUpdate {
+            url: format!(
+                "{}/repos/{}/statuses/{}",
+                api_url.trim_end_matches('/'),
+                trigger.repository,
+                trigger.commit_sha
+            ),
+            headers: vec![
+                ("Authorization", format!("Bearer {}", reporter.token)),
+                ("Accept", "application/vnd.github+json".to_string()),
+            ],
+            body: serde_json::json!({
+                "state": state.github(),
+                "context": context,
+                "description": state.description(),
+                "target_url": target_url,
+            }),
+        }
+    }
+}
+
+/// Team reporters only report their own team's runs
+pub fn reporter_matches(reporter: &StatusReporter, run: &TestRun, trigger: &RunTrigger) -> bool {
+    reporter.provider == trigger.provider
+        && reporter
+            .team_id
+            .is_none_or(|team_id| run.team_id == Some(team_id))
+        && glob_match(
+            &reporter.repository.to_ascii_lowercase(),
+            &trigger.repository.to_ascii_lowercase(),
+        )
+}
+
+pub fn validate_reporter(reporter: &StatusReporter) -> Result<(), ApiError> {
+    if !matches!(reporter.provider.as_str(), "github" | "gitlab") {
+        return Err(ApiError::validation(
+            "provider must be 'github' or 'gitlab'",
+        ));
+    }
+    if !reporter.repository.contains('/') {
+        return Err(ApiError::validation(
+            "repository must be of the form 'owner/repo'",
+        ));
+    }
+    if reporter.token.is_empty() {
+        return Err(ApiError::validation("token is required"));
+    }
+    if reporter.context.is_empty() {
+        return Err(ApiError::validation("context must not be empty"));
+    }
+    if let Some(api_url) = &reporter.api_url {
+        if !(api_url.starts_with("https://") || api_url.starts_with("http://")) {
+            return Err(ApiError::validation(format!(
+                "'{api_url}' is not an http(s) URL"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Report a triggered run's current status to every matching reporter in the
+/// background. Runs that were not started by a Git event are ignored.
+pub fn report_run_status(state: &AppState, run: &TestRun) {
+    let Some(trigger) = run.trigger.clone() else {
+        return;
+    };
+    let (state, run) = (state.clone(), run.clone());
+    tokio::spawn(async move {
+        let commit_state = CommitState::of(&run);
+        for reporter in state.store.list_status_reporters().await {
+            if !reporter_matches(&reporter, &run, &trigger) {
+                continue;
+            }
+            let update = build_update(
+                &reporter,
+                &run,
+                &trigger,
+                commit_state,
+                state.reporting.public_url.as_deref(),
+            );
+            let state = state.clone();
+            tokio::spawn(async move {
+                match deliver(&state, run.id, reporter.id, commit_state, &update).await {
+                    Ok(()) => info!(
+                        "Reported {:?} for run {} to '{}'",
+                        commit_state, run.id, reporter.name
+                    ),
+                    Err(e) => warn!(
+                        "Failed to report status of run {} to '{}': {:#}",
+                        run.id, reporter.name, e
+                    ),
+                }
+            });
+        }
+    });
+}
+
+/// Send an update, retrying network errors, rate limits and server errors with
+/// exponential backoff. Updates for one run and reporter are sent one at a
+/// time, and only while the run is still in `commit_state`, so neither a slow
+/// first attempt nor a late retry overwrites a newer status.
+pub async fn deliver(
+    state: &AppState,
+    run_id: Uuid,
+    reporter_id: Uuid,
+    commit_state: CommitState,
+    update: &StatusUpdate,
+) -> Result<()> {
+    let key = (run_id, reporter_id);
+    let lock = state.reporting.deliveries.acquire(key);
+    let result = deliver_in_order(state, run_id, commit_state, update, &lock).await;
+    state.reporting.deliveries.release(key, lock);
+    result
+}
+
+async fn deliver_in_order(
+    state: &AppState,
+    run_id: Uuid,
+    commit_state: CommitState,
+    update: &StatusUpdate,
+    lock: &tokio::sync::Mutex<()>,
+) -> Result<()> {
+    let config = &state.reporting;
+    let mut delay = config.retry_delay;
+    let mut last_error = anyhow!("no delivery attempts configured");
+
+    for attempt in 1..=config.max_attempts {
+        if attempt > 1 {
+            sleep(delay).await;
+            delay *= 2;
+        }
+        let _in_order = lock.lock().await;
+        let current = state
+            .store
+            .get_run(run_id)
+            .await
+            .map(|run| CommitState::of(&run));
+        if current != Some(commit_state) {
+            return Ok(());
+        }
+
+        let mut request = config.http.post(&update.url).json(&update.body);
+        for (name, value) in &update.headers {
+            request = request.header(*name, value);
+        }
+        request = request.header("User-Agent", "sparktest");
+
+        match request.send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => {
+                let status = response.status();
+                let body = response.text().await.unwrap_or_default();
+                last_error = anyhow!("{} returned {status}: {}", update.url, body.trim());
+                // Anything else in the 4xx range won't get better by retrying
+                if status.is_client_error() && status.as_u16() != 429 {
+                    return Err(last_error);
+                }
+            }
+            Err(e) => {
+                last_error =
+                    anyhow::Error::new(e).context(format!("Failed to reach {}", update.url))
+            }
+        }
+    }
+    Err(last_error).with_context(|| format!("Gave up after {} attempts", config.max_attempts))
+}
+
+#[derive(sqlx::FromRow)]
+struct StatusReporterRow {
+    id: Uuid,
+    name: String,
+    provider: String,
+    repository: String,
+    api_url: Option<String>,
+    context: String,
+    encrypted_token: Vec<u8>,
+    team_id: Option<Uuid>,
+    created_at: DateTime<Utc>,
+}
+
+/// Restore the status reporters stored in the database by a previous process,
+/// returning how many. Their tokens need the key they were encrypted with.
+pub async fn load_status_reporters(state: &AppState) -> Result<usize> {
+    let Some(db) = &state.db else {
+        return Ok(0);
+    };
+    let rows: Vec<StatusReporterRow> = traced(
+        "SELECT id, name, provider, repository, api_url, context, encrypted_token, team_id, \
+         created_at FROM status_reporters",
+        |sql| sqlx::query_as(sql).fetch_all(db),
+    )
+    .await?;
+    if rows.is_empty() {
+        return Ok(0);
+    }
+    let key = state
+        .secret_key
+        .as_ref()
+        .context("Status reporters are stored, but SPARKTEST_SECRET_KEY is not set")?;
+
+    let count = rows.len();
+    for row in rows {
+        let token = key
+            .decrypt(&row.encrypted_token)
+            .with_context(|| format!("Failed to decrypt the token of reporter '{}'", row.name))?;
+        state
+            .store
+            .insert_status_reporter(StatusReporter {
+                id: row.id,
+                name: row.name,
+                provider: row.provider,
+                repository: row.repository,
+                api_url: row.api_url,
+                context: row.context,
+                token,
+                team_id: row.team_id,
+                created_at: row.created_at,
+            })
+            .await;
+    }
+    Ok(count)
+}
+
+/// Write a status reporter to the database, if there is one, with its token
+/// encrypted
+pub async fn save_status_reporter(
+    state: &AppState,
+    reporter: &StatusReporter,
+) -> Result<(), ApiError> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    let encrypted_token = storage_key(state, "status reporters")?.encrypt(&reporter.token);
+    traced(
+        "INSERT INTO status_reporters (id, name, provider, repository, api_url, context, \
+         encrypted_token, team_id, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) \
+         ON CONFLICT (id) DO UPDATE SET name = $2, provider = $3, repository = $4, \
+         api_url = $5, context = $6, encrypted_token = $7, team_id = $8",
+        |sql| {
+            sqlx::query(sql)
+                .bind(reporter.id)
+                .bind(&reporter.name)
+                .bind(&reporter.provider)
+                .bind(&reporter.repository)
+                .bind(&reporter.api_url)
+                .bind(&reporter.context)
+                .bind(&encrypted_token)
+                .bind(reporter.team_id)
+                .bind(reporter.created_at)
+                .execute(db)
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+/// Remove a status reporter from the database, if there is one
+pub async fn delete_saved_status_reporter(state: &AppState, id: Uuid) -> Result<(), sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    traced("DELETE FROM status_reporters WHERE id = $1", |sql| {
+        sqlx::query(sql).bind(id).execute(db)
+    })
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use crate::encryption::SecretKey;
+    use axum::{http::HeaderMap, http::StatusCode, routing::post, Json, Router};
+    use chrono::Utc;
+    use sparktest_core::GitEventKind;
+    use std::sync::{Arc, Mutex};
+
+    /// A Git host stub that records status requests and fails the first few
+    struct StatusStub {
+        url: String,
+        received: Arc<Mutex<Vec<(HeaderMap, serde_json::Value)>>>,
+    }
+
+    impl StatusStub {
+        async fn start(failures: usize, failure_status: StatusCode) -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let received = Arc::new(Mutex::new(Vec::new()));
+
+            let log = received.clone();
+            let app = Router::new().route(
+                "/repos/:owner/:repo/statuses/:sha",
+                post(
+                    move |headers: HeaderMap, Json(body): Json<serde_json::Value>| async move {
+                        let mut log = log.lock().unwrap();
+                        log.push((headers, body));
+                        if log.len() <= failures {
+                            failure_status
+                        } else {
+                            StatusCode::CREATED
+                        }
+                    },
+                ),
+            );
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+            Self { url, received }
+        }
+
+        fn received(&self) -> Vec<(HeaderMap, serde_json::Value)> {
+            self.received.lock().unwrap().clone()
+        }
+    }
+
+    fn reporter(provider: &str, api_url: &str) -> StatusReporter {
+        StatusReporter {
+            id: Uuid::new_v4(),
+            name: "example".to_string(),
+            provider: provider.to_string(),
+            repository: "example/*".to_string(),
+            api_url: Some(api_url.to_string()),
+            context: "sparktest".to_string(),
+            token: "ghp_token".to_string(),
+            team_id: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn triggered_run(status: &str) -> TestRun {
+        let mut run = TestRun::new(
+            "Unit Tests - PR #247".to_string(),
+            "node:20".to_string(),
+            vec![],
+        );
+        run.status = status.to_string();
+        run.trigger = Some(RunTrigger {
+            provider: "github".to_string(),
+            event: GitEventKind::PullRequest,
+            repository: "example/app".to_string(),
+            branch: "main".to_string(),
+            commit_sha: "e5bd3914e2e596debea16f433f57875b5b90bcd6".to_string(),
+            pull_request: Some(247),
+            sender: None,
+            rule_id: None,
+        });
+        run
+    }
+
+    fn state_with_fast_retries() -> AppState {
+        let mut state = AppState::default();
+        state.reporting.retry_delay = Duration::from_millis(10);
+        state.reporting.public_url = Some("https://sparktest.example.com".to_string());
+        state
+    }
+
+    async fn wait_for_requests(
+        stub: &StatusStub,
+        count: usize,
+    ) -> Vec<(HeaderMap, serde_json::Value)> {
+        for _ in 0..200 {
+            let received = stub.received();
+            if received.len() >= count {
+                return received;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        panic!(
+            "expected {count} status requests, got {:?}",
+            stub.received()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_status_is_reported_with_run_link() {
+        let stub = StatusStub::start(0, StatusCode::CREATED).await;
+        let state = state_with_fast_retries();
+        state
+            .store
+            .insert_status_reporter(reporter("github", &stub.url))
+            .await;
+
+        let run = triggered_run("succeeded");
+        state.store.insert_run(run.clone()).await.unwrap();
+        report_run_status(&state, &run);
+
+        let (headers, body) = wait_for_requests(&stub, 1).await.remove(0);
+        assert_eq!(headers["authorization"], "Bearer ghp_token");
+        assert_eq!(body["state"], "success");
+        assert_eq!(body["context"], "sparktest/Unit Tests - PR #247");
+        assert_eq!(
+            body["target_url"],
+            format!("https://sparktest.example.com/runs/{}", run.id)
+        );
+
+        // Runs without a trigger have nothing to report to
+        report_run_status(
+            &state,
+            &TestRun::new("Manual".to_string(), "alpine".to_string(), vec![]),
+        );
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(stub.received().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_are_retried() {
+        let stub = StatusStub::start(2, StatusCode::BAD_GATEWAY).await;
+        let state = state_with_fast_retries();
+        let run = triggered_run("failed");
+        state.store.insert_run(run.clone()).await.unwrap();
+
+        let update = build_update(
+            &reporter("github", &stub.url),
+            &run,
+            run.trigger.as_ref().unwrap(),
+            CommitState::Failure,
+            None,
+        );
+        deliver(&state, run.id, Uuid::nil(), CommitState::Failure, &update)
+            .await
+            .unwrap();
+        let received = stub.received();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[2].1["state"], "failure");
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_and_stale_statuses_are_not_retried() {
+        let stub = StatusStub::start(usize::MAX, StatusCode::UNAUTHORIZED).await;
+        let state = state_with_fast_retries();
+        let run = triggered_run("running");
+        state.store.insert_run(run.clone()).await.unwrap();
+        let update = build_update(
+            &reporter("github", &stub.url),
+            &run,
+            run.trigger.as_ref().unwrap(),
+            CommitState::Running,
+            None,
+        );
+        assert!(
+            deliver(&state, run.id, Uuid::nil(), CommitState::Running, &update)
+                .await
+                .is_err()
+        );
+        assert_eq!(stub.received().len(), 1);
+
+        // The run finished before "running" could be sent
+        let flaky = StatusStub::start(usize::MAX, StatusCode::SERVICE_UNAVAILABLE).await;
+        let update = StatusUpdate {
+            url: update.url.replace(&stub.url, &flaky.url),
+            ..update
+        };
+        state
+            .store
+            .update_run(run.id, |run| run.status = "succeeded".to_string())
+            .await;
+        deliver(&state, run.id, Uuid::nil(), CommitState::Running, &update)
+            .await
+            .unwrap();
+        assert!(flaky.received().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_updates_of_a_run_are_sent_in_order() {
+        let stub = StatusStub::start(0, StatusCode::CREATED).await;
+        let state = state_with_fast_retries();
+        state
+            .store
+            .insert_status_reporter(reporter("github", &stub.url))
+            .await;
+
+        let run = triggered_run("running");
+        state.store.insert_run(run.clone()).await.unwrap();
+        report_run_status(&state, &run);
+        let run = state
+            .store
+            .update_run(run.id, |run| run.status = "succeeded".to_string())
+            .await
+            .unwrap();
+        report_run_status(&state, &run);
+
+        // "running" may be skipped, but it never arrives after "success"
+        wait_for_requests(&stub, 1).await;
+        sleep(Duration::from_millis(50)).await;
+        let states: Vec<serde_json::Value> = stub
+            .received()
+            .into_iter()
+            .map(|(_, body)| body["state"].clone())
+            .collect();
+        assert_eq!(states.last().unwrap(), "success");
+    }
+
+    #[test]
+    fn test_gitlab_update() {
+        let run = triggered_run("failed");
+        let mut trigger = run.trigger.clone().unwrap();
+        trigger.provider = "gitlab".to_string();
+        trigger.repository = "group/sub/app".to_string();
+        let reporter = StatusReporter {
+            api_url: None,
+            ..reporter("gitlab", "")
+        };
+
+        let update = build_update(&reporter, &run, &trigger, CommitState::Failure, None);
+        assert_eq!(
+            update.url,
+            "https://gitlab.com/api/v4/projects/group%2Fsub%2Fapp/statuses/e5bd3914e2e596debea16f433f57875b5b90bcd6"
+        );
+        assert_eq!(
+            update.headers[0],
+            ("PRIVATE-TOKEN", "ghp_token".to_string())
+        );
+        assert_eq!(update.body["state"], "failed");
+        assert!(update.body["target_url"].is_null());
+        // "example/*" does not cover nested GitLab groups
+        assert!(!reporter_matches(&reporter, &run, &trigger));
+    }
+
+    #[tokio::test]
+    async fn test_reporters_survive_a_restart_with_encrypted_tokens() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        let key = SecretKey::from_hex(&"42".repeat(32)).unwrap();
+        let mut state = AppState {
+            db: Some(db.clone()),
+            ..AppState::default()
+        };
+        let reporter = reporter("github", "https://github.example.com/api/v3");
+        let error = save_status_reporter(&state, &reporter).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::FORBIDDEN);
+        state.secret_key = Some(key.clone());
+        save_status_reporter(&state, &reporter).await.unwrap();
+
+        let (stored,): (Vec<u8>,) =
+            sqlx::query_as("SELECT encrypted_token FROM status_reporters WHERE id = $1")
+                .bind(reporter.id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert!(!String::from_utf8_lossy(&stored).contains("ghp_token"));
+
+        // Other tests share the database; only look at this reporter
+        let mut restarted = AppState {
+            db: Some(db.clone()),
+            ..AppState::default()
+        };
+        assert!(load_status_reporters(&restarted).await.is_err());
+        restarted.secret_key = Some(key);
+        load_status_reporters(&restarted).await.unwrap();
+        let loaded = restarted
+            .store
+            .get_status_reporter(reporter.id)
+            .await
+            .unwrap();
+        assert_eq!(loaded.token, "ghp_token");
+        assert_eq!(loaded.api_url, reporter.api_url);
+
+        delete_saved_status_reporter(&restarted, reporter.id)
+            .await
+            .unwrap();
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM status_reporters WHERE id = $1")
+                .bind(reporter.id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(count, 0);
+    }
+}
@@ -0,0 +1,571 @@
+use crate::error::ApiError;
+use crate::job_metadata::validate_job_metadata;
+use crate::launch::{launch_definition, launch_suite, RunOrigin};
+use crate::secrets::validate_secret_refs;
+use crate::source::validate_source;
+use crate::state::AppState;
+use chrono::Utc;
+use kube::CustomResource;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::Schema;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sparktest_core::{
+    GitSource, ResourceLimits, RunPriority, SecretRef, TestDefinition, TestRun, TestSuite,
+};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// API group of the SparkTest custom resources
+pub const CRD_GROUP: &str = "sparktest.dev";
+
+/// A test definition declared in the cluster. The definition in SparkTest's
+/// store takes the resource's UID as its id and follows the spec on every change.
+#[derive(CustomResource, Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[kube(
+    group = "sparktest.dev",
+    version = "v1alpha1",
+    kind = "TestDefinition",
+    root = "TestDefinitionResource",
+    namespaced,
+    status = "SyncStatus",
+    shortname = "std",
+    printcolumn = r#"{"name":"Image","type":"string","jsonPath":".spec.image"}"#,
+    printcolumn = r#"{"name":"Synced","type":"boolean","jsonPath":".status.synced"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct TestDefinitionSpec {
+    /// Name shown in SparkTest; the resource name when unset
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub description: String,
+    pub image: String,
+    #[serde(default)]
+    pub commands: Vec<String>,
+    pub executor: Option<String>,
+    /// Environment variables of the test container
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+    #[serde(default)]
+    pub labels: Vec<String>,
+    /// Execution target the runs go to; the default target when unset
+    pub target: Option<String>,
+    pub resources: Option<ResourceLimitsSpec>,
+    #[serde(default)]
+    pub secrets: Vec<SecretRefSpec>,
+    pub source: Option<GitSourceSpec>,
+    #[serde(default)]
+    #[schemars(schema_with = "priority_schema")]
+    pub priority: RunPriority,
+    /// Extra labels for the Jobs of its runs
+    #[serde(default)]
+    pub job_labels: BTreeMap<String, String>,
+    /// Extra annotations for the Jobs of its runs
+    #[serde(default)]
+    pub job_annotations: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ResourceLimitsSpec {
+    pub cpu: Option<String>,
+    pub memory: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SecretRefSpec {
+    pub secret: String,
+    pub key: String,
+    pub env: Option<String>,
+    pub path: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GitSourceSpec {
+    pub url: String,
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+    pub subdirectory: Option<String>,
+}
+
+fn priority_schema(_: &mut SchemaGenerator) -> Schema {
+    serde_json::from_value(serde_json::json!({
+        "type": "string",
+        "enum": ["low", "normal", "high"],
+    }))
+    .expect("the schema is valid")
+}
+
+/// A suite of `TestDefinition` resources in the same namespace
+#[derive(CustomResource, Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[kube(
+    group = "sparktest.dev",
+    version = "v1alpha1",
+    kind = "TestSuite",
+    root = "TestSuiteResource",
+    namespaced,
+    status = "SyncStatus",
+    shortname = "sts",
+    printcolumn = r#"{"name":"Mode","type":"string","jsonPath":".spec.executionMode"}"#,
+    printcolumn = r#"{"name":"Synced","type":"boolean","jsonPath":".status.synced"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct TestSuiteSpec {
+    pub display_name: Option<String>,
+    #[serde(default)]
+    pub description: String,
+    /// Names of `TestDefinition` resources, in the order sequential suites run them
+    pub definitions: Vec<String>,
+    #[serde(default)]
+    pub execution_mode: ExecutionMode,
+    #[serde(default)]
+    pub labels: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ExecutionMode {
+    #[default]
+    Parallel,
+    Sequential,
+}
+
+impl ExecutionMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ExecutionMode::Parallel => "parallel",
+            ExecutionMode::Sequential => "sequential",
+        }
+    }
+}
+
+/// Where a definition or suite resource stands in SparkTest's store
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncStatus {
+    /// Id of the definition or suite in SparkTest
+    pub id: Option<String>,
+    pub synced: bool,
+    /// The `metadata.generation` last synced or rejected
+    pub observed_generation: Option<i64>,
+    /// Why the spec was rejected
+    pub message: Option<String>,
+}
+
+/// One run of a `TestDefinition`, or one run per definition of a `TestSuite`.
+/// Runs start when the resource is created; the status follows them.
+#[derive(CustomResource, Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[kube(
+    group = "sparktest.dev",
+    version = "v1alpha1",
+    kind = "TestRun",
+    root = "TestRunResource",
+    namespaced,
+    status = "TestRunStatus",
+    shortname = "str",
+    printcolumn = r#"{"name":"Phase","type":"string","jsonPath":".status.phase"}"#,
+    printcolumn = r#"{"name":"Passed","type":"integer","jsonPath":".status.passed"}"#,
+    printcolumn = r#"{"name":"Failed","type":"integer","jsonPath":".status.failed"}"#
+)]
+#[serde(rename_all = "camelCase")]
+pub struct TestRunSpec {
+    /// Name of a `TestDefinition` resource; set this or `suite`
+    pub definition: Option<String>,
+    /// Name of a `TestSuite` resource
+    pub suite: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TestRunStatus {
+    /// `pending`, `running`, `succeeded` or `failed` across all of the runs, or
+    /// `unknown` while some of them aren't known to the server
+    pub phase: Option<String>,
+    /// Ids of the runs in SparkTest
+    #[serde(default)]
+    pub run_ids: Vec<String>,
+    #[serde(default)]
+    pub passed: usize,
+    #[serde(default)]
+    pub failed: usize,
+    /// Seconds the longest run took
+    pub duration: Option<i32>,
+    /// Why the runs couldn't be started or followed
+    pub message: Option<String>,
+}
+
+impl TestRunStatus {
+    pub fn is_finished(&self) -> bool {
+        matches!(self.phase.as_deref(), Some("succeeded" | "failed"))
+    }
+}
+
+impl From<ResourceLimitsSpec> for ResourceLimits {
+    fn from(spec: ResourceLimitsSpec) -> Self {
+        Self {
+            cpu: spec.cpu,
+            memory: spec.memory,
+        }
+    }
+}
+
+impl From<SecretRefSpec> for SecretRef {
+    fn from(spec: SecretRefSpec) -> Self {
+        Self {
+            secret: spec.secret,
+            key: spec.key,
+            env: spec.env,
+            path: spec.path,
+        }
+    }
+}
+
+impl From<GitSourceSpec> for GitSource {
+    fn from(spec: GitSourceSpec) -> Self {
+        Self {
+            url: spec.url,
+            git_ref: spec.git_ref,
+            subdirectory: spec.subdirectory,
+        }
+    }
+}
+
+/// Create or update the definition declared by a resource, checked like one
+/// created through the API. Uploaded files are kept across updates.
+pub async fn apply_definition(
+    state: &AppState,
+    id: Uuid,
+    name: &str,
+    spec: &TestDefinitionSpec,
+) -> Result<TestDefinition, ApiError> {
+    let spec = spec.clone();
+    if spec.image.is_empty() {
+        return Err(ApiError::validation("image is required"));
+    }
+    if let Some(target) = &spec.target {
+        if state.targets.resolve(Some(target)).await.is_none() {
+            return Err(ApiError::validation(format!(
+                "Unknown execution target '{target}'"
+            )));
+        }
+    }
+    let variables = (!spec.variables.is_empty()).then(|| serde_json::json!(spec.variables));
+    let secrets: Vec<SecretRef> = spec.secrets.into_iter().map(SecretRef::from).collect();
+    validate_secret_refs(&secrets, variables.as_ref())?;
+    let source = spec.source.map(GitSource::from);
+    if let Some(source) = &source {
+        validate_source(source)?;
+    }
+    validate_job_metadata(&spec.job_labels, &spec.job_annotations)?;
+
+    let existing = state.store.get_definition(id).await;
+    let definition = TestDefinition {
+        id,
+        name: spec.display_name.unwrap_or_else(|| name.to_string()),
+        description: spec.description,
+        image: spec.image,
+        commands: spec.commands,
+        created_at: existing
+            .as_ref()
+            .map_or_else(Utc::now, |existing| existing.created_at),
+        executor_id: spec.executor,
+        variables,
+        labels: (!spec.labels.is_empty()).then_some(spec.labels),
+        target: spec.target,
+        team_id: None,
+        resources: spec.resources.map(ResourceLimits::from),
+        privileged: false,
+        secrets,
+        source,
+        files: existing.map(|existing| existing.files).unwrap_or_default(),
+        priority: spec.priority,
+        job_labels: spec.job_labels,
+        job_annotations: spec.job_annotations,
+    };
+    state
+        .policy
+        .read()
+        .await
+        .admit_definition(&definition, &state.runner.git_image)?;
+    state.store.insert_definition(definition.clone()).await?;
+    Ok(definition)
+}
+
+/// Create or update the suite declared by a resource, its definitions given
+/// by the ids their resources synced to
+pub async fn apply_suite(
+    state: &AppState,
+    id: Uuid,
+    name: &str,
+    spec: &TestSuiteSpec,
+    definition_ids: Vec<Uuid>,
+) -> Result<TestSuite, ApiError> {
+    for definition_id in &definition_ids {
+        if state.store.get_definition(*definition_id).await.is_none() {
+            return Err(ApiError::validation(format!(
+                "Test definition {definition_id} has not been synced"
+            )));
+        }
+    }
+    let spec = spec.clone();
+    let suite = TestSuite {
+        id,
+        name: spec.display_name.unwrap_or_else(|| name.to_string()),
+        description: spec.description,
+        test_definition_ids: definition_ids,
+        created_at: state
+            .store
+            .get_suite(id)
+            .await
+            .map_or_else(Utc::now, |existing| existing.created_at),
+        execution_mode: spec.execution_mode.as_str().to_string(),
+        labels: (!spec.labels.is_empty()).then_some(spec.labels),
+        team_id: None,
+    };
+    state.store.insert_suite(suite.clone()).await?;
+    Ok(suite)
+}
+
+/// What a `TestRun` resource runs, resolved to ids in the store
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunSource {
+    Definition(Uuid),
+    Suite(Uuid),
+}
+
+/// Start the runs a `TestRun` resource asks for. The runs record the resource's
+/// UID, so if they were started before but never made it into its status they
+/// are returned instead of being started again.
+pub async fn start_runs(
+    state: &AppState,
+    resource_uid: &str,
+    source: RunSource,
+) -> Result<Vec<TestRun>, ApiError> {
+    let started = state.store.find_runs_by_resource(resource_uid).await;
+    if !started.is_empty() {
+        return Ok(started);
+    }
+
+    let origin = RunOrigin {
+        resource_uid: Some(resource_uid.to_string()),
+        ..RunOrigin::default()
+    };
+    match source {
+        RunSource::Definition(id) => {
+            let definition = state.store.get_definition(id).await.ok_or_else(|| {
+                ApiError::not_found(format!("Test definition {id} has not been synced"))
+            })?;
+            Ok(vec![launch_definition(state, &definition, origin).await?])
+        }
+        RunSource::Suite(id) => {
+            let suite = state.store.get_suite(id).await.ok_or_else(|| {
+                ApiError::not_found(format!("Test suite {id} has not been synced"))
+            })?;
+            launch_suite(state, &suite, origin).await
+        }
+    }
+}
+
+/// The status of a `TestRun` resource from the runs it started. Runs this server
+/// doesn't know (e.g. after a restart without a database to keep them in) leave
+/// the phase `unknown` rather than counting as failed.
+pub async fn run_status(state: &AppState, run_ids: &[Uuid]) -> TestRunStatus {
+    let mut status = TestRunStatus {
+        run_ids: run_ids.iter().map(Uuid::to_string).collect(),
+        ..TestRunStatus::default()
+    };
+    let mut pending = 0;
+    let mut running = 0;
+    let mut missing = 0;
+    for id in run_ids {
+        let Some(run) = state.store.get_run(*id).await else {
+            missing += 1;
+            continue;
+        };
+        match run.status.as_str() {
+            "succeeded" => status.passed += 1,
+            "failed" => status.failed += 1,
+            "running" => running += 1,
+            _ => pending += 1,
+        }
+        status.duration = status.duration.max(run.duration);
+    }
+    if missing > 0 {
+        status.message = Some(format!("{missing} run(s) are not known to this server"));
+    }
+
+    status.phase = Some(
+        if running > 0 {
+            "running"
+        } else if pending > 0 {
+            "pending"
+        } else if missing > 0 {
+            "unknown"
+        } else if status.failed > 0 {
+            "failed"
+        } else {
+            "succeeded"
+        }
+        .to_string(),
+    );
+    status
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::CustomResourceExt;
+
+    fn definition_spec() -> TestDefinitionSpec {
+        TestDefinitionSpec {
+            image: "node:20".to_string(),
+            commands: vec!["npm test".to_string()],
+            variables: BTreeMap::from([("CI".to_string(), "true".to_string())]),
+            priority: RunPriority::High,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_crds_have_status_subresources() {
+        for crd in [
+            TestDefinitionResource::crd(),
+            TestSuiteResource::crd(),
+            TestRunResource::crd(),
+        ] {
+            assert_eq!(crd.spec.group, CRD_GROUP);
+            let version = &crd.spec.versions[0];
+            assert!(version.subresources.as_ref().unwrap().status.is_some());
+        }
+        assert_eq!(
+            TestRunResource::crd().metadata.name.as_deref(),
+            Some("testruns.sparktest.dev")
+        );
+    }
+
+    #[test]
+    fn test_specs_use_kubernetes_field_names() {
+        let spec: TestDefinitionSpec = serde_json::from_value(serde_json::json!({
+            "displayName": "Checkout",
+            "image": "node:20",
+            "source": {"url": "https://github.com/acme/shop.git", "ref": "main"},
+            "priority": "low",
+        }))
+        .unwrap();
+        assert_eq!(spec.display_name.as_deref(), Some("Checkout"));
+        assert_eq!(spec.source.unwrap().git_ref.as_deref(), Some("main"));
+        assert_eq!(spec.priority, RunPriority::Low);
+    }
+
+    #[tokio::test]
+    async fn test_apply_definition_and_suite() {
+        let state = AppState::default();
+        let id = Uuid::new_v4();
+        let definition = apply_definition(&state, id, "checkout", &definition_spec())
+            .await
+            .unwrap();
+        assert_eq!(definition.id, id);
+        assert_eq!(definition.name, "checkout");
+        assert_eq!(
+            definition.variables,
+            Some(serde_json::json!({"CI": "true"}))
+        );
+        assert_eq!(definition.priority, RunPriority::High);
+
+        let mut spec = definition_spec();
+        spec.image = "node:22".to_string();
+        let updated = apply_definition(&state, id, "checkout", &spec)
+            .await
+            .unwrap();
+        assert_eq!(updated.image, "node:22");
+        assert_eq!(updated.created_at, definition.created_at);
+        assert_eq!(state.store.list_definitions().await.len(), 1);
+
+        let suite_spec = TestSuiteSpec {
+            definitions: vec!["checkout".to_string()],
+            execution_mode: ExecutionMode::Sequential,
+            ..Default::default()
+        };
+        let suite = apply_suite(&state, Uuid::new_v4(), "nightly", &suite_spec, vec![id])
+            .await
+            .unwrap();
+        assert_eq!(suite.execution_mode, "sequential");
+        assert_eq!(suite.test_definition_ids, vec![id]);
+
+        let unsynced = apply_suite(
+            &state,
+            Uuid::new_v4(),
+            "broken",
+            &suite_spec,
+            vec![Uuid::new_v4()],
+        )
+        .await;
+        assert!(unsynced
+            .unwrap_err()
+            .message
+            .contains("has not been synced"));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_definitions_are_rejected() {
+        let state = AppState::default();
+        let mut spec = definition_spec();
+        spec.target = Some("missing".to_string());
+        let error = apply_definition(&state, Uuid::new_v4(), "checkout", &spec)
+            .await
+            .unwrap_err();
+        assert!(error.message.contains("Unknown execution target"));
+        assert!(state.store.list_definitions().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_runs_are_started_once_per_resource() {
+        let state = AppState::default();
+        let id = Uuid::new_v4();
+        apply_definition(&state, id, "checkout", &definition_spec())
+            .await
+            .unwrap();
+
+        let runs = start_runs(&state, "uid-1", RunSource::Definition(id))
+            .await
+            .unwrap();
+        assert_eq!(runs[0].resource_uid.as_deref(), Some("uid-1"));
+
+        // Starting again, e.g. because the run ids never reached the status,
+        // finds the same runs
+        let again = start_runs(&state, "uid-1", RunSource::Definition(id))
+            .await
+            .unwrap();
+        assert_eq!(again[0].id, runs[0].id);
+        assert_eq!(state.store.list_runs().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_run_status_follows_the_runs() {
+        let state = AppState::default();
+        let mut ids = Vec::new();
+        for status in ["succeeded", "running"] {
+            let mut run = TestRun::new("Unit".to_string(), "node:20".to_string(), vec![]);
+            run.status = status.to_string();
+            run.duration = Some(12);
+            ids.push(run.id);
+            state.store.insert_run(run).await.unwrap();
+        }
+
+        let status = run_status(&state, &ids).await;
+        assert_eq!(status.phase.as_deref(), Some("running"));
+        assert_eq!((status.passed, status.failed), (1, 0));
+        assert!(!status.is_finished());
+
+        state
+            .store
+            .update_run(ids[1], |run| run.status = "succeeded".to_string())
+            .await;
+        ids.push(Uuid::new_v4());
+        let status = run_status(&state, &ids).await;
+        assert_eq!(status.phase.as_deref(), Some("unknown"));
+        assert_eq!((status.passed, status.failed), (2, 0));
+        assert_eq!(status.duration, Some(12));
+        assert!(!status.is_finished());
+        assert!(status.message.unwrap().contains("not known"));
+    }
+}
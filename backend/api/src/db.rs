@@ -0,0 +1,54 @@
+//! Only PostgreSQL databases are migrated; the state kept in them is written
+//! through by its owners (targets, tokens, ...), which keep working in memory
+//! when the server runs without one.
+
+use std::future::Future;
+use tracing::Instrument;
+
+/// Run a query in a `db.query` span recording its statement, so every query
+/// shows up in traces the same way. `query` gets the statement to build from:
+///
+/// ```ignore
+/// db::traced("DELETE FROM schedules WHERE id = $1", |sql| {
+///     sqlx::query(sql).bind(id).execute(db)
+/// })
+/// .await?;
+/// ```
+pub(crate) async fn traced<'q, F, Q>(statement: &'q str, query: F) -> Q::Output
+where
+    F: FnOnce(&'q str) -> Q,
+    Q: Future,
+{
+    query(statement)
+        .instrument(tracing::info_span!(
+            "db.query",
+            db.system = "postgresql",
+            db.statement = statement
+        ))
+        .await
+}
+
+/// Set to a PostgreSQL URL to run the tests that need a database, e.g.
+/// `postgres://postgres@localhost/sparktest_test`; they are skipped otherwise
+#[cfg(test)]
+const TEST_DATABASE_URL: &str = "SPARKTEST_TEST_DATABASE_URL";
+
+/// A migrated database for tests, or `None` when `SPARKTEST_TEST_DATABASE_URL`
+/// is unset. Tests share it, so they only touch rows they created.
+#[cfg(test)]
+pub(crate) async fn test_database() -> Option<sqlx::PgPool> {
+    let Ok(url) = std::env::var(TEST_DATABASE_URL) else {
+        println!("⚠️ {TEST_DATABASE_URL} is not set, skipping database test");
+        return None;
+    };
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&url)
+        .await
+        .expect("Failed to connect to the test database");
+    sqlx::migrate!("../migrations")
+        .run(&pool)
+        .await
+        .expect("Failed to migrate the test database");
+    Some(pool)
+}
@@ -0,0 +1,251 @@
+use crate::backend::JobNotFound;
+use axum::{
+    extract::multipart::{MultipartError, MultipartRejection},
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use kube::Error as KubeError;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Stable, machine-readable category of an API error
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorType {
+    NotFound,
+    Validation,
+    Unauthorized,
+    Forbidden,
+    Conflict,
+    /// The request is well formed but the admission policy does not allow it
+    PolicyViolation,
+    PayloadTooLarge,
+    Upstream,
+    Internal,
+}
+
+impl ErrorType {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ErrorType::NotFound => StatusCode::NOT_FOUND,
+            ErrorType::Validation => StatusCode::BAD_REQUEST,
+            ErrorType::Unauthorized => StatusCode::UNAUTHORIZED,
+            ErrorType::Forbidden => StatusCode::FORBIDDEN,
+            ErrorType::Conflict => StatusCode::CONFLICT,
+            ErrorType::PolicyViolation => StatusCode::UNPROCESSABLE_ENTITY,
+            ErrorType::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ErrorType::Upstream => StatusCode::BAD_GATEWAY,
+            ErrorType::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// The error every handler returns; rendered as
+/// `{"error_type": ..., "message": ..., "details": ...}` with a matching status code
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiError {
+    pub error_type: ErrorType,
+    pub message: String,
+    pub details: Option<serde_json::Value>,
+}
+
+impl ApiError {
+    pub fn new(error_type: ErrorType, message: impl Into<String>) -> Self {
+        Self {
+            error_type,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(ErrorType::NotFound, message)
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::new(ErrorType::Validation, message)
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(ErrorType::Unauthorized, message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(ErrorType::Forbidden, message)
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        Self::new(ErrorType::Conflict, message)
+    }
+
+    pub fn policy_violation(message: impl Into<String>) -> Self {
+        Self::new(ErrorType::PolicyViolation, message)
+    }
+
+    pub fn payload_too_large(message: impl Into<String>) -> Self {
+        Self::new(ErrorType::PayloadTooLarge, message)
+    }
+
+    pub fn upstream(message: impl Into<String>) -> Self {
+        Self::new(ErrorType::Upstream, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(ErrorType::Internal, message)
+    }
+
+    pub fn with_details(mut self, details: serde_json::Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.error_type.status()
+    }
+
+    /// Classify a failed execution backend call: missing jobs are `not_found`,
+    /// anything else is an upstream failure of the cluster or container runtime
+    pub fn from_backend(error: anyhow::Error) -> Self {
+        let not_found = error.chain().any(|cause| {
+            cause.is::<JobNotFound>()
+                || matches!(
+                    cause.downcast_ref::<KubeError>(),
+                    Some(KubeError::Api(response)) if response.code == 404
+                )
+        });
+        let error_type = if not_found {
+            ErrorType::NotFound
+        } else {
+            ErrorType::Upstream
+        };
+
+        let causes: Vec<String> = error.chain().skip(1).map(|c| c.to_string()).collect();
+        let api_error = Self::new(error_type, error.to_string());
+        if causes.is_empty() {
+            api_error
+        } else {
+            api_error.with_details(serde_json::json!({ "causes": causes }))
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status(), Json(self)).into_response()
+    }
+}
+
+impl From<KubeError> for ApiError {
+    fn from(error: KubeError) -> Self {
+        let error_type = match &error {
+            KubeError::Api(response) if response.code == 404 => ErrorType::NotFound,
+            KubeError::Api(response) if response.code == 409 => ErrorType::Conflict,
+            _ => ErrorType::Upstream,
+        };
+        Self::new(error_type, error.to_string())
+            .with_details(serde_json::json!(format!("{error:?}")))
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(error: sqlx::Error) -> Self {
+        Self::internal(format!("Database error: {error}"))
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::validation(rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        Self::validation(rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::validation(rejection.body_text())
+    }
+}
+
+impl From<MultipartRejection> for ApiError {
+    fn from(rejection: MultipartRejection) -> Self {
+        Self::validation(rejection.body_text())
+    }
+}
+
+impl From<MultipartError> for ApiError {
+    fn from(error: MultipartError) -> Self {
+        if error.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            Self::payload_too_large(error.body_text())
+        } else {
+            Self::validation(error.body_text())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_status_codes() {
+        assert_eq!(ApiError::not_found("x").status(), StatusCode::NOT_FOUND);
+        assert_eq!(ApiError::validation("x").status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            ApiError::unauthorized("x").status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(ApiError::forbidden("x").status(), StatusCode::FORBIDDEN);
+        assert_eq!(ApiError::conflict("x").status(), StatusCode::CONFLICT);
+        assert_eq!(ApiError::upstream("x").status(), StatusCode::BAD_GATEWAY);
+        assert_eq!(
+            ApiError::internal("x").status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+
+    #[test]
+    fn test_json_body() {
+        let error = ApiError::conflict("Target 'staging' already exists")
+            .with_details(serde_json::json!({ "name": "staging" }));
+        let body = serde_json::to_value(&error).unwrap();
+        assert_eq!(body["error_type"], "conflict");
+        assert_eq!(body["message"], "Target 'staging' already exists");
+        assert_eq!(body["details"]["name"], "staging");
+
+        let body = serde_json::to_value(ApiError::internal("boom")).unwrap();
+        assert!(body["details"].is_null());
+    }
+
+    #[test]
+    fn test_from_backend_error() {
+        let missing: anyhow::Result<()> =
+            Err(JobNotFound("test-job".to_string())).context("Failed to get job 'test-job'");
+        let error = ApiError::from_backend(missing.unwrap_err());
+        assert_eq!(error.error_type, ErrorType::NotFound);
+        assert_eq!(error.message, "Failed to get job 'test-job'");
+        assert_eq!(
+            error.details.unwrap()["causes"][0],
+            "Job 'test-job' not found"
+        );
+
+        let error = ApiError::from_backend(anyhow::anyhow!("Kubernetes client unavailable"));
+        assert_eq!(error.error_type, ErrorType::Upstream);
+        assert!(error.details.is_none());
+    }
+}
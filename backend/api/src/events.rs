@@ -0,0 +1,262 @@
+use crate::auth::Principal;
+use crate::rbac::{Action, Resource};
+use axum::response::sse;
+use chrono::{DateTime, Utc};
+use futures::Stream;
+use serde::{Deserialize, Serialize};
+use sparktest_core::TestRun;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+use uuid::Uuid;
+
+/// Events a subscriber may fall behind by before it starts missing some
+const EVENT_BUFFER: usize = 1024;
+
+/// What happened to a run, suite or job
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventKind {
+    RunCreated {
+        name: String,
+        status: String,
+    },
+    StatusChanged {
+        status: String,
+    },
+    /// `container_started`, `completed` or `failed` was recorded on the run
+    LifecycleTimestamp {
+        field: &'static str,
+        at: DateTime<Utc>,
+    },
+    /// One of the runs a suite started has finished
+    SuiteProgress {
+        finished: usize,
+        failed: usize,
+        total: usize,
+    },
+    JobDeleted {
+        job_name: String,
+    },
+    RunDeleted,
+}
+
+impl EventKind {
+    /// Name of the server-sent event
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventKind::RunCreated { .. } => "run_created",
+            EventKind::StatusChanged { .. } => "status_changed",
+            EventKind::LifecycleTimestamp { .. } => "lifecycle_timestamp",
+            EventKind::SuiteProgress { .. } => "suite_progress",
+            EventKind::JobDeleted { .. } => "job_deleted",
+            EventKind::RunDeleted => "run_deleted",
+        }
+    }
+}
+
+/// An event with what it is about, as sent to subscribers
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    /// Increases by one with every event this process publishes
+    pub id: u64,
+    pub at: DateTime<Utc>,
+    pub run_id: Option<Uuid>,
+    pub definition_id: Option<Uuid>,
+    pub suite_id: Option<Uuid>,
+    #[serde(skip)]
+    pub team_id: Option<Uuid>,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+impl Event {
+    /// Events about runs and suites are visible to whoever can view the run;
+    /// jobs that belong to no run only to admins
+    pub fn visible_to(&self, principal: &Principal) -> bool {
+        match self.run_id.or(self.suite_id) {
+            Some(id) => principal.can(
+                Action::View,
+                &Resource {
+                    kind: "test_run",
+                    id: id.to_string(),
+                    team_id: self.team_id,
+                },
+            ),
+            None => principal.can(Action::Administer, &Resource::instance("job", "orphan")),
+        }
+    }
+}
+
+/// Fans events out from the runner and handlers to every subscriber
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+    next_id: Arc<AtomicU64>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            sender: broadcast::channel(EVENT_BUFFER).0,
+            next_id: Arc::new(AtomicU64::new(1)),
+        }
+    }
+}
+
+impl EventBus {
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    pub fn publish_run(&self, run: &TestRun, kind: EventKind) {
+        self.publish(
+            Some(run.id),
+            run.definition_id,
+            run.suite_id,
+            run.team_id,
+            kind,
+        );
+    }
+
+    pub fn publish_created(&self, run: &TestRun) {
+        self.publish_run(
+            run,
+            EventKind::RunCreated {
+                name: run.name.clone(),
+                status: run.status.clone(),
+            },
+        );
+    }
+
+    pub fn publish_suite(&self, suite_id: Uuid, team_id: Option<Uuid>, kind: EventKind) {
+        self.publish(None, None, Some(suite_id), team_id, kind);
+    }
+
+    /// A deleted job, with the run it belonged to if there was one
+    pub fn publish_job_deleted(&self, job_name: &str, run: Option<&TestRun>) {
+        let kind = EventKind::JobDeleted {
+            job_name: job_name.to_string(),
+        };
+        match run {
+            Some(run) => self.publish_run(run, kind),
+            None => self.publish(None, None, None, None, kind),
+        }
+    }
+
+    fn publish(
+        &self,
+        run_id: Option<Uuid>,
+        definition_id: Option<Uuid>,
+        suite_id: Option<Uuid>,
+        team_id: Option<Uuid>,
+        kind: EventKind,
+    ) {
+        let event = Event {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            at: Utc::now(),
+            run_id,
+            definition_id,
+            suite_id,
+            team_id,
+            kind,
+        };
+        // Nobody listening is fine
+        self.sender.send(event).ok();
+    }
+}
+
+/// `?run_id=`, `?suite_id=` and `?definition_id=`; every one given must match
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EventFilter {
+    pub run_id: Option<Uuid>,
+    pub suite_id: Option<Uuid>,
+    pub definition_id: Option<Uuid>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &Event) -> bool {
+        let matches = |wanted: Option<Uuid>, actual: Option<Uuid>| {
+            wanted.is_none_or(|wanted| actual == Some(wanted))
+        };
+        matches(self.run_id, event.run_id)
+            && matches(self.suite_id, event.suite_id)
+            && matches(self.definition_id, event.definition_id)
+    }
+}
+
+/// The events a subscriber may see, as server-sent events. A subscriber that
+/// falls too far behind gets a `lagged` event saying how many it missed, and
+/// should reload what it shows.
+pub fn event_stream(
+    receiver: broadcast::Receiver<Event>,
+    principal: Principal,
+    filter: EventFilter,
+) -> impl Stream<Item = Result<sse::Event, Infallible>> {
+    futures::stream::unfold(
+        (receiver, principal, filter),
+        |(mut receiver, principal, filter)| async move {
+            loop {
+                let item = match receiver.recv().await {
+                    Ok(event) if filter.matches(&event) && event.visible_to(&principal) => {
+                        sse::Event::default()
+                            .id(event.id.to_string())
+                            .event(event.kind.name())
+                            .json_data(&event)
+                            .expect("events serialize to JSON")
+                    }
+                    Ok(_) => continue,
+                    Err(RecvError::Lagged(missed)) => sse::Event::default()
+                        .event("lagged")
+                        .data(serde_json::json!({ "missed": missed }).to_string()),
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((Ok(item), (receiver, principal, filter)));
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_and_visibility() {
+        let bus = EventBus::default();
+        let mut receiver = bus.subscribe();
+        let mut run = TestRun::new("Unit".to_string(), "node:20".to_string(), vec![]);
+        run.definition_id = Some(Uuid::new_v4());
+        run.team_id = Some(Uuid::new_v4());
+        bus.publish_run(
+            &run,
+            EventKind::StatusChanged {
+                status: "running".to_string(),
+            },
+        );
+        bus.publish_job_deleted("test-run-orphan", None);
+
+        let event = receiver.try_recv().unwrap();
+        assert!(EventFilter::default().matches(&event));
+        assert!(EventFilter {
+            definition_id: run.definition_id,
+            ..Default::default()
+        }
+        .matches(&event));
+        assert!(!EventFilter {
+            suite_id: Some(Uuid::new_v4()),
+            ..Default::default()
+        }
+        .matches(&event));
+        assert!(event.visible_to(&Principal::anonymous()));
+        assert!(!event.visible_to(&Principal::webhook()));
+
+        let orphan = receiver.try_recv().unwrap();
+        assert_eq!(orphan.id, event.id + 1);
+        assert!(!orphan.visible_to(&Principal::webhook()));
+        let json = serde_json::to_value(&orphan).unwrap();
+        assert_eq!(json["type"], "job_deleted");
+        assert_eq!(json["job_name"], "test-run-orphan");
+    }
+}
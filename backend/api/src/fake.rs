@@ -0,0 +1,351 @@
+use crate::backend::{ExecutionBackend, JobNotFound, JobSpec, SecretValue};
+use crate::k8s::JobLogs;
+use anyhow::Result;
+use async_trait::async_trait;
+use chrono::Utc;
+use sparktest_core::ExecutionTarget;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use tokio::time::{Duration, Instant};
+
+/// Scripted behaviour for a fake job: how long it runs, how it exits and what it prints
+#[derive(Debug, Clone)]
+pub struct FakeScript {
+    pub duration: Duration,
+    pub exit_code: i32,
+    pub logs: Vec<String>,
+}
+
+impl FakeScript {
+    pub fn succeed(logs: &[&str]) -> Self {
+        Self {
+            exit_code: 0,
+            logs: logs.iter().map(|line| line.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn fail(exit_code: i32, logs: &[&str]) -> Self {
+        Self {
+            exit_code,
+            logs: logs.iter().map(|line| line.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+}
+
+impl Default for FakeScript {
+    fn default() -> Self {
+        Self {
+            duration: Duration::from_millis(100),
+            exit_code: 0,
+            logs: vec!["fake job completed".to_string()],
+        }
+    }
+}
+
+struct FakeJob {
+    script: FakeScript,
+    started: Instant,
+    /// Name of the target the job was created on
+    target: String,
+}
+
+impl FakeJob {
+    fn finished(&self) -> bool {
+        self.started.elapsed() >= self.script.duration
+    }
+
+    fn status(&self) -> &'static str {
+        match (self.finished(), self.script.exit_code) {
+            (false, _) => "running",
+            (true, 0) => "completed",
+            (true, _) => "failed",
+        }
+    }
+
+    /// Log lines appear evenly over the scripted duration
+    fn visible_logs(&self) -> &[String] {
+        let lines = &self.script.logs;
+        if self.finished() {
+            return lines;
+        }
+        let progress = self.started.elapsed().as_secs_f64() / self.script.duration.as_secs_f64();
+        &lines[..((lines.len() as f64 * progress) as usize).min(lines.len())]
+    }
+}
+
+/// Deterministic in-process backend for tests and demos.
+///
+/// Scripts are queued per image: each new job takes the next script for its image,
+/// and the last one stays in place for every later job. Images without a script
+/// use the default script.
+pub struct FakeBackend {
+    scripts: Mutex<HashMap<String, VecDeque<FakeScript>>>,
+    default_script: FakeScript,
+    jobs: Mutex<HashMap<String, FakeJob>>,
+    created: Mutex<Vec<String>>,
+    deleted: Mutex<Vec<String>>,
+    secrets: Mutex<HashMap<(String, String), String>>,
+    secret_teams: Mutex<HashMap<String, String>>,
+    files: Mutex<HashMap<String, BTreeMap<String, Vec<u8>>>>,
+    healthy: AtomicBool,
+}
+
+impl Default for FakeBackend {
+    fn default() -> Self {
+        Self::new(FakeScript::default())
+    }
+}
+
+impl FakeBackend {
+    pub fn new(default_script: FakeScript) -> Self {
+        Self {
+            scripts: Mutex::new(HashMap::new()),
+            default_script,
+            jobs: Mutex::new(HashMap::new()),
+            created: Mutex::new(Vec::new()),
+            deleted: Mutex::new(Vec::new()),
+            secrets: Mutex::new(HashMap::new()),
+            secret_teams: Mutex::new(HashMap::new()),
+            files: Mutex::new(HashMap::new()),
+            healthy: AtomicBool::new(true),
+        }
+    }
+
+    /// Queue a script for the next job that uses `image`
+    pub fn script(&self, image: &str, script: FakeScript) {
+        self.scripts
+            .lock()
+            .unwrap()
+            .entry(image.to_string())
+            .or_default()
+            .push_back(script);
+    }
+
+    /// Store a value under one key of a secret
+    pub fn set_secret(&self, name: &str, key: &str, value: &str) {
+        self.secrets
+            .lock()
+            .unwrap()
+            .insert((name.to_string(), key.to_string()), value.to_string());
+    }
+
+    /// Label a secret for a team, like `sparktest.dev/team` on a Kubernetes Secret
+    pub fn set_secret_team(&self, name: &str, team: &str) {
+        self.secret_teams
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), team.to_string());
+    }
+
+    pub fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::SeqCst);
+    }
+
+    /// Names of every job created so far, in order
+    pub fn created_jobs(&self) -> Vec<String> {
+        self.created.lock().unwrap().clone()
+    }
+
+    /// Names of every job deleted so far, in order
+    pub fn deleted_jobs(&self) -> Vec<String> {
+        self.deleted.lock().unwrap().clone()
+    }
+
+    /// Files that were handed to a job when it was created
+    pub fn job_files(&self, job_name: &str) -> BTreeMap<String, Vec<u8>> {
+        self.files
+            .lock()
+            .unwrap()
+            .get(job_name)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    fn next_script(&self, image: &str) -> FakeScript {
+        let mut scripts = self.scripts.lock().unwrap();
+        match scripts.get_mut(image) {
+            Some(queue) if queue.len() > 1 => queue.pop_front().unwrap(),
+            Some(queue) if !queue.is_empty() => queue[0].clone(),
+            _ => self.default_script.clone(),
+        }
+    }
+
+    /// Jobs only exist on the target they were created on, like on a cluster
+    fn with_job<T>(
+        &self,
+        target: &ExecutionTarget,
+        job_name: &str,
+        f: impl FnOnce(&FakeJob) -> T,
+    ) -> Result<T> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(job_name)
+            .filter(|job| job.target == target.name)
+            .map(f)
+            .ok_or_else(|| JobNotFound(job_name.to_string()).into())
+    }
+}
+
+#[async_trait]
+impl ExecutionBackend for FakeBackend {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    async fn create_job(&self, target: &ExecutionTarget, spec: &JobSpec) -> Result<()> {
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.contains_key(&spec.name) {
+            return Err(anyhow::anyhow!("Job '{}' already exists", spec.name));
+        }
+
+        let job = FakeJob {
+            script: self.next_script(&spec.image),
+            started: Instant::now(),
+            target: target.name.clone(),
+        };
+        jobs.insert(spec.name.clone(), job);
+        self.created.lock().unwrap().push(spec.name.clone());
+        self.files
+            .lock()
+            .unwrap()
+            .insert(spec.name.clone(), spec.files.clone());
+        Ok(())
+    }
+
+    async fn get_job_status(&self, target: &ExecutionTarget, job_name: &str) -> Result<String> {
+        self.with_job(target, job_name, |job| job.status().to_string())
+    }
+
+    async fn get_job_logs(&self, target: &ExecutionTarget, job_name: &str) -> Result<JobLogs> {
+        self.with_job(target, job_name, |job| JobLogs {
+            job_name: job_name.to_string(),
+            pod_name: format!("{job_name}-pod"),
+            logs: job.visible_logs().join("\n"),
+            timestamp: Utc::now(),
+            status: job.status().to_string(),
+        })
+    }
+
+    async fn delete_job(&self, target: &ExecutionTarget, job_name: &str) -> Result<()> {
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            if jobs
+                .get(job_name)
+                .is_none_or(|job| job.target != target.name)
+            {
+                return Err(JobNotFound(job_name.to_string()).into());
+            }
+            jobs.remove(job_name);
+        }
+        self.deleted.lock().unwrap().push(job_name.to_string());
+        Ok(())
+    }
+
+    async fn health_check(&self, _target: &ExecutionTarget) -> Result<bool> {
+        Ok(self.healthy.load(Ordering::SeqCst))
+    }
+
+    async fn read_secret(
+        &self,
+        _target: &ExecutionTarget,
+        name: &str,
+        key: &str,
+    ) -> Result<Option<SecretValue>> {
+        let value = self
+            .secrets
+            .lock()
+            .unwrap()
+            .get(&(name.to_string(), key.to_string()))
+            .cloned();
+        Ok(value.map(|value| SecretValue {
+            value,
+            team: self.secret_teams.lock().unwrap().get(name).cloned(),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::targets::TargetRegistry;
+
+    async fn default_target() -> ExecutionTarget {
+        TargetRegistry::default().resolve(None).await.unwrap()
+    }
+
+    fn spec(name: &str, image: &str) -> JobSpec {
+        JobSpec {
+            name: name.to_string(),
+            image: image.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scripted_job_lifecycle() {
+        let backend = FakeBackend::default();
+        let target = default_target().await;
+        backend.script(
+            "k6",
+            FakeScript::fail(99, &["threshold exceeded"]).with_duration(Duration::from_millis(50)),
+        );
+
+        backend
+            .create_job(&target, &spec("job-1", "k6"))
+            .await
+            .unwrap();
+        assert_eq!(
+            backend.get_job_status(&target, "job-1").await.unwrap(),
+            "running"
+        );
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(
+            backend.get_job_status(&target, "job-1").await.unwrap(),
+            "failed"
+        );
+        let logs = backend.get_job_logs(&target, "job-1").await.unwrap();
+        assert_eq!(logs.logs, "threshold exceeded");
+
+        backend.delete_job(&target, "job-1").await.unwrap();
+        assert!(backend.get_job_status(&target, "job-1").await.is_err());
+        assert_eq!(backend.deleted_jobs(), vec!["job-1"]);
+    }
+
+    #[tokio::test]
+    async fn test_script_queue_per_image() {
+        let backend = FakeBackend::default();
+        backend.script("pytest", FakeScript::fail(1, &[]));
+        backend.script("pytest", FakeScript::succeed(&[]));
+
+        assert_eq!(backend.next_script("pytest").exit_code, 1);
+        assert_eq!(backend.next_script("pytest").exit_code, 0);
+        // The last script sticks for later jobs
+        assert_eq!(backend.next_script("pytest").exit_code, 0);
+        assert_eq!(backend.next_script("jest").logs, FakeScript::default().logs);
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_job_is_rejected() {
+        let backend = FakeBackend::default();
+        let target = default_target().await;
+        backend
+            .create_job(&target, &spec("job-1", "node"))
+            .await
+            .unwrap();
+        assert!(backend
+            .create_job(&target, &spec("job-1", "node"))
+            .await
+            .is_err());
+        assert_eq!(backend.created_jobs(), vec!["job-1"]);
+    }
+}
@@ -0,0 +1,269 @@
+use crate::error::ApiError;
+use crate::extract::Multipart;
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use sparktest_core::{Executor, TestFile};
+use std::collections::HashMap;
+
+/// Where uploaded files are mounted in the test container
+pub const FILES_DIR: &str = "/sparktest/files";
+
+/// Upload limits. Files end up in a ConfigMap, which Kubernetes caps at 1 MiB,
+/// so the total stays a little below that by default.
+#[derive(Debug, Clone)]
+pub struct FileLimits {
+    pub max_file_bytes: u64,
+    pub max_total_bytes: u64,
+    pub max_files: usize,
+}
+
+impl Default for FileLimits {
+    fn default() -> Self {
+        Self {
+            max_file_bytes: 512 * 1024,
+            max_total_bytes: 900 * 1024,
+            max_files: 32,
+        }
+    }
+}
+
+impl FileLimits {
+    /// Overrides from `SPARKTEST_MAX_FILE_BYTES`, `SPARKTEST_MAX_UPLOAD_BYTES`
+    /// and `SPARKTEST_MAX_FILES`
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        let var = |name: &str| std::env::var(name).ok().and_then(|v| v.parse().ok());
+        Self {
+            max_file_bytes: var("SPARKTEST_MAX_FILE_BYTES").unwrap_or(defaults.max_file_bytes),
+            max_total_bytes: var("SPARKTEST_MAX_UPLOAD_BYTES").unwrap_or(defaults.max_total_bytes),
+            max_files: var("SPARKTEST_MAX_FILES")
+                .map(|n: u64| n as usize)
+                .unwrap_or(defaults.max_files),
+        }
+    }
+}
+
+/// A file read from a multipart upload
+#[derive(Debug, Clone)]
+pub struct UploadedFile {
+    pub name: String,
+    pub content_type: Option<String>,
+    pub content: Vec<u8>,
+}
+
+impl UploadedFile {
+    pub fn metadata(&self) -> TestFile {
+        TestFile {
+            name: self.name.clone(),
+            size: self.content.len() as u64,
+            content_type: self.content_type.clone(),
+            sha256: hex::encode(Sha256::digest(&self.content)),
+            uploaded_at: Utc::now(),
+        }
+    }
+}
+
+/// The parts of a multipart upload: every part with a file name is a file,
+/// anything else is a plain form field
+#[derive(Debug, Default)]
+pub struct Upload {
+    pub fields: HashMap<String, String>,
+    pub files: Vec<UploadedFile>,
+}
+
+/// Read a multipart body, enforcing the per-file limit while streaming
+pub async fn read_upload(
+    Multipart(mut multipart): Multipart,
+    limits: &FileLimits,
+) -> Result<Upload, ApiError> {
+    let mut upload = Upload::default();
+
+    while let Some(mut field) = multipart.next_field().await? {
+        let Some(file_name) = field.file_name().map(str::to_string) else {
+            let name = field.name().unwrap_or_default().to_string();
+            upload.fields.insert(name, field.text().await?);
+            continue;
+        };
+        let content_type = field.content_type().map(str::to_string);
+
+        let mut content = Vec::new();
+        while let Some(chunk) = field.chunk().await? {
+            content.extend_from_slice(&chunk);
+            if content.len() as u64 > limits.max_file_bytes {
+                return Err(ApiError::payload_too_large(format!(
+                    "'{file_name}' is larger than {} bytes",
+                    limits.max_file_bytes
+                )));
+            }
+        }
+        upload.files.push(UploadedFile {
+            name: file_name,
+            content_type,
+            content,
+        });
+    }
+    Ok(upload)
+}
+
+/// Check new files against the names, types and totals allowed alongside the
+/// files already attached
+pub fn check_files(
+    existing: &[TestFile],
+    new: &[UploadedFile],
+    executor: Option<&Executor>,
+    limits: &FileLimits,
+) -> Result<(), ApiError> {
+    if new.is_empty() {
+        return Err(ApiError::validation("No files in the upload"));
+    }
+
+    let supported_types =
+        executor.and_then(|executor| Some((executor, executor.supported_file_types.as_ref()?)));
+    for file in new {
+        if !is_valid_file_name(&file.name) {
+            return Err(ApiError::validation(format!(
+                "'{}' is not a valid file name; use letters, digits, '-', '_' and '.'",
+                file.name
+            )));
+        }
+        if new.iter().filter(|other| other.name == file.name).count() > 1 {
+            return Err(ApiError::validation(format!(
+                "'{}' appears more than once in the upload",
+                file.name
+            )));
+        }
+        if let Some((executor, supported)) = supported_types {
+            let extension = file.name.rsplit_once('.').map(|(_, ext)| ext);
+            let allowed =
+                extension.is_some_and(|ext| supported.iter().any(|s| s.eq_ignore_ascii_case(ext)));
+            if !allowed {
+                return Err(ApiError::validation(format!(
+                    "'{}' is not a supported file type for executor '{}' (supported: {})",
+                    file.name,
+                    executor.id,
+                    supported.join(", ")
+                ))
+                .with_details(serde_json::json!({
+                    "file": file.name,
+                    "executor_id": executor.id,
+                    "supported_file_types": supported,
+                })));
+            }
+        }
+    }
+
+    // Uploading a file with an existing name replaces it
+    let kept = existing
+        .iter()
+        .filter(|file| !new.iter().any(|n| n.name == file.name));
+    let count = kept.clone().count() + new.len();
+    let total: u64 = kept.map(|file| file.size).sum::<u64>()
+        + new
+            .iter()
+            .map(|file| file.content.len() as u64)
+            .sum::<u64>();
+    if count > limits.max_files {
+        return Err(ApiError::payload_too_large(format!(
+            "At most {} files can be attached",
+            limits.max_files
+        )));
+    }
+    if total > limits.max_total_bytes {
+        return Err(ApiError::payload_too_large(format!(
+            "Attached files would total {total} bytes, more than the {} allowed",
+            limits.max_total_bytes
+        )));
+    }
+    Ok(())
+}
+
+/// Names double as ConfigMap keys and file names in the mount
+pub fn is_valid_file_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 253
+        && !name.starts_with("..")
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Replace files of the same name and keep the list sorted by name
+pub fn merge_files(existing: &mut Vec<TestFile>, new: &[UploadedFile]) {
+    existing.retain(|file| !new.iter().any(|n| n.name == file.name));
+    existing.extend(new.iter().map(UploadedFile::metadata));
+    existing.sort_by(|a, b| a.name.cmp(&b.name));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(name: &str, size: usize) -> UploadedFile {
+        UploadedFile {
+            name: name.to_string(),
+            content_type: None,
+            content: vec![b'x'; size],
+        }
+    }
+
+    fn k6_executor() -> Executor {
+        Executor {
+            id: "k6".to_string(),
+            name: "K6".to_string(),
+            image: "grafana/k6:0.52.0".to_string(),
+            description: None,
+            command: None,
+            supported_file_types: Some(vec!["js".to_string(), "ts".to_string()]),
+            env: None,
+            created_at: Utc::now(),
+            team_id: None,
+        }
+    }
+
+    #[test]
+    fn test_file_types_follow_executor() {
+        let limits = FileLimits::default();
+        let executor = k6_executor();
+        assert!(check_files(&[], &[file("load.js", 10)], Some(&executor), &limits).is_ok());
+        assert!(check_files(&[], &[file("load.JS", 10)], Some(&executor), &limits).is_ok());
+
+        let error = check_files(
+            &[],
+            &[file("collection.json", 10)],
+            Some(&executor),
+            &limits,
+        )
+        .unwrap_err();
+        assert!(error.message.contains("not a supported file type"));
+        assert!(check_files(&[], &[file("Makefile", 10)], Some(&executor), &limits).is_err());
+
+        // Without an executor only names and sizes are checked
+        assert!(check_files(&[], &[file("collection.json", 10)], None, &limits).is_ok());
+        assert!(check_files(&[], &[file("../etc/passwd", 10)], None, &limits).is_err());
+        assert!(check_files(&[], &[], None, &limits).is_err());
+    }
+
+    #[test]
+    fn test_total_size_counts_existing_files() {
+        let limits = FileLimits {
+            max_total_bytes: 100,
+            ..Default::default()
+        };
+        let existing = vec![file("a.js", 60).metadata()];
+
+        let error = check_files(&existing, &[file("b.js", 50)], None, &limits).unwrap_err();
+        assert_eq!(error.status(), axum::http::StatusCode::PAYLOAD_TOO_LARGE);
+        // Replacing a file only counts the new version
+        assert!(check_files(&existing, &[file("a.js", 90)], None, &limits).is_ok());
+    }
+
+    #[test]
+    fn test_merge_files_replaces_by_name() {
+        let mut files = vec![file("b.js", 1).metadata(), file("a.js", 1).metadata()];
+        merge_files(&mut files, &[file("a.js", 5)]);
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].name, "a.js");
+        assert_eq!(files[0].size, 5);
+        assert_eq!(files[0].sha256.len(), 64);
+    }
+}
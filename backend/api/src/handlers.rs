@@ -1,7 +1,46 @@
-use crate::k8s::KubernetesClient;
-use axum::{extract::Path, http::StatusCode, response::Json, Json as JsonBody};
+use crate::analytics::{run_analytics, AnalyticsGroup, AnalyticsQuery, RunAnalytics};
+use crate::audit::AuditQuery;
+use crate::auth::Principal;
+use crate::commit_status::{delete_saved_status_reporter, save_status_reporter, validate_reporter};
+use crate::error::ApiError;
+use crate::events::{event_stream, EventFilter, EventKind};
+use crate::extract::{JsonBody, Multipart, Path, Query};
+use crate::files::{check_files, merge_files, read_upload, UploadedFile};
+use crate::job_metadata::validate_job_metadata;
+use crate::launch::{launch_definition, launch_suite, RunOrigin};
+use crate::metrics::metrics;
+use crate::notifications::{
+    delete_saved_notification_channel, delete_saved_notification_rule, save_notification_channel,
+    save_notification_rule, validate_channel, validate_template,
+};
+use crate::policy::{save_admission_policy, AdmissionPolicy};
+use crate::queue::{queue_status, QueueStatus};
+use crate::rbac::{Action, Resource};
+use crate::run_query::{query_runs, RunListQuery, RunPage};
+use crate::runner::spawn_run;
+use crate::scheduler::{delete_saved_schedule, prepare_schedule, save_schedule};
+use crate::secrets::{resolve_secret_values, validate_secret_refs, Redactor};
+use crate::source::validate_source;
+use crate::state::AppState;
+use crate::targets::is_valid_target_name;
+use crate::webhooks::{
+    delete_saved_trigger_rule, dispatch_trigger, parse_github_event, parse_gitlab_event,
+    save_trigger_rule, verify_github_signature, verify_gitlab_token, WebhookResponse,
+};
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{header, HeaderMap, StatusCode},
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        IntoResponse, Json, Response,
+    },
+};
+use futures::Stream;
 use serde::{Deserialize, Serialize};
 use sparktest_core::*;
+use std::collections::{BTreeMap, HashSet};
+use std::convert::Infallible;
 use uuid::Uuid;
 
 #[derive(Serialize)]
@@ -10,11 +49,211 @@ pub struct HealthResponse {
     pub timestamp: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 pub struct CreateRunRequest {
     pub name: String,
     pub image: String,
     pub commands: Vec<String>,
+    #[serde(default)]
+    pub target: Option<String>,
+    #[serde(default)]
+    pub retries: Option<i32>,
+    #[serde(default)]
+    pub team_id: Option<Uuid>,
+    /// Only used to check the types of uploaded files
+    #[serde(default)]
+    pub executor_id: Option<String>,
+    #[serde(default)]
+    pub resources: Option<ResourceLimits>,
+    #[serde(default)]
+    pub privileged: bool,
+    #[serde(default)]
+    pub secrets: Vec<SecretRef>,
+    #[serde(default)]
+    pub source: Option<GitSource>,
+    #[serde(default)]
+    pub priority: RunPriority,
+}
+
+#[derive(Deserialize, Default)]
+pub struct CreateDefinitionRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub image: String,
+    pub commands: Vec<String>,
+    pub executor_id: Option<String>,
+    pub variables: Option<serde_json::Value>,
+    pub labels: Option<Vec<String>>,
+    pub target: Option<String>,
+    pub team_id: Option<Uuid>,
+    pub resources: Option<ResourceLimits>,
+    #[serde(default)]
+    pub privileged: bool,
+    #[serde(default)]
+    pub secrets: Vec<SecretRef>,
+    #[serde(default)]
+    pub source: Option<GitSource>,
+    #[serde(default)]
+    pub priority: RunPriority,
+    #[serde(default)]
+    pub job_labels: BTreeMap<String, String>,
+    #[serde(default)]
+    pub job_annotations: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct CreateSuiteRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    pub test_definition_ids: Vec<Uuid>,
+    #[serde(default = "default_execution_mode")]
+    pub execution_mode: String,
+    pub labels: Option<Vec<String>>,
+    pub team_id: Option<Uuid>,
+}
+
+fn default_execution_mode() -> String {
+    "sequential".to_string()
+}
+
+#[derive(Deserialize)]
+pub struct CreateTargetRequest {
+    pub name: String,
+    pub description: Option<String>,
+    pub connection: TargetConnection,
+    #[serde(default = "default_namespace")]
+    pub namespace: String,
+    #[serde(default = "default_timeout_seconds")]
+    pub timeout_seconds: u64,
+    #[serde(default = "default_max_log_lines")]
+    pub max_log_lines: Option<i64>,
+}
+
+fn default_namespace() -> String {
+    "default".to_string()
+}
+
+fn default_timeout_seconds() -> u64 {
+    300
+}
+
+fn default_max_log_lines() -> Option<i64> {
+    Some(1000)
+}
+
+#[derive(Deserialize)]
+pub struct CreateTokenRequest {
+    pub name: String,
+    pub user_id: Option<Uuid>,
+    pub scope: TokenScope,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A freshly created token; `secret` is never shown again
+#[derive(Serialize)]
+pub struct CreatedToken {
+    #[serde(flatten)]
+    pub token: ApiToken,
+    pub secret: String,
+}
+
+#[derive(Deserialize, Default)]
+pub struct CreateUserRequest {
+    pub name: String,
+    pub email: Option<String>,
+    #[serde(default)]
+    pub is_admin: bool,
+}
+
+#[derive(Deserialize, Default)]
+pub struct CreateTeamRequest {
+    pub name: String,
+    pub description: Option<String>,
+    #[serde(default)]
+    pub members: Vec<TeamMember>,
+}
+
+#[derive(Deserialize)]
+pub struct SetMemberRequest {
+    pub role: Role,
+}
+
+#[derive(Deserialize, Default)]
+pub struct CreateTriggerRuleRequest {
+    pub name: String,
+    pub repository: String,
+    #[serde(default)]
+    pub branches: Vec<String>,
+    #[serde(default)]
+    pub events: Vec<GitEventKind>,
+    pub test_definition_id: Option<Uuid>,
+    pub test_suite_id: Option<Uuid>,
+    pub team_id: Option<Uuid>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct CreateStatusReporterRequest {
+    pub name: String,
+    pub provider: String,
+    pub repository: String,
+    pub api_url: Option<String>,
+    #[serde(default = "default_status_context")]
+    pub context: String,
+    pub token: String,
+    pub team_id: Option<Uuid>,
+}
+
+fn default_status_context() -> String {
+    "sparktest".to_string()
+}
+
+#[derive(Deserialize)]
+pub struct CreateNotificationChannelRequest {
+    pub name: String,
+    pub config: ChannelConfig,
+    pub team_id: Option<Uuid>,
+}
+
+#[derive(Deserialize, Default)]
+pub struct CreateNotificationRuleRequest {
+    pub name: String,
+    pub channel_id: Uuid,
+    pub test_definition_id: Option<Uuid>,
+    pub test_suite_id: Option<Uuid>,
+    #[serde(default)]
+    pub on: NotifyOn,
+    pub template: Option<String>,
+    pub team_id: Option<Uuid>,
+}
+
+/// Body of both creating and replacing a schedule
+#[derive(Deserialize, Default)]
+pub struct ScheduleRequest {
+    pub name: String,
+    pub cron: String,
+    #[serde(default = "default_timezone")]
+    pub timezone: String,
+    pub test_definition_id: Option<Uuid>,
+    pub test_suite_id: Option<Uuid>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    pub team_id: Option<Uuid>,
+}
+
+fn default_timezone() -> String {
+    "UTC".to_string()
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Optional `?target=` selector for the job endpoints
+#[derive(Deserialize, Default)]
+pub struct TargetQuery {
+    pub target: Option<String>,
 }
 
 pub async fn health_check() -> Json<HealthResponse> {
@@ -24,257 +263,3695 @@ pub async fn health_check() -> Json<HealthResponse> {
     })
 }
 
-pub async fn get_runs() -> Result<Json<Vec<TestRun>>, StatusCode> {
-    // In a real implementation, this would fetch from database
-    Ok(Json(vec![]))
+/// Fallback for unknown routes so every response carries the same error body
+pub async fn route_not_found(uri: axum::http::Uri) -> ApiError {
+    ApiError::not_found(format!("No route for {}", uri.path()))
+}
+
+/// Server-sent events about runs the caller can see, optionally narrowed to
+/// one run, suite or definition
+pub async fn get_events(
+    State(state): State<AppState>,
+    principal: Principal,
+    Query(filter): Query<EventFilter>,
+) -> Sse<impl Stream<Item = Result<SseEvent, Infallible>>> {
+    Sse::new(event_stream(state.events.subscribe(), principal, filter))
+        .keep_alive(KeepAlive::default())
+}
+
+/// Runs the principal may view, a page at a time; see [`RunListQuery`]
+pub async fn get_runs(
+    State(state): State<AppState>,
+    principal: Principal,
+    Query(query): Query<RunListQuery>,
+) -> Result<RunPage, ApiError> {
+    query_runs(&state, &principal, &query).await
+}
+
+/// Pass rate, durations and failure reasons of the runs the principal may view
+pub async fn get_run_analytics(
+    State(state): State<AppState>,
+    principal: Principal,
+    Query(query): Query<AnalyticsQuery>,
+) -> Result<Json<RunAnalytics>, ApiError> {
+    Ok(Json(run_analytics(&state, &principal, &query).await?))
+}
+
+/// Concurrency limits and the runs waiting for a free slot
+pub async fn get_queue(State(state): State<AppState>, principal: Principal) -> Json<QueueStatus> {
+    Json(queue_status(&state, &principal).await)
+}
+
+/// Run analytics of a single definition
+pub async fn get_definition_analytics(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+    Query(query): Query<AnalyticsQuery>,
+) -> Result<Json<RunAnalytics>, ApiError> {
+    let definition = state
+        .store
+        .get_definition(id)
+        .await
+        .ok_or_else(|| definition_not_found(id))?;
+    principal.authorize(Action::View, &(&definition).into())?;
+
+    let query = AnalyticsQuery {
+        group_by: AnalyticsGroup::Definition,
+        definition_id: Some(id),
+        ..query
+    };
+    Ok(Json(run_analytics(&state, &principal, &query).await?))
 }
 
 pub async fn create_run(
+    State(state): State<AppState>,
+    principal: Principal,
     JsonBody(req): JsonBody<CreateRunRequest>,
-) -> Result<Json<TestRun>, StatusCode> {
-    // In a real implementation, this would create a run in the database
-    let run = TestRun {
-        id: Uuid::new_v4(),
-        name: req.name,
-        image: req.image,
-        commands: req.commands,
-        status: "pending".to_string(),
-        created_at: chrono::Utc::now(),
-        definition_id: None,
-        executor_id: None,
-        suite_id: None,
-        variables: None,
-        artifacts: None,
-        duration: None,
-        retries: None,
-        logs: None,
-        k8s_job_name: None,
-        pod_scheduled: None,
-        container_created: None,
-        container_started: None,
-        completed: None,
-        failed: None,
-    };
+) -> Result<Json<TestRun>, ApiError> {
+    let run = prepare_run(&state, &principal, req).await?;
+
+    state.store.insert_run(run.clone()).await?;
+    state.events.publish_created(&run);
+    metrics().run_created(&run);
+    spawn_run(state, run.id);
 
     Ok(Json(run))
 }
 
-pub async fn get_run(Path(_id): Path<Uuid>) -> Result<Json<TestRun>, StatusCode> {
-    // In a real implementation, this would fetch from database
-    Err(StatusCode::NOT_FOUND)
+/// Start an ad-hoc run with files: a multipart body whose `run` field holds
+/// the same JSON as `POST /api/test-runs`, and whose file parts are mounted
+/// into the test container
+pub async fn create_run_with_files(
+    State(state): State<AppState>,
+    principal: Principal,
+    multipart: Multipart,
+) -> Result<(StatusCode, Json<TestRun>), ApiError> {
+    let upload = read_upload(multipart, &state.files).await?;
+    let req = upload
+        .fields
+        .get("run")
+        .ok_or_else(|| ApiError::validation("The upload has no 'run' field"))?;
+    let req: CreateRunRequest = serde_json::from_str(req)
+        .map_err(|e| ApiError::validation(format!("Invalid 'run' field: {e}")))?;
+
+    let mut run = prepare_run(&state, &principal, req).await?;
+    let executor = match &run.executor_id {
+        Some(id) => Some(executor_for_files(&state, id).await?),
+        None => None,
+    };
+    check_files(&[], &upload.files, executor.as_ref(), &state.files)?;
+    merge_files(&mut run.files, &upload.files);
+    store_files(&state, run.id, upload.files).await?;
+
+    state.store.insert_run(run.clone()).await?;
+    state.events.publish_created(&run);
+    metrics().run_created(&run);
+    spawn_run(state, run.id);
+
+    Ok((StatusCode::CREATED, Json(run)))
+}
+
+/// Validate an ad-hoc run request and build the run, without storing it
+async fn prepare_run(
+    state: &AppState,
+    principal: &Principal,
+    req: CreateRunRequest,
+) -> Result<TestRun, ApiError> {
+    ensure_team(state, req.team_id).await?;
+    principal.authorize(Action::Run, &Resource::new_in("test_run", req.team_id))?;
+    let target = resolve_target(state, req.target.as_deref()).await?;
+
+    if req.retries.is_some_and(|retries| retries < 0) {
+        return Err(ApiError::validation("retries must not be negative"));
+    }
+    validate_secret_refs(&req.secrets, None)?;
+    if let Some(source) = &req.source {
+        validate_source(source)?;
+    }
+
+    let mut run = TestRun::new(req.name, req.image, req.commands);
+    run.triggered_by = Some(principal.name.clone());
+    run.target = Some(target.name);
+    run.retries = req.retries;
+    run.team_id = req.team_id;
+    run.resources = req.resources;
+    run.privileged = req.privileged;
+    run.secrets = req.secrets;
+    run.source = req.source;
+    run.executor_id = req.executor_id;
+    run.priority = req.priority;
+    state
+        .policy
+        .read()
+        .await
+        .admit_run(&run, &state.runner.git_image)?;
+
+    Ok(run)
+}
+
+pub async fn get_run(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<Json<TestRun>, ApiError> {
+    let mut run = state
+        .store
+        .get_run(id)
+        .await
+        .ok_or_else(|| run_not_found(id))?;
+    principal.authorize(Action::View, &(&run).into())?;
+    state.queue.annotate(&mut run);
+    Ok(Json(run))
 }
 
-pub async fn delete_run(Path(_id): Path<Uuid>) -> Result<StatusCode, StatusCode> {
-    // In a real implementation, this would delete from database
+pub async fn delete_run(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    let run = state
+        .store
+        .remove_run_if(id, |run| principal.authorize(Action::Run, &run.into()))
+        .await?
+        .ok_or_else(|| run_not_found(id))?;
+
+    state.queue.cancel(id);
+    // Stop the workload too if the run hadn't finished yet
+    if let (Some(job_name), "running") = (&run.k8s_job_name, run.status.as_str()) {
+        if let Some(target) = state.targets.resolve(run.target.as_deref()).await {
+            if state.backend.delete_job(&target, job_name).await.is_ok() {
+                state.events.publish_job_deleted(job_name, Some(&run));
+            }
+        }
+    }
+    state.events.publish_run(&run, EventKind::RunDeleted);
+
     Ok(StatusCode::NO_CONTENT)
 }
 
-pub async fn k8s_health() -> Json<serde_json::Value> {
-    // Attempt to create Kubernetes client and check health
-    match KubernetesClient::new().await {
-        Ok(client) => match client.health_check().await {
-            Ok(is_healthy) => Json(serde_json::json!({
-                "kubernetes_connected": is_healthy,
-                "timestamp": chrono::Utc::now().to_rfc3339()
-            })),
-            Err(_) => Json(serde_json::json!({
+/// Logs for a run: live from the backend while it runs, stored once it has finished
+pub async fn get_run_logs(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let run = state
+        .store
+        .get_run(id)
+        .await
+        .ok_or_else(|| run_not_found(id))?;
+    principal.authorize(Action::View, &(&run).into())?;
+
+    if let (Some(job_name), "running") = (&run.k8s_job_name, run.status.as_str()) {
+        if let Some(target) = state.targets.resolve(run.target.as_deref()).await {
+            // Live logs are only shown if the run's secrets can be redacted from them
+            let redactor =
+                resolve_secret_values(state.backend.as_ref(), &target, &run.secrets).await;
+            let job_logs = state.backend.get_job_logs(&target, job_name).await;
+            if let (Ok(values), Ok(job_logs)) = (redactor, job_logs) {
+                let logs = job_logs.logs.lines().map(str::to_string).collect();
+                return Ok(Json(serde_json::json!({
+                    "run_id": run.id,
+                    "status": run.status,
+                    "source": "live",
+                    "logs": Redactor::new(values).redact_lines(logs),
+                    "timestamp": chrono::Utc::now().to_rfc3339()
+                })));
+            }
+        }
+    }
+
+    Ok(Json(serde_json::json!({
+        "run_id": run.id,
+        "status": run.status,
+        "source": "stored",
+        "logs": run.logs.unwrap_or_default(),
+        "timestamp": chrono::Utc::now().to_rfc3339()
+    })))
+}
+
+pub async fn k8s_health(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let targets = state.targets.list().await;
+
+    // Check every target concurrently so one unreachable cluster doesn't stall the rest
+    let backend = &state.backend;
+    let checks = targets.iter().map(|target| async move {
+        match backend.health_check(target).await {
+            Ok(is_healthy) => serde_json::json!({
+                "name": target.name,
+                "namespace": target.namespace,
+                "kubernetes_connected": is_healthy
+            }),
+            Err(e) => serde_json::json!({
+                "name": target.name,
+                "namespace": target.namespace,
                 "kubernetes_connected": false,
-                "timestamp": chrono::Utc::now().to_rfc3339(),
-                "error": "Kubernetes health check failed"
-            })),
-        },
-        Err(_) => Json(serde_json::json!({
-            "kubernetes_connected": false,
-            "timestamp": chrono::Utc::now().to_rfc3339(),
-            "error": "Could not create Kubernetes client"
-        })),
-    }
-}
-
-pub async fn get_job_logs(Path(job_name): Path<String>) -> Json<serde_json::Value> {
-    // Attempt to get real job logs from Kubernetes
-    match KubernetesClient::new().await {
-        Ok(client) => match client.get_job_logs(&job_name).await {
-            Ok(job_logs) => Json(serde_json::json!({
-                "job_name": job_logs.job_name,
-                "pod_name": job_logs.pod_name,
-                "logs": job_logs.logs,
-                "timestamp": job_logs.timestamp.to_rfc3339(),
-                "status": job_logs.status
-            })),
-            Err(e) => Json(serde_json::json!({
-                "job_name": job_name,
-                "error": format!("Failed to get job logs: {}", e),
-                "timestamp": chrono::Utc::now().to_rfc3339(),
-                "status": "error"
-            })),
-        },
-        Err(_) => Json(serde_json::json!({
-            "job_name": job_name,
-            "error": "Kubernetes client unavailable",
-            "timestamp": chrono::Utc::now().to_rfc3339(),
-            "status": "error"
-        })),
-    }
-}
-
-pub async fn get_job_status(Path(job_name): Path<String>) -> Json<serde_json::Value> {
-    // Attempt to get real job status from Kubernetes
-    match KubernetesClient::new().await {
-        Ok(client) => match client.get_job_status(&job_name).await {
-            Ok(status) => Json(serde_json::json!({
-                "job_name": job_name,
-                "status": status,
-                "timestamp": chrono::Utc::now().to_rfc3339()
-            })),
-            Err(e) => Json(serde_json::json!({
-                "job_name": job_name,
-                "status": "error",
-                "error": format!("Failed to get job status: {}", e),
-                "timestamp": chrono::Utc::now().to_rfc3339()
-            })),
-        },
-        Err(_) => Json(serde_json::json!({
-            "job_name": job_name,
-            "status": "error",
-            "error": "Kubernetes client unavailable",
-            "timestamp": chrono::Utc::now().to_rfc3339()
-        })),
-    }
-}
-
-pub async fn delete_job(Path(job_name): Path<String>) -> Json<serde_json::Value> {
-    // Attempt to delete real job from Kubernetes
-    match KubernetesClient::new().await {
-        Ok(client) => match client.delete_job(&job_name).await {
-            Ok(_) => Json(serde_json::json!({
-                "message": format!("Job {} deleted successfully", job_name),
-                "timestamp": chrono::Utc::now().to_rfc3339()
-            })),
-            Err(e) => Json(serde_json::json!({
-                "error": format!("Failed to delete job {}: {}", job_name, e),
-                "timestamp": chrono::Utc::now().to_rfc3339()
-            })),
-        },
-        Err(_) => Json(serde_json::json!({
-            "error": format!("Kubernetes client unavailable - cannot delete job {}", job_name),
-            "timestamp": chrono::Utc::now().to_rfc3339()
-        })),
-    }
-}
-
-pub async fn get_definitions() -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
-    // For this demo, return empty list since we're focusing on the K8s integration
-    Ok(Json(vec![]))
-}
-
-pub async fn get_executors() -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
-    // For this demo, return empty list since we're focusing on the K8s integration
-    Ok(Json(vec![]))
-}
-
-pub async fn get_suites() -> Result<Json<Vec<serde_json::Value>>, StatusCode> {
-    // For this demo, return empty list since we're focusing on the K8s integration
-    Ok(Json(vec![]))
+                "error": format!("Health check failed: {}", e)
+            }),
+        }
+    });
+    let results = futures::future::join_all(checks).await;
+
+    let unavailable = results
+        .iter()
+        .filter(|r| r["kubernetes_connected"] != true)
+        .count();
+
+    let mut body = serde_json::json!({
+        "backend": backend.name(),
+        "kubernetes_connected": unavailable == 0,
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "targets": results
+    });
+    if unavailable > 0 {
+        body["error"] = serde_json::json!(format!(
+            "{} of {} execution targets unavailable",
+            unavailable,
+            targets.len()
+        ));
+    }
+
+    Json(body)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use axum::Json as JsonBody;
+/// Resolve a target selection, falling back to the default target
+async fn resolve_target(state: &AppState, name: Option<&str>) -> Result<ExecutionTarget, ApiError> {
+    state.targets.resolve(name).await.ok_or_else(|| {
+        ApiError::validation(format!(
+            "Unknown execution target '{}'",
+            name.unwrap_or_default()
+        ))
+    })
+}
 
-    #[tokio::test]
-    async fn test_health_check() {
-        let response = health_check().await;
-        assert_eq!(response.0.status, "healthy");
-        assert!(!response.0.timestamp.is_empty());
+fn run_not_found(id: Uuid) -> ApiError {
+    ApiError::not_found(format!("Test run {id} not found"))
+}
+
+/// Reject owners that don't exist, so resources can't be created for phantom teams
+async fn ensure_team(state: &AppState, team_id: Option<Uuid>) -> Result<(), ApiError> {
+    match team_id {
+        Some(id) if state.store.get_team(id).await.is_none() => {
+            Err(ApiError::validation(format!("Team {id} does not exist")))
+        }
+        _ => Ok(()),
     }
+}
 
-    #[tokio::test]
-    async fn test_get_runs() {
-        let result = get_runs().await;
-        assert!(result.is_ok());
-        let runs = result.unwrap().0;
-        assert_eq!(runs.len(), 0);
+/// Jobs belong to the team of the run that launched them; any other job is
+/// only accessible to admins. Returns the run, if any.
+async fn authorize_job(
+    state: &AppState,
+    principal: &Principal,
+    action: Action,
+    job_name: &str,
+) -> Result<Option<TestRun>, ApiError> {
+    let run = state.store.find_run_by_job(job_name).await;
+    match &run {
+        Some(run) => principal.authorize(action, &run.into())?,
+        None => principal.authorize(Action::Administer, &Resource::instance("job", job_name))?,
     }
+    Ok(run)
+}
 
-    #[tokio::test]
-    async fn test_create_run() {
-        let request = CreateRunRequest {
-            name: "Test Run".to_string(),
-            image: "test:latest".to_string(),
-            commands: vec!["echo".to_string(), "hello".to_string()],
-        };
+/// The target a job lives on: the requested one, else its run's, else the default
+async fn job_target(
+    state: &AppState,
+    query: &TargetQuery,
+    run: Option<&TestRun>,
+) -> Result<ExecutionTarget, ApiError> {
+    let name = query
+        .target
+        .as_deref()
+        .or_else(|| run.and_then(|run| run.target.as_deref()));
+    resolve_target(state, name).await
+}
 
-        let result = create_run(JsonBody(request)).await;
-        assert!(result.is_ok());
+pub async fn get_job_logs(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(job_name): Path<String>,
+    Query(query): Query<TargetQuery>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let run = authorize_job(&state, &principal, Action::View, &job_name).await?;
+    let target = job_target(&state, &query, run.as_ref()).await?;
+    let job_logs = state
+        .backend
+        .get_job_logs(&target, &job_name)
+        .await
+        .map_err(ApiError::from_backend)?;
 
-        let run = result.unwrap().0;
-        assert_eq!(run.name, "Test Run");
-        assert_eq!(run.image, "test:latest");
-        assert_eq!(run.status, "pending");
-        assert_eq!(run.commands.len(), 2);
+    let secrets = run.map(|run| run.secrets).unwrap_or_default();
+    let values = resolve_secret_values(state.backend.as_ref(), &target, &secrets)
+        .await
+        .map_err(ApiError::from_backend)?;
+
+    Ok(Json(serde_json::json!({
+        "job_name": job_logs.job_name,
+        "pod_name": job_logs.pod_name,
+        "logs": Redactor::new(values).redact(&job_logs.logs),
+        "timestamp": job_logs.timestamp.to_rfc3339(),
+        "status": job_logs.status
+    })))
+}
+
+pub async fn get_job_status(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(job_name): Path<String>,
+    Query(query): Query<TargetQuery>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let run = authorize_job(&state, &principal, Action::View, &job_name).await?;
+    let target = job_target(&state, &query, run.as_ref()).await?;
+    let status = state
+        .backend
+        .get_job_status(&target, &job_name)
+        .await
+        .map_err(ApiError::from_backend)?;
+
+    Ok(Json(serde_json::json!({
+        "job_name": job_name,
+        "status": status,
+        "timestamp": chrono::Utc::now().to_rfc3339()
+    })))
+}
+
+pub async fn delete_job(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(job_name): Path<String>,
+    Query(query): Query<TargetQuery>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let run = authorize_job(&state, &principal, Action::Run, &job_name).await?;
+    let target = job_target(&state, &query, run.as_ref()).await?;
+    state
+        .backend
+        .delete_job(&target, &job_name)
+        .await
+        .map_err(ApiError::from_backend)?;
+    state.events.publish_job_deleted(&job_name, run.as_ref());
+
+    Ok(Json(serde_json::json!({
+        "message": format!("Job {} deleted successfully", job_name),
+        "timestamp": chrono::Utc::now().to_rfc3339()
+    })))
+}
+
+pub async fn get_targets(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> Result<Json<Vec<ExecutionTarget>>, ApiError> {
+    principal.authorize_member(&Resource::instance("execution_target", "*"))?;
+    Ok(Json(state.targets.list().await))
+}
+
+pub async fn get_target(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(name): Path<String>,
+) -> Result<Json<ExecutionTarget>, ApiError> {
+    principal.authorize_member(&Resource::instance("execution_target", &name))?;
+    state
+        .targets
+        .get(&name)
+        .await
+        .map(Json)
+        .ok_or_else(|| target_not_found(&name))
+}
+
+fn target_not_found(name: &str) -> ApiError {
+    ApiError::not_found(format!("Execution target '{name}' not found"))
+}
+
+pub async fn create_target(
+    State(state): State<AppState>,
+    principal: Principal,
+    JsonBody(req): JsonBody<CreateTargetRequest>,
+) -> Result<(StatusCode, Json<ExecutionTarget>), ApiError> {
+    principal.authorize(
+        Action::Administer,
+        &Resource::instance("execution_target", &req.name),
+    )?;
+    if !is_valid_target_name(&req.name) {
+        return Err(ApiError::validation(
+            "Target names must be lowercase alphanumerics and '-', at most 63 characters",
+        )
+        .with_details(serde_json::json!({ "name": req.name })));
+    }
+    if req.namespace.is_empty() {
+        return Err(ApiError::validation("namespace must not be empty"));
     }
 
-    #[tokio::test]
-    async fn test_get_run() {
-        let id = Uuid::new_v4();
-        let result = get_run(Path(id)).await;
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err(), StatusCode::NOT_FOUND);
+    let target = ExecutionTarget {
+        name: req.name,
+        description: req.description,
+        connection: req.connection,
+        namespace: req.namespace,
+        timeout_seconds: req.timeout_seconds,
+        max_log_lines: req.max_log_lines,
+        created_at: chrono::Utc::now(),
+    };
+
+    if !state.targets.register(target.clone()).await? {
+        return Err(ApiError::conflict(format!(
+            "Execution target '{}' already exists",
+            target.name
+        )));
     }
+    state.backend.forget_target(&target.name).await;
 
-    #[tokio::test]
-    async fn test_delete_run() {
-        let id = Uuid::new_v4();
-        let result = delete_run(Path(id)).await;
-        assert!(result.is_ok());
-        assert_eq!(result.unwrap(), StatusCode::NO_CONTENT);
+    Ok((StatusCode::CREATED, Json(target)))
+}
+
+pub async fn delete_target(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    principal.authorize(
+        Action::Administer,
+        &Resource::instance("execution_target", &name),
+    )?;
+    // The default target backs every run that doesn't pick one explicitly
+    if name == crate::targets::DEFAULT_TARGET {
+        return Err(ApiError::conflict(
+            "The default execution target cannot be deleted",
+        ));
     }
 
-    #[tokio::test]
-    async fn test_k8s_health() {
-        let response = k8s_health().await;
-        let value = response.0;
-        // In test environment, Kubernetes is typically not available
-        assert_eq!(value["kubernetes_connected"], false);
-        assert!(value["timestamp"].is_string());
-        assert!(value["error"].is_string());
+    match state.targets.remove(&name).await? {
+        Some(_) => {
+            state.backend.forget_target(&name).await;
+            Ok(StatusCode::NO_CONTENT)
+        }
+        None => Err(target_not_found(&name)),
     }
+}
 
-    #[tokio::test]
-    async fn test_get_job_logs() {
-        let job_name = "test-job".to_string();
-        let response = get_job_logs(Path(job_name.clone())).await;
-        let value = response.0;
-        assert_eq!(value["job_name"], job_name);
-        // In test environment, Kubernetes is not available, so expect error
-        assert_eq!(value["status"], "error");
-        assert!(value["error"].is_string());
-        assert!(value["timestamp"].is_string());
+pub async fn get_tokens(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> Result<Json<Vec<ApiToken>>, ApiError> {
+    principal.authorize(Action::Administer, &Resource::instance("api_token", "*"))?;
+    Ok(Json(state.tokens.list().await))
+}
+
+pub async fn create_token(
+    State(state): State<AppState>,
+    principal: Principal,
+    JsonBody(req): JsonBody<CreateTokenRequest>,
+) -> Result<(StatusCode, Json<CreatedToken>), ApiError> {
+    principal.authorize(Action::Administer, &Resource::instance("api_token", "new"))?;
+    if req.name.trim().is_empty() {
+        return Err(ApiError::validation("Token name must not be empty"));
+    }
+    if req.expires_at.is_some_and(|at| at <= chrono::Utc::now()) {
+        return Err(ApiError::validation("expires_at must be in the future"));
+    }
+    if let Some(user_id) = req.user_id {
+        if state.store.get_user(user_id).await.is_none() {
+            return Err(ApiError::validation(format!(
+                "User {user_id} does not exist"
+            )));
+        }
     }
 
-    #[tokio::test]
-    async fn test_get_job_status() {
-        let job_name = "test-job".to_string();
-        let response = get_job_status(Path(job_name.clone())).await;
-        let value = response.0;
-        assert_eq!(value["job_name"], job_name);
-        // In test environment, Kubernetes is not available, so expect error
-        assert_eq!(value["status"], "error");
-        assert!(value["error"].is_string());
-        assert!(value["timestamp"].is_string());
+    let (token, secret) = state
+        .tokens
+        .create(req.name, req.user_id, req.scope, req.expires_at)
+        .await?;
+    Ok((StatusCode::CREATED, Json(CreatedToken { token, secret })))
+}
+
+pub async fn revoke_token(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    principal.authorize(Action::Administer, &Resource::instance("api_token", id))?;
+    match state.tokens.revoke(id).await? {
+        Some(_) => Ok(StatusCode::NO_CONTENT),
+        None => Err(ApiError::not_found(format!("Token {id} not found"))),
     }
+}
 
-    #[tokio::test]
-    async fn test_delete_job() {
-        let job_name = "test-job".to_string();
-        let response = delete_job(Path(job_name.clone())).await;
-        let value = response.0;
-        // In test environment, Kubernetes is not available, so expect error
-        assert!(value["error"].is_string());
-        assert!(value["timestamp"].is_string());
-        let error_msg = value["error"].as_str().unwrap();
-        assert!(error_msg.contains("Kubernetes client unavailable"));
+pub async fn get_definitions(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> Result<Json<Vec<TestDefinition>>, ApiError> {
+    let mut definitions = state.store.list_definitions().await;
+    definitions.retain(|definition| principal.can(Action::View, &definition.into()));
+    Ok(Json(definitions))
+}
+
+pub async fn get_definition(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<Json<TestDefinition>, ApiError> {
+    let definition = state
+        .store
+        .get_definition(id)
+        .await
+        .ok_or_else(|| definition_not_found(id))?;
+    principal.authorize(Action::View, &(&definition).into())?;
+    Ok(Json(definition))
+}
+
+fn definition_not_found(id: Uuid) -> ApiError {
+    ApiError::not_found(format!("Test definition {id} not found"))
+}
+
+pub async fn create_definition(
+    State(state): State<AppState>,
+    principal: Principal,
+    JsonBody(req): JsonBody<CreateDefinitionRequest>,
+) -> Result<(StatusCode, Json<TestDefinition>), ApiError> {
+    ensure_team(&state, req.team_id).await?;
+    principal.authorize(
+        Action::Manage,
+        &Resource::new_in("test_definition", req.team_id),
+    )?;
+    if req.name.is_empty() || req.image.is_empty() {
+        return Err(ApiError::validation("name and image are required"));
+    }
+    if req.target.is_some() {
+        resolve_target(&state, req.target.as_deref()).await?;
+    }
+    validate_secret_refs(&req.secrets, req.variables.as_ref())?;
+    if let Some(source) = &req.source {
+        validate_source(source)?;
+    }
+    validate_job_metadata(&req.job_labels, &req.job_annotations)?;
+
+    let definition = TestDefinition {
+        id: Uuid::new_v4(),
+        name: req.name,
+        description: req.description,
+        image: req.image,
+        commands: req.commands,
+        created_at: chrono::Utc::now(),
+        executor_id: req.executor_id,
+        variables: req.variables,
+        labels: req.labels,
+        target: req.target,
+        team_id: req.team_id,
+        resources: req.resources,
+        privileged: req.privileged,
+        secrets: req.secrets,
+        source: req.source,
+        files: Vec::new(),
+        priority: req.priority,
+        job_labels: req.job_labels,
+        job_annotations: req.job_annotations,
+    };
+    state
+        .policy
+        .read()
+        .await
+        .admit_definition(&definition, &state.runner.git_image)?;
+    state.store.insert_definition(definition.clone()).await?;
+
+    Ok((StatusCode::CREATED, Json(definition)))
+}
+
+/// Start a run of a stored definition
+pub async fn run_definition(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<(StatusCode, Json<TestRun>), ApiError> {
+    let definition = state
+        .store
+        .get_definition(id)
+        .await
+        .ok_or_else(|| definition_not_found(id))?;
+    principal.authorize(Action::Run, &(&definition).into())?;
+
+    let run = launch_definition(&state, &definition, started_by(&principal)).await?;
+
+    Ok((StatusCode::CREATED, Json(run)))
+}
+
+/// Attach files to a definition; files with an existing name are replaced.
+/// Runs started from the definition afterwards get a copy of them.
+pub async fn upload_definition_files(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+    multipart: Multipart,
+) -> Result<(StatusCode, Json<TestDefinition>), ApiError> {
+    let mut definition = state
+        .store
+        .get_definition(id)
+        .await
+        .ok_or_else(|| definition_not_found(id))?;
+    principal.authorize(Action::Manage, &(&definition).into())?;
+
+    let upload = read_upload(multipart, &state.files).await?;
+    let executor = match &definition.executor_id {
+        Some(id) => Some(executor_for_files(&state, id).await?),
+        None => None,
+    };
+    check_files(
+        &definition.files,
+        &upload.files,
+        executor.as_ref(),
+        &state.files,
+    )?;
+
+    merge_files(&mut definition.files, &upload.files);
+    store_files(&state, definition.id, upload.files).await?;
+    state.store.insert_definition(definition.clone()).await?;
+
+    Ok((StatusCode::CREATED, Json(definition)))
+}
+
+pub async fn get_definition_file(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path((id, name)): Path<(Uuid, String)>,
+) -> Result<Response, ApiError> {
+    let definition = state
+        .store
+        .get_definition(id)
+        .await
+        .ok_or_else(|| definition_not_found(id))?;
+    principal.authorize(Action::View, &(&definition).into())?;
+    file_response(&state, id, &definition.files, &name).await
+}
+
+pub async fn delete_definition_file(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path((id, name)): Path<(Uuid, String)>,
+) -> Result<StatusCode, ApiError> {
+    let mut definition = state
+        .store
+        .get_definition(id)
+        .await
+        .ok_or_else(|| definition_not_found(id))?;
+    principal.authorize(Action::Manage, &(&definition).into())?;
+
+    if !state.store.remove_file(id, &name).await? {
+        return Err(file_not_found(&name));
+    }
+    definition.files.retain(|file| file.name != name);
+    state.store.insert_definition(definition).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_run_file(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path((id, name)): Path<(Uuid, String)>,
+) -> Result<Response, ApiError> {
+    let run = state
+        .store
+        .get_run(id)
+        .await
+        .ok_or_else(|| run_not_found(id))?;
+    principal.authorize(Action::View, &(&run).into())?;
+    file_response(&state, id, &run.files, &name).await
+}
+
+fn file_not_found(name: &str) -> ApiError {
+    ApiError::not_found(format!("File '{name}' not found"))
+}
+
+/// Uploads are checked against the executor's supported file types, so the
+/// executor has to exist
+async fn executor_for_files(state: &AppState, id: &str) -> Result<Executor, ApiError> {
+    state
+        .store
+        .get_executor(id)
+        .await
+        .ok_or_else(|| ApiError::validation(format!("Executor '{id}' does not exist")))
+}
+
+async fn store_files(
+    state: &AppState,
+    owner: Uuid,
+    files: Vec<UploadedFile>,
+) -> Result<(), sqlx::Error> {
+    for file in files {
+        state.store.put_file(owner, file.name, file.content).await?;
+    }
+    Ok(())
+}
+
+async fn file_response(
+    state: &AppState,
+    owner: Uuid,
+    files: &[TestFile],
+    name: &str,
+) -> Result<Response, ApiError> {
+    let (Some(file), Some(content)) = (
+        files.iter().find(|file| file.name == name),
+        state.store.get_file(owner, name).await,
+    ) else {
+        return Err(file_not_found(name));
+    };
+    let content_type = file
+        .content_type
+        .clone()
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", file.name),
+            ),
+        ],
+        content,
+    )
+        .into_response())
+}
+
+pub async fn get_executors(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> Result<Json<Vec<Executor>>, ApiError> {
+    let mut executors = state.store.list_executors().await;
+    executors.retain(|executor| principal.can(Action::View, &executor.into()));
+    Ok(Json(executors))
+}
+
+pub async fn get_suites(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> Result<Json<Vec<TestSuite>>, ApiError> {
+    let mut suites = state.store.list_suites().await;
+    suites.retain(|suite| principal.can(Action::View, &suite.into()));
+    Ok(Json(suites))
+}
+
+pub async fn get_suite(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<Json<TestSuite>, ApiError> {
+    let suite = state
+        .store
+        .get_suite(id)
+        .await
+        .ok_or_else(|| suite_not_found(id))?;
+    principal.authorize(Action::View, &(&suite).into())?;
+    Ok(Json(suite))
+}
+
+fn suite_not_found(id: Uuid) -> ApiError {
+    ApiError::not_found(format!("Test suite {id} not found"))
+}
+
+pub async fn create_suite(
+    State(state): State<AppState>,
+    principal: Principal,
+    JsonBody(req): JsonBody<CreateSuiteRequest>,
+) -> Result<(StatusCode, Json<TestSuite>), ApiError> {
+    ensure_team(&state, req.team_id).await?;
+    principal.authorize(Action::Manage, &Resource::new_in("test_suite", req.team_id))?;
+    if req.name.is_empty() {
+        return Err(ApiError::validation("name is required"));
+    }
+    if !matches!(req.execution_mode.as_str(), "sequential" | "parallel") {
+        return Err(ApiError::validation(
+            "execution_mode must be 'sequential' or 'parallel'",
+        ));
+    }
+    for definition_id in &req.test_definition_ids {
+        let Some(definition) = state.store.get_definition(*definition_id).await else {
+            return Err(ApiError::validation(format!(
+                "Test definition {definition_id} does not exist"
+            )));
+        };
+        principal.authorize(Action::View, &(&definition).into())?;
+    }
+
+    let suite = TestSuite {
+        id: Uuid::new_v4(),
+        name: req.name,
+        description: req.description,
+        test_definition_ids: req.test_definition_ids,
+        created_at: chrono::Utc::now(),
+        execution_mode: req.execution_mode,
+        labels: req.labels,
+        team_id: req.team_id,
+    };
+    state.store.insert_suite(suite.clone()).await?;
+
+    Ok((StatusCode::CREATED, Json(suite)))
+}
+
+/// Runs started through the API record who asked for them
+fn started_by(principal: &Principal) -> RunOrigin {
+    RunOrigin {
+        triggered_by: Some(principal.name.clone()),
+        ..RunOrigin::default()
+    }
+}
+
+/// Start one run per definition in the suite, honouring its execution mode
+pub async fn run_suite(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<(StatusCode, Json<Vec<TestRun>>), ApiError> {
+    let suite = state
+        .store
+        .get_suite(id)
+        .await
+        .ok_or_else(|| suite_not_found(id))?;
+    principal.authorize(Action::Run, &(&suite).into())?;
+
+    let runs = launch_suite(&state, &suite, started_by(&principal)).await?;
+
+    Ok((StatusCode::CREATED, Json(runs)))
+}
+
+/// Admins see every user; everyone else sees themselves and the members of their teams
+pub async fn get_users(State(state): State<AppState>, principal: Principal) -> Json<Vec<User>> {
+    let mut users = state.store.list_users().await;
+    if !principal.is_admin {
+        let visible = visible_users(&state, &principal).await;
+        users.retain(|user| visible.contains(&user.id));
+    }
+    Json(users)
+}
+
+pub async fn get_user(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<Json<User>, ApiError> {
+    if !principal.is_admin && !visible_users(&state, &principal).await.contains(&id) {
+        principal.authorize(Action::Administer, &Resource::instance("user", id))?;
+    }
+    state
+        .store
+        .get_user(id)
+        .await
+        .map(Json)
+        .ok_or_else(|| user_not_found(id))
+}
+
+async fn visible_users(state: &AppState, principal: &Principal) -> HashSet<Uuid> {
+    let mut visible: HashSet<Uuid> = principal.user_id.into_iter().collect();
+    for team in state.store.list_teams().await {
+        if principal.role_in(team.id).is_some() {
+            visible.extend(team.members.iter().map(|member| member.user_id));
+        }
+    }
+    visible
+}
+
+fn user_not_found(id: Uuid) -> ApiError {
+    ApiError::not_found(format!("User {id} not found"))
+}
+
+pub async fn create_user(
+    State(state): State<AppState>,
+    principal: Principal,
+    JsonBody(req): JsonBody<CreateUserRequest>,
+) -> Result<(StatusCode, Json<User>), ApiError> {
+    principal.authorize(Action::Administer, &Resource::instance("user", "new"))?;
+    if req.name.trim().is_empty() {
+        return Err(ApiError::validation("name is required"));
+    }
+    if let Some(email) = &req.email {
+        let users = state.store.list_users().await;
+        if users.iter().any(|user| user.email.as_ref() == Some(email)) {
+            return Err(ApiError::conflict(format!(
+                "A user with email '{email}' already exists"
+            )));
+        }
+    }
+
+    let user = User {
+        id: Uuid::new_v4(),
+        name: req.name,
+        email: req.email,
+        subject: None,
+        is_admin: req.is_admin,
+        created_at: chrono::Utc::now(),
+    };
+    state.store.insert_user(user.clone()).await?;
+
+    Ok((StatusCode::CREATED, Json(user)))
+}
+
+pub async fn get_teams(State(state): State<AppState>, principal: Principal) -> Json<Vec<Team>> {
+    let mut teams = state.store.list_teams().await;
+    teams.retain(|team| principal.can(Action::View, &team.into()));
+    Json(teams)
+}
+
+pub async fn get_team(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Team>, ApiError> {
+    let team = state
+        .store
+        .get_team(id)
+        .await
+        .ok_or_else(|| team_not_found(id))?;
+    principal.authorize(Action::View, &(&team).into())?;
+    Ok(Json(team))
+}
+
+fn team_not_found(id: Uuid) -> ApiError {
+    ApiError::not_found(format!("Team {id} not found"))
+}
+
+pub async fn create_team(
+    State(state): State<AppState>,
+    principal: Principal,
+    JsonBody(req): JsonBody<CreateTeamRequest>,
+) -> Result<(StatusCode, Json<Team>), ApiError> {
+    principal.authorize(Action::Administer, &Resource::instance("team", "new"))?;
+    if req.name.trim().is_empty() {
+        return Err(ApiError::validation("name is required"));
+    }
+    for member in &req.members {
+        if state.store.get_user(member.user_id).await.is_none() {
+            return Err(ApiError::validation(format!(
+                "User {} does not exist",
+                member.user_id
+            )));
+        }
+    }
+
+    let team = Team {
+        id: Uuid::new_v4(),
+        name: req.name,
+        description: req.description,
+        members: req.members,
+        created_at: chrono::Utc::now(),
+    };
+    state.store.insert_team(team.clone()).await?;
+
+    Ok((StatusCode::CREATED, Json(team)))
+}
+
+/// Add a user to a team or change their role; team admins manage their own team
+pub async fn set_team_member(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path((team_id, user_id)): Path<(Uuid, Uuid)>,
+    JsonBody(req): JsonBody<SetMemberRequest>,
+) -> Result<Json<Team>, ApiError> {
+    principal.authorize(Action::Administer, &Resource::team(team_id))?;
+    if state.store.get_user(user_id).await.is_none() {
+        return Err(user_not_found(user_id));
+    }
+
+    state
+        .store
+        .set_team_member(team_id, user_id, req.role)
+        .await?
+        .map(Json)
+        .ok_or_else(|| team_not_found(team_id))
+}
+
+pub async fn remove_team_member(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path((team_id, user_id)): Path<(Uuid, Uuid)>,
+) -> Result<StatusCode, ApiError> {
+    principal.authorize(Action::Administer, &Resource::team(team_id))?;
+    state
+        .store
+        .remove_team_member(team_id, user_id)
+        .await?
+        .ok_or_else(|| team_not_found(team_id))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Query the audit log, newest first; only admins can read it
+pub async fn get_audit_events(
+    State(state): State<AppState>,
+    principal: Principal,
+    Query(query): Query<AuditQuery>,
+) -> Result<Json<Vec<AuditEvent>>, ApiError> {
+    principal.authorize(Action::Administer, &Resource::instance("audit_log", "*"))?;
+    Ok(Json(state.audit.query(&query).await?))
+}
+
+pub async fn get_admission_policy(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> Result<Json<AdmissionPolicy>, ApiError> {
+    principal.authorize_member(&Resource::instance("admission_policy", "*"))?;
+    Ok(Json(state.policy.read().await.clone()))
+}
+
+/// Replace the admission policy; applies to runs launched from now on
+pub async fn update_admission_policy(
+    State(state): State<AppState>,
+    principal: Principal,
+    JsonBody(policy): JsonBody<AdmissionPolicy>,
+) -> Result<Json<AdmissionPolicy>, ApiError> {
+    principal.authorize(
+        Action::Administer,
+        &Resource::instance("admission_policy", "*"),
+    )?;
+    policy.validate()?;
+    let mut current = state.policy.write().await;
+    save_admission_policy(&state, &policy).await?;
+    *current = policy.clone();
+    Ok(Json(policy))
+}
+
+pub async fn get_trigger_rules(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> Result<Json<Vec<TriggerRule>>, ApiError> {
+    let mut rules = state.store.list_trigger_rules().await;
+    rules.retain(|rule| principal.can(Action::View, &rule.into()));
+    Ok(Json(rules))
+}
+
+pub async fn get_trigger_rule(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<Json<TriggerRule>, ApiError> {
+    let rule = state
+        .store
+        .get_trigger_rule(id)
+        .await
+        .ok_or_else(|| trigger_rule_not_found(id))?;
+    principal.authorize(Action::View, &(&rule).into())?;
+    Ok(Json(rule))
+}
+
+fn trigger_rule_not_found(id: Uuid) -> ApiError {
+    ApiError::not_found(format!("Trigger rule {id} not found"))
+}
+
+/// Rules run their definition or suite without a caller, so creating one
+/// requires permission to run it
+pub async fn create_trigger_rule(
+    State(state): State<AppState>,
+    principal: Principal,
+    JsonBody(req): JsonBody<CreateTriggerRuleRequest>,
+) -> Result<(StatusCode, Json<TriggerRule>), ApiError> {
+    ensure_team(&state, req.team_id).await?;
+    principal.authorize(
+        Action::Manage,
+        &Resource::new_in("trigger_rule", req.team_id),
+    )?;
+    if req.name.is_empty() {
+        return Err(ApiError::validation("name is required"));
+    }
+    if !req.repository.contains('/') {
+        return Err(ApiError::validation(
+            "repository must be of the form 'owner/repo'",
+        ));
+    }
+    if req.branches.iter().any(|branch| branch.is_empty()) {
+        return Err(ApiError::validation("branch patterns must not be empty"));
+    }
+    authorize_launch_target(
+        &state,
+        &principal,
+        req.test_definition_id,
+        req.test_suite_id,
+    )
+    .await?;
+
+    let rule = TriggerRule {
+        id: Uuid::new_v4(),
+        name: req.name,
+        repository: req.repository,
+        branches: req.branches,
+        events: req.events,
+        test_definition_id: req.test_definition_id,
+        test_suite_id: req.test_suite_id,
+        team_id: req.team_id,
+        created_at: chrono::Utc::now(),
+    };
+    save_trigger_rule(&state, &rule).await?;
+    state.store.insert_trigger_rule(rule.clone()).await;
+
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+/// Rules and schedules start exactly one definition or suite, which the
+/// caller must be allowed to run
+async fn authorize_launch_target(
+    state: &AppState,
+    principal: &Principal,
+    test_definition_id: Option<Uuid>,
+    test_suite_id: Option<Uuid>,
+) -> Result<(), ApiError> {
+    match (test_definition_id, test_suite_id) {
+        (Some(id), None) => {
+            let definition = state.store.get_definition(id).await.ok_or_else(|| {
+                ApiError::validation(format!("Test definition {id} does not exist"))
+            })?;
+            principal.authorize(Action::Run, &(&definition).into())
+        }
+        (None, Some(id)) => {
+            let suite =
+                state.store.get_suite(id).await.ok_or_else(|| {
+                    ApiError::validation(format!("Test suite {id} does not exist"))
+                })?;
+            principal.authorize(Action::Run, &(&suite).into())
+        }
+        _ => Err(ApiError::validation(
+            "set exactly one of test_definition_id or test_suite_id",
+        )),
+    }
+}
+
+pub async fn delete_trigger_rule(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    let rule = state
+        .store
+        .get_trigger_rule(id)
+        .await
+        .ok_or_else(|| trigger_rule_not_found(id))?;
+    principal.authorize(Action::Manage, &(&rule).into())?;
+    delete_saved_trigger_rule(&state, id).await?;
+    state.store.remove_trigger_rule(id).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_status_reporters(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> Result<Json<Vec<StatusReporter>>, ApiError> {
+    let mut reporters = state.store.list_status_reporters().await;
+    reporters.retain(|reporter| principal.can(Action::View, &reporter.into()));
+    Ok(Json(reporters))
+}
+
+pub async fn get_status_reporter(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<Json<StatusReporter>, ApiError> {
+    let reporter = state
+        .store
+        .get_status_reporter(id)
+        .await
+        .ok_or_else(|| status_reporter_not_found(id))?;
+    principal.authorize(Action::View, &(&reporter).into())?;
+    Ok(Json(reporter))
+}
+
+fn status_reporter_not_found(id: Uuid) -> ApiError {
+    ApiError::not_found(format!("Status reporter {id} not found"))
+}
+
+pub async fn create_status_reporter(
+    State(state): State<AppState>,
+    principal: Principal,
+    JsonBody(req): JsonBody<CreateStatusReporterRequest>,
+) -> Result<(StatusCode, Json<StatusReporter>), ApiError> {
+    ensure_team(&state, req.team_id).await?;
+    principal.authorize(
+        Action::Manage,
+        &Resource::new_in("status_reporter", req.team_id),
+    )?;
+    if req.name.is_empty() {
+        return Err(ApiError::validation("name is required"));
+    }
+
+    let reporter = StatusReporter {
+        id: Uuid::new_v4(),
+        name: req.name,
+        provider: req.provider,
+        repository: req.repository,
+        api_url: req.api_url,
+        context: req.context,
+        token: req.token,
+        team_id: req.team_id,
+        created_at: chrono::Utc::now(),
+    };
+    validate_reporter(&reporter)?;
+    save_status_reporter(&state, &reporter).await?;
+    state.store.insert_status_reporter(reporter.clone()).await;
+
+    Ok((StatusCode::CREATED, Json(reporter)))
+}
+
+pub async fn delete_status_reporter(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    let reporter = state
+        .store
+        .get_status_reporter(id)
+        .await
+        .ok_or_else(|| status_reporter_not_found(id))?;
+    principal.authorize(Action::Manage, &(&reporter).into())?;
+    delete_saved_status_reporter(&state, id).await?;
+    state.store.remove_status_reporter(id).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_schedules(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> Result<Json<Vec<Schedule>>, ApiError> {
+    let mut schedules = state.store.list_schedules().await;
+    schedules.retain(|schedule| principal.can(Action::View, &schedule.into()));
+    Ok(Json(schedules))
+}
+
+pub async fn get_schedule(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Schedule>, ApiError> {
+    let schedule = state
+        .store
+        .get_schedule(id)
+        .await
+        .ok_or_else(|| schedule_not_found(id))?;
+    principal.authorize(Action::View, &(&schedule).into())?;
+    Ok(Json(schedule))
+}
+
+fn schedule_not_found(id: Uuid) -> ApiError {
+    ApiError::not_found(format!("Schedule {id} not found"))
+}
+
+/// Schedules run their definition or suite without a caller, so creating one
+/// requires permission to run it
+pub async fn create_schedule(
+    State(state): State<AppState>,
+    principal: Principal,
+    JsonBody(req): JsonBody<ScheduleRequest>,
+) -> Result<(StatusCode, Json<Schedule>), ApiError> {
+    ensure_team(&state, req.team_id).await?;
+    principal.authorize(Action::Manage, &Resource::new_in("schedule", req.team_id))?;
+    authorize_launch_target(
+        &state,
+        &principal,
+        req.test_definition_id,
+        req.test_suite_id,
+    )
+    .await?;
+
+    let now = chrono::Utc::now();
+    let mut schedule = Schedule {
+        id: Uuid::new_v4(),
+        name: req.name,
+        cron: req.cron,
+        timezone: req.timezone,
+        test_definition_id: req.test_definition_id,
+        test_suite_id: req.test_suite_id,
+        enabled: req.enabled,
+        team_id: req.team_id,
+        created_at: now,
+        updated_at: now,
+        last_fired_at: None,
+        last_outcome: None,
+        last_error: None,
+        next_run_at: None,
+    };
+    prepare_schedule(&mut schedule)?;
+    save_schedule(&state, &schedule).await?;
+    state.store.insert_schedule(schedule.clone()).await;
+
+    Ok((StatusCode::CREATED, Json(schedule)))
+}
+
+/// Replace a schedule's settings. Occurrences that passed before the change,
+/// including while it was disabled, are not started afterwards.
+pub async fn update_schedule(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+    JsonBody(req): JsonBody<ScheduleRequest>,
+) -> Result<Json<Schedule>, ApiError> {
+    let existing = state
+        .store
+        .get_schedule(id)
+        .await
+        .ok_or_else(|| schedule_not_found(id))?;
+    principal.authorize(Action::Manage, &(&existing).into())?;
+    if req.team_id != existing.team_id {
+        ensure_team(&state, req.team_id).await?;
+        principal.authorize(Action::Manage, &Resource::new_in("schedule", req.team_id))?;
+    }
+    authorize_launch_target(
+        &state,
+        &principal,
+        req.test_definition_id,
+        req.test_suite_id,
+    )
+    .await?;
+
+    let mut schedule = Schedule {
+        name: req.name,
+        cron: req.cron,
+        timezone: req.timezone,
+        test_definition_id: req.test_definition_id,
+        test_suite_id: req.test_suite_id,
+        enabled: req.enabled,
+        team_id: req.team_id,
+        updated_at: chrono::Utc::now(),
+        ..existing
+    };
+    prepare_schedule(&mut schedule)?;
+    save_schedule(&state, &schedule).await?;
+    state.store.insert_schedule(schedule.clone()).await;
+
+    Ok(Json(schedule))
+}
+
+pub async fn delete_schedule(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    let schedule = state
+        .store
+        .get_schedule(id)
+        .await
+        .ok_or_else(|| schedule_not_found(id))?;
+    principal.authorize(Action::Manage, &(&schedule).into())?;
+    delete_saved_schedule(&state, id).await?;
+    state.store.remove_schedule(id).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_notification_channels(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> Result<Json<Vec<NotificationChannel>>, ApiError> {
+    let mut channels = state.store.list_notification_channels().await;
+    channels.retain(|channel| principal.can(Action::View, &channel.into()));
+    Ok(Json(channels))
+}
+
+pub async fn get_notification_channel(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<Json<NotificationChannel>, ApiError> {
+    let channel = state
+        .store
+        .get_notification_channel(id)
+        .await
+        .ok_or_else(|| notification_channel_not_found(id))?;
+    principal.authorize(Action::View, &(&channel).into())?;
+    Ok(Json(channel))
+}
+
+fn notification_channel_not_found(id: Uuid) -> ApiError {
+    ApiError::not_found(format!("Notification channel {id} not found"))
+}
+
+pub async fn create_notification_channel(
+    State(state): State<AppState>,
+    principal: Principal,
+    JsonBody(req): JsonBody<CreateNotificationChannelRequest>,
+) -> Result<(StatusCode, Json<NotificationChannel>), ApiError> {
+    ensure_team(&state, req.team_id).await?;
+    principal.authorize(
+        Action::Manage,
+        &Resource::new_in("notification_channel", req.team_id),
+    )?;
+
+    let channel = NotificationChannel {
+        id: Uuid::new_v4(),
+        name: req.name,
+        config: req.config,
+        team_id: req.team_id,
+        created_at: chrono::Utc::now(),
+    };
+    validate_channel(&channel)?;
+    save_notification_channel(&state, &channel).await?;
+    state
+        .store
+        .insert_notification_channel(channel.clone())
+        .await;
+
+    Ok((StatusCode::CREATED, Json(channel)))
+}
+
+/// Also removes the rules that send to the channel
+pub async fn delete_notification_channel(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    let channel = state
+        .store
+        .get_notification_channel(id)
+        .await
+        .ok_or_else(|| notification_channel_not_found(id))?;
+    principal.authorize(Action::Manage, &(&channel).into())?;
+    delete_saved_notification_channel(&state, id).await?;
+    state.store.remove_notification_channel(id).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn get_notification_rules(
+    State(state): State<AppState>,
+    principal: Principal,
+) -> Result<Json<Vec<NotificationRule>>, ApiError> {
+    let mut rules = state.store.list_notification_rules().await;
+    rules.retain(|rule| principal.can(Action::View, &rule.into()));
+    Ok(Json(rules))
+}
+
+pub async fn get_notification_rule(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<Json<NotificationRule>, ApiError> {
+    let rule = state
+        .store
+        .get_notification_rule(id)
+        .await
+        .ok_or_else(|| notification_rule_not_found(id))?;
+    principal.authorize(Action::View, &(&rule).into())?;
+    Ok(Json(rule))
+}
+
+fn notification_rule_not_found(id: Uuid) -> ApiError {
+    ApiError::not_found(format!("Notification rule {id} not found"))
+}
+
+/// Notifications carry run names and failure reasons, so a rule requires
+/// permission to view what it covers and the channel it sends to
+pub async fn create_notification_rule(
+    State(state): State<AppState>,
+    principal: Principal,
+    JsonBody(req): JsonBody<CreateNotificationRuleRequest>,
+) -> Result<(StatusCode, Json<NotificationRule>), ApiError> {
+    ensure_team(&state, req.team_id).await?;
+    principal.authorize(
+        Action::Manage,
+        &Resource::new_in("notification_rule", req.team_id),
+    )?;
+    if req.name.is_empty() {
+        return Err(ApiError::validation("name is required"));
+    }
+    let channel = state
+        .store
+        .get_notification_channel(req.channel_id)
+        .await
+        .ok_or_else(|| {
+            ApiError::validation(format!(
+                "Notification channel {} does not exist",
+                req.channel_id
+            ))
+        })?;
+    principal.authorize(Action::View, &(&channel).into())?;
+    match (req.test_definition_id, req.test_suite_id) {
+        (Some(_), Some(_)) => {
+            return Err(ApiError::validation(
+                "set at most one of test_definition_id or test_suite_id",
+            ))
+        }
+        (Some(id), None) => {
+            let definition = state.store.get_definition(id).await.ok_or_else(|| {
+                ApiError::validation(format!("Test definition {id} does not exist"))
+            })?;
+            principal.authorize(Action::View, &(&definition).into())?;
+        }
+        (None, Some(id)) => {
+            let suite =
+                state.store.get_suite(id).await.ok_or_else(|| {
+                    ApiError::validation(format!("Test suite {id} does not exist"))
+                })?;
+            principal.authorize(Action::View, &(&suite).into())?;
+        }
+        (None, None) => {}
+    }
+    if let Some(template) = &req.template {
+        validate_template(template)?;
+    }
+
+    let rule = NotificationRule {
+        id: Uuid::new_v4(),
+        name: req.name,
+        channel_id: req.channel_id,
+        test_definition_id: req.test_definition_id,
+        test_suite_id: req.test_suite_id,
+        on: req.on,
+        template: req.template,
+        team_id: req.team_id,
+        created_at: chrono::Utc::now(),
+    };
+    save_notification_rule(&state, &rule).await?;
+    state.store.insert_notification_rule(rule.clone()).await;
+
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+pub async fn delete_notification_rule(
+    State(state): State<AppState>,
+    principal: Principal,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, ApiError> {
+    let rule = state
+        .store
+        .get_notification_rule(id)
+        .await
+        .ok_or_else(|| notification_rule_not_found(id))?;
+    principal.authorize(Action::Manage, &(&rule).into())?;
+    delete_saved_notification_rule(&state, id).await?;
+    state.store.remove_notification_rule(id).await;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GitHub deliveries, signed with `X-Hub-Signature-256`
+pub async fn receive_github_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<WebhookResponse>, ApiError> {
+    verify_github_signature(state.webhooks.secret()?, &headers, &body)?;
+    let event = headers
+        .get("x-github-event")
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| ApiError::validation("Missing X-GitHub-Event header"))?;
+    let trigger = parse_github_event(event, &body)?;
+    Ok(Json(dispatch_trigger(&state, trigger).await))
+}
+
+/// GitLab deliveries, authenticated with `X-Gitlab-Token`
+pub async fn receive_gitlab_webhook(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<WebhookResponse>, ApiError> {
+    verify_gitlab_token(state.webhooks.secret()?, &headers)?;
+    let trigger = parse_gitlab_event(&body)?;
+    Ok(Json(dispatch_trigger(&state, trigger).await))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorType;
+    use crate::fake::{FakeBackend, FakeScript};
+    use crate::queue::{QueueConfig, RunQueue};
+    use crate::run_query::{RunSortField, SortOrder};
+    use std::sync::Arc;
+    use tokio::time::{sleep, Duration};
+
+    fn fake_state() -> (AppState, Arc<FakeBackend>) {
+        let backend = Arc::new(FakeBackend::new(
+            FakeScript::succeed(&["ok"]).with_duration(Duration::from_millis(20)),
+        ));
+        let mut state = AppState::new(backend.clone());
+        state.runner.poll_interval = Duration::from_millis(5);
+        (state, backend)
+    }
+
+    async fn wait_for_status(state: &AppState, id: Uuid, statuses: &[&str]) -> TestRun {
+        for _ in 0..500 {
+            if let Some(run) = state.store.get_run(id).await {
+                if statuses.contains(&run.status.as_str()) {
+                    return run;
+                }
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        panic!("run {id} never reached {statuses:?}");
+    }
+
+    async fn wait_for_finish(state: &AppState, id: Uuid) -> TestRun {
+        wait_for_status(state, id, &["succeeded", "failed"]).await
+    }
+
+    async fn create_test_definition(state: &AppState, name: &str, image: &str) -> TestDefinition {
+        let request = CreateDefinitionRequest {
+            name: name.to_string(),
+            image: image.to_string(),
+            commands: vec!["run".to_string()],
+            ..Default::default()
+        };
+        let (_, Json(definition)) = create_definition(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap();
+        definition
+    }
+
+    #[tokio::test]
+    async fn test_health_check() {
+        let response = health_check().await;
+        assert_eq!(response.0.status, "healthy");
+        assert!(!response.0.timestamp.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_get_runs() {
+        let result = get_runs(
+            State(AppState::default()),
+            Principal::anonymous(),
+            Query(RunListQuery::default()),
+        )
+        .await;
+        assert!(result.is_ok());
+        let runs = result.unwrap().runs;
+        assert_eq!(runs.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_get_runs_pages_filters_and_sorts() {
+        let state = AppState::default();
+        let mut definition = create_test_definition(&state, "Nightly", "node:20").await;
+        definition.labels = Some(vec!["nightly".to_string()]);
+        state
+            .store
+            .insert_definition(definition.clone())
+            .await
+            .unwrap();
+
+        let start = chrono::Utc::now() - chrono::Duration::hours(1);
+        for i in 0..5 {
+            let mut run = TestRun::new(format!("Run {i}"), "node:20".to_string(), vec![]);
+            run.created_at = start + chrono::Duration::minutes(i);
+            run.status = if i % 2 == 0 { "succeeded" } else { "failed" }.to_string();
+            run.duration = (i > 0).then_some(10 - i as i32);
+            run.logs = Some(vec![format!("line {i}")]);
+            if i >= 3 {
+                run.definition_id = Some(definition.id);
+            }
+            state.store.insert_run(run).await.unwrap();
+        }
+        let list = |query: RunListQuery| {
+            let state = state.clone();
+            async move {
+                get_runs(State(state), Principal::anonymous(), Query(query))
+                    .await
+                    .unwrap()
+            }
+        };
+        let names = |page: &RunPage| page.runs.iter().map(|r| r.name.clone()).collect::<Vec<_>>();
+
+        let first = list(RunListQuery {
+            limit: Some(2),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(names(&first), ["Run 4", "Run 3"]);
+        assert_eq!(first.total, 5);
+        assert!(first.runs.iter().all(|run| run.logs.is_none()));
+        let second = list(RunListQuery {
+            limit: Some(2),
+            cursor: first.next_cursor.clone(),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(names(&second), ["Run 2", "Run 1"]);
+        let last = list(RunListQuery {
+            limit: Some(2),
+            cursor: second.next_cursor.clone(),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(names(&last), ["Run 0"]);
+        assert!(last.next_cursor.is_none());
+
+        let by_duration = list(RunListQuery {
+            sort: RunSortField::Duration,
+            order: SortOrder::Asc,
+            include: Some("logs".to_string()),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(
+            names(&by_duration),
+            ["Run 0", "Run 4", "Run 3", "Run 2", "Run 1"]
+        );
+        assert!(by_duration.runs.iter().all(|run| run.logs.is_some()));
+
+        let filtered = list(RunListQuery {
+            status: Some("failed".to_string()),
+            label: Some("nightly".to_string()),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(names(&filtered), ["Run 3"]);
+        let filtered = list(RunListQuery {
+            q: Some("RUN 1".to_string()),
+            created_after: Some(start),
+            created_before: Some(start + chrono::Duration::minutes(2)),
+            ..Default::default()
+        })
+        .await;
+        assert_eq!(names(&filtered), ["Run 1"]);
+
+        let error = get_runs(
+            State(state.clone()),
+            Principal::anonymous(),
+            Query(RunListQuery {
+                sort: RunSortField::Name,
+                cursor: first.next_cursor,
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Validation);
+    }
+
+    #[tokio::test]
+    async fn test_definition_analytics() {
+        let state = AppState::default();
+        let definition =
+            create_test_definition(&state, "REST API Integration Tests", "node:20").await;
+        let other = create_test_definition(&state, "Other", "node:20").await;
+
+        let day: chrono::DateTime<chrono::Utc> = "2026-10-14T09:00:00Z".parse().unwrap();
+        for (hours, status, duration, definition_id) in [
+            (0, "succeeded", 10, definition.id),
+            (1, "failed", 30, definition.id),
+            (25, "succeeded", 50, definition.id),
+            (2, "failed", 99, other.id),
+        ] {
+            let mut run = TestRun::new("run".to_string(), "node:20".to_string(), vec![]);
+            run.created_at = day + chrono::Duration::hours(hours);
+            run.status = status.to_string();
+            run.duration = Some(duration);
+            run.logs = Some(vec!["ECONNREFUSED".to_string()]);
+            run.definition_id = Some(definition_id);
+            state.store.insert_run(run).await.unwrap();
+        }
+
+        let query = AnalyticsQuery {
+            from: Some(day - chrono::Duration::days(1)),
+            to: Some(day + chrono::Duration::days(2)),
+            ..Default::default()
+        };
+        let Json(analytics) = get_definition_analytics(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(definition.id),
+            Query(query.clone()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(analytics.groups.len(), 1);
+        let group = &analytics.groups[0];
+        assert_eq!(group.name.as_deref(), Some("REST API Integration Tests"));
+        assert_eq!(group.summary.runs, 3);
+        assert_eq!(group.summary.p50_duration_seconds, Some(30));
+        assert_eq!(group.summary.failure_reasons[0].reason, "ECONNREFUSED");
+        assert_eq!(group.buckets.len(), 2);
+        assert_eq!(group.buckets[0].stats.pass_rate, Some(0.5));
+        assert_eq!(group.buckets[1].stats.pass_rate, Some(1.0));
+
+        let Json(all) =
+            get_run_analytics(State(state.clone()), Principal::anonymous(), Query(query))
+                .await
+                .unwrap();
+        assert_eq!(all.groups.len(), 2);
+
+        let error = get_definition_analytics(
+            State(state),
+            Principal::anonymous(),
+            Path(Uuid::new_v4()),
+            Query(AnalyticsQuery::default()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::NotFound);
+    }
+
+    #[tokio::test]
+    async fn test_create_run() {
+        let request = CreateRunRequest {
+            name: "Test Run".to_string(),
+            image: "test:latest".to_string(),
+            commands: vec!["echo".to_string(), "hello".to_string()],
+            ..Default::default()
+        };
+
+        let state = AppState::default();
+        let result = create_run(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await;
+        assert!(result.is_ok());
+
+        let run = result.unwrap().0;
+        assert_eq!(run.name, "Test Run");
+        assert_eq!(run.image, "test:latest");
+        assert_eq!(run.status, "pending");
+        assert_eq!(run.commands.len(), 2);
+        assert_eq!(run.target.as_deref(), Some("default"));
+
+        let stored = get_run(State(state), Principal::anonymous(), Path(run.id))
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(stored.id, run.id);
+    }
+
+    #[tokio::test]
+    async fn test_create_run_with_unknown_target() {
+        let request = CreateRunRequest {
+            name: "Test Run".to_string(),
+            image: "test:latest".to_string(),
+            commands: vec!["echo".to_string()],
+            target: Some("load-testing".to_string()),
+            ..Default::default()
+        };
+
+        let result = create_run(
+            State(AppState::default()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_get_run() {
+        let id = Uuid::new_v4();
+        let result = get_run(State(AppState::default()), Principal::anonymous(), Path(id)).await;
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_delete_run() {
+        let id = Uuid::new_v4();
+        let result = delete_run(State(AppState::default()), Principal::anonymous(), Path(id)).await;
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_k8s_health() {
+        let response = k8s_health(State(AppState::default())).await;
+        let value = response.0;
+        // In test environment, Kubernetes is typically not available
+        assert_eq!(value["kubernetes_connected"], false);
+        assert!(value["timestamp"].is_string());
+        assert!(value["error"].is_string());
+        assert_eq!(value["targets"][0]["name"], "default");
+        assert_eq!(value["targets"][0]["kubernetes_connected"], false);
+    }
+
+    #[tokio::test]
+    async fn test_target_lifecycle() {
+        let state = AppState::default();
+        let request = CreateTargetRequest {
+            name: "load-testing".to_string(),
+            description: None,
+            connection: TargetConnection::Kubeconfig {
+                context: "load-testing".to_string(),
+            },
+            namespace: "perf".to_string(),
+            timeout_seconds: 1800,
+            max_log_lines: None,
+        };
+
+        let (status, target) = create_target(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(target.0.namespace, "perf");
+
+        let targets = get_targets(State(state.clone()), Principal::anonymous())
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(targets.len(), 2);
+
+        let result = delete_target(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path("load-testing".to_string()),
+        )
+        .await;
+        assert_eq!(result.unwrap(), StatusCode::NO_CONTENT);
+
+        let result = get_target(
+            State(state),
+            Principal::anonymous(),
+            Path("load-testing".to_string()),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_default_target_cannot_be_deleted() {
+        let result = delete_target(
+            State(AppState::default()),
+            Principal::anonymous(),
+            Path("default".to_string()),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_get_job_logs() {
+        let job_name = "test-job".to_string();
+        let response = get_job_logs(
+            State(AppState::default()),
+            Principal::anonymous(),
+            Path(job_name.clone()),
+            Query(TargetQuery::default()),
+        )
+        .await;
+        // In test environment, Kubernetes is not available, so expect an upstream error
+        let error = response.unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Upstream);
+        assert_eq!(error.status(), StatusCode::BAD_GATEWAY);
+        assert!(error.message.contains("Kubernetes client unavailable"));
+    }
+
+    #[tokio::test]
+    async fn test_get_job_status() {
+        let job_name = "test-job".to_string();
+        let response = get_job_status(
+            State(AppState::default()),
+            Principal::anonymous(),
+            Path(job_name.clone()),
+            Query(TargetQuery::default()),
+        )
+        .await;
+        // In test environment, Kubernetes is not available, so expect an upstream error
+        let error = response.unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Upstream);
+        assert_eq!(error.status(), StatusCode::BAD_GATEWAY);
+        assert!(error.message.contains("Kubernetes client unavailable"));
+    }
+
+    #[tokio::test]
+    async fn test_delete_job() {
+        let job_name = "test-job".to_string();
+        let response = delete_job(
+            State(AppState::default()),
+            Principal::anonymous(),
+            Path(job_name.clone()),
+            Query(TargetQuery::default()),
+        )
+        .await;
+        // In test environment, Kubernetes is not available, so expect an upstream error
+        let error = response.unwrap_err();
+        assert_eq!(error.status(), StatusCode::BAD_GATEWAY);
+        assert!(error.message.contains("Kubernetes client unavailable"));
+    }
+
+    #[tokio::test]
+    async fn test_job_endpoints_with_unknown_job() {
+        let (state, _backend) = fake_state();
+
+        let error = get_job_status(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path("missing-job".to_string()),
+            Query(TargetQuery::default()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::NotFound);
+
+        let error = delete_job(
+            State(state),
+            Principal::anonymous(),
+            Path("missing-job".to_string()),
+            Query(TargetQuery::default()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_job_endpoints_with_unknown_target() {
+        let query = TargetQuery {
+            target: Some("load-testing".to_string()),
+        };
+        let error = get_job_logs(
+            State(AppState::default()),
+            Principal::anonymous(),
+            Path("test-job".to_string()),
+            Query(query),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Validation);
+        assert_eq!(error.message, "Unknown execution target 'load-testing'");
+    }
+
+    #[tokio::test]
+    async fn test_job_endpoints_default_to_the_runs_target() {
+        let (state, _backend) = fake_state();
+        let request = CreateTargetRequest {
+            name: "load-testing".to_string(),
+            description: None,
+            connection: TargetConnection::Kubeconfig {
+                context: "load-testing".to_string(),
+            },
+            namespace: "perf".to_string(),
+            timeout_seconds: 1800,
+            max_log_lines: None,
+        };
+        let _ = create_target(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap();
+
+        let request = CreateRunRequest {
+            name: "Load Test".to_string(),
+            image: "k6".to_string(),
+            target: Some("load-testing".to_string()),
+            ..Default::default()
+        };
+        let Json(run) = create_run(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap();
+        let job_name = wait_for_finish(&state, run.id).await.k8s_job_name.unwrap();
+
+        let Json(status) = get_job_status(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(job_name.clone()),
+            Query(TargetQuery::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status["status"], "completed");
+        let Json(logs) = get_job_logs(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(job_name.clone()),
+            Query(TargetQuery::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(logs["status"], "completed");
+
+        // Asking for another target still looks there
+        let query = TargetQuery {
+            target: Some("default".to_string()),
+        };
+        let error = delete_job(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(job_name.clone()),
+            Query(query),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::NotFound);
+        let _ = delete_job(
+            State(state),
+            Principal::anonymous(),
+            Path(job_name),
+            Query(TargetQuery::default()),
+        )
+        .await
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_run_lifecycle_with_fake_backend() {
+        let (state, backend) = fake_state();
+        backend.script(
+            "node:18-alpine",
+            FakeScript::succeed(&["PASS src/app.test.ts", "Tests: 12 passed"])
+                .with_duration(Duration::from_millis(30)),
+        );
+
+        let request = CreateRunRequest {
+            name: "Unit Tests".to_string(),
+            image: "node:18-alpine".to_string(),
+            commands: vec!["npm".to_string(), "test".to_string()],
+            ..Default::default()
+        };
+        let run = create_run(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(run.status, "pending");
+
+        let run = wait_for_finish(&state, run.id).await;
+        assert_eq!(run.status, "succeeded");
+        assert_eq!(run.k8s_job_name, Some(format!("test-run-{}", run.id)));
+        assert!(run.completed.is_some());
+        assert!(run.failed.is_none());
+        assert!(run.duration.is_some());
+        assert_eq!(
+            run.logs.unwrap(),
+            vec!["PASS src/app.test.ts", "Tests: 12 passed"]
+        );
+
+        let runs = get_runs(
+            State(state),
+            Principal::anonymous(),
+            Query(RunListQuery::default()),
+        )
+        .await
+        .unwrap()
+        .runs;
+        assert_eq!(runs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_run_is_retried() {
+        let (state, backend) = fake_state();
+        backend.script("flaky", FakeScript::fail(1, &["connection reset"]));
+        backend.script("flaky", FakeScript::succeed(&["all green"]));
+
+        let request = CreateRunRequest {
+            name: "Flaky Tests".to_string(),
+            image: "flaky".to_string(),
+            commands: vec!["pytest".to_string()],
+            retries: Some(2),
+            ..Default::default()
+        };
+        let run = create_run(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap()
+        .0;
+
+        let run = wait_for_finish(&state, run.id).await;
+        assert_eq!(run.status, "succeeded");
+        assert_eq!(
+            backend.created_jobs(),
+            vec![
+                format!("test-run-{}", run.id),
+                format!("test-run-{}-retry-1", run.id)
+            ]
+        );
+        assert_eq!(
+            run.logs.unwrap(),
+            vec!["connection reset", "Retrying (attempt 2 of 3)", "all green"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retries_are_exhausted() {
+        let (state, backend) = fake_state();
+        backend.script("broken", FakeScript::fail(2, &["assertion failed"]));
+
+        let request = CreateRunRequest {
+            name: "Broken Tests".to_string(),
+            image: "broken".to_string(),
+            commands: vec!["pytest".to_string()],
+            retries: Some(1),
+            ..Default::default()
+        };
+        let run = create_run(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap()
+        .0;
+
+        let run = wait_for_finish(&state, run.id).await;
+        assert_eq!(run.status, "failed");
+        assert!(run.failed.is_some());
+        assert_eq!(backend.created_jobs().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_negative_retries_are_rejected() {
+        let request = CreateRunRequest {
+            name: "Test Run".to_string(),
+            image: "test:latest".to_string(),
+            retries: Some(-1),
+            ..Default::default()
+        };
+
+        let result = create_run(
+            State(AppState::default()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_sequential_suite_run() {
+        let (state, backend) = fake_state();
+        let lint = create_test_definition(&state, "Lint", "lint").await;
+        let unit = create_test_definition(&state, "Unit", "unit").await;
+        backend.script("lint", FakeScript::fail(1, &["2 lint errors"]));
+
+        let request = CreateSuiteRequest {
+            name: "CI".to_string(),
+            test_definition_ids: vec![lint.id, unit.id],
+            execution_mode: "sequential".to_string(),
+            ..Default::default()
+        };
+        let (_, Json(suite)) = create_suite(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap();
+
+        let (status, runs) =
+            run_suite(State(state.clone()), Principal::anonymous(), Path(suite.id))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        let runs = runs.0;
+        assert_eq!(runs.len(), 2);
+        assert!(runs.iter().all(|run| run.suite_id == Some(suite.id)));
+        assert_eq!(runs[0].definition_id, Some(lint.id));
+
+        let first = wait_for_finish(&state, runs[0].id).await;
+        let second = wait_for_finish(&state, runs[1].id).await;
+        assert_eq!(first.status, "failed");
+        assert_eq!(second.status, "succeeded");
+        // Sequential suites launch jobs strictly in definition order
+        assert_eq!(
+            backend.created_jobs(),
+            vec![
+                format!("test-run-{}", runs[0].id),
+                format!("test-run-{}", runs[1].id)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_parallel_suite_run() {
+        let (state, _backend) = fake_state();
+        let e2e = create_test_definition(&state, "E2E", "cypress").await;
+        let api = create_test_definition(&state, "API", "newman").await;
+
+        let request = CreateSuiteRequest {
+            name: "Nightly".to_string(),
+            test_definition_ids: vec![e2e.id, api.id],
+            execution_mode: "parallel".to_string(),
+            ..Default::default()
+        };
+        let (_, Json(suite)) = create_suite(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap();
+
+        let (_, Json(runs)) =
+            run_suite(State(state.clone()), Principal::anonymous(), Path(suite.id))
+                .await
+                .unwrap();
+        for run in runs {
+            assert_eq!(wait_for_finish(&state, run.id).await.status, "succeeded");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_suite_with_unknown_definition_is_rejected() {
+        let request = CreateSuiteRequest {
+            name: "Broken".to_string(),
+            test_definition_ids: vec![Uuid::new_v4()],
+            ..Default::default()
+        };
+
+        let result = create_suite(
+            State(AppState::default()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await;
+        assert_eq!(result.unwrap_err().status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_run_definition_inherits_definition() {
+        let (state, _backend) = fake_state();
+        let definition = create_test_definition(&state, "Smoke", "smoke").await;
+
+        let (_, Json(run)) = run_definition(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(definition.id),
+        )
+        .await
+        .unwrap();
+        assert_eq!(run.definition_id, Some(definition.id));
+        assert_eq!(run.image, "smoke");
+        assert_eq!(wait_for_finish(&state, run.id).await.status, "succeeded");
+    }
+
+    #[tokio::test]
+    async fn test_run_lifecycle_is_published() {
+        let (state, _backend) = fake_state();
+        let definition = create_test_definition(&state, "Smoke", "smoke").await;
+        let mut events = state.events.subscribe();
+
+        let (_, Json(run)) = run_definition(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(definition.id),
+        )
+        .await
+        .unwrap();
+        wait_for_finish(&state, run.id).await;
+
+        let mut kinds = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            assert_eq!(event.run_id, Some(run.id));
+            assert_eq!(event.definition_id, Some(definition.id));
+            kinds.push(event.kind.name());
+        }
+        assert_eq!(kinds.first(), Some(&"run_created"));
+        assert_eq!(kinds.get(1), Some(&"status_changed"));
+        assert!(kinds.contains(&"lifecycle_timestamp"));
+        assert_eq!(
+            &kinds[kinds.len() - 2..],
+            ["status_changed", "lifecycle_timestamp"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_live_and_stored_run_logs() {
+        let (state, backend) = fake_state();
+        backend.script(
+            "k6",
+            FakeScript::succeed(&["iteration 1", "iteration 2"])
+                .with_duration(Duration::from_millis(300)),
+        );
+
+        let request = CreateRunRequest {
+            name: "Load Test".to_string(),
+            image: "k6".to_string(),
+            ..Default::default()
+        };
+        let run = create_run(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap()
+        .0;
+
+        let running = wait_for_status(&state, run.id, &["running"]).await;
+        let logs = get_run_logs(State(state.clone()), Principal::anonymous(), Path(run.id))
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(logs["source"], "live");
+        assert_eq!(logs["status"], "running");
+
+        let job_name = running.k8s_job_name.unwrap();
+        let job_logs = get_job_logs(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(job_name.clone()),
+            Query(TargetQuery::default()),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(job_logs["job_name"], job_name);
+        assert_eq!(job_logs["pod_name"], format!("{job_name}-pod"));
+
+        wait_for_finish(&state, run.id).await;
+        let logs = get_run_logs(State(state.clone()), Principal::anonymous(), Path(run.id))
+            .await
+            .unwrap()
+            .0;
+        assert_eq!(logs["source"], "stored");
+        assert_eq!(
+            logs["logs"],
+            serde_json::json!(["iteration 1", "iteration 2"])
+        );
+
+        let status = get_job_status(
+            State(state),
+            Principal::anonymous(),
+            Path(job_name),
+            Query(TargetQuery::default()),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(status["status"], "completed");
+    }
+
+    #[tokio::test]
+    async fn test_deleting_running_run_deletes_job() {
+        let (state, backend) = fake_state();
+        backend.script(
+            "slow",
+            FakeScript::succeed(&[]).with_duration(Duration::from_secs(30)),
+        );
+
+        let request = CreateRunRequest {
+            name: "Slow Tests".to_string(),
+            image: "slow".to_string(),
+            ..Default::default()
+        };
+        let run = create_run(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap()
+        .0;
+        wait_for_status(&state, run.id, &["running"]).await;
+
+        let result = delete_run(State(state.clone()), Principal::anonymous(), Path(run.id)).await;
+        assert_eq!(result.unwrap(), StatusCode::NO_CONTENT);
+        assert_eq!(backend.deleted_jobs(), vec![format!("test-run-{}", run.id)]);
+        assert!(state.store.get_run(run.id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_k8s_health_with_fake_backend() {
+        let (state, backend) = fake_state();
+
+        let value = k8s_health(State(state.clone())).await.0;
+        assert_eq!(value["backend"], "fake");
+        assert_eq!(value["kubernetes_connected"], true);
+        assert!(value.get("error").is_none());
+
+        backend.set_healthy(false);
+        let value = k8s_health(State(state)).await.0;
+        assert_eq!(value["kubernetes_connected"], false);
+        assert!(value["error"].is_string());
+    }
+
+    /// A user holding `role` in a fresh team, returned as the principal they authenticate as
+    async fn team_member(state: &AppState, role: Role) -> (Principal, Uuid) {
+        let (_, Json(user)) = create_user(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(CreateUserRequest {
+                name: format!("{role:?}").to_lowercase(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        let (_, Json(team)) = create_team(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(CreateTeamRequest {
+                name: "payments".to_string(),
+                members: vec![TeamMember {
+                    user_id: user.id,
+                    role,
+                }],
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+
+        let principal = Principal {
+            token_id: None,
+            user_id: Some(user.id),
+            name: user.name,
+            scope: TokenScope::Admin,
+            is_admin: false,
+            roles: state.store.roles_of(user.id).await,
+        };
+        (principal, team.id)
+    }
+
+    async fn create_team_definition(state: &AppState, team_id: Uuid) -> TestDefinition {
+        let request = CreateDefinitionRequest {
+            name: "Team Tests".to_string(),
+            image: "node:18".to_string(),
+            team_id: Some(team_id),
+            ..Default::default()
+        };
+        let (_, Json(definition)) = create_definition(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap();
+        definition
+    }
+
+    #[tokio::test]
+    async fn test_viewer_cannot_run_or_change_team_resources() {
+        let (state, _) = fake_state();
+        let (viewer, team_id) = team_member(&state, Role::Viewer).await;
+        let definition = create_team_definition(&state, team_id).await;
+
+        let fetched = get_definition(State(state.clone()), viewer.clone(), Path(definition.id))
+            .await
+            .unwrap();
+        assert_eq!(fetched.0.id, definition.id);
+
+        let error = run_definition(State(state.clone()), viewer.clone(), Path(definition.id))
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Forbidden);
+        let details = error.details.unwrap();
+        assert_eq!(details["actor"], "viewer");
+        assert_eq!(details["action"], "run");
+        assert_eq!(details["resource_type"], "test_definition");
+        assert_eq!(details["resource_id"], definition.id.to_string());
+        assert_eq!(details["required_role"], "runner");
+
+        let request = CreateDefinitionRequest {
+            name: "Sneaky".to_string(),
+            image: "alpine".to_string(),
+            team_id: Some(team_id),
+            ..Default::default()
+        };
+        let error = create_definition(State(state), viewer, JsonBody(request))
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn test_runner_runs_belong_to_the_team() {
+        let (state, _) = fake_state();
+        let (runner, team_id) = team_member(&state, Role::Runner).await;
+        let definition = create_team_definition(&state, team_id).await;
+
+        let (_, Json(run)) =
+            run_definition(State(state.clone()), runner.clone(), Path(definition.id))
+                .await
+                .unwrap();
+        assert_eq!(run.team_id, Some(team_id));
+        assert_eq!(run.triggered_by.as_deref(), Some("runner"));
+        wait_for_finish(&state, run.id).await;
+
+        let result = delete_run(State(state.clone()), runner, Path(run.id)).await;
+        assert_eq!(result.unwrap(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn test_other_teams_cannot_see_runs() {
+        let (state, _) = fake_state();
+        let (owner, team_id) = team_member(&state, Role::Maintainer).await;
+        let (outsider, _) = team_member(&state, Role::Admin).await;
+        let definition = create_team_definition(&state, team_id).await;
+
+        let (_, Json(run)) =
+            run_definition(State(state.clone()), owner.clone(), Path(definition.id))
+                .await
+                .unwrap();
+
+        let visible = get_runs(State(state.clone()), owner, Query(RunListQuery::default()))
+            .await
+            .unwrap()
+            .runs;
+        assert_eq!(visible.len(), 1);
+        let visible = get_runs(
+            State(state.clone()),
+            outsider.clone(),
+            Query(RunListQuery::default()),
+        )
+        .await
+        .unwrap()
+        .runs;
+        assert!(visible.is_empty());
+        let definitions = get_definitions(State(state.clone()), outsider.clone())
+            .await
+            .unwrap()
+            .0;
+        assert!(definitions.is_empty());
+
+        let error = delete_run(State(state.clone()), outsider.clone(), Path(run.id))
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Forbidden);
+        assert!(state.store.get_run(run.id).await.is_some());
+
+        let job_name = format!("test-run-{}", run.id);
+        let error = delete_job(
+            State(state.clone()),
+            outsider,
+            Path(job_name),
+            Query(TargetQuery::default()),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn test_users_are_visible_within_teams() {
+        let (state, _) = fake_state();
+        let (member, _) = team_member(&state, Role::Viewer).await;
+        let (outsider, _) = team_member(&state, Role::Admin).await;
+        let member_id = member.user_id.unwrap();
+
+        let users = get_users(State(state.clone()), member.clone()).await.0;
+        assert_eq!(
+            users.iter().map(|user| user.id).collect::<Vec<_>>(),
+            vec![member_id]
+        );
+        let all = get_users(State(state.clone()), Principal::anonymous())
+            .await
+            .0;
+        assert_eq!(all.len(), 2);
+
+        assert!(
+            get_user(State(state.clone()), member.clone(), Path(member_id))
+                .await
+                .is_ok()
+        );
+        let error = get_user(State(state.clone()), outsider, Path(member_id))
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Forbidden);
+
+        let no_team = Principal {
+            roles: Default::default(),
+            ..member
+        };
+        let error = get_targets(State(state.clone()), no_team.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Forbidden);
+        assert!(get_admission_policy(State(state), no_team).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_user_emails_are_unique() {
+        let (state, _) = fake_state();
+        let request = || CreateUserRequest {
+            name: "alice".to_string(),
+            email: Some("alice@example.com".to_string()),
+            ..Default::default()
+        };
+        let _ = create_user(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request()),
+        )
+        .await
+        .unwrap();
+
+        let error = create_user(State(state), Principal::anonymous(), JsonBody(request()))
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Conflict);
+    }
+
+    #[tokio::test]
+    async fn test_team_admin_manages_members() {
+        let (state, _) = fake_state();
+        let (team_admin, team_id) = team_member(&state, Role::Admin).await;
+        let (_, Json(user)) = create_user(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(CreateUserRequest {
+                name: "bob".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+
+        let team = set_team_member(
+            State(state.clone()),
+            team_admin.clone(),
+            Path((team_id, user.id)),
+            JsonBody(SetMemberRequest { role: Role::Runner }),
+        )
+        .await
+        .unwrap()
+        .0;
+        assert_eq!(team.role_of(user.id), Some(Role::Runner));
+
+        // Only instance admins create users, teams and targets
+        let error = create_team(
+            State(state.clone()),
+            team_admin,
+            JsonBody(CreateTeamRequest {
+                name: "other".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Forbidden);
+        assert_eq!(error.details.unwrap()["required_role"], "instance_admin");
+    }
+
+    #[tokio::test]
+    async fn test_admission_policy_rejects_runs() {
+        let (state, _) = fake_state();
+        *state.policy.write().await = AdmissionPolicy {
+            allowed_images: vec!["docker.io/library/*".to_string()],
+            forbid_latest: true,
+            ..Default::default()
+        };
+
+        let request = CreateRunRequest {
+            name: "Miner".to_string(),
+            image: "evil.io/miner:latest".to_string(),
+            commands: vec!["mine".to_string()],
+            privileged: true,
+            ..Default::default()
+        };
+        let error = create_run(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::PolicyViolation);
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let details = error.details.unwrap();
+        assert_eq!(details["violations"].as_array().unwrap().len(), 3);
+        assert!(state.store.list_runs().await.is_empty());
+
+        let request = CreateRunRequest {
+            name: "Unit".to_string(),
+            image: "python:3.11".to_string(),
+            commands: vec!["pytest".to_string()],
+            ..Default::default()
+        };
+        assert!(create_run(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request)
+        )
+        .await
+        .is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_tightened_policy_blocks_whole_suite() {
+        let (state, _) = fake_state();
+        let pinned = create_test_definition(&state, "Pinned", "python:3.11").await;
+        let floating = create_test_definition(&state, "Floating", "python").await;
+        let (_, Json(suite)) = create_suite(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(CreateSuiteRequest {
+                name: "CI".to_string(),
+                test_definition_ids: vec![pinned.id, floating.id],
+                execution_mode: "parallel".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+
+        let Json(policy) = update_admission_policy(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(AdmissionPolicy {
+                forbid_latest: true,
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert!(policy.forbid_latest);
+
+        let error = run_suite(State(state.clone()), Principal::anonymous(), Path(suite.id))
+            .await
+            .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::PolicyViolation);
+        assert!(error.message.starts_with("Test definition 'Floating'"));
+        assert!(state.store.list_runs().await.is_empty());
+
+        let error = run_definition(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(floating.id),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::PolicyViolation);
+    }
+
+    #[tokio::test]
+    async fn test_only_admins_change_admission_policy() {
+        let (state, _) = fake_state();
+        let (maintainer, _) = team_member(&state, Role::Maintainer).await;
+        let error = update_admission_policy(
+            State(state.clone()),
+            maintainer,
+            JsonBody(AdmissionPolicy {
+                allow_privileged: true,
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Forbidden);
+        assert!(
+            !get_admission_policy(State(state), Principal::anonymous())
+                .await
+                .unwrap()
+                .0
+                .allow_privileged
+        );
+    }
+
+    fn token_ref() -> SecretRef {
+        SecretRef {
+            secret: "api-credentials".to_string(),
+            key: "token".to_string(),
+            env: Some("API_TOKEN".to_string()),
+            path: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_secret_values_are_redacted_from_logs() {
+        let (state, backend) = fake_state();
+        backend.set_secret("api-credentials", "token", "s3cr3t-token");
+        backend.script(
+            "newman",
+            FakeScript::succeed(&["GET /users with Bearer s3cr3t-token", "1 passed"]),
+        );
+
+        let (_, Json(definition)) = create_definition(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(CreateDefinitionRequest {
+                name: "API".to_string(),
+                image: "newman".to_string(),
+                commands: vec!["newman".to_string()],
+                secrets: vec![token_ref()],
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(definition.secrets, [token_ref()]);
+        assert!(!serde_json::to_string(&definition)
+            .unwrap()
+            .contains("s3cr3t"));
+
+        let (_, Json(run)) = run_definition(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(definition.id),
+        )
+        .await
+        .unwrap();
+        let run = wait_for_finish(&state, run.id).await;
+        assert_eq!(run.status, "succeeded");
+        assert_eq!(
+            run.logs.unwrap(),
+            ["GET /users with Bearer [REDACTED]", "1 passed"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_missing_secret_fails_run() {
+        let (state, backend) = fake_state();
+        let request = CreateRunRequest {
+            name: "API".to_string(),
+            image: "newman".to_string(),
+            commands: vec!["newman".to_string()],
+            secrets: vec![token_ref()],
+            ..Default::default()
+        };
+        let Json(run) = create_run(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap();
+
+        let run = wait_for_finish(&state, run.id).await;
+        assert_eq!(run.status, "failed");
+        assert!(run.logs.unwrap()[0].contains("has no key 'token'"));
+        assert!(backend.created_jobs().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_team_runs_only_use_their_team_secrets() {
+        let (state, backend) = fake_state();
+        backend.set_secret("api-credentials", "token", "s3cr3t-token");
+        let (runner, team_id) = team_member(&state, Role::Runner).await;
+        let request = || CreateRunRequest {
+            name: "API".to_string(),
+            image: "newman".to_string(),
+            commands: vec!["newman".to_string()],
+            secrets: vec![token_ref()],
+            team_id: Some(team_id),
+            ..Default::default()
+        };
+
+        let Json(run) = create_run(State(state.clone()), runner.clone(), JsonBody(request()))
+            .await
+            .unwrap();
+        let run = wait_for_finish(&state, run.id).await;
+        assert_eq!(run.status, "failed");
+        assert!(run.logs.unwrap()[0].contains("is not available to team"));
+        assert!(backend.created_jobs().is_empty());
+
+        backend.set_secret_team("api-credentials", &team_id.to_string());
+        let Json(run) = create_run(State(state.clone()), runner, JsonBody(request()))
+            .await
+            .unwrap();
+        let run = wait_for_finish(&state, run.id).await;
+        assert_eq!(run.status, "succeeded");
+    }
+
+    #[tokio::test]
+    async fn test_runs_wait_for_a_free_slot() {
+        let backend = Arc::new(FakeBackend::new(
+            FakeScript::succeed(&["ok"]).with_duration(Duration::from_millis(200)),
+        ));
+        let mut state = AppState::new(backend.clone());
+        state.runner.poll_interval = Duration::from_millis(5);
+        state.queue = RunQueue::new(QueueConfig {
+            max_concurrent: Some(1),
+            ..Default::default()
+        });
+        let create = |name: &str, priority: RunPriority| {
+            let request = CreateRunRequest {
+                name: name.to_string(),
+                image: "node:20".to_string(),
+                commands: vec!["npm test".to_string()],
+                priority,
+                ..Default::default()
+            };
+            create_run(
+                State(state.clone()),
+                Principal::anonymous(),
+                JsonBody(request),
+            )
+        };
+
+        let Json(first) = create("first", RunPriority::Normal).await.unwrap();
+        wait_for_status(&state, first.id, &["running"]).await;
+        let Json(low) = create("low", RunPriority::Low).await.unwrap();
+        let Json(high) = create("high", RunPriority::High).await.unwrap();
+        let Json(dropped) = create("dropped", RunPriority::Normal).await.unwrap();
+        sleep(Duration::from_millis(20)).await;
+
+        let Json(queue) = get_queue(State(state.clone()), Principal::anonymous()).await;
+        assert_eq!(queue.running, 1);
+        let waiting: Vec<Uuid> = queue.waiting.iter().map(|run| run.id).collect();
+        assert_eq!(waiting, vec![high.id, dropped.id, low.id]);
+        let Json(run) = get_run(State(state.clone()), Principal::anonymous(), Path(low.id))
+            .await
+            .unwrap();
+        assert_eq!(
+            (run.status.as_str(), run.queue_position),
+            ("pending", Some(3))
+        );
+
+        delete_run(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(dropped.id),
+        )
+        .await
+        .unwrap();
+        for run in [&first, &high, &low] {
+            wait_for_finish(&state, run.id).await;
+        }
+        let expected: Vec<String> = [&first, &high, &low]
+            .iter()
+            .map(|run| format!("test-run-{}", run.id))
+            .collect();
+        assert_eq!(backend.created_jobs(), expected);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_secret_refs_are_rejected() {
+        let (state, _) = fake_state();
+        let error = create_definition(
+            State(state),
+            Principal::anonymous(),
+            JsonBody(CreateDefinitionRequest {
+                name: "API".to_string(),
+                image: "newman".to_string(),
+                variables: Some(serde_json::json!({ "API_TOKEN": "plain" })),
+                secrets: vec![token_ref()],
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Validation);
+    }
+
+    #[tokio::test]
+    async fn test_git_source_is_inherited_by_runs() {
+        let (state, _) = fake_state();
+        let source = GitSource {
+            url: "https://github.com/example/tests.git".to_string(),
+            git_ref: Some("main".to_string()),
+            subdirectory: Some("e2e".to_string()),
+        };
+        let (_, Json(definition)) = create_definition(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(CreateDefinitionRequest {
+                name: "E2E".to_string(),
+                image: "node:20".to_string(),
+                commands: vec!["npm".to_string(), "test".to_string()],
+                source: Some(source.clone()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+
+        let (_, Json(run)) = run_definition(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(definition.id),
+        )
+        .await
+        .unwrap();
+        assert_eq!(run.source, Some(source));
+
+        let error = create_definition(
+            State(state),
+            Principal::anonymous(),
+            JsonBody(CreateDefinitionRequest {
+                name: "E2E".to_string(),
+                image: "node:20".to_string(),
+                source: Some(GitSource {
+                    url: "--upload-pack=id".to_string(),
+                    git_ref: None,
+                    subdirectory: None,
+                }),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Validation);
+    }
+
+    /// A multipart body of `(field, file name, content)` parts
+    async fn multipart(parts: &[(&str, Option<&str>, &[u8])]) -> Multipart {
+        use axum::extract::FromRequest;
+
+        let boundary = "sparktest-boundary";
+        let mut body = Vec::new();
+        for (field, file_name, content) in parts {
+            body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+            let disposition = match file_name {
+                Some(file_name) => {
+                    format!("form-data; name=\"{field}\"; filename=\"{file_name}\"")
+                }
+                None => format!("form-data; name=\"{field}\""),
+            };
+            body.extend_from_slice(
+                format!("Content-Disposition: {disposition}\r\n\r\n").as_bytes(),
+            );
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+
+        let request = axum::http::Request::builder()
+            .method("POST")
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={boundary}"),
+            )
+            .body(axum::body::Body::from(body))
+            .unwrap();
+        Multipart::from_request(request, &()).await.ok().unwrap()
+    }
+
+    async fn insert_k6_executor(state: &AppState) {
+        state
+            .store
+            .insert_executor(Executor {
+                id: "k6".to_string(),
+                name: "K6".to_string(),
+                image: "grafana/k6:0.52.0".to_string(),
+                description: None,
+                command: None,
+                supported_file_types: Some(vec!["js".to_string()]),
+                env: None,
+                created_at: chrono::Utc::now(),
+                team_id: None,
+            })
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_definition_files_are_copied_to_runs() {
+        let (state, backend) = fake_state();
+        insert_k6_executor(&state).await;
+        let (_, Json(definition)) = create_definition(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(CreateDefinitionRequest {
+                name: "Load".to_string(),
+                image: "grafana/k6:0.52.0".to_string(),
+                commands: vec!["k6".to_string(), "run".to_string()],
+                executor_id: Some("k6".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+
+        let error = upload_definition_files(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(definition.id),
+            multipart(&[("file", Some("notes.txt"), b"hello")]).await,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Validation);
+
+        let (status, Json(definition)) = upload_definition_files(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(definition.id),
+            multipart(&[("file", Some("load.js"), b"export default function () {}")]).await,
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(definition.files.len(), 1);
+        assert_eq!(definition.files[0].size, 29);
+
+        let (_, Json(run)) = run_definition(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(definition.id),
+        )
+        .await
+        .unwrap();
+        assert_eq!(run.files, definition.files);
+        let run = wait_for_finish(&state, run.id).await;
+        let job_files = backend.job_files(run.k8s_job_name.as_deref().unwrap());
+        assert_eq!(job_files["load.js"], b"export default function () {}");
+
+        // Removing the file from the definition leaves the run's copy alone
+        delete_definition_file(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path((definition.id, "load.js".to_string())),
+        )
+        .await
+        .unwrap();
+        assert!(state
+            .store
+            .get_definition(definition.id)
+            .await
+            .unwrap()
+            .files
+            .is_empty());
+        let response = get_run_file(
+            State(state),
+            Principal::anonymous(),
+            Path((run.id, "load.js".to_string())),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_create_run_with_files() {
+        let (state, backend) = fake_state();
+        let run_field = serde_json::json!({
+            "name": "Smoke",
+            "image": "alpine",
+            "commands": ["sh", "/sparktest/files/smoke.sh"],
+        })
+        .to_string();
+
+        let (_, Json(run)) = create_run_with_files(
+            State(state.clone()),
+            Principal::anonymous(),
+            multipart(&[
+                ("run", None, run_field.as_bytes()),
+                ("file", Some("smoke.sh"), b"echo ok"),
+            ])
+            .await,
+        )
+        .await
+        .unwrap();
+        assert_eq!(run.files[0].name, "smoke.sh");
+        let run = wait_for_finish(&state, run.id).await;
+        let job_files = backend.job_files(run.k8s_job_name.as_deref().unwrap());
+        assert_eq!(job_files["smoke.sh"], b"echo ok");
+
+        let error = create_run_with_files(
+            State(state.clone()),
+            Principal::anonymous(),
+            multipart(&[("file", Some("smoke.sh"), b"echo ok")]).await,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Validation);
+
+        let state = AppState {
+            files: crate::files::FileLimits {
+                max_file_bytes: 4,
+                ..Default::default()
+            },
+            ..state
+        };
+        let error = create_run_with_files(
+            State(state),
+            Principal::anonymous(),
+            multipart(&[
+                ("run", None, run_field.as_bytes()),
+                ("file", Some("smoke.sh"), b"echo ok"),
+            ])
+            .await,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::PayloadTooLarge);
+    }
+
+    async fn github_delivery(
+        state: &AppState,
+        event: &str,
+        payload: serde_json::Value,
+    ) -> Result<Json<WebhookResponse>, ApiError> {
+        use hmac::Mac;
+
+        let body = payload.to_string();
+        let mut mac =
+            hmac::Hmac::<sha2::Sha256>::new_from_slice(b"s3cret").expect("any key length works");
+        mac.update(body.as_bytes());
+        let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-github-event", event.parse().unwrap());
+        headers.insert("x-hub-signature-256", signature.parse().unwrap());
+        receive_github_webhook(State(state.clone()), headers, Bytes::from(body)).await
+    }
+
+    #[tokio::test]
+    async fn test_pull_request_webhook_starts_matching_rules() {
+        let (mut state, _) = fake_state();
+        state.webhooks.secret = Some("s3cret".to_string());
+        let (_, Json(definition)) = create_definition(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(CreateDefinitionRequest {
+                name: "React Component Unit Tests".to_string(),
+                image: "node:20".to_string(),
+                commands: vec!["npm".to_string(), "test".to_string()],
+                source: Some(GitSource {
+                    url: "https://github.com/example/app.git".to_string(),
+                    git_ref: Some("main".to_string()),
+                    subdirectory: None,
+                }),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        let (_, Json(rule)) = create_trigger_rule(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(CreateTriggerRuleRequest {
+                name: "PRs against main".to_string(),
+                repository: "example/app".to_string(),
+                branches: vec!["main".to_string()],
+                events: vec![GitEventKind::PullRequest],
+                test_definition_id: Some(definition.id),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+
+        let pull_request = serde_json::json!({
+            "action": "opened",
+            "number": 247,
+            "pull_request": {
+                "head": { "sha": "e5bd3914e2e596debea16f433f57875b5b90bcd6" },
+                "base": { "ref": "main" },
+            },
+            "repository": { "full_name": "example/app" },
+            "sender": { "login": "octocat" },
+        });
+        let Json(response) = github_delivery(&state, "pull_request", pull_request)
+            .await
+            .unwrap();
+        assert!(response.errors.is_empty());
+        assert_eq!(response.runs.len(), 1);
+        let run = wait_for_finish(&state, response.runs[0].id).await;
+        assert_eq!(run.name, "React Component Unit Tests - PR #247");
+        assert_eq!(run.triggered_by.as_deref(), Some("octocat"));
+        let trigger = run.trigger.unwrap();
+        assert_eq!(trigger.pull_request, Some(247));
+        assert_eq!(
+            trigger.commit_sha,
+            "e5bd3914e2e596debea16f433f57875b5b90bcd6"
+        );
+        assert_eq!(trigger.rule_id, Some(rule.id));
+        // The checkout follows the pull request's head commit
+        assert_eq!(
+            run.source.unwrap().git_ref.as_deref(),
+            Some("e5bd3914e2e596debea16f433f57875b5b90bcd6")
+        );
+
+        // Pushes don't match a pull-request-only rule
+        let push = serde_json::json!({
+            "ref": "refs/heads/main",
+            "after": "6dcb09b5b57875f334f61aebed695e2e4193db5e",
+            "repository": { "full_name": "example/app" },
+        });
+        let Json(response) = github_delivery(&state, "push", push).await.unwrap();
+        assert_eq!(response.trigger.unwrap().branch, "main");
+        assert!(response.runs.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_trigger_rules_need_a_runnable_target() {
+        let (state, _) = fake_state();
+        let definition = create_test_definition(&state, "Smoke", "alpine").await;
+
+        let both = CreateTriggerRuleRequest {
+            name: "Everything".to_string(),
+            repository: "example/app".to_string(),
+            test_definition_id: Some(definition.id),
+            test_suite_id: Some(Uuid::new_v4()),
+            ..Default::default()
+        };
+        let error =
+            create_trigger_rule(State(state.clone()), Principal::anonymous(), JsonBody(both))
+                .await
+                .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Validation);
+
+        let (viewer, team_id) = team_member(&state, Role::Viewer).await;
+        let definition = create_team_definition(&state, team_id).await;
+        let error = create_trigger_rule(
+            State(state),
+            viewer,
+            JsonBody(CreateTriggerRuleRequest {
+                name: "Team tests".to_string(),
+                repository: "example/app".to_string(),
+                test_definition_id: Some(definition.id),
+                team_id: Some(team_id),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn test_status_reporter_token_is_write_only() {
+        let (state, _) = fake_state();
+        let request = CreateStatusReporterRequest {
+            name: "example".to_string(),
+            provider: "github".to_string(),
+            repository: "example/*".to_string(),
+            context: "sparktest".to_string(),
+            token: "ghp_s3cret".to_string(),
+            ..Default::default()
+        };
+        let (_, Json(reporter)) = create_status_reporter(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap();
+        assert_eq!(reporter.token, "ghp_s3cret");
+        let shown = serde_json::to_value(&reporter).unwrap();
+        assert!(shown.get("token").is_none());
+        assert_eq!(shown["repository"], "example/*");
+
+        let error = create_status_reporter(
+            State(state),
+            Principal::anonymous(),
+            JsonBody(CreateStatusReporterRequest {
+                name: "bitbucket".to_string(),
+                provider: "bitbucket".to_string(),
+                repository: "example/app".to_string(),
+                context: "sparktest".to_string(),
+                token: "token".to_string(),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Validation);
+    }
+
+    #[tokio::test]
+    async fn test_schedule_lifecycle() {
+        let (state, _) = fake_state();
+        let definition = create_test_definition(&state, "OWASP Security Scan", "zaproxy").await;
+        let request = ScheduleRequest {
+            name: "Nightly scan".to_string(),
+            cron: "0 3 * * *".to_string(),
+            timezone: "Europe/Oslo".to_string(),
+            test_definition_id: Some(definition.id),
+            enabled: true,
+            ..Default::default()
+        };
+        let (status, Json(schedule)) = create_schedule(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(request),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(schedule.next_run_at.unwrap() > schedule.created_at);
+
+        let Json(updated) = update_schedule(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(schedule.id),
+            JsonBody(ScheduleRequest {
+                name: "Nightly scan".to_string(),
+                cron: "0 3 * * *".to_string(),
+                timezone: "Europe/Oslo".to_string(),
+                test_definition_id: Some(definition.id),
+                enabled: false,
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated.created_at, schedule.created_at);
+        assert!(!updated.enabled);
+        assert!(updated.next_run_at.is_none());
+
+        let status = delete_schedule(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(schedule.id),
+        )
+        .await
+        .unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert!(state.store.list_schedules().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_schedule_validation() {
+        let (state, _) = fake_state();
+        let definition = create_test_definition(&state, "K6 Load Test", "grafana/k6").await;
+        let invalid = [
+            ("0 3 * *", "UTC", Some(definition.id)),
+            ("0 3 * * *", "Nowhere/Special", Some(definition.id)),
+            ("0 3 * * *", "UTC", None),
+        ];
+        for (cron, timezone, test_definition_id) in invalid {
+            let error = create_schedule(
+                State(state.clone()),
+                Principal::anonymous(),
+                JsonBody(ScheduleRequest {
+                    name: "nightly".to_string(),
+                    cron: cron.to_string(),
+                    timezone: timezone.to_string(),
+                    test_definition_id,
+                    enabled: true,
+                    ..Default::default()
+                }),
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(error.error_type, ErrorType::Validation, "{cron} {timezone}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_viewer_cannot_schedule_team_definition() {
+        let (state, _) = fake_state();
+        let (viewer, team_id) = team_member(&state, Role::Viewer).await;
+        let definition = create_team_definition(&state, team_id).await;
+
+        let error = create_schedule(
+            State(state),
+            viewer,
+            JsonBody(ScheduleRequest {
+                name: "nightly".to_string(),
+                cron: "0 3 * * *".to_string(),
+                timezone: "UTC".to_string(),
+                test_definition_id: Some(definition.id),
+                enabled: true,
+                team_id: Some(team_id),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Forbidden);
+    }
+
+    #[tokio::test]
+    async fn test_notification_channel_password_is_write_only() {
+        let (state, _) = fake_state();
+        let config: ChannelConfig = serde_json::from_value(serde_json::json!({
+            "type": "email",
+            "smtp_host": "smtp.example.com",
+            "smtp_port": 587,
+            "username": "sparktest",
+            "password": "s3cret",
+            "from": "sparktest@example.com",
+            "to": ["qa@example.com"],
+        }))
+        .unwrap();
+        let (_, Json(channel)) = create_notification_channel(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(CreateNotificationChannelRequest {
+                name: "QA".to_string(),
+                config,
+                team_id: None,
+            }),
+        )
+        .await
+        .unwrap();
+        let shown = serde_json::to_value(&channel).unwrap();
+        assert_eq!(shown["config"]["smtp_tls"], "starttls");
+        assert!(shown["config"].get("password").is_none());
+
+        let error = create_notification_channel(
+            State(state),
+            Principal::anonymous(),
+            JsonBody(CreateNotificationChannelRequest {
+                name: "Slack".to_string(),
+                config: ChannelConfig::Slack {
+                    url: "hooks.slack.com/services/T0/B0/x".to_string(),
+                },
+                team_id: None,
+            }),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.error_type, ErrorType::Validation);
+    }
+
+    #[tokio::test]
+    async fn test_notification_rule_validation() {
+        let (state, _) = fake_state();
+        let (_, Json(channel)) = create_notification_channel(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(CreateNotificationChannelRequest {
+                name: "Slack".to_string(),
+                config: ChannelConfig::Slack {
+                    url: "https://hooks.slack.com/services/T0/B0/x".to_string(),
+                },
+                team_id: None,
+            }),
+        )
+        .await
+        .unwrap();
+
+        let invalid = [
+            CreateNotificationRuleRequest {
+                name: "unknown channel".to_string(),
+                channel_id: Uuid::new_v4(),
+                ..Default::default()
+            },
+            CreateNotificationRuleRequest {
+                name: "bad template".to_string(),
+                channel_id: channel.id,
+                template: Some("{{name}} by {{author}}".to_string()),
+                ..Default::default()
+            },
+        ];
+        for request in invalid {
+            let error = create_notification_rule(
+                State(state.clone()),
+                Principal::anonymous(),
+                JsonBody(request),
+            )
+            .await
+            .unwrap_err();
+            assert_eq!(error.error_type, ErrorType::Validation);
+        }
+
+        let (_, Json(rule)) = create_notification_rule(
+            State(state.clone()),
+            Principal::anonymous(),
+            JsonBody(CreateNotificationRuleRequest {
+                name: "all failures".to_string(),
+                channel_id: channel.id,
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(rule.on, NotifyOn::Failure);
+
+        // Rules go with their channel
+        delete_notification_channel(
+            State(state.clone()),
+            Principal::anonymous(),
+            Path(channel.id),
+        )
+        .await
+        .unwrap();
+        assert!(state.store.get_notification_rule(rule.id).await.is_none());
     }
 }
@@ -0,0 +1,483 @@
+use crate::events::EventKind;
+use crate::job_metadata::{RUN_ID_LABEL, RUN_NAME_ANNOTATION};
+use crate::k8s::{job_phase, KubernetesClient};
+use crate::metrics::metrics;
+use crate::runner::finish_run;
+use crate::state::AppState;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use futures::{future, StreamExt};
+use k8s_openapi::api::batch::v1::Job;
+use kube::api::Api;
+use kube::runtime::controller::{Action, Controller};
+use kube::runtime::watcher;
+use kube::ResourceExt;
+use sparktest_core::{ExecutionTarget, TestRun};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Selects the Jobs SparkTest owns, whoever created them
+pub const JOB_SELECTOR: &str = "app=sparktest";
+
+/// How often each Job is looked at again even if nothing about it changed, so
+/// Jobs are collected after their run is deleted
+const RESYNC: Duration = Duration::from_secs(300);
+/// How soon a Job is retried after reconciling it failed
+const RETRY: Duration = Duration::from_secs(30);
+/// How often targets are checked for ones registered or removed since, and for
+/// controllers that stopped
+const TARGET_POLL: Duration = Duration::from_secs(30);
+
+/// What reconciling a Job did
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobReconciled {
+    /// The runner in this process follows the Job's run
+    Followed(Uuid),
+    /// The run was brought up to date with the Job
+    Synced(Uuid),
+    /// The Job had no run, so one was created for it
+    Adopted(Uuid),
+    /// The Job's run was deleted, so the Job was too
+    Deleted,
+}
+
+/// Bring SparkTest in line with one of its Jobs. A Job is only deleted when its
+/// run is known to have been deleted; a Job whose run the store doesn't know
+/// (e.g. one from before runs were stored) is adopted under its run id.
+pub async fn reconcile_job(
+    state: &AppState,
+    target: &ExecutionTarget,
+    job: &Job,
+) -> Result<JobReconciled> {
+    let name = job.name_any();
+    // Jobs from before runs were labelled are matched by name
+    let labelled_run = job
+        .labels()
+        .get(RUN_ID_LABEL)
+        .and_then(|id| id.parse::<Uuid>().ok());
+    let found = match labelled_run {
+        Some(run_id) => state.store.get_run(run_id).await,
+        None => state.store.find_run_by_job(&name).await,
+    };
+    let run_id = labelled_run.or_else(|| run_id_from_name(&name));
+    let (run, adopted) = match (found, run_id) {
+        (Some(run), _) => (run, false),
+        (None, Some(run_id)) if state.store.was_deleted(run_id).await => {
+            state.backend.delete_job(target, &name).await?;
+            state.events.publish_job_deleted(&name, None);
+            info!("Deleted job '{}' whose run was deleted", name);
+            return Ok(JobReconciled::Deleted);
+        }
+        (None, run_id) => (adopt(state, target, job, run_id).await?, true),
+    };
+
+    if state.queue.contains(run.id) {
+        return Ok(JobReconciled::Followed(run.id));
+    }
+    if !matches!(run.status.as_str(), "succeeded" | "failed") {
+        sync_run(state, target, job, &run).await;
+    }
+    Ok(if adopted {
+        JobReconciled::Adopted(run.id)
+    } else {
+        JobReconciled::Synced(run.id)
+    })
+}
+
+/// The run of a Job the runner created, which are named after their run unlike
+/// Jobs created elsewhere
+fn run_id_from_name(job_name: &str) -> Option<Uuid> {
+    job_name
+        .strip_prefix("test-run-")
+        .and_then(|rest| rest.get(..36))
+        .and_then(|id| id.parse().ok())
+}
+
+/// Record a run for a Job the store has no run for: one created outside the API,
+/// such as with `kubectl`, or one whose run was lost when the server restarted
+async fn adopt(
+    state: &AppState,
+    target: &ExecutionTarget,
+    job: &Job,
+    run_id: Option<Uuid>,
+) -> Result<TestRun> {
+    let container = job
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.template.spec.as_ref())
+        .and_then(|pod| pod.containers.first());
+    let commands = container
+        .map(|container| {
+            let command = container.command.iter().flatten();
+            command
+                .chain(container.args.iter().flatten())
+                .cloned()
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut run = TestRun::new(
+        job.annotations()
+            .get(RUN_NAME_ANNOTATION)
+            .cloned()
+            .unwrap_or_else(|| job.name_any()),
+        container
+            .and_then(|container| container.image.clone())
+            .unwrap_or_default(),
+        commands,
+    );
+    if let Some(run_id) = run_id {
+        run.id = run_id;
+    }
+    if let Some(created) = &job.metadata.creation_timestamp {
+        run.created_at = created.0;
+    }
+    run.k8s_job_name = Some(job.name_any());
+    run.target = Some(target.name.clone());
+
+    state.store.insert_run(run.clone()).await?;
+    state.events.publish_created(&run);
+    metrics().run_created(&run);
+    info!("Adopted job '{}' as run {}", job.name_any(), run.id);
+    Ok(run)
+}
+
+/// Carry the Job's progress over to a run nobody else is following
+async fn sync_run(state: &AppState, target: &ExecutionTarget, job: &Job, run: &TestRun) {
+    let name = job.name_any();
+    let started = job
+        .status
+        .as_ref()
+        .and_then(|status| status.start_time.as_ref())
+        .map(|time| time.0);
+
+    match job_phase(job) {
+        "running" => {
+            let updated = state
+                .store
+                .update_run(run.id, |run| {
+                    run.status = "running".to_string();
+                    run.container_started = run.container_started.or(started);
+                })
+                .await;
+            if let Some(updated) = updated.filter(|updated| updated.status != run.status) {
+                state.events.publish_run(
+                    &updated,
+                    EventKind::StatusChanged {
+                        status: updated.status.clone(),
+                    },
+                );
+            }
+        }
+        phase @ ("completed" | "failed") => {
+            let logs = match state.backend.get_job_logs(target, &name).await {
+                Ok(job_logs) => job_logs.logs.lines().map(str::to_string).collect(),
+                Err(e) => vec![format!("Failed to collect logs: {e:#}")],
+            };
+            let started: DateTime<Utc> = started.unwrap_or(run.created_at);
+            finish_run(state, run.id, phase == "completed", logs, started).await;
+        }
+        _ => {}
+    }
+}
+
+struct JobContext {
+    state: AppState,
+    target: ExecutionTarget,
+}
+
+#[derive(Debug)]
+struct JobReconcileError(anyhow::Error);
+
+impl std::fmt::Display for JobReconcileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#}", self.0)
+    }
+}
+
+impl std::error::Error for JobReconcileError {}
+
+/// Reconcile SparkTest's Jobs in the target's namespace until the process exits
+pub async fn run_job_controller(state: AppState, target: ExecutionTarget) -> Result<()> {
+    let client = KubernetesClient::for_target(&target).await?;
+    let jobs: Api<Job> = Api::namespaced(client.client().clone(), &target.namespace);
+    info!(
+        "Reconciling jobs in namespace '{}' of target '{}'",
+        target.namespace, target.name
+    );
+
+    Controller::new(jobs, watcher::Config::default().labels(JOB_SELECTOR))
+        .run(
+            reconcile,
+            |_, error, _| {
+                warn!("Retrying job in {}s: {}", RETRY.as_secs(), error);
+                Action::requeue(RETRY)
+            },
+            Arc::new(JobContext { state, target }),
+        )
+        .for_each(|result| {
+            if let Err(e) = result {
+                warn!("Failed to reconcile job: {}", e);
+            }
+            future::ready(())
+        })
+        .await;
+    Ok(())
+}
+
+/// Run a job controller for every target, starting one for each target registered
+/// later and stopping those of removed targets, until the process exits. A
+/// controller that stops, e.g. because its cluster is unreachable, is started again.
+pub async fn run_job_controllers(state: AppState) {
+    let mut controllers: HashMap<String, (ExecutionTarget, JoinHandle<()>)> = HashMap::new();
+    let mut poll = tokio::time::interval(TARGET_POLL);
+    loop {
+        poll.tick().await;
+        let targets: HashMap<String, ExecutionTarget> = state
+            .targets
+            .list()
+            .await
+            .into_iter()
+            .map(|target| (target.name.clone(), target))
+            .collect();
+
+        controllers.retain(|name, (running, controller)| {
+            // A target removed and registered again under the same name may point elsewhere
+            let current = targets.get(name).is_some_and(|target| {
+                target.created_at == running.created_at && !controller.is_finished()
+            });
+            if !current {
+                controller.abort();
+            }
+            current
+        });
+        for (name, target) in targets {
+            if controllers.contains_key(&name) {
+                continue;
+            }
+            let (job_state, job_target) = (state.clone(), target.clone());
+            let controller = tokio::spawn(async move {
+                if let Err(e) = run_job_controller(job_state, job_target).await {
+                    warn!("Job controller of target '{}' stopped: {:#}", name, e);
+                }
+            });
+            controllers.insert(target.name.clone(), (target, controller));
+        }
+    }
+}
+
+async fn reconcile(job: Arc<Job>, context: Arc<JobContext>) -> Result<Action, JobReconcileError> {
+    reconcile_job(&context.state, &context.target, &job)
+        .await
+        .map_err(JobReconcileError)?;
+    Ok(Action::requeue(RESYNC))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::{ExecutionBackend, JobSpec};
+    use crate::db::test_database;
+    use crate::fake::{FakeBackend, FakeScript};
+    use crate::k8s::build_k8s_job;
+    use crate::store::Store;
+    use k8s_openapi::api::batch::v1::{JobCondition, JobStatus};
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+
+    async fn fake_state() -> (AppState, Arc<FakeBackend>, ExecutionTarget) {
+        let backend = Arc::new(FakeBackend::new(
+            FakeScript::succeed(&["1 passing"]).with_duration(Duration::ZERO),
+        ));
+        let state = AppState::new(backend.clone());
+        let target = state.targets.resolve(None).await.unwrap();
+        (state, backend, target)
+    }
+
+    /// A Job as the cluster reports it, after the fake backend has created it
+    async fn job(
+        backend: &FakeBackend,
+        target: &ExecutionTarget,
+        name: &str,
+        condition: Option<&str>,
+    ) -> Job {
+        let spec = JobSpec {
+            name: name.to_string(),
+            image: "node:20".to_string(),
+            commands: vec!["npm".to_string(), "test".to_string()],
+            ..Default::default()
+        };
+        backend.create_job(target, &spec).await.unwrap();
+
+        let mut job = build_k8s_job(&spec);
+        job.status = Some(JobStatus {
+            start_time: Some(Time(Utc::now())),
+            conditions: condition.map(|type_| {
+                vec![JobCondition {
+                    type_: type_.to_string(),
+                    status: "True".to_string(),
+                    ..Default::default()
+                }]
+            }),
+            ..Default::default()
+        });
+        job
+    }
+
+    /// A run that was deleted through the store
+    async fn deleted_run(state: &AppState) -> Uuid {
+        let run = TestRun::new("Unit".to_string(), "node:20".to_string(), vec![]);
+        state.store.insert_run(run.clone()).await.unwrap();
+        state.store.remove_run(run.id).await.unwrap();
+        run.id
+    }
+
+    #[tokio::test]
+    async fn test_jobs_of_deleted_runs_are_collected() {
+        let (state, backend, target) = fake_state().await;
+        let name = format!("test-run-{}-retry-1", deleted_run(&state).await);
+        let named = job(&backend, &target, &name, Some("Complete")).await;
+
+        let reconciled = reconcile_job(&state, &target, &named).await.unwrap();
+        assert_eq!(reconciled, JobReconciled::Deleted);
+        assert_eq!(backend.deleted_jobs(), vec![name.clone()]);
+
+        // Whatever it is called, a Job labelled with a run is the run's
+        let mut renamed = job(&backend, &target, "nightly-checkout", None).await;
+        renamed.labels_mut().insert(
+            RUN_ID_LABEL.to_string(),
+            deleted_run(&state).await.to_string(),
+        );
+        let reconciled = reconcile_job(&state, &target, &renamed).await.unwrap();
+        assert_eq!(reconciled, JobReconciled::Deleted);
+        assert_eq!(
+            backend.deleted_jobs(),
+            vec![name, "nightly-checkout".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_jobs_of_runs_deleted_before_a_restart_are_collected() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        let (mut state, backend, target) = fake_state().await;
+        state.store = Store::load(db.clone()).await.unwrap();
+        let run_id = deleted_run(&state).await;
+
+        // The restarted server knows neither the run nor that it was deleted in memory
+        state.store = Store::load(db).await.unwrap();
+        assert!(state.store.get_run(run_id).await.is_none());
+        let name = format!("test-run-{run_id}");
+        let named = job(&backend, &target, &name, None).await;
+        let reconciled = reconcile_job(&state, &target, &named).await.unwrap();
+        assert_eq!(reconciled, JobReconciled::Deleted);
+        assert_eq!(backend.deleted_jobs(), vec![name]);
+    }
+
+    #[tokio::test]
+    async fn test_restart_keeps_the_jobs_of_unknown_runs() {
+        // A fresh server knows none of the runs whose Jobs are still in the cluster
+        let (state, backend, target) = fake_state().await;
+        let named_run = Uuid::new_v4();
+        let named = job(&backend, &target, &format!("test-run-{named_run}"), None).await;
+        let labelled_run = Uuid::new_v4();
+        let mut labelled = job(&backend, &target, "nightly-checkout", Some("Complete")).await;
+        labelled
+            .labels_mut()
+            .insert(RUN_ID_LABEL.to_string(), labelled_run.to_string());
+        labelled
+            .annotations_mut()
+            .insert(RUN_NAME_ANNOTATION.to_string(), "Nightly".to_string());
+
+        assert_eq!(
+            reconcile_job(&state, &target, &named).await.unwrap(),
+            JobReconciled::Adopted(named_run)
+        );
+        assert_eq!(
+            reconcile_job(&state, &target, &labelled).await.unwrap(),
+            JobReconciled::Adopted(labelled_run)
+        );
+        assert!(backend.deleted_jobs().is_empty());
+
+        let run = state.store.get_run(labelled_run).await.unwrap();
+        assert_eq!(run.name, "Nightly");
+        assert_eq!(run.status, "succeeded");
+    }
+
+    #[tokio::test]
+    async fn test_orphan_jobs_are_adopted() {
+        let (state, backend, target) = fake_state().await;
+        let job = job(&backend, &target, "smoke-tests", Some("Complete")).await;
+
+        let JobReconciled::Adopted(run_id) = reconcile_job(&state, &target, &job).await.unwrap()
+        else {
+            panic!("the job was not adopted");
+        };
+        let run = state.store.get_run(run_id).await.unwrap();
+        assert_eq!(run.name, "smoke-tests");
+        assert_eq!(run.image, "node:20");
+        assert_eq!(run.commands, vec!["npm", "test"]);
+        assert_eq!(run.status, "succeeded");
+        assert_eq!(run.logs, Some(vec!["1 passing".to_string()]));
+
+        // Known from then on
+        assert_eq!(
+            reconcile_job(&state, &target, &job).await.unwrap(),
+            JobReconciled::Synced(run_id)
+        );
+        assert!(backend.deleted_jobs().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_runs_nobody_follows_are_updated() {
+        let (state, backend, target) = fake_state().await;
+        let run = TestRun::new("Unit".to_string(), "node:20".to_string(), vec![]);
+        state.store.insert_run(run.clone()).await.unwrap();
+        let name = format!("test-run-{}", run.id);
+
+        // Started, with no condition yet
+        let mut running = job(&backend, &target, &name, None).await;
+        running.status.as_mut().unwrap().conditions = Some(vec![]);
+        reconcile_job(&state, &target, &running).await.unwrap();
+        let updated = state.store.get_run(run.id).await.unwrap();
+        assert_eq!(updated.status, "running");
+        assert!(updated.container_started.is_some());
+
+        let mut failed = running;
+        failed.status.as_mut().unwrap().conditions = Some(vec![JobCondition {
+            type_: "Failed".to_string(),
+            status: "True".to_string(),
+            ..Default::default()
+        }]);
+        assert_eq!(
+            reconcile_job(&state, &target, &failed).await.unwrap(),
+            JobReconciled::Synced(run.id)
+        );
+        assert_eq!(state.store.get_run(run.id).await.unwrap().status, "failed");
+    }
+
+    #[tokio::test]
+    async fn test_runs_followed_by_the_runner_are_left_alone() {
+        let (state, backend, target) = fake_state().await;
+        let run = TestRun::new("Unit".to_string(), "node:20".to_string(), vec![]);
+        state.store.insert_run(run.clone()).await.unwrap();
+        let _slot = state.queue.enter(&run).await.unwrap();
+        let job = job(
+            &backend,
+            &target,
+            &format!("test-run-{}", run.id),
+            Some("Complete"),
+        )
+        .await;
+
+        assert_eq!(
+            reconcile_job(&state, &target, &job).await.unwrap(),
+            JobReconciled::Followed(run.id)
+        );
+        assert_eq!(state.store.get_run(run.id).await.unwrap().status, "pending");
+    }
+}
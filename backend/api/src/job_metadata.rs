@@ -0,0 +1,178 @@
+use crate::error::ApiError;
+use sparktest_core::TestRun;
+use std::collections::BTreeMap;
+
+/// Prefix of the labels and annotations SparkTest sets itself
+pub const JOB_METADATA_PREFIX: &str = "sparktest.dev/";
+
+pub const RUN_ID_LABEL: &str = "sparktest.dev/run-id";
+pub const DEFINITION_ID_LABEL: &str = "sparktest.dev/definition-id";
+pub const SUITE_ID_LABEL: &str = "sparktest.dev/suite-id";
+pub const EXECUTOR_LABEL: &str = "sparktest.dev/executor";
+pub const COMMIT_SHA_LABEL: &str = "sparktest.dev/commit-sha";
+pub const RUN_NAME_ANNOTATION: &str = "sparktest.dev/run-name";
+pub const TRIGGERED_BY_ANNOTATION: &str = "sparktest.dev/triggered-by";
+
+/// Labels every Job gets whatever its run, which extra labels can't replace
+const BASE_LABELS: [&str; 3] = ["app", "component", "job-name"];
+
+/// Labels and annotations for the run's Job: what it belongs to, what started it
+/// and the extras from its definition. Values that aren't valid label values
+/// (e.g. an executor id with spaces) are written as annotations instead.
+pub fn job_metadata(run: &TestRun) -> (BTreeMap<String, String>, BTreeMap<String, String>) {
+    let mut labels = run.job_labels.clone();
+    let mut annotations = run.job_annotations.clone();
+
+    let mut identify = |key: &str, value: String| {
+        if is_valid_label_value(&value) {
+            labels.insert(key.to_string(), value);
+        } else {
+            annotations.insert(key.to_string(), value);
+        }
+    };
+    identify(RUN_ID_LABEL, run.id.to_string());
+    if let Some(definition_id) = run.definition_id {
+        identify(DEFINITION_ID_LABEL, definition_id.to_string());
+    }
+    if let Some(suite_id) = run.suite_id {
+        identify(SUITE_ID_LABEL, suite_id.to_string());
+    }
+    if let Some(executor_id) = &run.executor_id {
+        identify(EXECUTOR_LABEL, executor_id.clone());
+    }
+    if let Some(trigger) = &run.trigger {
+        identify(COMMIT_SHA_LABEL, trigger.commit_sha.clone());
+    }
+
+    annotations.insert(RUN_NAME_ANNOTATION.to_string(), run.name.clone());
+    if let Some(triggered_by) = &run.triggered_by {
+        annotations.insert(TRIGGERED_BY_ANNOTATION.to_string(), triggered_by.clone());
+    }
+    (labels, annotations)
+}
+
+/// Check that extra labels and annotations are valid in Kubernetes and don't
+/// collide with the ones SparkTest sets
+pub fn validate_job_metadata(
+    labels: &BTreeMap<String, String>,
+    annotations: &BTreeMap<String, String>,
+) -> Result<(), ApiError> {
+    for (kind, keys) in [("label", labels.keys()), ("annotation", annotations.keys())] {
+        for key in keys {
+            if !is_valid_key(key) {
+                return Err(ApiError::validation(format!(
+                    "'{key}' is not a valid Kubernetes {kind} key"
+                )));
+            }
+            if key.starts_with(JOB_METADATA_PREFIX) || BASE_LABELS.contains(&key.as_str()) {
+                return Err(ApiError::validation(format!(
+                    "{kind} '{key}' is set by SparkTest"
+                )));
+            }
+        }
+    }
+    for (key, value) in labels {
+        if !is_valid_label_value(value) {
+            return Err(ApiError::validation(format!(
+                "label '{key}': '{value}' is not a valid Kubernetes label value"
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// An optional DNS subdomain prefix and `/`, then a name
+fn is_valid_key(key: &str) -> bool {
+    let (prefix, name) = match key.rsplit_once('/') {
+        Some((prefix, name)) => (Some(prefix), name),
+        None => (None, key),
+    };
+    let valid_prefix = prefix.is_none_or(|prefix| {
+        !prefix.is_empty()
+            && prefix.len() <= 253
+            && prefix
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.')
+            && prefix.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && prefix.ends_with(|c: char| c.is_ascii_alphanumeric())
+    });
+    valid_prefix && !name.is_empty() && is_valid_label_value(name)
+}
+
+/// At most 63 alphanumerics, `-`, `_` and `.`, starting and ending alphanumeric;
+/// may be empty
+fn is_valid_label_value(value: &str) -> bool {
+    value.is_empty()
+        || (value.len() <= 63
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+            && value.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && value.ends_with(|c: char| c.is_ascii_alphanumeric()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sparktest_core::{GitEventKind, RunTrigger};
+    use uuid::Uuid;
+
+    #[test]
+    fn test_job_metadata_identifies_the_run() {
+        let mut run = TestRun::new("Checkout".to_string(), "node:20".to_string(), vec![]);
+        run.definition_id = Some(Uuid::new_v4());
+        run.executor_id = Some("custom runner".to_string());
+        run.trigger = Some(RunTrigger {
+            provider: "github".to_string(),
+            event: GitEventKind::Push,
+            repository: "acme/shop".to_string(),
+            branch: "main".to_string(),
+            commit_sha: "9f2c1e4b7a0d3f6e8c5b2a1d4e7f0a3b6c9d2e5f".to_string(),
+            pull_request: None,
+            sender: Some("octocat".to_string()),
+            rule_id: None,
+        });
+        run.triggered_by = Some("octocat".to_string());
+        run.job_labels = BTreeMap::from([("cost-center".to_string(), "qa".to_string())]);
+
+        let (labels, annotations) = job_metadata(&run);
+        assert_eq!(labels[RUN_ID_LABEL], run.id.to_string());
+        assert_eq!(
+            labels[DEFINITION_ID_LABEL],
+            run.definition_id.unwrap().to_string()
+        );
+        assert!(!labels.contains_key(SUITE_ID_LABEL));
+        assert_eq!(
+            labels[COMMIT_SHA_LABEL],
+            "9f2c1e4b7a0d3f6e8c5b2a1d4e7f0a3b6c9d2e5f"
+        );
+        assert_eq!(labels["cost-center"], "qa");
+        // Not a valid label value
+        assert!(!labels.contains_key(EXECUTOR_LABEL));
+        assert_eq!(annotations[EXECUTOR_LABEL], "custom runner");
+        assert_eq!(annotations[TRIGGERED_BY_ANNOTATION], "octocat");
+        assert_eq!(annotations[RUN_NAME_ANNOTATION], "Checkout");
+    }
+
+    #[test]
+    fn test_validate_job_metadata() {
+        let valid = |labels: &[(&str, &str)], annotations: &[(&str, &str)]| {
+            let map = |pairs: &[(&str, &str)]| {
+                pairs
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect()
+            };
+            validate_job_metadata(&map(labels), &map(annotations)).is_ok()
+        };
+        assert!(valid(
+            &[("acme.com/cost-center", "qa-1"), ("team", "")],
+            &[("owner", "QA team <qa@acme.com>")]
+        ));
+        assert!(!valid(&[("cost center", "qa")], &[]));
+        assert!(!valid(&[("Acme.com/team", "qa")], &[]));
+        assert!(!valid(&[("team", "QA team")], &[]));
+        assert!(!valid(&[("app", "other")], &[]));
+        assert!(!valid(&[], &[("sparktest.dev/run-id", "x")]));
+    }
+}
@@ -1,17 +1,25 @@
+use crate::backend::{JobSpec, SecretValue};
+use crate::files::FILES_DIR;
+use crate::secrets::SECRET_TEAM_LABEL;
+use crate::source::{checkout_env, working_dir, CHECKOUT_SCRIPT, WORKSPACE_DIR};
 use anyhow::{Context, Result};
 use chrono::Utc;
 use k8s_openapi::api::batch::v1::Job;
-use k8s_openapi::api::core::v1::{Container, Pod, PodSpec, PodTemplateSpec};
-use k8s_openapi::apimachinery::pkg::apis::meta::v1::ObjectMeta;
+use k8s_openapi::api::core::v1::{
+    ConfigMap, ConfigMapVolumeSource, Container, EmptyDirVolumeSource, EnvVar, EnvVarSource,
+    KeyToPath, Pod, PodSpec, PodTemplateSpec, ResourceRequirements, Secret, SecretKeySelector,
+    SecretVolumeSource, SecurityContext, Volume, VolumeMount,
+};
+use k8s_openapi::apimachinery::pkg::api::resource::Quantity;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference};
+use k8s_openapi::ByteString;
 use kube::{
     api::{Api, ListParams, LogParams, PostParams},
-    Client, Error as KubeError,
+    Client,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
-use tokio::time::{sleep, Duration};
-use tracing::{info, warn};
-use uuid::Uuid;
+use sparktest_core::{ExecutionTarget, TargetConnection};
+use tracing::{info, instrument, warn};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct KubeConfig {
@@ -30,6 +38,16 @@ impl Default for KubeConfig {
     }
 }
 
+impl From<&ExecutionTarget> for KubeConfig {
+    fn from(target: &ExecutionTarget) -> Self {
+        Self {
+            namespace: target.namespace.clone(),
+            timeout_seconds: target.timeout_seconds,
+            max_log_lines: target.max_log_lines,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct JobLogs {
     pub job_name: String,
@@ -39,46 +57,177 @@ pub struct JobLogs {
     pub status: String,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct KubernetesError {
-    pub error_type: String,
-    pub message: String,
-    pub details: Option<String>,
-}
+/// Render the Kubernetes Job manifest for a job spec
+pub fn build_k8s_job(spec: &JobSpec) -> Job {
+    let mut env = spec
+        .env
+        .iter()
+        .map(|(name, value)| EnvVar {
+            name: name.clone(),
+            value: Some(value.clone()),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+
+    // Secret values are never written into the Job: env vars use secretKeyRef and
+    // files are single keys of a secret volume mounted at the requested path
+    let mut volumes = Vec::new();
+    let mut volume_mounts = Vec::new();
+    for (index, secret_ref) in spec.secrets.iter().enumerate() {
+        if let Some(name) = &secret_ref.env {
+            env.push(EnvVar {
+                name: name.clone(),
+                value_from: Some(EnvVarSource {
+                    secret_key_ref: Some(SecretKeySelector {
+                        name: Some(secret_ref.secret.clone()),
+                        key: secret_ref.key.clone(),
+                        optional: None,
+                    }),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+        }
+        if let Some(path) = &secret_ref.path {
+            let volume_name = format!("secret-{index}");
+            volumes.push(Volume {
+                name: volume_name.clone(),
+                secret: Some(SecretVolumeSource {
+                    secret_name: Some(secret_ref.secret.clone()),
+                    items: Some(vec![KeyToPath {
+                        key: secret_ref.key.clone(),
+                        path: secret_ref.key.clone(),
+                        mode: None,
+                    }]),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            });
+            volume_mounts.push(VolumeMount {
+                name: volume_name,
+                mount_path: path.clone(),
+                sub_path: Some(secret_ref.key.clone()),
+                read_only: Some(true),
+                ..Default::default()
+            });
+        }
+    }
 
-pub async fn create_k8s_job(
-    client: &Client,
-    job_name: &str,
-    image: &str,
-    command: &[String],
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let jobs: Api<Job> = Api::namespaced(client.clone(), "default");
+    // Requests equal limits so a run gets exactly what the admission policy allowed
+    let resources = spec.resources.as_ref().map(|resources| {
+        let quantities: std::collections::BTreeMap<String, Quantity> = [
+            ("cpu", resources.cpu.as_ref()),
+            ("memory", resources.memory.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name.to_string(), Quantity(value?.clone()))))
+        .collect();
+        ResourceRequirements {
+            limits: Some(quantities.clone()),
+            requests: Some(quantities),
+            ..Default::default()
+        }
+    });
+
+    // Uploaded files come from a ConfigMap created next to the Job
+    if !spec.files.is_empty() {
+        volumes.push(Volume {
+            name: "files".to_string(),
+            config_map: Some(ConfigMapVolumeSource {
+                name: Some(files_config_map_name(&spec.name)),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        volume_mounts.push(VolumeMount {
+            name: "files".to_string(),
+            mount_path: FILES_DIR.to_string(),
+            read_only: Some(true),
+            ..Default::default()
+        });
+        env.push(EnvVar {
+            name: "SPARKTEST_FILES_DIR".to_string(),
+            value: Some(FILES_DIR.to_string()),
+            ..Default::default()
+        });
+    }
+
+    // A Git source is cloned by an init container into a workspace volume
+    // shared with the test container, which starts in the checkout
+    let mut init_containers = Vec::new();
+    if let Some(source) = &spec.source {
+        let workspace_mount = VolumeMount {
+            name: "workspace".to_string(),
+            mount_path: WORKSPACE_DIR.to_string(),
+            ..Default::default()
+        };
+        volumes.push(Volume {
+            name: "workspace".to_string(),
+            empty_dir: Some(EmptyDirVolumeSource::default()),
+            ..Default::default()
+        });
+        init_containers.push(Container {
+            name: "checkout".to_string(),
+            image: Some(spec.git_image.clone()),
+            command: Some(vec![
+                "sh".to_string(),
+                "-c".to_string(),
+                CHECKOUT_SCRIPT.to_string(),
+            ]),
+            env: Some(
+                checkout_env(source)
+                    .into_iter()
+                    .map(|(name, value)| EnvVar {
+                        name,
+                        value: Some(value),
+                        ..Default::default()
+                    })
+                    .collect(),
+            ),
+            volume_mounts: Some(vec![workspace_mount.clone()]),
+            ..Default::default()
+        });
+        volume_mounts.push(workspace_mount);
+    }
+
+    let mut labels = spec.labels.clone();
+    labels.insert("app".to_string(), "sparktest".to_string());
+    labels.insert("component".to_string(), "test-runner".to_string());
+    let annotations = (!spec.annotations.is_empty()).then(|| spec.annotations.clone());
+    let mut pod_labels = labels.clone();
+    pod_labels.insert("job-name".to_string(), spec.name.clone());
 
-    let job = Job {
+    Job {
         metadata: ObjectMeta {
-            name: Some(job_name.to_string()),
-            labels: Some(std::collections::BTreeMap::from([
-                ("app".to_string(), "sparktest".to_string()),
-                ("component".to_string(), "test-runner".to_string()),
-            ])),
+            name: Some(spec.name.clone()),
+            labels: Some(labels),
+            annotations: annotations.clone(),
             ..Default::default()
         },
         spec: Some(k8s_openapi::api::batch::v1::JobSpec {
             template: PodTemplateSpec {
                 metadata: Some(ObjectMeta {
-                    labels: Some(std::collections::BTreeMap::from([
-                        ("job-name".to_string(), job_name.to_string()),
-                        ("app".to_string(), "sparktest".to_string()),
-                    ])),
+                    labels: Some(pod_labels),
+                    annotations,
                     ..Default::default()
                 }),
                 spec: Some(PodSpec {
                     containers: vec![Container {
-                        name: job_name.to_string(),
-                        image: Some(image.to_string()),
-                        command: Some(command.to_vec()),
+                        name: spec.name.clone(),
+                        image: Some(spec.image.clone()),
+                        command: (!spec.commands.is_empty()).then(|| spec.commands.clone()),
+                        env: (!env.is_empty()).then_some(env),
+                        resources,
+                        security_context: spec.privileged.then(|| SecurityContext {
+                            privileged: Some(true),
+                            ..Default::default()
+                        }),
+                        volume_mounts: (!volume_mounts.is_empty()).then_some(volume_mounts),
+                        working_dir: spec.source.as_ref().map(working_dir),
                         ..Default::default()
                     }],
+                    init_containers: (!init_containers.is_empty()).then_some(init_containers),
+                    volumes: (!volumes.is_empty()).then_some(volumes),
                     restart_policy: Some("Never".to_string()),
                     ..Default::default()
                 }),
@@ -88,54 +237,106 @@ pub async fn create_k8s_job(
             ..Default::default()
         }),
         ..Default::default()
-    };
+    }
+}
 
-    jobs.create(&PostParams::default(), &job).await?;
-    Ok(())
+/// `pending`, `running`, `completed` or `failed`, from the Job's conditions
+pub fn job_phase(job: &Job) -> &'static str {
+    let Some(conditions) = job.status.as_ref().and_then(|s| s.conditions.as_ref()) else {
+        return "pending";
+    };
+    let holds = |type_: &str| {
+        conditions
+            .iter()
+            .any(|c| c.type_ == type_ && c.status == "True")
+    };
+    if holds("Complete") {
+        "completed"
+    } else if holds("Failed") {
+        "failed"
+    } else {
+        "running"
+    }
 }
 
-pub async fn monitor_job_and_update_status(
-    run_id: Uuid,
-    job_name: String,
-    pool: PgPool,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let client = Client::try_default().await?;
-    let jobs: Api<Job> = Api::namespaced(client.clone(), "default");
+pub fn files_config_map_name(job_name: &str) -> String {
+    format!("{job_name}-files")
+}
 
-    let start_time = Utc::now();
-    let mut status = "running".to_string();
+/// The ConfigMap holding a job's uploaded files, owned by the Job so it is
+/// garbage collected with it. Text files go in `data`, anything else in `binaryData`.
+pub fn build_files_config_map(spec: &JobSpec, owner: &Job) -> Option<ConfigMap> {
+    if spec.files.is_empty() {
+        return None;
+    }
 
-    for _ in 0..30 {
-        let job = jobs.get(&job_name).await?;
-        if let Some(s) = &job.status {
-            if let Some(conds) = &s.conditions {
-                if conds
-                    .iter()
-                    .any(|c| c.type_ == "Complete" && c.status == "True")
-                {
-                    status = "succeeded".to_string();
-                    break;
-                } else if conds
-                    .iter()
-                    .any(|c| c.type_ == "Failed" && c.status == "True")
-                {
-                    status = "failed".to_string();
-                    break;
-                }
+    let mut data = std::collections::BTreeMap::new();
+    let mut binary_data = std::collections::BTreeMap::new();
+    for (name, content) in &spec.files {
+        match String::from_utf8(content.clone()) {
+            Ok(text) => {
+                data.insert(name.clone(), text);
+            }
+            Err(_) => {
+                binary_data.insert(name.clone(), ByteString(content.clone()));
             }
         }
-        sleep(Duration::from_secs(2)).await;
     }
 
-    let duration = (Utc::now() - start_time).num_seconds() as i32;
+    Some(ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(files_config_map_name(&spec.name)),
+            labels: Some(std::collections::BTreeMap::from([(
+                "app".to_string(),
+                "sparktest".to_string(),
+            )])),
+            owner_references: Some(vec![OwnerReference {
+                api_version: "batch/v1".to_string(),
+                kind: "Job".to_string(),
+                name: spec.name.clone(),
+                uid: owner.metadata.uid.clone().unwrap_or_default(),
+                ..Default::default()
+            }]),
+            ..Default::default()
+        },
+        data: (!data.is_empty()).then_some(data),
+        binary_data: (!binary_data.is_empty()).then_some(binary_data),
+        ..Default::default()
+    })
+}
 
-    sqlx::query("UPDATE test_runs SET status = $1, duration = $2 WHERE id = $3")
-        .bind(&status)
-        .bind(duration)
-        .bind(run_id)
-        .execute(&pool)
+pub async fn create_k8s_job(
+    client: &Client,
+    namespace: &str,
+    spec: &JobSpec,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let jobs: Api<Job> = Api::namespaced(client.clone(), namespace);
+
+    let job = jobs
+        .create(&PostParams::default(), &build_k8s_job(spec))
         .await?;
 
+    // The pod waits in ContainerCreating until the ConfigMap it mounts exists.
+    // The ConfigMap is owned by the Job, so it can only be created afterwards;
+    // if that fails the Job would never start, so it is removed again.
+    if let Some(config_map) = build_files_config_map(spec, &job) {
+        let config_maps: Api<ConfigMap> = Api::namespaced(client.clone(), namespace);
+        if let Err(e) = config_maps
+            .create(&PostParams::default(), &config_map)
+            .await
+        {
+            if let Err(cleanup) = jobs
+                .delete(&spec.name, &kube::api::DeleteParams::background())
+                .await
+            {
+                warn!(
+                    "Failed to delete job '{}' after its files could not be stored: {}",
+                    spec.name, cleanup
+                );
+            }
+            return Err(e.into());
+        }
+    }
     Ok(())
 }
 
@@ -159,6 +360,35 @@ impl KubernetesClient {
         Ok(Self { client, config })
     }
 
+    /// Create a client for a registered execution target
+    pub async fn for_target(target: &ExecutionTarget) -> Result<Self> {
+        let client = match &target.connection {
+            TargetConnection::Auto => Self::create_authenticated_client().await?,
+            TargetConnection::InCluster => {
+                let config =
+                    kube::Config::incluster().context("Failed to create in-cluster config")?;
+                Client::try_from(config)?
+            }
+            TargetConnection::Kubeconfig { context } => {
+                let options = kube::config::KubeConfigOptions {
+                    context: Some(context.clone()),
+                    ..Default::default()
+                };
+                let config = kube::Config::from_kubeconfig(&options)
+                    .await
+                    .with_context(|| format!("Failed to load kubeconfig context '{context}'"))?;
+                Client::try_from(config)?
+            }
+        };
+        let config = KubeConfig::from(target);
+
+        Ok(Self { client, config })
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
     /// Create authenticated Kubernetes client with fallback mechanisms
     async fn create_authenticated_client() -> Result<Client> {
         // Try different authentication methods in order of preference
@@ -207,7 +437,20 @@ impl KubernetesClient {
         Err(anyhow::anyhow!("No valid Kubernetes configuration found"))
     }
 
+    /// Launch a Job for the given spec in the configured namespace
+    #[instrument(name = "k8s.create_job", skip_all, fields(namespace = %self.config.namespace, job = %spec.name), err)]
+    pub async fn create_job(&self, spec: &JobSpec) -> Result<()> {
+        create_k8s_job(&self.client, &self.config.namespace, spec)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+            .with_context(|| format!("Failed to create job '{}'", spec.name))?;
+
+        info!("Created job '{}'", spec.name);
+        Ok(())
+    }
+
     /// Get job logs with comprehensive error handling
+    #[instrument(name = "k8s.get_job_logs", skip(self), fields(namespace = %self.config.namespace), err)]
     pub async fn get_job_logs(&self, job_name: &str) -> Result<JobLogs> {
         let jobs: Api<Job> = Api::namespaced(self.client.clone(), &self.config.namespace);
 
@@ -310,6 +553,7 @@ impl KubernetesClient {
     }
 
     /// Check if the Kubernetes cluster is accessible
+    #[instrument(name = "k8s.health_check", skip(self), fields(namespace = %self.config.namespace))]
     pub async fn health_check(&self) -> Result<bool> {
         let pods: Api<Pod> = Api::namespaced(self.client.clone(), &self.config.namespace);
 
@@ -323,6 +567,7 @@ impl KubernetesClient {
     }
 
     /// Get job status
+    #[instrument(name = "k8s.get_job_status", skip(self), fields(namespace = %self.config.namespace), err)]
     pub async fn get_job_status(&self, job_name: &str) -> Result<String> {
         let jobs: Api<Job> = Api::namespaced(self.client.clone(), &self.config.namespace);
 
@@ -331,31 +576,11 @@ impl KubernetesClient {
             .await
             .with_context(|| format!("Failed to get job '{job_name}'"))?;
 
-        let status = job
-            .status
-            .as_ref()
-            .and_then(|s| s.conditions.as_ref())
-            .map(|conditions| {
-                if conditions
-                    .iter()
-                    .any(|c| c.type_ == "Complete" && c.status == "True")
-                {
-                    "completed".to_string()
-                } else if conditions
-                    .iter()
-                    .any(|c| c.type_ == "Failed" && c.status == "True")
-                {
-                    "failed".to_string()
-                } else {
-                    "running".to_string()
-                }
-            })
-            .unwrap_or_else(|| "pending".to_string());
-
-        Ok(status)
+        Ok(job_phase(&job).to_string())
     }
 
     /// Delete a job and its associated pods
+    #[instrument(name = "k8s.delete_job", skip(self), fields(namespace = %self.config.namespace), err)]
     pub async fn delete_job(&self, job_name: &str) -> Result<()> {
         let jobs: Api<Job> = Api::namespaced(self.client.clone(), &self.config.namespace);
 
@@ -369,6 +594,32 @@ impl KubernetesClient {
         Ok(())
     }
 
+    /// Read one key of a Secret in the target namespace
+    #[instrument(name = "k8s.read_secret", skip(self), fields(namespace = %self.config.namespace), err)]
+    pub async fn read_secret(&self, name: &str, key: &str) -> Result<Option<SecretValue>> {
+        let secrets: Api<Secret> = Api::namespaced(self.client.clone(), &self.config.namespace);
+
+        let Some(mut secret) = secrets
+            .get_opt(name)
+            .await
+            .with_context(|| format!("Failed to get secret '{name}'"))?
+        else {
+            return Ok(None);
+        };
+        let team = secret
+            .metadata
+            .labels
+            .as_mut()
+            .and_then(|labels| labels.remove(SECRET_TEAM_LABEL));
+        Ok(secret
+            .data
+            .and_then(|mut data| data.remove(key))
+            .map(|value| SecretValue {
+                value: String::from_utf8_lossy(&value.0).into_owned(),
+                team,
+            }))
+    }
+
     /// Get pod status
     async fn get_pod_status(&self, pod_name: &str) -> Result<String> {
         let pods: Api<Pod> = Api::namespaced(self.client.clone(), &self.config.namespace);
@@ -414,17 +665,6 @@ impl KubernetesClient {
     }
 }
 
-// Convert Kubernetes errors to our custom error type
-impl From<KubeError> for KubernetesError {
-    fn from(error: KubeError) -> Self {
-        KubernetesError {
-            error_type: "KubernetesError".to_string(),
-            message: error.to_string(),
-            details: Some(format!("{error:?}")),
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -446,6 +686,195 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_build_k8s_job() {
+        let spec = JobSpec {
+            name: "test-run-1".to_string(),
+            image: "node:18-alpine".to_string(),
+            commands: vec!["npm".to_string(), "test".to_string()],
+            env: std::collections::BTreeMap::from([("CI".to_string(), "true".to_string())]),
+            labels: std::collections::BTreeMap::from([(
+                "cost-center".to_string(),
+                "qa".to_string(),
+            )]),
+            annotations: std::collections::BTreeMap::from([(
+                "sparktest.dev/run-name".to_string(),
+                "Unit tests".to_string(),
+            )]),
+            ..Default::default()
+        };
+
+        let job = build_k8s_job(&spec);
+        let labels = job.metadata.labels.unwrap();
+        assert_eq!(labels["app"], "sparktest");
+        assert_eq!(labels["cost-center"], "qa");
+        assert_eq!(
+            job.metadata.annotations.unwrap()["sparktest.dev/run-name"],
+            "Unit tests"
+        );
+
+        let template = job.spec.unwrap().template;
+        let pod_labels = template.metadata.unwrap().labels.unwrap();
+        assert_eq!(pod_labels["job-name"], "test-run-1");
+        assert_eq!(pod_labels["cost-center"], "qa");
+        let pod = template.spec.unwrap();
+        let container = &pod.containers[0];
+        assert_eq!(container.image.as_deref(), Some("node:18-alpine"));
+        assert_eq!(container.command.as_ref().unwrap().len(), 2);
+        assert_eq!(container.env.as_ref().unwrap()[0].name, "CI");
+        assert_eq!(pod.restart_policy.as_deref(), Some("Never"));
+        assert!(container.resources.is_none());
+        assert!(container.security_context.is_none());
+    }
+
+    #[test]
+    fn test_build_k8s_job_with_resources() {
+        let spec = JobSpec {
+            name: "test-run-2".to_string(),
+            image: "node:18-alpine".to_string(),
+            resources: Some(sparktest_core::ResourceLimits {
+                cpu: Some("500m".to_string()),
+                memory: None,
+            }),
+            privileged: true,
+            ..Default::default()
+        };
+
+        let pod = build_k8s_job(&spec).spec.unwrap().template.spec.unwrap();
+        let container = &pod.containers[0];
+        let resources = container.resources.as_ref().unwrap();
+        assert_eq!(resources.limits.as_ref().unwrap()["cpu"].0, "500m");
+        assert_eq!(resources.requests.as_ref().unwrap()["cpu"].0, "500m");
+        assert!(!resources.limits.as_ref().unwrap().contains_key("memory"));
+        let security_context = container.security_context.as_ref().unwrap();
+        assert_eq!(security_context.privileged, Some(true));
+    }
+
+    #[test]
+    fn test_build_k8s_job_with_secrets() {
+        let spec = JobSpec {
+            name: "test-run-3".to_string(),
+            image: "postman/newman:6".to_string(),
+            secrets: vec![
+                sparktest_core::SecretRef {
+                    secret: "api-credentials".to_string(),
+                    key: "token".to_string(),
+                    env: Some("API_TOKEN".to_string()),
+                    path: None,
+                },
+                sparktest_core::SecretRef {
+                    secret: "api-credentials".to_string(),
+                    key: "client.pem".to_string(),
+                    env: None,
+                    path: Some("/etc/certs/client.pem".to_string()),
+                },
+            ],
+            ..Default::default()
+        };
+
+        let pod = build_k8s_job(&spec).spec.unwrap().template.spec.unwrap();
+        let container = &pod.containers[0];
+        let env = &container.env.as_ref().unwrap()[0];
+        assert_eq!(env.name, "API_TOKEN");
+        assert!(env.value.is_none());
+        let selector = env
+            .value_from
+            .as_ref()
+            .and_then(|source| source.secret_key_ref.as_ref())
+            .unwrap();
+        assert_eq!(selector.name.as_deref(), Some("api-credentials"));
+        assert_eq!(selector.key, "token");
+
+        let mount = &container.volume_mounts.as_ref().unwrap()[0];
+        assert_eq!(mount.mount_path, "/etc/certs/client.pem");
+        assert_eq!(mount.sub_path.as_deref(), Some("client.pem"));
+        let volume = &pod.volumes.as_ref().unwrap()[0];
+        assert_eq!(volume.name, mount.name);
+        let source = volume.secret.as_ref().unwrap();
+        assert_eq!(source.secret_name.as_deref(), Some("api-credentials"));
+        assert_eq!(source.items.as_ref().unwrap()[0].key, "client.pem");
+    }
+
+    #[test]
+    fn test_build_k8s_job_with_git_source() {
+        let spec = JobSpec {
+            name: "test-run-4".to_string(),
+            image: "mcr.microsoft.com/playwright:v1.45.0".to_string(),
+            commands: vec![
+                "npx".to_string(),
+                "playwright".to_string(),
+                "test".to_string(),
+            ],
+            source: Some(sparktest_core::GitSource {
+                url: "https://github.com/example/tests.git".to_string(),
+                git_ref: Some("v1.2.0".to_string()),
+                subdirectory: Some("e2e".to_string()),
+            }),
+            git_image: "registry.example.com/mirror/git:2.45.2".to_string(),
+            ..Default::default()
+        };
+
+        let pod = build_k8s_job(&spec).spec.unwrap().template.spec.unwrap();
+        let init = &pod.init_containers.as_ref().unwrap()[0];
+        assert_eq!(
+            init.image.as_deref(),
+            Some("registry.example.com/mirror/git:2.45.2")
+        );
+        let init_env = init.env.as_ref().unwrap();
+        assert!(init_env
+            .iter()
+            .any(|e| e.name == "GIT_REF" && e.value.as_deref() == Some("v1.2.0")));
+        assert!(!init.command.as_ref().unwrap()[2].contains("github.com"));
+
+        let container = &pod.containers[0];
+        assert_eq!(container.working_dir.as_deref(), Some("/workspace/src/e2e"));
+        let mount = &container.volume_mounts.as_ref().unwrap()[0];
+        assert_eq!(mount.mount_path, "/workspace");
+        assert_eq!(init.volume_mounts.as_ref().unwrap()[0].name, mount.name);
+        assert!(pod.volumes.as_ref().unwrap()[0].empty_dir.is_some());
+    }
+
+    #[test]
+    fn test_uploaded_files_are_mounted_from_config_map() {
+        let spec = JobSpec {
+            name: "test-run-5".to_string(),
+            image: "grafana/k6:0.52.0".to_string(),
+            files: std::collections::BTreeMap::from([
+                (
+                    "load.js".to_string(),
+                    b"export default function () {}".to_vec(),
+                ),
+                ("data.bin".to_string(), vec![0xff, 0x00]),
+            ]),
+            ..Default::default()
+        };
+
+        let pod = build_k8s_job(&spec).spec.unwrap().template.spec.unwrap();
+        let volume = &pod.volumes.as_ref().unwrap()[0];
+        assert_eq!(
+            volume.config_map.as_ref().unwrap().name.as_deref(),
+            Some("test-run-5-files")
+        );
+        let mount = &pod.containers[0].volume_mounts.as_ref().unwrap()[0];
+        assert_eq!(mount.mount_path, FILES_DIR);
+        assert_eq!(mount.read_only, Some(true));
+
+        let mut owner = build_k8s_job(&spec);
+        owner.metadata.uid = Some("job-uid".to_string());
+        let config_map = build_files_config_map(&spec, &owner).unwrap();
+        assert_eq!(
+            config_map.data.unwrap()["load.js"],
+            "export default function () {}"
+        );
+        assert_eq!(config_map.binary_data.unwrap()["data.bin"].0, [0xff, 0x00]);
+        assert_eq!(
+            config_map.metadata.owner_references.unwrap()[0].uid,
+            "job-uid"
+        );
+
+        assert!(build_files_config_map(&JobSpec::default(), &owner).is_none());
+    }
+
     #[tokio::test]
     async fn test_job_name_generation() {
         // Test that job names are generated correctly for test runs
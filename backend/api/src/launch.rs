@@ -0,0 +1,171 @@
+use crate::error::ApiError;
+use crate::metrics::metrics;
+use crate::runner::{spawn_run, spawn_suite};
+use crate::state::AppState;
+use crate::targets::DEFAULT_TARGET;
+use sparktest_core::{RunTrigger, TestDefinition, TestRun, TestSuite};
+use uuid::Uuid;
+
+/// What started a run other than a direct API call, recorded on the run
+#[derive(Debug, Clone, Default)]
+pub struct RunOrigin {
+    pub trigger: Option<RunTrigger>,
+    pub schedule_id: Option<Uuid>,
+    /// UID of the Kubernetes `TestRun` resource asking for the run
+    pub resource_uid: Option<String>,
+    /// The user or API token that asked for the run, or the Git event's sender
+    pub triggered_by: Option<String>,
+}
+
+impl From<RunTrigger> for RunOrigin {
+    fn from(trigger: RunTrigger) -> Self {
+        Self {
+            triggered_by: trigger.sender.clone(),
+            trigger: Some(trigger),
+            ..Self::default()
+        }
+    }
+}
+
+/// Admit and start a run of a stored definition. Callers check permissions.
+pub async fn launch_definition(
+    state: &AppState,
+    definition: &TestDefinition,
+    origin: RunOrigin,
+) -> Result<TestRun, ApiError> {
+    // The policy may have tightened since the definition was stored
+    state
+        .policy
+        .read()
+        .await
+        .admit_definition(definition, &state.runner.git_image)?;
+
+    let run = new_run(state, definition, origin).await?;
+    state.store.insert_run(run.clone()).await?;
+    state.events.publish_created(&run);
+    metrics().run_created(&run);
+    spawn_run(state.clone(), run.id);
+
+    Ok(run)
+}
+
+/// Admit and start one run per definition in the suite, honouring its execution
+/// mode. Callers check permissions.
+pub async fn launch_suite(
+    state: &AppState,
+    suite: &TestSuite,
+    origin: RunOrigin,
+) -> Result<Vec<TestRun>, ApiError> {
+    // Definitions deleted since the suite was created are skipped
+    let mut definitions = Vec::new();
+    for definition_id in &suite.test_definition_ids {
+        if let Some(definition) = state.store.get_definition(*definition_id).await {
+            definitions.push(definition);
+        }
+    }
+    // Admit the whole suite before launching any of it
+    {
+        let policy = state.policy.read().await;
+        for definition in &definitions {
+            policy
+                .admit_definition(definition, &state.runner.git_image)
+                .map_err(|e| {
+                    ApiError::new(
+                        e.error_type,
+                        format!("Test definition '{}': {}", definition.name, e.message),
+                    )
+                    .with_details(serde_json::json!({
+                        "test_definition_id": definition.id,
+                        "policy": e.details,
+                    }))
+                })?;
+        }
+    }
+
+    let mut runs = Vec::new();
+    for definition in &definitions {
+        let mut run = new_run(state, definition, origin.clone()).await?;
+        run.suite_id = Some(suite.id);
+        // Runs belong to the suite's team, whoever owns the individual definitions
+        run.team_id = suite.team_id;
+        state.store.insert_run(run.clone()).await?;
+        state.events.publish_created(&run);
+        metrics().run_created(&run);
+        runs.push(run);
+    }
+
+    let run_ids = runs.iter().map(|run| run.id).collect();
+    spawn_suite(state.clone(), run_ids, suite.execution_mode == "sequential");
+
+    Ok(runs)
+}
+
+/// A pending run of the definition with a copy of its files. A triggered run is
+/// named after its event and, when the definition checks out the repository
+/// that triggered it, tests the pushed commit instead of the configured ref.
+async fn new_run(
+    state: &AppState,
+    definition: &TestDefinition,
+    origin: RunOrigin,
+) -> Result<TestRun, sqlx::Error> {
+    let mut run = TestRun::from_definition(definition);
+    if run.target.is_none() {
+        run.target = Some(DEFAULT_TARGET.to_string());
+    }
+    run.schedule_id = origin.schedule_id;
+    run.resource_uid = origin.resource_uid;
+    run.triggered_by = origin.triggered_by;
+    if let Some(trigger) = origin.trigger {
+        run.name = format!("{} - {}", run.name, trigger.describe());
+        if let Some(source) = &mut run.source {
+            if is_same_repository(&source.url, &trigger.repository) {
+                source.git_ref = Some(trigger.commit_sha.clone());
+            }
+        }
+        run.trigger = Some(trigger);
+    }
+    state.store.copy_files(definition.id, run.id).await?;
+    Ok(run)
+}
+
+/// Whether a clone URL (https, ssh or scp-like) points at `owner/repo`
+fn is_same_repository(url: &str, repository: &str) -> bool {
+    let path = url.trim_end_matches('/').trim_end_matches(".git");
+    let Some(prefix) = path
+        .to_ascii_lowercase()
+        .strip_suffix(&repository.to_ascii_lowercase())
+        .map(str::to_string)
+    else {
+        return false;
+    };
+    prefix.ends_with('/') || prefix.ends_with(':')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_same_repository() {
+        assert!(is_same_repository(
+            "https://github.com/Example/App.git",
+            "example/app"
+        ));
+        assert!(is_same_repository(
+            "git@github.com:example/app.git",
+            "example/app"
+        ));
+        assert!(is_same_repository(
+            "https://gitlab.com/group/sub/app",
+            "group/sub/app"
+        ));
+        assert!(!is_same_repository(
+            "https://github.com/example/other-app",
+            "example/app"
+        ));
+        assert!(!is_same_repository(
+            "https://github.com/example/app-tests",
+            "example/app"
+        ));
+    }
+}
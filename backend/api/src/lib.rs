@@ -1,7 +1,71 @@
+pub mod analytics;
+pub mod audit;
+pub mod auth;
+pub mod backend;
+pub mod commit_status;
+pub mod crd;
+pub mod db;
+pub mod encryption;
+pub mod error;
+pub mod events;
+pub mod extract;
+pub mod fake;
+pub mod files;
 pub mod handlers;
+pub mod job_controller;
+pub mod job_metadata;
 pub mod k8s;
+pub mod launch;
+pub mod local;
+pub mod metrics;
+pub mod notifications;
+pub mod oidc;
+pub mod policy;
+pub mod queue;
+pub mod rbac;
 pub mod routes;
+pub mod run_query;
+pub mod runner;
+pub mod scheduler;
+pub mod secrets;
+pub mod source;
+pub mod state;
+pub mod store;
+pub mod targets;
+pub mod telemetry;
+pub mod webhooks;
 
+pub use analytics::*;
+pub use audit::*;
+pub use auth::*;
+pub use backend::*;
+pub use commit_status::*;
+pub use crd::*;
+pub use encryption::*;
+pub use error::*;
+pub use events::*;
+pub use fake::*;
+pub use files::*;
 pub use handlers::*;
+pub use job_controller::*;
+pub use job_metadata::*;
 pub use k8s::*;
+pub use launch::*;
+pub use local::*;
+pub use metrics::*;
+pub use notifications::*;
+pub use oidc::*;
+pub use policy::*;
+pub use queue::*;
+pub use rbac::*;
 pub use routes::*;
+pub use run_query::*;
+pub use runner::*;
+pub use scheduler::*;
+pub use secrets::*;
+pub use source::*;
+pub use state::*;
+pub use store::*;
+pub use targets::*;
+pub use telemetry::*;
+pub use webhooks::*;
@@ -0,0 +1,430 @@
+use crate::backend::{ExecutionBackend, JobNotFound, JobSpec};
+use crate::files::FILES_DIR;
+use crate::k8s::JobLogs;
+use crate::policy::{cpu_millis, memory_bytes};
+use crate::source::{checkout_env, working_dir, CHECKOUT_SCRIPT, WORKSPACE_DIR};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use sparktest_core::ExecutionTarget;
+use std::path::PathBuf;
+use tokio::process::Command;
+use tracing::info;
+
+/// Container runtime CLIs that understand the docker command set
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContainerRuntime {
+    Docker,
+    Podman,
+}
+
+impl ContainerRuntime {
+    pub fn binary(&self) -> &'static str {
+        match self {
+            ContainerRuntime::Docker => "docker",
+            ContainerRuntime::Podman => "podman",
+        }
+    }
+}
+
+/// Runs tests as containers on the local Docker or Podman daemon, for developer
+/// machines without a cluster. Targets only contribute their log line limit.
+pub struct LocalBackend {
+    runtime: ContainerRuntime,
+}
+
+impl LocalBackend {
+    pub fn new(runtime: ContainerRuntime) -> Self {
+        Self { runtime }
+    }
+
+    /// Arguments for `docker run`; the first command becomes the entrypoint,
+    /// matching how `command` overrides the entrypoint in the Kubernetes Job
+    pub fn run_args(spec: &JobSpec) -> Vec<String> {
+        let mut args = vec![
+            "run".to_string(),
+            "--detach".to_string(),
+            "--name".to_string(),
+            spec.name.clone(),
+            "--label".to_string(),
+            "app=sparktest".to_string(),
+            "--label".to_string(),
+            "component=test-runner".to_string(),
+        ];
+        for (key, value) in spec.labels.iter().chain(&spec.annotations) {
+            args.push("--label".to_string());
+            args.push(format!("{key}={value}"));
+        }
+
+        // Quantities were validated by the admission policy; docker wants plain numbers
+        if let Some(resources) = &spec.resources {
+            if let Some(millis) = resources.cpu.as_deref().and_then(cpu_millis) {
+                args.push("--cpus".to_string());
+                args.push(format!("{}", millis as f64 / 1000.0));
+            }
+            if let Some(bytes) = resources.memory.as_deref().and_then(memory_bytes) {
+                args.push("--memory".to_string());
+                args.push(bytes.to_string());
+            }
+        }
+        if spec.privileged {
+            args.push("--privileged".to_string());
+        }
+
+        if let Some(source) = &spec.source {
+            args.push("--volume".to_string());
+            args.push(format!("{}:{WORKSPACE_DIR}", workspace_volume(&spec.name)));
+            args.push("--workdir".to_string());
+            args.push(working_dir(source));
+        }
+
+        if !spec.files.is_empty() {
+            args.push("--volume".to_string());
+            args.push(format!(
+                "{}:{FILES_DIR}:ro",
+                files_dir(&spec.name).display()
+            ));
+            args.push("--env".to_string());
+            args.push(format!("SPARKTEST_FILES_DIR={FILES_DIR}"));
+        }
+
+        for (key, value) in &spec.env {
+            args.push("--env".to_string());
+            args.push(format!("{key}={value}"));
+        }
+
+        let mut commands = spec.commands.iter();
+        if let Some(entrypoint) = commands.next() {
+            args.push("--entrypoint".to_string());
+            args.push(entrypoint.clone());
+        }
+        args.push(spec.image.clone());
+        args.extend(commands.cloned());
+
+        args
+    }
+
+    /// Arguments for the `docker run` that clones a Git source into the job's
+    /// workspace volume, the local equivalent of the Job's init container
+    pub fn checkout_args(spec: &JobSpec) -> Option<Vec<String>> {
+        let source = spec.source.as_ref()?;
+        let mut args = vec![
+            "run".to_string(),
+            "--rm".to_string(),
+            "--volume".to_string(),
+            format!("{}:{WORKSPACE_DIR}", workspace_volume(&spec.name)),
+        ];
+        for (key, value) in checkout_env(source) {
+            args.push("--env".to_string());
+            args.push(format!("{key}={value}"));
+        }
+        args.extend([
+            "--entrypoint".to_string(),
+            "sh".to_string(),
+            spec.git_image.clone(),
+            "-c".to_string(),
+            CHECKOUT_SCRIPT.to_string(),
+        ]);
+        Some(args)
+    }
+
+    /// Map `docker inspect` state and exit code to a job status
+    pub fn job_status(state: &str, exit_code: i64) -> &'static str {
+        match state {
+            "created" => "pending",
+            "running" | "restarting" | "paused" => "running",
+            "exited" if exit_code == 0 => "completed",
+            "exited" | "dead" => "failed",
+            _ => "pending",
+        }
+    }
+
+    /// Best effort: most jobs have no workspace volume at all
+    async fn remove_volume(&self, volume: &str) {
+        self.run(&[
+            "volume".to_string(),
+            "rm".to_string(),
+            "--force".to_string(),
+            volume.to_string(),
+        ])
+        .await
+        .ok();
+    }
+
+    async fn run(&self, args: &[String]) -> Result<String> {
+        let output = Command::new(self.runtime.binary())
+            .args(args)
+            .output()
+            .await
+            .with_context(|| format!("Failed to execute '{}'", self.runtime.binary()))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            // Both runtimes report unknown containers as "no such container/object"
+            if stderr.to_ascii_lowercase().contains("no such") {
+                return Err(JobNotFound(args.last().cloned().unwrap_or_default()).into());
+            }
+            return Err(anyhow::anyhow!(
+                "'{} {}' failed: {}",
+                self.runtime.binary(),
+                args.first().map(String::as_str).unwrap_or_default(),
+                stderr.trim()
+            ));
+        }
+
+        // Test output can go to either stream, so keep both like `kubectl logs` does
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        Ok(text)
+    }
+}
+
+fn workspace_volume(job_name: &str) -> String {
+    format!("{job_name}-workspace")
+}
+
+/// Host directory bind-mounted as the job's uploaded files
+fn files_dir(job_name: &str) -> PathBuf {
+    std::env::temp_dir()
+        .join("sparktest")
+        .join(job_name)
+        .join("files")
+}
+
+async fn write_files(spec: &JobSpec) -> Result<()> {
+    let dir = files_dir(&spec.name);
+    tokio::fs::create_dir_all(&dir)
+        .await
+        .with_context(|| format!("Failed to create '{}'", dir.display()))?;
+    for (name, content) in &spec.files {
+        tokio::fs::write(dir.join(name), content)
+            .await
+            .with_context(|| format!("Failed to write file '{name}'"))?;
+    }
+    Ok(())
+}
+
+/// Best effort, like removing the workspace volume
+async fn remove_files(job_name: &str) {
+    if let Some(job_dir) = files_dir(job_name).parent() {
+        tokio::fs::remove_dir_all(job_dir).await.ok();
+    }
+}
+
+#[async_trait]
+impl ExecutionBackend for LocalBackend {
+    fn name(&self) -> &'static str {
+        self.runtime.binary()
+    }
+
+    async fn create_job(&self, _target: &ExecutionTarget, spec: &JobSpec) -> Result<()> {
+        if let Some(checkout) = Self::checkout_args(spec) {
+            let volume = workspace_volume(&spec.name);
+            self.run(&["volume".to_string(), "create".to_string(), volume.clone()])
+                .await
+                .with_context(|| format!("Failed to create volume '{volume}'"))?;
+            if let Err(e) = self.run(&checkout).await {
+                self.remove_volume(&volume).await;
+                return Err(e.context(format!("Failed to check out source for '{}'", spec.name)));
+            }
+        }
+
+        if !spec.files.is_empty() {
+            write_files(spec).await?;
+        }
+
+        if let Err(e) = self.run(&Self::run_args(spec)).await {
+            remove_files(&spec.name).await;
+            return Err(e.context(format!("Failed to start container '{}'", spec.name)));
+        }
+
+        info!("Started container '{}'", spec.name);
+        Ok(())
+    }
+
+    async fn get_job_status(&self, _target: &ExecutionTarget, job_name: &str) -> Result<String> {
+        let output = self
+            .run(&[
+                "inspect".to_string(),
+                "--format".to_string(),
+                "{{.State.Status}} {{.State.ExitCode}}".to_string(),
+                job_name.to_string(),
+            ])
+            .await
+            .with_context(|| format!("Failed to get container '{job_name}'"))?;
+
+        let mut parts = output.split_whitespace();
+        let state = parts.next().unwrap_or_default();
+        let exit_code = parts.next().and_then(|c| c.parse().ok()).unwrap_or(0);
+
+        Ok(Self::job_status(state, exit_code).to_string())
+    }
+
+    async fn get_job_logs(&self, target: &ExecutionTarget, job_name: &str) -> Result<JobLogs> {
+        let status = self.get_job_status(target, job_name).await?;
+
+        let mut args = vec!["logs".to_string(), "--timestamps".to_string()];
+        if let Some(tail_lines) = target.max_log_lines {
+            args.push("--tail".to_string());
+            args.push(tail_lines.to_string());
+        }
+        args.push(job_name.to_string());
+
+        let logs = self
+            .run(&args)
+            .await
+            .with_context(|| format!("Failed to get logs for container '{job_name}'"))?;
+
+        Ok(JobLogs {
+            job_name: job_name.to_string(),
+            pod_name: job_name.to_string(),
+            logs,
+            timestamp: Utc::now(),
+            status,
+        })
+    }
+
+    async fn delete_job(&self, _target: &ExecutionTarget, job_name: &str) -> Result<()> {
+        self.run(&[
+            "rm".to_string(),
+            "--force".to_string(),
+            job_name.to_string(),
+        ])
+        .await
+        .with_context(|| format!("Failed to delete container '{job_name}'"))?;
+        self.remove_volume(&workspace_volume(job_name)).await;
+        remove_files(job_name).await;
+
+        info!("Successfully deleted container '{}'", job_name);
+        Ok(())
+    }
+
+    async fn health_check(&self, _target: &ExecutionTarget) -> Result<bool> {
+        Ok(self.run(&["version".to_string()]).await.is_ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_run_args() {
+        let spec = JobSpec {
+            name: "test-run-1".to_string(),
+            image: "python:3.11-slim".to_string(),
+            commands: vec![
+                "pytest".to_string(),
+                "--verbose".to_string(),
+                "tests/".to_string(),
+            ],
+            env: BTreeMap::from([("CI".to_string(), "true".to_string())]),
+            ..Default::default()
+        };
+
+        let args = LocalBackend::run_args(&spec);
+        assert_eq!(args[..4], ["run", "--detach", "--name", "test-run-1"]);
+        assert!(args.windows(2).any(|w| w == ["--env", "CI=true"]));
+        assert!(args.windows(2).any(|w| w == ["--entrypoint", "pytest"]));
+        assert_eq!(
+            args[args.len() - 3..],
+            ["python:3.11-slim", "--verbose", "tests/"]
+        );
+    }
+
+    #[test]
+    fn test_run_args_without_commands() {
+        let spec = JobSpec {
+            name: "test-run-2".to_string(),
+            image: "grafana/k6:latest".to_string(),
+            ..Default::default()
+        };
+
+        let args = LocalBackend::run_args(&spec);
+        assert!(!args.contains(&"--entrypoint".to_string()));
+        assert_eq!(args.last().unwrap(), "grafana/k6:latest");
+    }
+
+    #[test]
+    fn test_run_args_with_resources() {
+        let spec = JobSpec {
+            name: "test-run-3".to_string(),
+            image: "docker:dind".to_string(),
+            resources: Some(sparktest_core::ResourceLimits {
+                cpu: Some("1500m".to_string()),
+                memory: Some("512Mi".to_string()),
+            }),
+            privileged: true,
+            ..Default::default()
+        };
+
+        let args = LocalBackend::run_args(&spec);
+        assert!(args.windows(2).any(|w| w == ["--cpus", "1.5"]));
+        assert!(args.windows(2).any(|w| w == ["--memory", "536870912"]));
+        assert!(args.contains(&"--privileged".to_string()));
+    }
+
+    #[test]
+    fn test_git_source_uses_workspace_volume() {
+        let spec = JobSpec {
+            name: "test-run-4".to_string(),
+            image: "node:20".to_string(),
+            source: Some(sparktest_core::GitSource {
+                url: "https://github.com/example/tests.git".to_string(),
+                git_ref: None,
+                subdirectory: Some("e2e".to_string()),
+            }),
+            git_image: "alpine/git:2.45.2".to_string(),
+            ..Default::default()
+        };
+
+        let checkout = LocalBackend::checkout_args(&spec).unwrap();
+        assert!(checkout.contains(&"alpine/git:2.45.2".to_string()));
+        assert!(checkout
+            .windows(2)
+            .any(|w| w == ["--volume", "test-run-4-workspace:/workspace"]));
+        assert!(checkout.contains(&"GIT_URL=https://github.com/example/tests.git".to_string()));
+
+        let args = LocalBackend::run_args(&spec);
+        assert!(args
+            .windows(2)
+            .any(|w| w == ["--volume", "test-run-4-workspace:/workspace"]));
+        assert!(args
+            .windows(2)
+            .any(|w| w == ["--workdir", "/workspace/src/e2e"]));
+
+        let without_source = JobSpec {
+            source: None,
+            ..spec
+        };
+        assert!(LocalBackend::checkout_args(&without_source).is_none());
+    }
+
+    #[test]
+    fn test_uploaded_files_are_bind_mounted() {
+        let spec = JobSpec {
+            name: "test-run-5".to_string(),
+            image: "grafana/k6:0.52.0".to_string(),
+            files: BTreeMap::from([("load.js".to_string(), b"export default".to_vec())]),
+            ..Default::default()
+        };
+
+        let args = LocalBackend::run_args(&spec);
+        let mount = format!("{}:/sparktest/files:ro", files_dir("test-run-5").display());
+        assert!(args.windows(2).any(|w| w == ["--volume", mount.as_str()]));
+        assert!(args.contains(&"SPARKTEST_FILES_DIR=/sparktest/files".to_string()));
+
+        let without_files = LocalBackend::run_args(&JobSpec::default());
+        assert!(!without_files.iter().any(|arg| arg.contains(FILES_DIR)));
+    }
+
+    #[test]
+    fn test_job_status_mapping() {
+        assert_eq!(LocalBackend::job_status("created", 0), "pending");
+        assert_eq!(LocalBackend::job_status("running", 0), "running");
+        assert_eq!(LocalBackend::job_status("exited", 0), "completed");
+        assert_eq!(LocalBackend::job_status("exited", 1), "failed");
+        assert_eq!(LocalBackend::job_status("dead", 137), "failed");
+    }
+}
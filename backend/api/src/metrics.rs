@@ -0,0 +1,225 @@
+use anyhow::Result;
+use axum::{
+    extract::{MatchedPath, Request},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use prometheus::{
+    core::Collector, Encoder, Histogram, HistogramOpts, HistogramVec, IntCounterVec, IntGauge,
+    Opts, Registry, TextEncoder,
+};
+use sparktest_core::TestRun;
+use std::future::Future;
+use std::sync::LazyLock;
+use std::time::Instant;
+
+/// Seconds; runs take anything from a second to the default one hour timeout
+const RUN_DURATION_BUCKETS: &[f64] = &[
+    1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1200.0, 1800.0, 3600.0,
+];
+/// Seconds between a run being created and starting to execute
+const QUEUE_WAIT_BUCKETS: &[f64] = &[0.01, 0.1, 0.5, 1.0, 5.0, 15.0, 60.0, 300.0, 900.0];
+
+/// Everything exported on `/metrics`. Process-wide, since Kubernetes clients are
+/// created per call without access to the application state.
+pub struct Metrics {
+    registry: Registry,
+    pub runs_created: IntCounterVec,
+    pub runs_finished: IntCounterVec,
+    pub run_duration: HistogramVec,
+    pub queue_wait: Histogram,
+    pub active_runs: IntGauge,
+    pub k8s_request_duration: HistogramVec,
+    pub k8s_request_errors: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+}
+
+impl Metrics {
+    fn new() -> prometheus::Result<Self> {
+        let registry = Registry::new();
+        let metrics = Self {
+            runs_created: IntCounterVec::new(
+                Opts::new("sparktest_runs_created_total", "Runs created"),
+                &["executor"],
+            )?,
+            runs_finished: IntCounterVec::new(
+                Opts::new("sparktest_runs_finished_total", "Runs that finished"),
+                &["status", "executor"],
+            )?,
+            run_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "sparktest_run_duration_seconds",
+                    "How long finished runs took",
+                )
+                .buckets(RUN_DURATION_BUCKETS.to_vec()),
+                &["status", "executor"],
+            )?,
+            queue_wait: Histogram::with_opts(
+                HistogramOpts::new(
+                    "sparktest_run_queue_wait_seconds",
+                    "Time from a run being created to it starting to execute",
+                )
+                .buckets(QUEUE_WAIT_BUCKETS.to_vec()),
+            )?,
+            active_runs: IntGauge::new("sparktest_active_runs", "Runs executing right now")?,
+            k8s_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "sparktest_k8s_request_duration_seconds",
+                    "Kubernetes API calls by operation",
+                ),
+                &["operation"],
+            )?,
+            k8s_request_errors: IntCounterVec::new(
+                Opts::new(
+                    "sparktest_k8s_request_errors_total",
+                    "Kubernetes API calls that failed",
+                ),
+                &["operation"],
+            )?,
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "sparktest_http_request_duration_seconds",
+                    "HTTP requests by route",
+                ),
+                &["method", "route", "status"],
+            )?,
+            registry,
+        };
+
+        let collectors: [Box<dyn Collector>; 8] = [
+            Box::new(metrics.runs_created.clone()),
+            Box::new(metrics.runs_finished.clone()),
+            Box::new(metrics.run_duration.clone()),
+            Box::new(metrics.queue_wait.clone()),
+            Box::new(metrics.active_runs.clone()),
+            Box::new(metrics.k8s_request_duration.clone()),
+            Box::new(metrics.k8s_request_errors.clone()),
+            Box::new(metrics.http_request_duration.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector)?;
+        }
+        Ok(metrics)
+    }
+
+    /// Everything in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("metrics encode as text");
+        String::from_utf8(buffer).expect("the text format is UTF-8")
+    }
+
+    pub fn run_created(&self, run: &TestRun) {
+        self.runs_created
+            .with_label_values(&[executor_label(run)])
+            .inc();
+    }
+
+    pub fn run_finished(&self, run: &TestRun, seconds: f64) {
+        let labels = [run.status.as_str(), executor_label(run)];
+        self.runs_finished.with_label_values(&labels).inc();
+        self.run_duration
+            .with_label_values(&labels)
+            .observe(seconds);
+    }
+
+    /// Counts a run as active until the guard is dropped
+    pub fn active_run(&self) -> ActiveRun<'_> {
+        self.active_runs.inc();
+        ActiveRun(&self.active_runs)
+    }
+}
+
+pub struct ActiveRun<'a>(&'a IntGauge);
+
+impl Drop for ActiveRun<'_> {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+fn executor_label(run: &TestRun) -> &str {
+    run.executor_id.as_deref().unwrap_or("none")
+}
+
+static METRICS: LazyLock<Metrics> =
+    LazyLock::new(|| Metrics::new().expect("metric names are valid and unique"));
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+/// Time a Kubernetes API call and count it if it fails
+pub async fn observe_k8s<T>(operation: &str, call: impl Future<Output = Result<T>>) -> Result<T> {
+    let timer = metrics()
+        .k8s_request_duration
+        .with_label_values(&[operation])
+        .start_timer();
+    let result = call.await;
+    timer.observe_duration();
+    if result.is_err() {
+        metrics()
+            .k8s_request_errors
+            .with_label_values(&[operation])
+            .inc();
+    }
+    result
+}
+
+/// Record how long each request took, labelled with its route template rather
+/// than the path so ids don't multiply the series
+pub async fn track_http_requests(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched| matched.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+    let started = Instant::now();
+
+    let response = next.run(request).await;
+
+    metrics()
+        .http_request_duration
+        .with_label_values(&[method.as_str(), &route, response.status().as_str()])
+        .observe(started.elapsed().as_secs_f64());
+    response
+}
+
+/// `GET /metrics` in the Prometheus text format; unauthenticated like most scrape targets
+pub async fn get_metrics() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+        metrics().render(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_k8s_calls_are_observed() {
+        let errors = || {
+            metrics()
+                .k8s_request_errors
+                .with_label_values(&["test_call"])
+                .get()
+        };
+        let before = errors();
+
+        observe_k8s("test_call", async { Ok(()) }).await.unwrap();
+        observe_k8s::<()>("test_call", async { Err(anyhow::anyhow!("unreachable")) })
+            .await
+            .unwrap_err();
+
+        assert_eq!(errors(), before + 1);
+        let text = metrics().render();
+        assert!(
+            text.contains(r#"sparktest_k8s_request_duration_seconds_count{operation="test_call"}"#)
+        );
+    }
+}
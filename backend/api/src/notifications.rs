@@ -0,0 +1,1170 @@
+use crate::db::traced;
+use crate::encryption::storage_key;
+use crate::error::ApiError;
+use crate::state::AppState;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::Serialize;
+use sparktest_core::{
+    ChannelConfig, NotificationChannel, NotificationRule, NotifyOn, SmtpTls, TestRun,
+};
+use sqlx::types::Json;
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// What message templates can refer to
+const PLACEHOLDERS: [&str; 5] = ["name", "status", "duration", "reason", "url"];
+
+/// How notifications are delivered
+#[derive(Debug, Clone)]
+pub struct NotificationConfig {
+    pub max_attempts: u32,
+    /// Delay before the first retry; it doubles with every further attempt
+    pub retry_delay: Duration,
+    pub http: reqwest::Client,
+    pub smtp_timeout: Duration,
+}
+
+impl Default for NotificationConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            retry_delay: Duration::from_secs(2),
+            http: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("static client configuration"),
+            smtp_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A finished run or suite, as posted to webhook channels
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    /// `run.finished` or `suite.finished`
+    pub event: &'static str,
+    /// The run or suite
+    pub id: Uuid,
+    pub name: String,
+    /// `succeeded` or `failed`
+    pub status: String,
+    /// Status of the previous run of the same definition or suite
+    pub previous_status: Option<String>,
+    pub duration_seconds: Option<i64>,
+    pub reason: Option<String>,
+    pub url: Option<String>,
+    pub definition_id: Option<Uuid>,
+    pub suite_id: Option<Uuid>,
+    pub run_ids: Vec<Uuid>,
+    pub team_id: Option<Uuid>,
+}
+
+const RUN_FINISHED: &str = "run.finished";
+const SUITE_FINISHED: &str = "suite.finished";
+
+impl Notification {
+    pub fn for_run(
+        run: &TestRun,
+        previous_status: Option<String>,
+        public_url: Option<&str>,
+    ) -> Self {
+        Self {
+            event: RUN_FINISHED,
+            id: run.id,
+            name: run.name.clone(),
+            status: run.status.clone(),
+            previous_status,
+            duration_seconds: run.duration.map(i64::from),
+            reason: (run.status == "failed")
+                .then(|| failure_reason(run))
+                .flatten(),
+            url: public_url.map(|url| format!("{url}/runs/{}", run.id)),
+            definition_id: run.definition_id,
+            suite_id: run.suite_id,
+            run_ids: vec![run.id],
+            team_id: run.team_id,
+        }
+    }
+
+    /// One notification for all runs a suite started; it failed if any of them did
+    pub fn for_suite(
+        suite_id: Uuid,
+        name: String,
+        runs: &[TestRun],
+        previous_status: Option<String>,
+        public_url: Option<&str>,
+    ) -> Self {
+        let failed: Vec<&TestRun> = runs.iter().filter(|run| run.status == "failed").collect();
+        let started = runs.iter().map(|run| run.created_at).min();
+        let finished = runs
+            .iter()
+            .filter_map(|run| run.completed.or(run.failed))
+            .max();
+        let reason = (!failed.is_empty()).then(|| {
+            let names: Vec<String> = failed
+                .iter()
+                .map(|run| match failure_reason(run) {
+                    Some(reason) => format!("{} ({reason})", run.name),
+                    None => run.name.clone(),
+                })
+                .collect();
+            format!(
+                "{} of {} runs failed: {}",
+                failed.len(),
+                runs.len(),
+                names.join(", ")
+            )
+        });
+
+        Self {
+            event: SUITE_FINISHED,
+            id: suite_id,
+            name,
+            status: if failed.is_empty() {
+                "succeeded"
+            } else {
+                "failed"
+            }
+            .to_string(),
+            previous_status,
+            duration_seconds: started
+                .zip(finished)
+                .map(|(started, finished)| (finished - started).num_seconds()),
+            reason,
+            url: public_url.map(|url| format!("{url}/suites/{suite_id}")),
+            definition_id: None,
+            suite_id: Some(suite_id),
+            run_ids: runs.iter().map(|run| run.id).collect(),
+            team_id: runs.first().and_then(|run| run.team_id),
+        }
+    }
+}
+
+/// The last thing a failed run logged, which is usually why it failed
+pub fn failure_reason(run: &TestRun) -> Option<String> {
+    run.logs
+        .as_ref()?
+        .iter()
+        .rev()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty())
+        .map(str::to_string)
+}
+
+/// Whether a rule covers a notification at all
+pub fn rule_applies(rule: &NotificationRule, notification: &Notification) -> bool {
+    match (rule.test_definition_id, rule.test_suite_id) {
+        (Some(definition_id), _) => {
+            notification.event == RUN_FINISHED && notification.definition_id == Some(definition_id)
+        }
+        (None, Some(suite_id)) => {
+            notification.event == SUITE_FINISHED && notification.suite_id == Some(suite_id)
+        }
+        (None, None) => {
+            notification.event == RUN_FINISHED
+                && (rule.team_id.is_none() || rule.team_id == notification.team_id)
+        }
+    }
+}
+
+pub fn should_notify(on: NotifyOn, status: &str, previous_status: Option<&str>) -> bool {
+    let failed = status == "failed";
+    match on {
+        NotifyOn::Failure => failed,
+        NotifyOn::Success => !failed,
+        NotifyOn::Finished => true,
+        NotifyOn::StatusChange => match previous_status {
+            Some(previous) => previous != status,
+            None => failed,
+        },
+    }
+}
+
+/// Fill in a rule's template, or the default message
+pub fn render(template: Option<&str>, notification: &Notification) -> String {
+    let default;
+    let template = match template {
+        Some(template) => template,
+        None => {
+            default = default_template(notification);
+            &default
+        }
+    };
+    let values = [
+        notification.name.clone(),
+        notification.status.clone(),
+        format_duration(notification.duration_seconds),
+        notification.reason.clone().unwrap_or_default(),
+        notification.url.clone().unwrap_or_default(),
+    ];
+    PLACEHOLDERS
+        .iter()
+        .zip(values)
+        .fold(template.to_string(), |text, (placeholder, value)| {
+            text.replace(&format!("{{{{{placeholder}}}}}"), &value)
+        })
+}
+
+fn default_template(notification: &Notification) -> String {
+    let mut template = "{{name}} {{status}} after {{duration}}".to_string();
+    if notification.reason.is_some() {
+        template.push_str("\n{{reason}}");
+    }
+    if notification.url.is_some() {
+        template.push_str("\n{{url}}");
+    }
+    template
+}
+
+fn format_duration(seconds: Option<i64>) -> String {
+    match seconds {
+        None => "an unknown time".to_string(),
+        Some(seconds) if seconds < 60 => format!("{seconds}s"),
+        Some(seconds) if seconds < 3600 => format!("{}m {}s", seconds / 60, seconds % 60),
+        Some(seconds) => format!("{}h {}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+pub fn validate_template(template: &str) -> Result<(), ApiError> {
+    if template.trim().is_empty() {
+        return Err(ApiError::validation("template must not be empty"));
+    }
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        let placeholder = &rest[start + 2..start + end];
+        if !PLACEHOLDERS.contains(&placeholder) {
+            return Err(ApiError::validation(format!(
+                "Unknown placeholder '{{{{{placeholder}}}}}'; use one of {}",
+                PLACEHOLDERS.join(", ")
+            )));
+        }
+        rest = &rest[start + end + 2..];
+    }
+    Ok(())
+}
+
+pub fn validate_channel(channel: &NotificationChannel) -> Result<(), ApiError> {
+    if channel.name.is_empty() {
+        return Err(ApiError::validation("name is required"));
+    }
+    match &channel.config {
+        ChannelConfig::Webhook { url } | ChannelConfig::Slack { url } => {
+            if !(url.starts_with("https://") || url.starts_with("http://")) {
+                return Err(ApiError::validation(format!(
+                    "'{url}' is not an http(s) URL"
+                )));
+            }
+        }
+        ChannelConfig::Email {
+            smtp_host,
+            smtp_port,
+            from,
+            to,
+            ..
+        } => {
+            if smtp_host.is_empty() || *smtp_port == 0 {
+                return Err(ApiError::validation("smtp_host and smtp_port are required"));
+            }
+            if to.is_empty() {
+                return Err(ApiError::validation("to needs at least one recipient"));
+            }
+            for address in std::iter::once(from).chain(to) {
+                address.parse::<Mailbox>().map_err(|_| {
+                    ApiError::validation(format!("'{address}' is not an email address"))
+                })?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Notify the rules covering a finished run, in the background
+pub fn notify_run_finished(state: &AppState, run: &TestRun) {
+    let (state, run) = (state.clone(), run.clone());
+    tokio::spawn(async move {
+        let previous_status = match run.definition_id {
+            Some(definition_id) => state.store.record_outcome(definition_id, &run.status).await,
+            None => None,
+        };
+        let notification =
+            Notification::for_run(&run, previous_status, state.reporting.public_url.as_deref());
+        dispatch(&state, &notification).await;
+    });
+}
+
+/// Notify the rules covering a suite once all the runs it started have finished
+pub async fn notify_suite_finished(state: &AppState, run_ids: &[Uuid]) {
+    let mut runs = Vec::new();
+    for run_id in run_ids {
+        // Runs deleted while the suite was running are left out
+        if let Some(run) = state.store.get_run(*run_id).await {
+            runs.push(run);
+        }
+    }
+    let Some(suite_id) = runs.first().and_then(|run| run.suite_id) else {
+        return;
+    };
+    let name = match state.store.get_suite(suite_id).await {
+        Some(suite) => suite.name,
+        None => format!("Test suite {suite_id}"),
+    };
+
+    let mut notification = Notification::for_suite(
+        suite_id,
+        name,
+        &runs,
+        None,
+        state.reporting.public_url.as_deref(),
+    );
+    notification.previous_status = state
+        .store
+        .record_outcome(suite_id, &notification.status)
+        .await;
+    dispatch(state, &notification).await;
+}
+
+async fn dispatch(state: &AppState, notification: &Notification) {
+    for rule in state.store.list_notification_rules().await {
+        if !rule_applies(&rule, notification)
+            || !should_notify(
+                rule.on,
+                &notification.status,
+                notification.previous_status.as_deref(),
+            )
+        {
+            continue;
+        }
+        let Some(channel) = state.store.get_notification_channel(rule.channel_id).await else {
+            continue;
+        };
+        let text = render(rule.template.as_deref(), notification);
+        let (state, notification) = (state.clone(), notification.clone());
+        tokio::spawn(async move {
+            match deliver_notification(&state, &channel, &notification, &text).await {
+                Ok(()) => info!(
+                    "Sent notification about '{}' to '{}'",
+                    notification.name, channel.name
+                ),
+                Err(e) => warn!(
+                    "Failed to notify '{}' about '{}': {:#}",
+                    channel.name, notification.name, e
+                ),
+            }
+        });
+    }
+}
+
+/// Why an attempt failed, and whether trying again could help
+enum SendError {
+    Transient(anyhow::Error),
+    Permanent(anyhow::Error),
+}
+
+/// Send a notification, retrying unreachable hosts, rate limits and server
+/// errors with exponential backoff
+pub async fn deliver_notification(
+    state: &AppState,
+    channel: &NotificationChannel,
+    notification: &Notification,
+    text: &str,
+) -> Result<()> {
+    let config = &state.notifications;
+    let mut delay = config.retry_delay;
+    let mut last_error = anyhow!("no delivery attempts configured");
+
+    for attempt in 1..=config.max_attempts {
+        if attempt > 1 {
+            sleep(delay).await;
+            delay *= 2;
+        }
+        match send(config, channel, notification, text).await {
+            Ok(()) => return Ok(()),
+            Err(SendError::Permanent(e)) => return Err(e),
+            Err(SendError::Transient(e)) => last_error = e,
+        }
+    }
+    Err(last_error).with_context(|| format!("Gave up after {} attempts", config.max_attempts))
+}
+
+/// Body of generic webhook deliveries: the notification plus its rendered text
+#[derive(Serialize)]
+struct WebhookBody<'a> {
+    #[serde(flatten)]
+    notification: &'a Notification,
+    text: &'a str,
+}
+
+async fn send(
+    config: &NotificationConfig,
+    channel: &NotificationChannel,
+    notification: &Notification,
+    text: &str,
+) -> Result<(), SendError> {
+    match &channel.config {
+        ChannelConfig::Webhook { url } => {
+            let body = WebhookBody { notification, text };
+            post_json(config, url, &serde_json::to_value(body).unwrap_or_default()).await
+        }
+        ChannelConfig::Slack { url } => {
+            post_json(config, url, &serde_json::json!({ "text": text })).await
+        }
+        ChannelConfig::Email {
+            smtp_host,
+            smtp_port,
+            smtp_tls,
+            username,
+            password,
+            from,
+            to,
+        } => {
+            let permanent = |e: &dyn std::fmt::Display| SendError::Permanent(anyhow!("{e}"));
+            let mut message = Message::builder()
+                .from(from.parse().map_err(|e| permanent(&e))?)
+                .subject(text.lines().next().unwrap_or_default())
+                .header(ContentType::TEXT_PLAIN);
+            for recipient in to {
+                message = message.to(recipient.parse().map_err(|e| permanent(&e))?);
+            }
+            let message = message.body(text.to_string()).map_err(|e| permanent(&e))?;
+
+            let mut transport = match smtp_tls {
+                SmtpTls::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(smtp_host),
+                SmtpTls::StartTls => {
+                    AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(smtp_host)
+                        .map_err(|e| permanent(&e))?
+                }
+                SmtpTls::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(smtp_host)
+                    .map_err(|e| permanent(&e))?,
+            }
+            .port(*smtp_port)
+            .timeout(Some(config.smtp_timeout));
+            if let Some(username) = username {
+                transport = transport.credentials(Credentials::new(
+                    username.clone(),
+                    password.clone().unwrap_or_default(),
+                ));
+            }
+
+            match transport.build().send(message).await {
+                Ok(_) => Ok(()),
+                // 5xx replies such as an unknown recipient won't get better by retrying
+                Err(e) if e.is_permanent() => Err(SendError::Permanent(
+                    anyhow::Error::new(e).context(format!("{smtp_host} rejected the message")),
+                )),
+                Err(e) => Err(SendError::Transient(
+                    anyhow::Error::new(e).context(format!("Failed to send through {smtp_host}")),
+                )),
+            }
+        }
+    }
+}
+
+async fn post_json(
+    config: &NotificationConfig,
+    url: &str,
+    body: &serde_json::Value,
+) -> Result<(), SendError> {
+    let request = config
+        .http
+        .post(url)
+        .json(body)
+        .header("User-Agent", "sparktest");
+    match request.send().await {
+        Ok(response) if response.status().is_success() => Ok(()),
+        Ok(response) => {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            let error = anyhow!("{url} returned {status}: {}", body.trim());
+            // Anything else in the 4xx range won't get better by retrying
+            if status.is_client_error() && status.as_u16() != 429 {
+                Err(SendError::Permanent(error))
+            } else {
+                Err(SendError::Transient(error))
+            }
+        }
+        Err(e) => Err(SendError::Transient(
+            anyhow::Error::new(e).context(format!("Failed to reach {url}")),
+        )),
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct ChannelRow {
+    id: Uuid,
+    name: String,
+    config: Json<ChannelConfig>,
+    encrypted_password: Option<Vec<u8>>,
+    team_id: Option<Uuid>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct RuleRow {
+    id: Uuid,
+    name: String,
+    channel_id: Uuid,
+    test_definition_id: Option<Uuid>,
+    test_suite_id: Option<Uuid>,
+    on: String,
+    template: Option<String>,
+    team_id: Option<Uuid>,
+    created_at: DateTime<Utc>,
+}
+
+/// Restore the notification channels and rules stored in the database by a
+/// previous process, returning how many of each. SMTP passwords need the key
+/// they were encrypted with.
+pub async fn load_notifications(state: &AppState) -> Result<(usize, usize)> {
+    let Some(db) = &state.db else {
+        return Ok((0, 0));
+    };
+    let channels: Vec<ChannelRow> = traced(
+        "SELECT id, name, config, encrypted_password, team_id, created_at \
+         FROM notification_channels",
+        |sql| sqlx::query_as(sql).fetch_all(db),
+    )
+    .await?;
+    let rules: Vec<RuleRow> = traced(
+        "SELECT id, name, channel_id, test_definition_id, test_suite_id, \"on\", template, \
+         team_id, created_at FROM notification_rules",
+        |sql| sqlx::query_as(sql).fetch_all(db),
+    )
+    .await?;
+
+    let counts = (channels.len(), rules.len());
+    for row in channels {
+        let mut config = row.config.0;
+        if let (ChannelConfig::Email { password, .. }, Some(encrypted)) =
+            (&mut config, &row.encrypted_password)
+        {
+            let key = state.secret_key.as_ref().context(
+                "Notification channels with SMTP passwords are stored, but \
+                 SPARKTEST_SECRET_KEY is not set",
+            )?;
+            *password = Some(key.decrypt(encrypted).with_context(|| {
+                format!(
+                    "Failed to decrypt the SMTP password of channel '{}'",
+                    row.name
+                )
+            })?);
+        }
+        state
+            .store
+            .insert_notification_channel(NotificationChannel {
+                id: row.id,
+                name: row.name,
+                config,
+                team_id: row.team_id,
+                created_at: row.created_at,
+            })
+            .await;
+    }
+    for row in rules {
+        state
+            .store
+            .insert_notification_rule(NotificationRule {
+                id: row.id,
+                name: row.name,
+                channel_id: row.channel_id,
+                test_definition_id: row.test_definition_id,
+                test_suite_id: row.test_suite_id,
+                on: parse_notify_on(&row.on).unwrap_or_default(),
+                template: row.template,
+                team_id: row.team_id,
+                created_at: row.created_at,
+            })
+            .await;
+    }
+    Ok(counts)
+}
+
+/// Write a notification channel to the database, if there is one. The SMTP
+/// password is left out of `config` and stored encrypted next to it.
+pub async fn save_notification_channel(
+    state: &AppState,
+    channel: &NotificationChannel,
+) -> Result<(), ApiError> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    let encrypted_password = match &channel.config {
+        ChannelConfig::Email {
+            password: Some(password),
+            ..
+        } => Some(storage_key(state, "notification channels")?.encrypt(password)),
+        _ => None,
+    };
+    traced(
+        "INSERT INTO notification_channels (id, name, config, encrypted_password, team_id, \
+         created_at) VALUES ($1, $2, $3, $4, $5, $6) \
+         ON CONFLICT (id) DO UPDATE SET name = $2, config = $3, encrypted_password = $4, \
+         team_id = $5",
+        |sql| {
+            sqlx::query(sql)
+                .bind(channel.id)
+                .bind(&channel.name)
+                // The password is never serialized
+                .bind(Json(&channel.config))
+                .bind(&encrypted_password)
+                .bind(channel.team_id)
+                .bind(channel.created_at)
+                .execute(db)
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+/// Remove a notification channel and the rules that send to it from the
+/// database, if there is one
+pub async fn delete_saved_notification_channel(
+    state: &AppState,
+    id: Uuid,
+) -> Result<(), sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    traced("DELETE FROM notification_channels WHERE id = $1", |sql| {
+        sqlx::query(sql).bind(id).execute(db)
+    })
+    .await?;
+    Ok(())
+}
+
+/// Write a notification rule to the database, if there is one
+pub async fn save_notification_rule(
+    state: &AppState,
+    rule: &NotificationRule,
+) -> Result<(), sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    traced(
+        "INSERT INTO notification_rules (id, name, channel_id, test_definition_id, \
+         test_suite_id, \"on\", template, team_id, created_at) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) \
+         ON CONFLICT (id) DO UPDATE SET name = $2, channel_id = $3, test_definition_id = $4, \
+         test_suite_id = $5, \"on\" = $6, template = $7, team_id = $8",
+        |sql| {
+            sqlx::query(sql)
+                .bind(rule.id)
+                .bind(&rule.name)
+                .bind(rule.channel_id)
+                .bind(rule.test_definition_id)
+                .bind(rule.test_suite_id)
+                .bind(notify_on_name(rule.on))
+                .bind(&rule.template)
+                .bind(rule.team_id)
+                .bind(rule.created_at)
+                .execute(db)
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+/// Remove a notification rule from the database, if there is one
+pub async fn delete_saved_notification_rule(state: &AppState, id: Uuid) -> Result<(), sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    traced("DELETE FROM notification_rules WHERE id = $1", |sql| {
+        sqlx::query(sql).bind(id).execute(db)
+    })
+    .await?;
+    Ok(())
+}
+
+fn notify_on_name(on: NotifyOn) -> &'static str {
+    match on {
+        NotifyOn::Failure => "failure",
+        NotifyOn::Success => "success",
+        NotifyOn::Finished => "finished",
+        NotifyOn::StatusChange => "status_change",
+    }
+}
+
+fn parse_notify_on(name: &str) -> Option<NotifyOn> {
+    match name {
+        "failure" => Some(NotifyOn::Failure),
+        "success" => Some(NotifyOn::Success),
+        "finished" => Some(NotifyOn::Finished),
+        "status_change" => Some(NotifyOn::StatusChange),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use crate::encryption::SecretKey;
+    use crate::fake::{FakeBackend, FakeScript};
+    use crate::launch::{launch_suite, RunOrigin};
+    use axum::{http::StatusCode, routing::post, Json, Router};
+    use chrono::Utc;
+    use sparktest_core::{RunPriority, TestDefinition, TestSuite};
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// A webhook receiver that records requests and fails the first few
+    struct HookStub {
+        url: String,
+        received: Arc<Mutex<Vec<serde_json::Value>>>,
+    }
+
+    impl HookStub {
+        async fn start(failures: usize, failure_status: StatusCode) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}/hook", listener.local_addr().unwrap());
+            let received = Arc::new(Mutex::new(Vec::new()));
+
+            let log = received.clone();
+            let app = Router::new().route(
+                "/hook",
+                post(move |Json(body): Json<serde_json::Value>| async move {
+                    let mut log = log.lock().unwrap();
+                    log.push(body);
+                    if log.len() <= failures {
+                        failure_status
+                    } else {
+                        StatusCode::OK
+                    }
+                }),
+            );
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+            Self { url, received }
+        }
+
+        fn received(&self) -> Vec<serde_json::Value> {
+            self.received.lock().unwrap().clone()
+        }
+
+        async fn wait_for(&self, count: usize) -> Vec<serde_json::Value> {
+            for _ in 0..300 {
+                if self.received().len() >= count {
+                    return self.received();
+                }
+                sleep(Duration::from_millis(10)).await;
+            }
+            panic!("expected {count} requests, got {:?}", self.received());
+        }
+    }
+
+    /// Just enough of an SMTP server to accept mail and keep it
+    struct SmtpStub {
+        port: u16,
+        messages: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl SmtpStub {
+        async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let messages = Arc::new(Mutex::new(Vec::new()));
+
+            let inbox = messages.clone();
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let inbox = inbox.clone();
+                    tokio::spawn(async move {
+                        let (read, mut write) = stream.into_split();
+                        let mut lines = BufReader::new(read).lines();
+                        write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            let command = line.to_ascii_uppercase();
+                            let reply: &[u8] = if command.starts_with("EHLO") {
+                                b"250 localhost\r\n"
+                            } else if command == "DATA" {
+                                write.write_all(b"354 End with .\r\n").await.unwrap();
+                                let mut message = Vec::new();
+                                while let Ok(Some(line)) = lines.next_line().await {
+                                    if line == "." {
+                                        break;
+                                    }
+                                    message.push(line);
+                                }
+                                inbox.lock().unwrap().push(message.join("\n"));
+                                b"250 Queued\r\n"
+                            } else if command == "QUIT" {
+                                write.write_all(b"221 Bye\r\n").await.ok();
+                                break;
+                            } else {
+                                b"250 OK\r\n"
+                            };
+                            write.write_all(reply).await.unwrap();
+                        }
+                    });
+                }
+            });
+
+            Self { port, messages }
+        }
+
+        fn messages(&self) -> Vec<String> {
+            self.messages.lock().unwrap().clone()
+        }
+    }
+
+    fn channel(config: ChannelConfig) -> NotificationChannel {
+        NotificationChannel {
+            id: Uuid::new_v4(),
+            name: "alerts".to_string(),
+            config,
+            team_id: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn rule(channel_id: Uuid, on: NotifyOn) -> NotificationRule {
+        NotificationRule {
+            id: Uuid::new_v4(),
+            name: "nightly".to_string(),
+            channel_id,
+            test_definition_id: None,
+            test_suite_id: None,
+            on,
+            template: None,
+            team_id: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn failed_run() -> TestRun {
+        let mut run = TestRun::new(
+            "K6 Performance Load Tests".to_string(),
+            "grafana/k6".to_string(),
+            vec![],
+        );
+        run.status = "failed".to_string();
+        run.duration = Some(65);
+        run.logs = Some(vec![
+            "running (1m05s)".to_string(),
+            "thresholds on metrics 'http_req_duration' have been crossed".to_string(),
+            String::new(),
+        ]);
+        run
+    }
+
+    fn state_with_fast_retries() -> AppState {
+        let mut state = AppState::default();
+        state.notifications.retry_delay = Duration::from_millis(10);
+        state.reporting.public_url = Some("https://sparktest.example.com".to_string());
+        state
+    }
+
+    fn definition(name: &str, image: &str) -> TestDefinition {
+        TestDefinition {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            description: String::new(),
+            image: image.to_string(),
+            commands: vec!["test".to_string()],
+            created_at: Utc::now(),
+            executor_id: None,
+            variables: None,
+            labels: None,
+            target: None,
+            team_id: None,
+            resources: None,
+            privileged: false,
+            secrets: Vec::new(),
+            source: None,
+            files: Vec::new(),
+            priority: RunPriority::default(),
+            job_labels: BTreeMap::new(),
+            job_annotations: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_render_messages() {
+        let run = failed_run();
+        let notification = Notification::for_run(&run, None, Some("https://sparktest.example.com"));
+        assert_eq!(
+            render(None, &notification),
+            format!(
+                "K6 Performance Load Tests failed after 1m 5s\n\
+                 thresholds on metrics 'http_req_duration' have been crossed\n\
+                 https://sparktest.example.com/runs/{}",
+                run.id
+            )
+        );
+        assert_eq!(
+            render(Some(":boom: {{name}} {{status}}"), &notification),
+            ":boom: K6 Performance Load Tests failed"
+        );
+
+        assert!(validate_template("{{name}} took {{duration}}").is_ok());
+        assert!(validate_template("{{name}} by {{author}}").is_err());
+        assert!(validate_template("{{ name }}").is_err());
+        assert!(validate_template("  ").is_err());
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(Some(42)), "42s");
+        assert_eq!(format_duration(Some(605)), "10m 5s");
+        assert_eq!(format_duration(Some(7380)), "2h 3m");
+    }
+
+    #[test]
+    fn test_should_notify() {
+        assert!(should_notify(NotifyOn::Failure, "failed", None));
+        assert!(!should_notify(NotifyOn::Failure, "succeeded", None));
+        assert!(should_notify(NotifyOn::Success, "succeeded", None));
+        assert!(should_notify(NotifyOn::Finished, "succeeded", None));
+        assert!(should_notify(
+            NotifyOn::StatusChange,
+            "succeeded",
+            Some("failed")
+        ));
+        assert!(!should_notify(
+            NotifyOn::StatusChange,
+            "failed",
+            Some("failed")
+        ));
+        assert!(should_notify(NotifyOn::StatusChange, "failed", None));
+        assert!(!should_notify(NotifyOn::StatusChange, "succeeded", None));
+    }
+
+    #[test]
+    fn test_rule_scope() {
+        let channel_id = Uuid::new_v4();
+        let mut run = failed_run();
+        run.definition_id = Some(Uuid::new_v4());
+        let notification = Notification::for_run(&run, None, None);
+
+        let everything = rule(channel_id, NotifyOn::Failure);
+        assert!(rule_applies(&everything, &notification));
+
+        let mut other_definition = rule(channel_id, NotifyOn::Failure);
+        other_definition.test_definition_id = Some(Uuid::new_v4());
+        assert!(!rule_applies(&other_definition, &notification));
+
+        let mut other_team = rule(channel_id, NotifyOn::Failure);
+        other_team.team_id = Some(Uuid::new_v4());
+        assert!(!rule_applies(&other_team, &notification));
+
+        let mut suite_rule = rule(channel_id, NotifyOn::Failure);
+        suite_rule.test_suite_id = Some(Uuid::new_v4());
+        assert!(!rule_applies(&suite_rule, &notification));
+    }
+
+    #[tokio::test]
+    async fn test_webhook_delivery_is_retried() {
+        let stub = HookStub::start(2, StatusCode::BAD_GATEWAY).await;
+        let state = state_with_fast_retries();
+        let webhook = channel(ChannelConfig::Webhook {
+            url: stub.url.clone(),
+        });
+        let run = failed_run();
+        let notification = Notification::for_run(&run, Some("succeeded".to_string()), None);
+
+        deliver_notification(&state, &webhook, &notification, "text")
+            .await
+            .unwrap();
+
+        let received = stub.received();
+        assert_eq!(received.len(), 3);
+        assert_eq!(received[2]["event"], "run.finished");
+        assert_eq!(received[2]["status"], "failed");
+        assert_eq!(received[2]["previous_status"], "succeeded");
+        assert_eq!(received[2]["duration_seconds"], 65);
+        assert_eq!(received[2]["text"], "text");
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_retried() {
+        let stub = HookStub::start(10, StatusCode::NOT_FOUND).await;
+        let state = state_with_fast_retries();
+        let slack = channel(ChannelConfig::Slack {
+            url: stub.url.clone(),
+        });
+        let notification = Notification::for_run(&failed_run(), None, None);
+
+        let error = deliver_notification(&state, &slack, &notification, "text")
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("404"), "{error:#}");
+        assert_eq!(stub.received(), vec![serde_json::json!({ "text": "text" })]);
+    }
+
+    #[tokio::test]
+    async fn test_email_is_sent_over_smtp() {
+        let smtp = SmtpStub::start().await;
+        let state = state_with_fast_retries();
+        let email = channel(ChannelConfig::Email {
+            smtp_host: "127.0.0.1".to_string(),
+            smtp_port: smtp.port,
+            smtp_tls: SmtpTls::None,
+            username: None,
+            password: None,
+            from: "SparkTest <sparktest@example.com>".to_string(),
+            to: vec!["qa@example.com".to_string()],
+        });
+        validate_channel(&email).unwrap();
+        let notification = Notification::for_run(&failed_run(), None, None);
+        let text = render(None, &notification);
+
+        deliver_notification(&state, &email, &notification, &text)
+            .await
+            .unwrap();
+
+        let messages = smtp.messages();
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].contains("Subject: K6 Performance Load Tests failed after 1m 5s"));
+        assert!(messages[0].contains("To: qa@example.com"));
+        assert!(messages[0].contains("have been crossed"));
+    }
+
+    #[tokio::test]
+    async fn test_failed_suite_notifies_once() {
+        let stub = HookStub::start(0, StatusCode::OK).await;
+        let backend = Arc::new(FakeBackend::new(
+            FakeScript::succeed(&["ok"]).with_duration(Duration::from_millis(20)),
+        ));
+        backend.script("zaproxy", FakeScript::fail(2, &["2 high risk alerts"]));
+        let mut state = AppState::new(backend);
+        state.runner.poll_interval = Duration::from_millis(5);
+
+        let load = definition("K6 Performance Load Tests", "grafana/k6");
+        let scan = definition("OWASP Security Scan", "zaproxy");
+        state.store.insert_definition(load.clone()).await.unwrap();
+        state.store.insert_definition(scan.clone()).await.unwrap();
+        let suite = TestSuite {
+            id: Uuid::new_v4(),
+            name: "Nightly".to_string(),
+            description: String::new(),
+            test_definition_ids: vec![load.id, scan.id],
+            created_at: Utc::now(),
+            execution_mode: "parallel".to_string(),
+            labels: None,
+            team_id: None,
+        };
+        state.store.insert_suite(suite.clone()).await.unwrap();
+
+        let webhook = channel(ChannelConfig::Webhook {
+            url: stub.url.clone(),
+        });
+        state
+            .store
+            .insert_notification_channel(webhook.clone())
+            .await;
+        let mut on_suite_failure = rule(webhook.id, NotifyOn::Failure);
+        on_suite_failure.test_suite_id = Some(suite.id);
+        state.store.insert_notification_rule(on_suite_failure).await;
+
+        launch_suite(&state, &suite, RunOrigin::default())
+            .await
+            .unwrap();
+
+        let received = stub.wait_for(1).await;
+        assert_eq!(received[0]["event"], "suite.finished");
+        assert_eq!(received[0]["status"], "failed");
+        assert_eq!(
+            received[0]["reason"],
+            "1 of 2 runs failed: OWASP Security Scan (2 high risk alerts)"
+        );
+        assert_eq!(received[0]["run_ids"].as_array().unwrap().len(), 2);
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(stub.received().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_channels_and_rules_survive_a_restart() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        let key = SecretKey::from_hex(&"42".repeat(32)).unwrap();
+        let mut state = AppState {
+            db: Some(db.clone()),
+            ..AppState::default()
+        };
+        let email = channel(ChannelConfig::Email {
+            smtp_host: "smtp.example.com".to_string(),
+            smtp_port: 587,
+            smtp_tls: SmtpTls::StartTls,
+            username: Some("sparktest".to_string()),
+            password: Some("hunter2".to_string()),
+            from: "sparktest@example.com".to_string(),
+            to: vec!["qa@example.com".to_string()],
+        });
+        let webhook = channel(ChannelConfig::Webhook {
+            url: "https://hooks.example.com/sparktest".to_string(),
+        });
+        // Only SMTP passwords need the key
+        let error = save_notification_channel(&state, &email).await.unwrap_err();
+        assert_eq!(error.status(), StatusCode::FORBIDDEN);
+        save_notification_channel(&state, &webhook).await.unwrap();
+        state.secret_key = Some(key.clone());
+        save_notification_channel(&state, &email).await.unwrap();
+        let mut rule = rule(email.id, NotifyOn::StatusChange);
+        rule.template = Some("{{name}} is now {{status}}".to_string());
+        save_notification_rule(&state, &rule).await.unwrap();
+
+        let (config, encrypted): (serde_json::Value, Vec<u8>) = sqlx::query_as(
+            "SELECT config, encrypted_password FROM notification_channels WHERE id = $1",
+        )
+        .bind(email.id)
+        .fetch_one(&db)
+        .await
+        .unwrap();
+        assert!(!config.to_string().contains("hunter2"));
+        assert!(!String::from_utf8_lossy(&encrypted).contains("hunter2"));
+
+        let restarted = AppState {
+            db: Some(db.clone()),
+            secret_key: Some(key),
+            ..AppState::default()
+        };
+        load_notifications(&restarted).await.unwrap();
+        let loaded = restarted
+            .store
+            .get_notification_channel(email.id)
+            .await
+            .unwrap();
+        let ChannelConfig::Email { password, .. } = loaded.config else {
+            panic!("expected an email channel");
+        };
+        assert_eq!(password.as_deref(), Some("hunter2"));
+        assert!(restarted
+            .store
+            .get_notification_channel(webhook.id)
+            .await
+            .is_some());
+        let loaded = restarted
+            .store
+            .get_notification_rule(rule.id)
+            .await
+            .unwrap();
+        assert_eq!(loaded.on, NotifyOn::StatusChange);
+        assert_eq!(loaded.template, rule.template);
+
+        // Deleting a channel deletes its rules
+        delete_saved_notification_channel(&restarted, email.id)
+            .await
+            .unwrap();
+        delete_saved_notification_channel(&restarted, webhook.id)
+            .await
+            .unwrap();
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM notification_rules WHERE id = $1")
+                .bind(rule.id)
+                .fetch_one(&db)
+                .await
+                .unwrap();
+        assert_eq!(count, 0);
+    }
+}
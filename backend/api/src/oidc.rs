@@ -0,0 +1,639 @@
+use crate::auth::Principal;
+use crate::error::ApiError;
+use crate::store::Store;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use jsonwebtoken::{
+    decode, decode_header,
+    jwk::{Jwk, JwkSet},
+    Algorithm, DecodingKey, Validation,
+};
+use serde::Deserialize;
+use sparktest_core::{Role, TokenScope, User};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tokio::time::{Duration, Instant};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+/// What membership of an identity provider group grants in SparkTest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupGrant {
+    /// Members are instance admins
+    Admin,
+    /// Members hold `role` in the team with this name
+    Team { team: String, role: Role },
+}
+
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub audience: String,
+    /// Fetch keys from here instead of the issuer's discovery document
+    pub jwks_url: Option<String>,
+    /// Claim holding the user's groups; dots select nested claims, e.g. `realm_access.roles`
+    pub groups_claim: String,
+    pub group_grants: HashMap<String, Vec<GroupGrant>>,
+    /// How long fetched keys are trusted before they are refreshed
+    pub jwks_ttl: Duration,
+    /// Minimum time between refreshes triggered by tokens signed with an unknown key
+    pub min_refresh_interval: Duration,
+}
+
+impl OidcConfig {
+    pub fn new(issuer: impl Into<String>, audience: impl Into<String>) -> Self {
+        Self {
+            issuer: issuer.into(),
+            audience: audience.into(),
+            jwks_url: None,
+            groups_claim: "groups".to_string(),
+            group_grants: HashMap::new(),
+            jwks_ttl: Duration::from_secs(600),
+            min_refresh_interval: Duration::from_secs(30),
+        }
+    }
+
+    /// OIDC is enabled by `SPARKTEST_OIDC_ISSUER`, which also needs
+    /// `SPARKTEST_OIDC_AUDIENCE`. `SPARKTEST_OIDC_JWKS_URL`,
+    /// `SPARKTEST_OIDC_GROUPS_CLAIM` and `SPARKTEST_OIDC_GROUP_ROLES` are optional.
+    pub fn from_env() -> Result<Option<Self>> {
+        let Ok(issuer) = std::env::var("SPARKTEST_OIDC_ISSUER") else {
+            return Ok(None);
+        };
+        let audience = std::env::var("SPARKTEST_OIDC_AUDIENCE")
+            .context("SPARKTEST_OIDC_AUDIENCE is required when SPARKTEST_OIDC_ISSUER is set")?;
+
+        let mut config = Self::new(issuer, audience);
+        config.jwks_url = std::env::var("SPARKTEST_OIDC_JWKS_URL").ok();
+        if let Ok(claim) = std::env::var("SPARKTEST_OIDC_GROUPS_CLAIM") {
+            config.groups_claim = claim;
+        }
+        if let Ok(spec) = std::env::var("SPARKTEST_OIDC_GROUP_ROLES") {
+            config.group_grants = parse_group_grants(&spec)?;
+        }
+        Ok(Some(config))
+    }
+}
+
+/// Parse `group=admin,group=team:role` pairs, e.g.
+/// `sparktest-admins=admin,payments-dev=payments:runner`
+pub fn parse_group_grants(spec: &str) -> Result<HashMap<String, Vec<GroupGrant>>> {
+    let mut grants: HashMap<String, Vec<GroupGrant>> = HashMap::new();
+
+    for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let (group, grant) = entry
+            .split_once('=')
+            .with_context(|| format!("Expected 'group=grant' in '{entry}'"))?;
+        let grant = match grant.split_once(':') {
+            None if grant == "admin" => GroupGrant::Admin,
+            Some((team, role)) => GroupGrant::Team {
+                team: team.to_string(),
+                role: serde_json::from_value(serde_json::json!(role))
+                    .with_context(|| format!("Unknown role '{role}' in '{entry}'"))?,
+            },
+            None => anyhow::bail!("Expected 'admin' or 'team:role' in '{entry}'"),
+        };
+        grants.entry(group.to_string()).or_default().push(grant);
+    }
+
+    Ok(grants)
+}
+
+/// The parts of a validated ID or access token that SparkTest uses
+#[derive(Debug, Clone)]
+pub struct OidcClaims {
+    pub subject: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub groups: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct DiscoveryDocument {
+    jwks_uri: String,
+}
+
+#[derive(Default)]
+struct JwksCache {
+    jwks_url: Option<String>,
+    keys: Option<JwkSet>,
+    fetched_at: Option<Instant>,
+    /// Last refresh, successful or not
+    attempted_at: Option<Instant>,
+}
+
+/// Validates bearer JWTs against an OIDC issuer, caching its signing keys
+#[derive(Clone)]
+pub struct OidcValidator {
+    config: Arc<OidcConfig>,
+    http: reqwest::Client,
+    cache: Arc<RwLock<JwksCache>>,
+}
+
+impl OidcValidator {
+    pub fn new(config: OidcConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            // Refreshes hold the key cache, so a hanging issuer must not stall sign-ins
+            http: reqwest::Client::builder()
+                .connect_timeout(Duration::from_secs(5))
+                .timeout(Duration::from_secs(10))
+                .build()
+                .expect("static client configuration"),
+            cache: Arc::default(),
+        }
+    }
+
+    pub fn config(&self) -> &OidcConfig {
+        &self.config
+    }
+
+    /// Check signature, issuer, audience and expiry, and extract the claims
+    pub async fn validate(&self, token: &str) -> Result<OidcClaims, ApiError> {
+        let header = decode_header(token)
+            .map_err(|e| ApiError::unauthorized(format!("Malformed bearer token: {e}")))?;
+        // Shared-secret algorithms would let anyone holding the JWKS forge tokens
+        if matches!(
+            header.alg,
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+        ) {
+            return Err(ApiError::unauthorized(format!(
+                "Unsupported token signing algorithm {:?}",
+                header.alg
+            )));
+        }
+
+        let jwk = self.key_for(header.kid.as_deref()).await?;
+        let key = DecodingKey::from_jwk(&jwk)
+            .map_err(|e| ApiError::unauthorized(format!("Unusable signing key: {e}")))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.config.issuer]);
+        validation.set_audience(&[&self.config.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud", "sub"]);
+
+        let claims = decode::<serde_json::Value>(token, &key, &validation)
+            .map_err(|e| ApiError::unauthorized(format!("Invalid bearer token: {e}")))?
+            .claims;
+
+        let string_claim = |name: &str| claims[name].as_str().map(str::to_string);
+        Ok(OidcClaims {
+            subject: string_claim("sub").unwrap_or_default(),
+            name: string_claim("preferred_username").or_else(|| string_claim("name")),
+            email: string_claim("email"),
+            groups: groups_from(&claims, &self.config.groups_claim),
+        })
+    }
+
+    /// Validate a token and resolve it to a principal, provisioning the user on
+    /// first sign-in. Group grants are added on top of the user's team memberships.
+    pub async fn authenticate(&self, store: &Store, token: &str) -> Result<Principal, ApiError> {
+        let claims = self.validate(token).await?;
+
+        let user = match store.find_user_by_subject(&claims.subject).await {
+            Some(user) => user,
+            None => {
+                let user = User {
+                    id: Uuid::new_v4(),
+                    name: claims
+                        .name
+                        .clone()
+                        .or_else(|| claims.email.clone())
+                        .unwrap_or_else(|| claims.subject.clone()),
+                    email: claims.email.clone(),
+                    subject: Some(claims.subject.clone()),
+                    is_admin: false,
+                    created_at: Utc::now(),
+                };
+                // A concurrent first sign-in may have provisioned them meanwhile
+                let user = store.provision_user(user).await?;
+                debug!("Provisioned user '{}' from OIDC", user.name);
+                user
+            }
+        };
+
+        let mut is_admin = user.is_admin;
+        let mut roles = store.roles_of(user.id).await;
+        let grants: Vec<GroupGrant> = claims
+            .groups
+            .iter()
+            .filter_map(|group| self.config.group_grants.get(group))
+            .flatten()
+            .cloned()
+            .collect();
+        for grant in &grants {
+            match grant {
+                GroupGrant::Admin => is_admin = true,
+                GroupGrant::Team { team, role } => match store.find_team_by_name(team).await {
+                    Some(team) => {
+                        let current = roles.entry(team.id).or_insert(*role);
+                        *current = (*current).max(*role);
+                    }
+                    None => debug!("Ignoring group grant for unknown team '{}'", team),
+                },
+            }
+        }
+
+        Ok(Principal {
+            token_id: None,
+            user_id: Some(user.id),
+            name: user.name,
+            // Roles, not token scopes, limit what SSO users can do
+            scope: TokenScope::Admin,
+            is_admin,
+            roles,
+        })
+    }
+
+    /// Find the signing key for `kid`, refreshing the cache when it has expired
+    /// or the issuer has rotated to a key we haven't seen yet
+    async fn key_for(&self, kid: Option<&str>) -> Result<Jwk, ApiError> {
+        {
+            let cache = self.cache.read().await;
+            let fresh = cache
+                .fetched_at
+                .is_some_and(|at| at.elapsed() < self.config.jwks_ttl);
+            if let (true, Some(jwk)) = (fresh, cache.keys.as_ref().and_then(|k| find_key(k, kid))) {
+                return Ok(jwk);
+            }
+        }
+
+        let mut cache = self.cache.write().await;
+        // Another request may have refreshed the keys while we waited for the lock,
+        // and a failing issuer isn't retried more often than a working one
+        let recently_attempted = cache
+            .attempted_at
+            .is_some_and(|at| at.elapsed() < self.config.min_refresh_interval);
+        if recently_attempted && cache.keys.is_none() {
+            return Err(ApiError::upstream("OIDC signing keys are unavailable"));
+        }
+        if !recently_attempted {
+            cache.attempted_at = Some(Instant::now());
+            match self.fetch_keys(&mut cache).await {
+                Ok(keys) => {
+                    cache.keys = Some(keys);
+                    cache.fetched_at = Some(Instant::now());
+                }
+                // Keep serving the keys we have if the issuer is briefly unreachable
+                Err(e) if cache.keys.is_some() => warn!("Failed to refresh OIDC keys: {:#}", e),
+                Err(e) => {
+                    return Err(ApiError::upstream("Failed to fetch OIDC signing keys")
+                        .with_details(serde_json::json!({ "cause": format!("{e:#}") })))
+                }
+            }
+        }
+
+        cache
+            .keys
+            .as_ref()
+            .and_then(|keys| find_key(keys, kid))
+            .ok_or_else(|| {
+                ApiError::unauthorized(format!(
+                    "Token is signed with unknown key '{}'",
+                    kid.unwrap_or_default()
+                ))
+            })
+    }
+
+    async fn fetch_keys(&self, cache: &mut JwksCache) -> Result<JwkSet> {
+        let jwks_url = match (&self.config.jwks_url, &cache.jwks_url) {
+            (Some(url), _) | (None, Some(url)) => url.clone(),
+            (None, None) => {
+                let url = format!(
+                    "{}/.well-known/openid-configuration",
+                    self.config.issuer.trim_end_matches('/')
+                );
+                let discovery: DiscoveryDocument = self
+                    .http
+                    .get(&url)
+                    .send()
+                    .await
+                    .and_then(|r| r.error_for_status())
+                    .with_context(|| format!("Failed to fetch {url}"))?
+                    .json()
+                    .await
+                    .with_context(|| format!("Invalid discovery document at {url}"))?;
+                cache.jwks_url = Some(discovery.jwks_uri.clone());
+                discovery.jwks_uri
+            }
+        };
+
+        self.http
+            .get(&jwks_url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .with_context(|| format!("Failed to fetch {jwks_url}"))?
+            .json()
+            .await
+            .with_context(|| format!("Invalid JWKS at {jwks_url}"))
+    }
+}
+
+/// Tokens without a `kid` are accepted only when the issuer has a single key
+fn find_key(keys: &JwkSet, kid: Option<&str>) -> Option<Jwk> {
+    match kid {
+        Some(kid) => keys.find(kid).cloned(),
+        None if keys.keys.len() == 1 => keys.keys.first().cloned(),
+        None => None,
+    }
+}
+
+/// Read a group claim given as an array or a single string, following dotted paths
+fn groups_from(claims: &serde_json::Value, claim: &str) -> Vec<String> {
+    let value = claim
+        .split('.')
+        .try_fold(claims, |value, key| value.get(key));
+
+    match value {
+        Some(serde_json::Value::Array(groups)) => groups
+            .iter()
+            .filter_map(|g| g.as_str().map(str::to_string))
+            .collect(),
+        Some(serde_json::Value::String(group)) => vec![group.clone()],
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::routes::create_app_with_state;
+    use crate::state::AppState;
+    use axum::{body::Body, http::Request, routing::get, Json, Router};
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    const KEY1_PEM: &str = include_str!("../testdata/oidc/key1.pem");
+    const KEY1_JWKS: &str = include_str!("../testdata/oidc/key1.jwks.json");
+    const KEY2_PEM: &str = include_str!("../testdata/oidc/key2.pem");
+    const KEY2_JWKS: &str = include_str!("../testdata/oidc/key2.jwks.json");
+    const AUDIENCE: &str = "sparktest";
+
+    /// A local issuer serving a discovery document and a swappable static JWKS
+    struct MockIssuer {
+        url: String,
+        jwks: Arc<Mutex<String>>,
+        jwks_requests: Arc<AtomicUsize>,
+    }
+
+    impl MockIssuer {
+        async fn start(jwks: &str) -> Self {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let url = format!("http://{}", listener.local_addr().unwrap());
+            let jwks = Arc::new(Mutex::new(jwks.to_string()));
+            let jwks_requests = Arc::new(AtomicUsize::new(0));
+
+            let discovery = serde_json::json!({
+                "issuer": url,
+                "jwks_uri": format!("{url}/jwks"),
+            });
+            let (served, requests) = (jwks.clone(), jwks_requests.clone());
+            let app = Router::new()
+                .route(
+                    "/.well-known/openid-configuration",
+                    get(move || async move { Json(discovery) }),
+                )
+                .route(
+                    "/jwks",
+                    get(move || async move {
+                        requests.fetch_add(1, Ordering::SeqCst);
+                        let body = served.lock().unwrap().clone();
+                        ([("content-type", "application/json")], body)
+                    }),
+                );
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+            Self {
+                url,
+                jwks,
+                jwks_requests,
+            }
+        }
+
+        fn rotate(&self, jwks: &str) {
+            *self.jwks.lock().unwrap() = jwks.to_string();
+        }
+
+        fn config(&self) -> OidcConfig {
+            let mut config = OidcConfig::new(&self.url, AUDIENCE);
+            config.min_refresh_interval = Duration::ZERO;
+            config
+        }
+
+        fn token(&self, pem: &str, kid: &str, claims: serde_json::Value) -> String {
+            let mut body = serde_json::json!({
+                "iss": self.url,
+                "aud": AUDIENCE,
+                "sub": "user-123",
+                "exp": Utc::now().timestamp() + 300,
+            });
+            body.as_object_mut()
+                .unwrap()
+                .extend(claims.as_object().unwrap().clone());
+
+            let mut header = Header::new(Algorithm::RS256);
+            header.kid = Some(kid.to_string());
+            encode(
+                &header,
+                &body,
+                &EncodingKey::from_rsa_pem(pem.as_bytes()).unwrap(),
+            )
+            .unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_validates_signature_issuer_and_audience() {
+        let issuer = MockIssuer::start(KEY1_JWKS).await;
+        let validator = OidcValidator::new(issuer.config());
+
+        let token = issuer.token(
+            KEY1_PEM,
+            "key1",
+            serde_json::json!({ "email": "dev@example.com", "groups": ["qa"] }),
+        );
+        let claims = validator.validate(&token).await.unwrap();
+        assert_eq!(claims.subject, "user-123");
+        assert_eq!(claims.email.as_deref(), Some("dev@example.com"));
+        assert_eq!(claims.groups, vec!["qa"]);
+
+        let rejected = [
+            issuer.token(KEY1_PEM, "key1", serde_json::json!({ "aud": "other" })),
+            issuer.token(
+                KEY1_PEM,
+                "key1",
+                serde_json::json!({ "iss": "https://evil" }),
+            ),
+            issuer.token(
+                KEY1_PEM,
+                "key1",
+                serde_json::json!({ "exp": Utc::now().timestamp() - 600 }),
+            ),
+            // Signed with a key the issuer doesn't publish
+            issuer.token(KEY2_PEM, "key1", serde_json::json!({})),
+            "not-a-jwt".to_string(),
+        ];
+        for token in rejected {
+            let error = validator.validate(&token).await.unwrap_err();
+            assert_eq!(error.status(), axum::http::StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_jwks_is_cached_and_follows_rotation() {
+        let issuer = MockIssuer::start(KEY1_JWKS).await;
+        let validator = OidcValidator::new(issuer.config());
+
+        for _ in 0..3 {
+            let token = issuer.token(KEY1_PEM, "key1", serde_json::json!({}));
+            validator.validate(&token).await.unwrap();
+        }
+        assert_eq!(issuer.jwks_requests.load(Ordering::SeqCst), 1);
+
+        // The issuer rotates to a new key; the unknown kid triggers a refresh
+        issuer.rotate(KEY2_JWKS);
+        let token = issuer.token(KEY2_PEM, "key2", serde_json::json!({}));
+        validator.validate(&token).await.unwrap();
+        assert_eq!(issuer.jwks_requests.load(Ordering::SeqCst), 2);
+
+        // Tokens signed with the retired key stop working
+        let token = issuer.token(KEY1_PEM, "key1", serde_json::json!({}));
+        assert!(validator.validate(&token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_kid_refreshes_are_rate_limited() {
+        let issuer = MockIssuer::start(KEY1_JWKS).await;
+        let mut config = issuer.config();
+        config.min_refresh_interval = Duration::from_secs(60);
+        let validator = OidcValidator::new(config);
+
+        for _ in 0..3 {
+            let token = issuer.token(KEY2_PEM, "key2", serde_json::json!({}));
+            assert!(validator.validate(&token).await.is_err());
+        }
+        assert_eq!(issuer.jwks_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_refreshes_are_rate_limited() {
+        let issuer = MockIssuer::start("not a key set").await;
+        let mut config = issuer.config();
+        config.min_refresh_interval = Duration::from_secs(60);
+        let validator = OidcValidator::new(config);
+
+        for _ in 0..3 {
+            let token = issuer.token(KEY1_PEM, "key1", serde_json::json!({}));
+            let error = validator.validate(&token).await.unwrap_err();
+            assert_eq!(error.status(), axum::http::StatusCode::BAD_GATEWAY);
+        }
+        assert_eq!(issuer.jwks_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_group_claims_map_to_roles() {
+        let issuer = MockIssuer::start(KEY1_JWKS).await;
+        let mut config = issuer.config();
+        config.groups_claim = "realm_access.roles".to_string();
+        config.group_grants =
+            parse_group_grants("payments-dev=payments:runner,payments-leads=payments:maintainer")
+                .unwrap();
+        let validator = OidcValidator::new(config);
+
+        let store = Store::default();
+        let team = sparktest_core::Team {
+            id: Uuid::new_v4(),
+            name: "payments".to_string(),
+            description: None,
+            members: Vec::new(),
+            created_at: Utc::now(),
+        };
+        store.insert_team(team.clone()).await.unwrap();
+
+        let token = issuer.token(
+            KEY1_PEM,
+            "key1",
+            serde_json::json!({
+                "preferred_username": "dana",
+                "realm_access": { "roles": ["payments-dev", "payments-leads", "unrelated"] },
+            }),
+        );
+        let principal = validator.authenticate(&store, &token).await.unwrap();
+        assert_eq!(principal.name, "dana");
+        assert!(!principal.is_admin);
+        assert_eq!(principal.role_in(team.id), Some(Role::Maintainer));
+
+        // The user is provisioned once and reused on later sign-ins
+        let again = validator.authenticate(&store, &token).await.unwrap();
+        assert_eq!(again.user_id, principal.user_id);
+        assert_eq!(store.list_users().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_router_accepts_oidc_tokens() {
+        let issuer = MockIssuer::start(KEY1_JWKS).await;
+        let mut config = issuer.config();
+        config.group_grants = parse_group_grants("sparktest-admins=admin").unwrap();
+
+        let state = AppState {
+            oidc: Some(OidcValidator::new(config)),
+            ..Default::default()
+        };
+        let app = create_app_with_state(state);
+
+        let token = issuer.token(
+            KEY1_PEM,
+            "key1",
+            serde_json::json!({ "groups": "sparktest-admins" }),
+        );
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/api/tokens")
+                    .header("authorization", format!("Bearer {token}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::OK);
+
+        let forged = issuer.token(KEY2_PEM, "key1", serde_json::json!({}));
+        let response = app
+            .oneshot(
+                Request::get("/api/test-runs")
+                    .header("authorization", format!("Bearer {forged}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), axum::http::StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_parse_group_grants() {
+        let grants = parse_group_grants("ops=admin, qa=web:viewer,qa=api:runner").unwrap();
+        assert_eq!(grants["ops"], vec![GroupGrant::Admin]);
+        assert_eq!(
+            grants["qa"],
+            vec![
+                GroupGrant::Team {
+                    team: "web".to_string(),
+                    role: Role::Viewer
+                },
+                GroupGrant::Team {
+                    team: "api".to_string(),
+                    role: Role::Runner
+                },
+            ]
+        );
+
+        assert!(parse_group_grants("qa=web:owner").is_err());
+        assert!(parse_group_grants("qa").is_err());
+    }
+}
@@ -0,0 +1,591 @@
+use crate::db::traced;
+use crate::error::ApiError;
+use crate::state::AppState;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use sparktest_core::{ResourceLimits, TestDefinition, TestRun};
+use sqlx::types::Json;
+
+/// Rules every run has to satisfy before it is launched. The default policy
+/// allows any image but refuses privileged containers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdmissionPolicy {
+    /// Glob patterns matched against the fully qualified repository, e.g.
+    /// `docker.io/library/*` or `ghcr.io/acme/**`. Empty allows every image.
+    #[serde(default)]
+    pub allowed_images: Vec<String>,
+    /// Reject images tagged `latest` or without a tag at all
+    #[serde(default)]
+    pub forbid_latest: bool,
+    #[serde(default)]
+    pub allow_privileged: bool,
+    /// Largest CPU limit a run may ask for ("2", "500m"); when set, runs must set one
+    pub max_cpu: Option<String>,
+    /// Largest memory limit a run may ask for ("4Gi"); when set, runs must set one
+    pub max_memory: Option<String>,
+}
+
+/// A single rule a run broke
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PolicyViolation {
+    pub rule: &'static str,
+    pub message: String,
+}
+
+impl PolicyViolation {
+    fn new(rule: &'static str, message: String) -> Self {
+        Self { rule, message }
+    }
+}
+
+impl AdmissionPolicy {
+    /// Read the policy from `SPARKTEST_ALLOWED_IMAGES` (comma separated patterns),
+    /// `SPARKTEST_FORBID_LATEST_TAG`, `SPARKTEST_ALLOW_PRIVILEGED`,
+    /// `SPARKTEST_MAX_CPU` and `SPARKTEST_MAX_MEMORY`
+    pub fn from_env() -> anyhow::Result<Self> {
+        let flag = |name: &str| {
+            std::env::var(name)
+                .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+                .unwrap_or(false)
+        };
+        let policy = Self {
+            allowed_images: std::env::var("SPARKTEST_ALLOWED_IMAGES")
+                .map(|patterns| {
+                    patterns
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|pattern| !pattern.is_empty())
+                        .map(str::to_string)
+                        .collect()
+                })
+                .unwrap_or_default(),
+            forbid_latest: flag("SPARKTEST_FORBID_LATEST_TAG"),
+            allow_privileged: flag("SPARKTEST_ALLOW_PRIVILEGED"),
+            max_cpu: std::env::var("SPARKTEST_MAX_CPU").ok(),
+            max_memory: std::env::var("SPARKTEST_MAX_MEMORY").ok(),
+        };
+        policy
+            .validate()
+            .map_err(|e| anyhow::anyhow!(e.message))
+            .context("Invalid admission policy")?;
+        Ok(policy)
+    }
+
+    /// Check that the policy itself is well formed
+    pub fn validate(&self) -> Result<(), ApiError> {
+        if self.allowed_images.iter().any(|pattern| pattern.is_empty()) {
+            return Err(ApiError::validation(
+                "allowed_images must not contain empty patterns",
+            ));
+        }
+        parse_limit("max_cpu", self.max_cpu.as_deref(), cpu_millis)?;
+        parse_limit("max_memory", self.max_memory.as_deref(), memory_bytes)?;
+        Ok(())
+    }
+
+    /// Admit the run, and the image that clones its Git source if it has one
+    pub fn admit_run(&self, run: &TestRun, git_image: &str) -> Result<(), ApiError> {
+        self.admit(&run.image, run.resources.as_ref(), run.privileged)?;
+        if run.source.is_some() {
+            self.admit_git_image(git_image)?;
+        }
+        Ok(())
+    }
+
+    pub fn admit_definition(
+        &self,
+        definition: &TestDefinition,
+        git_image: &str,
+    ) -> Result<(), ApiError> {
+        self.admit(
+            &definition.image,
+            definition.resources.as_ref(),
+            definition.privileged,
+        )?;
+        if definition.source.is_some() {
+            self.admit_git_image(git_image)?;
+        }
+        Ok(())
+    }
+
+    /// The checkout container runs next to the tests, so its image has to pass
+    /// the same image rules
+    fn admit_git_image(&self, git_image: &str) -> Result<(), ApiError> {
+        let violations = self.image_violations(git_image);
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        let summary: Vec<&str> = violations.iter().map(|v| v.message.as_str()).collect();
+        Err(ApiError::policy_violation(format!(
+            "Rejected by admission policy: Git checkout {}",
+            summary.join("; ")
+        ))
+        .with_details(serde_json::json!({
+            "image": git_image,
+            "violations": violations,
+        })))
+    }
+
+    /// Reject the workload with a `policy_violation` error listing every rule it breaks
+    pub fn admit(
+        &self,
+        image: &str,
+        resources: Option<&ResourceLimits>,
+        privileged: bool,
+    ) -> Result<(), ApiError> {
+        let violations = self.violations(image, resources, privileged)?;
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        let summary: Vec<&str> = violations.iter().map(|v| v.message.as_str()).collect();
+        Err(ApiError::policy_violation(format!(
+            "Rejected by admission policy: {}",
+            summary.join("; ")
+        ))
+        .with_details(serde_json::json!({
+            "image": image,
+            "violations": violations,
+        })))
+    }
+
+    /// Every rule the workload breaks; malformed resource quantities are a
+    /// validation error rather than a violation
+    pub fn violations(
+        &self,
+        image: &str,
+        resources: Option<&ResourceLimits>,
+        privileged: bool,
+    ) -> Result<Vec<PolicyViolation>, ApiError> {
+        let cpu = resources.and_then(|r| r.cpu.as_deref());
+        let memory = resources.and_then(|r| r.memory.as_deref());
+        let cpu_limit = parse_limit("cpu", cpu, cpu_millis)?;
+        let memory_limit = parse_limit("memory", memory, memory_bytes)?;
+
+        let mut violations = self.image_violations(image);
+
+        if privileged && !self.allow_privileged {
+            violations.push(PolicyViolation::new(
+                "allow_privileged",
+                "privileged containers are not allowed".to_string(),
+            ));
+        }
+
+        violations.extend(limit_violation(
+            "max_cpu",
+            "cpu",
+            self.max_cpu.as_deref(),
+            cpu.zip(cpu_limit),
+            cpu_millis,
+        ));
+        violations.extend(limit_violation(
+            "max_memory",
+            "memory",
+            self.max_memory.as_deref(),
+            memory.zip(memory_limit),
+            memory_bytes,
+        ));
+
+        Ok(violations)
+    }
+
+    /// The `allowed_images` and `forbid_latest` rules the image breaks
+    fn image_violations(&self, image: &str) -> Vec<PolicyViolation> {
+        let mut violations = Vec::new();
+        let reference = ImageReference::parse(image);
+
+        if !self.allowed_images.is_empty() {
+            let name = reference.name();
+            if !self
+                .allowed_images
+                .iter()
+                .any(|pattern| glob_match(pattern, &name))
+            {
+                violations.push(PolicyViolation::new(
+                    "allowed_images",
+                    format!("image '{image}' ({name}) does not match any allowed image pattern"),
+                ));
+            }
+        }
+
+        if self.forbid_latest && reference.digest.is_none() {
+            match reference.tag.as_deref() {
+                Some("latest") => violations.push(PolicyViolation::new(
+                    "forbid_latest",
+                    format!("image '{image}' uses the 'latest' tag"),
+                )),
+                None => violations.push(PolicyViolation::new(
+                    "forbid_latest",
+                    format!("image '{image}' has no tag, which means 'latest'"),
+                )),
+                Some(_) => {}
+            }
+        }
+
+        violations
+    }
+}
+
+/// A requested limit, given as the original quantity and its parsed amount,
+/// checked against the policy maximum
+fn limit_violation(
+    rule: &'static str,
+    resource: &str,
+    max: Option<&str>,
+    requested: Option<(&str, u64)>,
+    parse: fn(&str) -> Option<u64>,
+) -> Option<PolicyViolation> {
+    let max = max?;
+    let max_amount = parse(max)?;
+    match requested {
+        Some((quantity, amount)) if amount > max_amount => Some(PolicyViolation::new(
+            rule,
+            format!("{resource} limit {quantity} exceeds the maximum of {max}"),
+        )),
+        Some(_) => None,
+        None => Some(PolicyViolation::new(
+            rule,
+            format!("a {resource} limit is required (maximum {max})"),
+        )),
+    }
+}
+
+fn parse_limit(
+    field: &str,
+    quantity: Option<&str>,
+    parse: fn(&str) -> Option<u64>,
+) -> Result<Option<u64>, ApiError> {
+    quantity
+        .map(|quantity| {
+            parse(quantity).ok_or_else(|| {
+                ApiError::validation(format!("{field} '{quantity}' is not a valid quantity"))
+            })
+        })
+        .transpose()
+}
+
+/// An image reference split the way registries see it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageReference {
+    pub registry: String,
+    pub repository: String,
+    pub tag: Option<String>,
+    pub digest: Option<String>,
+}
+
+impl ImageReference {
+    /// Parse `[registry/]repository[:tag][@digest]`, filling in Docker Hub defaults
+    pub fn parse(image: &str) -> Self {
+        let (rest, digest) = match image.split_once('@') {
+            Some((rest, digest)) => (rest, Some(digest.to_string())),
+            None => (image, None),
+        };
+        // A colon after the last slash separates the tag; earlier ones belong to a registry port
+        let (rest, tag) = match rest.rsplit_once(':') {
+            Some((name, tag)) if !tag.contains('/') => (name, Some(tag.to_string())),
+            _ => (rest, None),
+        };
+
+        let (registry, repository) = match rest.split_once('/') {
+            Some((first, path))
+                if first.contains('.') || first.contains(':') || first == "localhost" =>
+            {
+                (first.to_string(), path.to_string())
+            }
+            Some(_) => ("docker.io".to_string(), rest.to_string()),
+            None => ("docker.io".to_string(), format!("library/{rest}")),
+        };
+
+        Self {
+            registry,
+            repository,
+            tag,
+            digest,
+        }
+    }
+
+    /// `registry/repository`, which allow-list patterns are matched against
+    pub fn name(&self) -> String {
+        format!("{}/{}", self.registry, self.repository)
+    }
+}
+
+/// `*` matches within one path segment, `**` matches across segments
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[u8], text: &[u8]) -> bool {
+        match pattern {
+            [] => text.is_empty(),
+            [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| matches(rest, &text[i..])),
+            [b'*', rest @ ..] => (0..=text.len())
+                .take_while(|&i| i == 0 || text[i - 1] != b'/')
+                .any(|i| matches(rest, &text[i..])),
+            [c, rest @ ..] => text.first() == Some(c) && matches(rest, &text[1..]),
+        }
+    }
+    matches(pattern.as_bytes(), text.as_bytes())
+}
+
+/// A CPU quantity in millicores: "500m", "2" or "0.5"
+pub fn cpu_millis(quantity: &str) -> Option<u64> {
+    match quantity.strip_suffix('m') {
+        Some(millis) => millis.parse().ok(),
+        None => scaled(quantity, 1000.0),
+    }
+}
+
+/// A memory quantity in bytes, with binary ("Mi", "Gi") or decimal ("M", "G") suffixes
+pub fn memory_bytes(quantity: &str) -> Option<u64> {
+    const SUFFIXES: [(&str, f64); 10] = [
+        ("Ki", 1024.0),
+        ("Mi", 1024.0 * 1024.0),
+        ("Gi", 1024.0 * 1024.0 * 1024.0),
+        ("Ti", 1024.0 * 1024.0 * 1024.0 * 1024.0),
+        ("Pi", 1024.0 * 1024.0 * 1024.0 * 1024.0 * 1024.0),
+        ("k", 1e3),
+        ("M", 1e6),
+        ("G", 1e9),
+        ("T", 1e12),
+        ("P", 1e15),
+    ];
+    SUFFIXES
+        .iter()
+        .find_map(|(suffix, factor)| {
+            quantity
+                .strip_suffix(suffix)
+                .map(|number| scaled(number, *factor))
+        })
+        .unwrap_or_else(|| scaled(quantity, 1.0))
+}
+
+fn scaled(number: &str, factor: f64) -> Option<u64> {
+    let value: f64 = number.parse().ok()?;
+    (value.is_finite() && value >= 0.0).then(|| (value * factor).round() as u64)
+}
+
+/// The policy last replaced through the API, as stored in the database by a
+/// previous process. `None` without a database or while it was never replaced.
+pub async fn load_admission_policy(
+    state: &AppState,
+) -> Result<Option<AdmissionPolicy>, sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(None);
+    };
+    let policy: Option<Json<AdmissionPolicy>> =
+        traced("SELECT policy FROM admission_policy", |sql| {
+            sqlx::query_scalar(sql).fetch_optional(db)
+        })
+        .await?;
+    Ok(policy.map(|policy| policy.0))
+}
+
+/// Store a policy replaced through the API in the database, if there is one
+pub async fn save_admission_policy(
+    state: &AppState,
+    policy: &AdmissionPolicy,
+) -> Result<(), sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    traced(
+        "INSERT INTO admission_policy (id, policy, updated_at) VALUES (TRUE, $1, NOW()) \
+         ON CONFLICT (id) DO UPDATE SET policy = $1, updated_at = NOW()",
+        |sql| sqlx::query(sql).bind(Json(policy)).execute(db),
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use crate::error::ErrorType;
+
+    fn limits(cpu: Option<&str>, memory: Option<&str>) -> ResourceLimits {
+        ResourceLimits {
+            cpu: cpu.map(str::to_string),
+            memory: memory.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_image_reference_parsing() {
+        let python = ImageReference::parse("python:3.11-slim");
+        assert_eq!(python.name(), "docker.io/library/python");
+        assert_eq!(python.tag.as_deref(), Some("3.11-slim"));
+
+        let k6 = ImageReference::parse("grafana/k6");
+        assert_eq!(k6.name(), "docker.io/grafana/k6");
+        assert_eq!(k6.tag, None);
+
+        let private = ImageReference::parse("registry.local:5000/team/app:1.2@sha256:abc");
+        assert_eq!(private.registry, "registry.local:5000");
+        assert_eq!(private.repository, "team/app");
+        assert_eq!(private.tag.as_deref(), Some("1.2"));
+        assert_eq!(private.digest.as_deref(), Some("sha256:abc"));
+
+        let port_only = ImageReference::parse("localhost:5000/app");
+        assert_eq!(port_only.name(), "localhost:5000/app");
+        assert_eq!(port_only.tag, None);
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(
+            "docker.io/library/*",
+            "docker.io/library/python"
+        ));
+        assert!(!glob_match("docker.io/library/*", "docker.io/grafana/k6"));
+        assert!(!glob_match("ghcr.io/acme/*", "ghcr.io/acme/team/app"));
+        assert!(glob_match("ghcr.io/acme/**", "ghcr.io/acme/team/app"));
+        assert!(glob_match("docker.io/*/k6", "docker.io/grafana/k6"));
+        assert!(!glob_match(
+            "docker.io/library/python",
+            "docker.io/library/python2"
+        ));
+    }
+
+    #[test]
+    fn test_quantities() {
+        assert_eq!(cpu_millis("500m"), Some(500));
+        assert_eq!(cpu_millis("2"), Some(2000));
+        assert_eq!(cpu_millis("0.25"), Some(250));
+        assert_eq!(cpu_millis("lots"), None);
+        assert_eq!(memory_bytes("512Mi"), Some(512 * 1024 * 1024));
+        assert_eq!(memory_bytes("1G"), Some(1_000_000_000));
+        assert_eq!(memory_bytes("1024"), Some(1024));
+        assert_eq!(memory_bytes("-1Gi"), None);
+    }
+
+    #[test]
+    fn test_default_policy_only_refuses_privileged() {
+        let policy = AdmissionPolicy::default();
+        assert!(policy.admit("anything:latest", None, false).is_ok());
+
+        let error = policy.admit("anything:latest", None, true).unwrap_err();
+        assert_eq!(error.error_type, ErrorType::PolicyViolation);
+        assert_eq!(
+            error.details.unwrap()["violations"][0]["rule"],
+            "allow_privileged"
+        );
+    }
+
+    #[test]
+    fn test_allowed_images_and_latest() {
+        let policy = AdmissionPolicy {
+            allowed_images: vec![
+                "docker.io/library/*".to_string(),
+                "ghcr.io/acme/**".to_string(),
+            ],
+            forbid_latest: true,
+            ..Default::default()
+        };
+
+        assert!(policy.admit("python:3.11", None, false).is_ok());
+        assert!(policy
+            .admit("ghcr.io/acme/e2e/runner:v2", None, false)
+            .is_ok());
+        assert!(policy.admit("node@sha256:abc", None, false).is_ok());
+
+        let rules = |image| -> Vec<&'static str> {
+            policy
+                .violations(image, None, false)
+                .unwrap()
+                .iter()
+                .map(|v| v.rule)
+                .collect()
+        };
+        assert_eq!(rules("python:latest"), ["forbid_latest"]);
+        assert_eq!(rules("python"), ["forbid_latest"]);
+        assert_eq!(rules("evil.io/miner:1.0"), ["allowed_images"]);
+        assert_eq!(rules("grafana/k6"), ["allowed_images", "forbid_latest"]);
+    }
+
+    #[test]
+    fn test_git_image_is_admitted_for_sources() {
+        let policy = AdmissionPolicy {
+            allowed_images: vec!["docker.io/library/*".to_string()],
+            ..Default::default()
+        };
+        let mut run = TestRun::new("Checkout".to_string(), "node:20".to_string(), vec![]);
+        assert!(policy.admit_run(&run, "alpine/git:2.45.2").is_ok());
+
+        run.source = Some(sparktest_core::GitSource {
+            url: "https://github.com/example/tests.git".to_string(),
+            git_ref: None,
+            subdirectory: None,
+        });
+        let error = policy.admit_run(&run, "alpine/git:2.45.2").unwrap_err();
+        assert_eq!(error.error_type, ErrorType::PolicyViolation);
+        assert_eq!(error.details.unwrap()["image"], "alpine/git:2.45.2");
+        assert!(policy.admit_run(&run, "docker.io/library/git:2.45").is_ok());
+    }
+
+    #[test]
+    fn test_max_resources() {
+        let policy = AdmissionPolicy {
+            max_cpu: Some("2".to_string()),
+            max_memory: Some("1Gi".to_string()),
+            ..Default::default()
+        };
+
+        let within = limits(Some("1500m"), Some("512Mi"));
+        assert!(policy.admit("app:1", Some(&within), false).is_ok());
+
+        let error = policy
+            .admit("app:1", Some(&limits(Some("4"), None)), false)
+            .unwrap_err();
+        assert_eq!(error.status(), axum::http::StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(error
+            .message
+            .contains("cpu limit 4 exceeds the maximum of 2"));
+        assert!(error.message.contains("a memory limit is required"));
+
+        let invalid = policy
+            .admit("app:1", Some(&limits(Some("fast"), Some("1Gi"))), false)
+            .unwrap_err();
+        assert_eq!(invalid.error_type, ErrorType::Validation);
+    }
+
+    #[test]
+    fn test_policy_validation() {
+        let policy = AdmissionPolicy {
+            max_memory: Some("a lot".to_string()),
+            ..Default::default()
+        };
+        assert!(policy.validate().is_err());
+        assert!(AdmissionPolicy::default().validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_replaced_policy_survives_a_restart() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        let state = AppState {
+            db: Some(db.clone()),
+            ..AppState::default()
+        };
+        let policy = AdmissionPolicy {
+            allowed_images: vec!["ghcr.io/acme/**".to_string()],
+            forbid_latest: true,
+            max_memory: Some("4Gi".to_string()),
+            ..AdmissionPolicy::default()
+        };
+        save_admission_policy(&state, &policy).await.unwrap();
+
+        let restarted = AppState {
+            db: Some(db.clone()),
+            ..AppState::default()
+        };
+        let loaded = load_admission_policy(&restarted).await.unwrap();
+        assert_eq!(loaded, Some(policy));
+
+        sqlx::query("DELETE FROM admission_policy")
+            .execute(&db)
+            .await
+            .unwrap();
+        assert_eq!(load_admission_policy(&restarted).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,390 @@
+use crate::auth::Principal;
+use crate::rbac::Action;
+use crate::state::AppState;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sparktest_core::{RunPriority, TestRun};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+/// How many runs may execute at once; `None` is unlimited. Runs without an
+/// executor or team only count towards the global limit.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct QueueConfig {
+    pub max_concurrent: Option<usize>,
+    pub max_per_executor: Option<usize>,
+    pub max_per_team: Option<usize>,
+}
+
+impl QueueConfig {
+    /// Read `SPARKTEST_MAX_CONCURRENT_RUNS`, `SPARKTEST_MAX_CONCURRENT_RUNS_PER_EXECUTOR`
+    /// and `SPARKTEST_MAX_CONCURRENT_RUNS_PER_TEAM`; unset or 0 means unlimited
+    pub fn from_env() -> Self {
+        let limit = |name: &str| {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.trim().parse().ok())
+                .filter(|&limit: &usize| limit > 0)
+        };
+        Self {
+            max_concurrent: limit("SPARKTEST_MAX_CONCURRENT_RUNS"),
+            max_per_executor: limit("SPARKTEST_MAX_CONCURRENT_RUNS_PER_EXECUTOR"),
+            max_per_team: limit("SPARKTEST_MAX_CONCURRENT_RUNS_PER_TEAM"),
+        }
+    }
+}
+
+/// What the limits are counted by
+#[derive(Debug, Clone)]
+struct Holder {
+    executor_id: Option<String>,
+    team_id: Option<Uuid>,
+}
+
+impl Holder {
+    fn of(run: &TestRun) -> Self {
+        Self {
+            executor_id: run.executor_id.clone(),
+            team_id: run.team_id,
+        }
+    }
+}
+
+struct Waiting {
+    run_id: Uuid,
+    priority: RunPriority,
+    created_at: DateTime<Utc>,
+    holder: Holder,
+    start: oneshot::Sender<()>,
+}
+
+#[derive(Default)]
+struct QueueState {
+    /// In the order runs will start: highest priority first, then oldest first
+    waiting: Vec<Waiting>,
+    active: HashMap<Uuid, Holder>,
+}
+
+impl QueueState {
+    fn has_room_for(&self, holder: &Holder, config: &QueueConfig) -> bool {
+        let below = |limit: Option<usize>, count: usize| limit.is_none_or(|limit| count < limit);
+        below(config.max_concurrent, self.active.len())
+            && holder.executor_id.as_ref().is_none_or(|executor_id| {
+                below(
+                    config.max_per_executor,
+                    self.active
+                        .values()
+                        .filter(|active| active.executor_id.as_ref() == Some(executor_id))
+                        .count(),
+                )
+            })
+            && holder.team_id.is_none_or(|team_id| {
+                below(
+                    config.max_per_team,
+                    self.active
+                        .values()
+                        .filter(|active| active.team_id == Some(team_id))
+                        .count(),
+                )
+            })
+    }
+
+    /// Start every waiting run that fits within the limits. A run held back by
+    /// its executor's or team's limit doesn't hold up the runs behind it.
+    fn dispatch(&mut self, config: &QueueConfig) {
+        let mut index = 0;
+        while index < self.waiting.len() {
+            if !self.has_room_for(&self.waiting[index].holder, config) {
+                index += 1;
+                continue;
+            }
+            let waiting = self.waiting.remove(index);
+            // Nobody is waiting any more if the run's task has gone away
+            if waiting.start.send(()).is_ok() {
+                self.active.insert(waiting.run_id, waiting.holder);
+            }
+        }
+    }
+}
+
+/// Runs waiting for a free slot and the runs holding one, ordered by each run's
+/// priority and creation time. Waiting runs are the pending runs in the store, so
+/// with PostgreSQL [`resume_queued_runs`](crate::runner::resume_queued_runs)
+/// queues them up again after a restart.
+#[derive(Clone, Default)]
+pub struct RunQueue {
+    config: QueueConfig,
+    state: Arc<Mutex<QueueState>>,
+}
+
+impl RunQueue {
+    pub fn new(config: QueueConfig) -> Self {
+        Self {
+            config,
+            state: Arc::default(),
+        }
+    }
+
+    pub fn config(&self) -> &QueueConfig {
+        &self.config
+    }
+
+    /// Wait until the run may start. `None` if it was cancelled while waiting.
+    pub async fn enter(&self, run: &TestRun) -> Option<QueueSlot> {
+        let (start, started) = oneshot::channel();
+        {
+            let mut state = self.state.lock().unwrap();
+            let position = state.waiting.partition_point(|waiting| {
+                (std::cmp::Reverse(waiting.priority), waiting.created_at)
+                    <= (std::cmp::Reverse(run.priority), run.created_at)
+            });
+            state.waiting.insert(
+                position,
+                Waiting {
+                    run_id: run.id,
+                    priority: run.priority,
+                    created_at: run.created_at,
+                    holder: Holder::of(run),
+                    start,
+                },
+            );
+            state.dispatch(&self.config);
+        }
+        started.await.ok()?;
+        Some(QueueSlot {
+            queue: self.clone(),
+            run_id: run.id,
+        })
+    }
+
+    /// Take a waiting run out of the queue; its `enter` returns `None`
+    pub fn cancel(&self, run_id: Uuid) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let before = state.waiting.len();
+        state.waiting.retain(|waiting| waiting.run_id != run_id);
+        before != state.waiting.len()
+    }
+
+    /// 1 for the next run to start, `None` unless the run is waiting
+    pub fn position(&self, run_id: Uuid) -> Option<usize> {
+        self.state
+            .lock()
+            .unwrap()
+            .waiting
+            .iter()
+            .position(|waiting| waiting.run_id == run_id)
+            .map(|index| index + 1)
+    }
+
+    /// Whether the run is waiting or executing in this process
+    pub fn contains(&self, run_id: Uuid) -> bool {
+        let state = self.state.lock().unwrap();
+        state.active.contains_key(&run_id)
+            || state.waiting.iter().any(|waiting| waiting.run_id == run_id)
+    }
+
+    /// Fill in where the run stands in the queue
+    pub fn annotate(&self, run: &mut TestRun) {
+        run.queue_position = self.position(run.id);
+    }
+
+    /// Waiting runs in the order they will start
+    pub fn waiting(&self) -> Vec<Uuid> {
+        let state = self.state.lock().unwrap();
+        state.waiting.iter().map(|waiting| waiting.run_id).collect()
+    }
+
+    /// How many runs hold a slot
+    pub fn active(&self) -> usize {
+        self.state.lock().unwrap().active.len()
+    }
+
+    fn leave(&self, run_id: Uuid) {
+        let mut state = self.state.lock().unwrap();
+        state.active.remove(&run_id);
+        state.dispatch(&self.config);
+    }
+}
+
+/// A run's place among the executing runs; dropping it lets the next run start
+pub struct QueueSlot {
+    queue: RunQueue,
+    run_id: Uuid,
+}
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        self.queue.leave(self.run_id);
+    }
+}
+
+/// `GET /api/queue`: the limits and what is waiting on them
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueStatus {
+    pub limits: QueueConfig,
+    /// Runs holding a slot, including ones the principal may not view
+    pub running: usize,
+    /// The waiting runs the principal may view, next to start first, without logs
+    pub waiting: Vec<TestRun>,
+}
+
+pub async fn queue_status(state: &AppState, principal: &Principal) -> QueueStatus {
+    let mut waiting = Vec::new();
+    for (index, run_id) in state.queue.waiting().into_iter().enumerate() {
+        let Some(mut run) = state.store.get_run(run_id).await else {
+            continue;
+        };
+        if principal.can(Action::View, &(&run).into()) {
+            run.logs = None;
+            run.queue_position = Some(index + 1);
+            waiting.push(run);
+        }
+    }
+    QueueStatus {
+        limits: state.queue.config().clone(),
+        running: state.queue.active(),
+        waiting,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use crate::runner::resume_queued_runs;
+    use crate::store::Store;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    fn run(name: &str, priority: RunPriority) -> TestRun {
+        let mut run = TestRun::new(name.to_string(), "node:20".to_string(), vec![]);
+        run.priority = priority;
+        run
+    }
+
+    /// The slot, if the run started within a moment
+    async fn started(
+        entering: &mut tokio::task::JoinHandle<Option<QueueSlot>>,
+    ) -> Option<QueueSlot> {
+        timeout(Duration::from_millis(50), entering)
+            .await
+            .ok()
+            .and_then(|joined| joined.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_priority_and_global_limit() {
+        let queue = RunQueue::new(QueueConfig {
+            max_concurrent: Some(1),
+            ..Default::default()
+        });
+        let first = queue.enter(&run("first", RunPriority::Low)).await.unwrap();
+
+        let normal = run("normal", RunPriority::Normal);
+        let high = run("high", RunPriority::High);
+        let low = run("low", RunPriority::Low);
+        let spawn = |run: TestRun| {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.enter(&run).await })
+        };
+        let mut normal_slot = spawn(normal.clone());
+        let mut low_slot = spawn(low.clone());
+        tokio::task::yield_now().await;
+        let mut high_slot = spawn(high.clone());
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        assert_eq!(queue.waiting(), vec![high.id, normal.id, low.id]);
+        assert_eq!(queue.position(normal.id), Some(2));
+        assert_eq!(queue.active(), 1);
+
+        drop(first);
+        let _high = started(&mut high_slot).await.unwrap();
+        assert!(started(&mut normal_slot).await.is_none());
+        assert_eq!(queue.position(normal.id), Some(1));
+
+        assert!(queue.cancel(normal.id));
+        assert!(normal_slot.await.unwrap().is_none());
+        assert!(started(&mut low_slot).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_executor_and_team_limits() {
+        let queue = RunQueue::new(QueueConfig {
+            max_per_executor: Some(1),
+            max_per_team: Some(1),
+            ..Default::default()
+        });
+        let team = Uuid::new_v4();
+        let mut k6 = run("k6", RunPriority::Normal);
+        k6.executor_id = Some("k6".to_string());
+        let _k6_slot = queue.enter(&k6).await.unwrap();
+        let mut team_run = run("team", RunPriority::Normal);
+        team_run.team_id = Some(team);
+        let _team_slot = queue.enter(&team_run).await.unwrap();
+
+        // Held back by their limits, without holding up the unrelated run behind them
+        let mut second_k6 = run("second k6", RunPriority::High);
+        second_k6.executor_id = Some("k6".to_string());
+        let mut second_team = run("second team", RunPriority::High);
+        second_team.team_id = Some(team);
+        for run in [second_k6.clone(), second_team.clone()] {
+            let queue = queue.clone();
+            tokio::spawn(async move { queue.enter(&run).await });
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(queue.waiting(), vec![second_k6.id, second_team.id]);
+
+        let unrelated = queue.enter(&run("unrelated", RunPriority::Low)).await;
+        assert!(unrelated.is_some());
+        assert_eq!(queue.active(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_waiting_runs_are_queued_again_after_a_restart() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        let store = Store::load(db.clone()).await.unwrap();
+        let mut waiting = Vec::new();
+        for priority in [RunPriority::Low, RunPriority::High, RunPriority::Normal] {
+            let run = run("waiting", priority);
+            store.insert_run(run.clone()).await.unwrap();
+            waiting.push(run);
+        }
+        let mut finished = run("finished", RunPriority::High);
+        finished.status = "succeeded".to_string();
+        store.insert_run(finished.clone()).await.unwrap();
+
+        // The runs resumed by the restarted server wait behind a run holding the only slot
+        let state = AppState {
+            store: Store::load(db).await.unwrap(),
+            queue: RunQueue::new(QueueConfig {
+                max_concurrent: Some(1),
+                ..Default::default()
+            }),
+            ..AppState::default()
+        };
+        let _slot = state
+            .queue
+            .enter(&run("blocker", RunPriority::High))
+            .await
+            .unwrap();
+        assert!(resume_queued_runs(&state).await >= waiting.len());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // Other tests share the database, so only these runs are looked at
+        let ours: Vec<Uuid> = state
+            .queue
+            .waiting()
+            .into_iter()
+            .filter(|id| waiting.iter().any(|run| run.id == *id) || *id == finished.id)
+            .collect();
+        assert_eq!(ours, vec![waiting[1].id, waiting[2].id, waiting[0].id]);
+        assert_eq!(
+            state.store.get_run(waiting[1].id).await.unwrap().priority,
+            RunPriority::High
+        );
+    }
+}
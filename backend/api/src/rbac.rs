@@ -0,0 +1,316 @@
+use crate::auth::Principal;
+use crate::error::ApiError;
+use serde::Serialize;
+use sparktest_core::{
+    Executor, NotificationChannel, NotificationRule, Role, Schedule, StatusReporter, Team,
+    TestDefinition, TestRun, TestSuite, TriggerRule,
+};
+use uuid::Uuid;
+
+/// What a caller wants to do with a resource
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    View,
+    Run,
+    Manage,
+    Administer,
+}
+
+impl Action {
+    /// The lowest team role allowed to perform the action
+    pub fn required_role(self) -> Role {
+        match self {
+            Action::View => Role::Viewer,
+            Action::Run => Role::Runner,
+            Action::Manage => Role::Maintainer,
+            Action::Administer => Role::Admin,
+        }
+    }
+
+    fn verb(self) -> &'static str {
+        match self {
+            Action::View => "view",
+            Action::Run => "run",
+            Action::Manage => "manage",
+            Action::Administer => "administer",
+        }
+    }
+}
+
+/// The thing a permission check is about, and the team that owns it
+#[derive(Debug, Clone)]
+pub struct Resource {
+    pub kind: &'static str,
+    pub id: String,
+    pub team_id: Option<Uuid>,
+}
+
+impl Resource {
+    /// An instance-wide resource such as an execution target, owned by no team
+    pub fn instance(kind: &'static str, id: impl Into<String>) -> Self {
+        Self {
+            kind,
+            id: id.into(),
+            team_id: None,
+        }
+    }
+
+    pub fn team(team_id: Uuid) -> Self {
+        Self {
+            kind: "team",
+            id: team_id.to_string(),
+            team_id: Some(team_id),
+        }
+    }
+
+    /// A new resource about to be created for a team, or without one
+    pub fn new_in(kind: &'static str, team_id: Option<Uuid>) -> Self {
+        Self {
+            kind,
+            id: "new".to_string(),
+            team_id,
+        }
+    }
+}
+
+impl From<&TestRun> for Resource {
+    fn from(run: &TestRun) -> Self {
+        Self {
+            kind: "test_run",
+            id: run.id.to_string(),
+            team_id: run.team_id,
+        }
+    }
+}
+
+impl From<&TestDefinition> for Resource {
+    fn from(definition: &TestDefinition) -> Self {
+        Self {
+            kind: "test_definition",
+            id: definition.id.to_string(),
+            team_id: definition.team_id,
+        }
+    }
+}
+
+impl From<&TestSuite> for Resource {
+    fn from(suite: &TestSuite) -> Self {
+        Self {
+            kind: "test_suite",
+            id: suite.id.to_string(),
+            team_id: suite.team_id,
+        }
+    }
+}
+
+impl From<&TriggerRule> for Resource {
+    fn from(rule: &TriggerRule) -> Self {
+        Self {
+            kind: "trigger_rule",
+            id: rule.id.to_string(),
+            team_id: rule.team_id,
+        }
+    }
+}
+
+impl From<&StatusReporter> for Resource {
+    fn from(reporter: &StatusReporter) -> Self {
+        Self {
+            kind: "status_reporter",
+            id: reporter.id.to_string(),
+            team_id: reporter.team_id,
+        }
+    }
+}
+
+impl From<&Schedule> for Resource {
+    fn from(schedule: &Schedule) -> Self {
+        Self {
+            kind: "schedule",
+            id: schedule.id.to_string(),
+            team_id: schedule.team_id,
+        }
+    }
+}
+
+impl From<&NotificationChannel> for Resource {
+    fn from(channel: &NotificationChannel) -> Self {
+        Self {
+            kind: "notification_channel",
+            id: channel.id.to_string(),
+            team_id: channel.team_id,
+        }
+    }
+}
+
+impl From<&NotificationRule> for Resource {
+    fn from(rule: &NotificationRule) -> Self {
+        Self {
+            kind: "notification_rule",
+            id: rule.id.to_string(),
+            team_id: rule.team_id,
+        }
+    }
+}
+
+impl From<&Executor> for Resource {
+    fn from(executor: &Executor) -> Self {
+        Self {
+            kind: "test_executor",
+            id: executor.id.clone(),
+            team_id: executor.team_id,
+        }
+    }
+}
+
+impl From<&Team> for Resource {
+    fn from(team: &Team) -> Self {
+        Self::team(team.id)
+    }
+}
+
+impl Principal {
+    pub fn role_in(&self, team_id: Uuid) -> Option<Role> {
+        self.roles.get(&team_id).copied()
+    }
+
+    /// Admins may do anything. Team members may act on their team's resources up to
+    /// their role. Resources without a team are shared: everyone may view them, but
+    /// only admins may change them.
+    pub fn can(&self, action: Action, resource: &Resource) -> bool {
+        if self.is_admin {
+            return true;
+        }
+        match resource.team_id {
+            Some(team_id) => self
+                .role_in(team_id)
+                .is_some_and(|role| role >= action.required_role()),
+            None => action == Action::View,
+        }
+    }
+
+    /// Like [`Principal::can`], but returns a `forbidden` error that records who was
+    /// denied what, so denials can be audited from the response alone
+    pub fn authorize(&self, action: Action, resource: &Resource) -> Result<(), ApiError> {
+        if self.can(action, resource) {
+            return Ok(());
+        }
+        Err(self.denied(action, resource))
+    }
+
+    /// Instance-wide settings such as execution targets and the admission policy
+    /// are shown to admins and team members, not to callers outside every team
+    pub fn authorize_member(&self, resource: &Resource) -> Result<(), ApiError> {
+        if self.is_admin || !self.roles.is_empty() {
+            return Ok(());
+        }
+        Err(self.denied(Action::View, resource))
+    }
+
+    fn denied(&self, action: Action, resource: &Resource) -> ApiError {
+        let required_role = match resource.team_id {
+            Some(_) => serde_json::json!(action.required_role()),
+            None => serde_json::json!("instance_admin"),
+        };
+        ApiError::forbidden(format!(
+            "'{}' is not allowed to {} {} '{}'",
+            self.name,
+            action.verb(),
+            resource.kind.replace('_', " "),
+            resource.id
+        ))
+        .with_details(serde_json::json!({
+            "actor": self.name,
+            "user_id": self.user_id,
+            "token_id": self.token_id,
+            "action": action,
+            "resource_type": resource.kind,
+            "resource_id": resource.id,
+            "team_id": resource.team_id,
+            "required_role": required_role,
+            "role": resource.team_id.and_then(|team_id| self.role_in(team_id)),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sparktest_core::TokenScope;
+    use std::collections::HashMap;
+
+    fn member(team_id: Uuid, role: Role) -> Principal {
+        Principal {
+            token_id: None,
+            user_id: Some(Uuid::new_v4()),
+            name: "alice".to_string(),
+            scope: TokenScope::Admin,
+            is_admin: false,
+            roles: HashMap::from([(team_id, role)]),
+        }
+    }
+
+    #[test]
+    fn test_roles_grant_actions_on_own_team() {
+        let team_id = Uuid::new_v4();
+        let resource = Resource::team(team_id);
+
+        let viewer = member(team_id, Role::Viewer);
+        assert!(viewer.can(Action::View, &resource));
+        assert!(!viewer.can(Action::Run, &resource));
+
+        let runner = member(team_id, Role::Runner);
+        assert!(runner.can(Action::Run, &resource));
+        assert!(!runner.can(Action::Manage, &resource));
+
+        let maintainer = member(team_id, Role::Maintainer);
+        assert!(maintainer.can(Action::Manage, &resource));
+        assert!(!maintainer.can(Action::Administer, &resource));
+
+        assert!(member(team_id, Role::Admin).can(Action::Administer, &resource));
+    }
+
+    #[test]
+    fn test_other_teams_and_shared_resources() {
+        let principal = member(Uuid::new_v4(), Role::Admin);
+        assert!(!principal.can(Action::View, &Resource::team(Uuid::new_v4())));
+
+        let target = Resource::instance("execution_target", "default");
+        assert!(principal.can(Action::View, &target));
+        assert!(!principal.can(Action::Manage, &target));
+        assert!(Principal::anonymous().can(Action::Administer, &target));
+    }
+
+    #[test]
+    fn test_instance_settings_need_a_team() {
+        let target = Resource::instance("execution_target", "default");
+        assert!(member(Uuid::new_v4(), Role::Viewer)
+            .authorize_member(&target)
+            .is_ok());
+        assert!(Principal::anonymous().authorize_member(&target).is_ok());
+
+        let outsider = Principal {
+            roles: HashMap::new(),
+            ..member(Uuid::new_v4(), Role::Viewer)
+        };
+        assert!(outsider.authorize_member(&target).is_err());
+    }
+
+    #[test]
+    fn test_denial_is_audit_friendly() {
+        let team_id = Uuid::new_v4();
+        let principal = member(team_id, Role::Viewer);
+        let error = principal
+            .authorize(Action::Run, &Resource::team(team_id))
+            .unwrap_err();
+
+        assert_eq!(error.status(), axum::http::StatusCode::FORBIDDEN);
+        let details = error.details.unwrap();
+        assert_eq!(details["actor"], "alice");
+        assert_eq!(details["action"], "run");
+        assert_eq!(details["resource_type"], "team");
+        assert_eq!(details["required_role"], "runner");
+        assert_eq!(details["role"], "viewer");
+    }
+}
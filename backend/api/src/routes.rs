@@ -1,26 +1,557 @@
+use crate::audit::record_audit;
+use crate::auth::require_auth;
 use crate::handlers::*;
+use crate::metrics::{get_metrics, track_http_requests};
+use crate::state::AppState;
+use crate::telemetry::trace_http_requests;
 use axum::{
-    routing::{delete, get},
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{delete, get, post, put},
     Router,
 };
 use tower_http::cors::CorsLayer;
 
 pub fn create_app() -> Router {
+    create_app_with_state(AppState::default())
+}
+
+pub fn create_app_with_state(state: AppState) -> Router {
+    // Room for the multipart framing around the largest allowed upload
+    let upload_limit = DefaultBodyLimit::max(state.files.max_total_bytes as usize + 64 * 1024);
+
     let api_routes = Router::new()
         .route("/health", get(health_check))
+        .route("/events", get(get_events))
         .route("/runs", get(get_runs).post(create_run))
         .route("/runs/:id", get(get_run).delete(delete_run))
         .route("/test-runs", get(get_runs).post(create_run))
+        .route(
+            "/test-runs/upload",
+            post(create_run_with_files).layer(upload_limit),
+        )
         .route("/test-runs/:id", get(get_run).delete(delete_run))
-        .route("/test-definitions", get(get_definitions))
+        .route("/test-runs/:id/logs", get(get_run_logs))
+        .route("/test-runs/:id/files/:name", get(get_run_file))
+        .route("/analytics/runs", get(get_run_analytics))
+        .route("/queue", get(get_queue))
+        .route(
+            "/test-definitions",
+            get(get_definitions).post(create_definition),
+        )
+        .route("/test-definitions/:id", get(get_definition))
+        .route("/test-definitions/:id/run", post(run_definition))
+        .route(
+            "/test-definitions/:id/analytics",
+            get(get_definition_analytics),
+        )
+        .route(
+            "/test-definitions/:id/files",
+            post(upload_definition_files).layer(upload_limit),
+        )
+        .route(
+            "/test-definitions/:id/files/:name",
+            get(get_definition_file).delete(delete_definition_file),
+        )
         .route("/test-executors", get(get_executors))
-        .route("/test-suites", get(get_suites))
+        .route("/test-suites", get(get_suites).post(create_suite))
+        .route("/test-suites/:id", get(get_suite))
+        .route("/test-suites/:id/run", post(run_suite))
         .route("/k8s/health", get(k8s_health))
         .route("/k8s/logs/:job_name", get(get_job_logs))
         .route("/k8s/status/:job_name", get(get_job_status))
-        .route("/k8s/jobs/:job_name", delete(delete_job));
+        .route("/k8s/jobs/:job_name", delete(delete_job))
+        .route("/execution-targets", get(get_targets).post(create_target))
+        .route(
+            "/execution-targets/:name",
+            get(get_target).delete(delete_target),
+        )
+        .route("/tokens", get(get_tokens).post(create_token))
+        .route("/tokens/:id", delete(revoke_token))
+        .route("/users", get(get_users).post(create_user))
+        .route("/users/:id", get(get_user))
+        .route("/teams", get(get_teams).post(create_team))
+        .route("/teams/:id", get(get_team))
+        .route(
+            "/teams/:id/members/:user_id",
+            put(set_team_member).delete(remove_team_member),
+        )
+        .route(
+            "/trigger-rules",
+            get(get_trigger_rules).post(create_trigger_rule),
+        )
+        .route(
+            "/trigger-rules/:id",
+            get(get_trigger_rule).delete(delete_trigger_rule),
+        )
+        .route(
+            "/status-reporters",
+            get(get_status_reporters).post(create_status_reporter),
+        )
+        .route(
+            "/status-reporters/:id",
+            get(get_status_reporter).delete(delete_status_reporter),
+        )
+        .route(
+            "/notification-channels",
+            get(get_notification_channels).post(create_notification_channel),
+        )
+        .route(
+            "/notification-channels/:id",
+            get(get_notification_channel).delete(delete_notification_channel),
+        )
+        .route(
+            "/notification-rules",
+            get(get_notification_rules).post(create_notification_rule),
+        )
+        .route(
+            "/notification-rules/:id",
+            get(get_notification_rule).delete(delete_notification_rule),
+        )
+        .route("/schedules", get(get_schedules).post(create_schedule))
+        .route(
+            "/schedules/:id",
+            get(get_schedule)
+                .put(update_schedule)
+                .delete(delete_schedule),
+        )
+        .route("/webhooks/github", post(receive_github_webhook))
+        .route("/webhooks/gitlab", post(receive_gitlab_webhook))
+        .route("/audit", get(get_audit_events))
+        .route(
+            "/admission-policy",
+            get(get_admission_policy).put(update_admission_policy),
+        );
 
     Router::new()
         .nest("/api", api_routes)
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_auth))
+        .route_layer(middleware::from_fn_with_state(state.clone(), record_audit))
+        .route("/metrics", get(get_metrics))
+        .route_layer(middleware::from_fn(track_http_requests))
+        .route_layer(middleware::from_fn(trace_http_requests))
+        .fallback(route_not_found)
         .layer(CorsLayer::permissive())
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::{to_bytes, Body},
+        http::{header, Request, StatusCode},
+    };
+    use futures::StreamExt;
+    use sparktest_core::{TokenScope, User};
+    use tower::ServiceExt;
+
+    const ADMIN_SECRET: &str = "spk_test_admin";
+
+    async fn app_with_admin_token() -> (Router, AppState) {
+        let state = AppState::default();
+        state
+            .tokens
+            .insert(
+                "admin".to_string(),
+                None,
+                TokenScope::Admin,
+                None,
+                ADMIN_SECRET,
+            )
+            .await
+            .unwrap();
+        (create_app_with_state(state.clone()), state)
+    }
+
+    async fn call(app: Router, request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        let response = app.oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    /// Send a request authenticated with an admin token
+    async fn send(mut request: Request<Body>) -> (StatusCode, serde_json::Value) {
+        request.headers_mut().insert(
+            header::AUTHORIZATION,
+            format!("Bearer {ADMIN_SECRET}").parse().unwrap(),
+        );
+        let (app, _) = app_with_admin_token().await;
+        call(app, request).await
+    }
+
+    #[tokio::test]
+    async fn test_malformed_body_returns_error_json() {
+        let request = Request::post("/api/test-runs")
+            .header("content-type", "application/json")
+            .body(Body::from("{\"name\": 42}"))
+            .unwrap();
+
+        let (status, body) = send(request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error_type"], "validation");
+        assert!(body["message"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_path_returns_error_json() {
+        let request = Request::get("/api/test-runs/not-a-uuid")
+            .body(Body::empty())
+            .unwrap();
+
+        let (status, body) = send(request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error_type"], "validation");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_run_returns_not_found_json() {
+        let request = Request::get(format!("/api/test-runs/{}", uuid::Uuid::new_v4()))
+            .body(Body::empty())
+            .unwrap();
+
+        let (status, body) = send(request).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error_type"], "not_found");
+        assert!(body["details"].is_null());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_route_returns_not_found_json() {
+        let request = Request::get("/api/nope").body(Body::empty()).unwrap();
+
+        let (status, body) = send(request).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["message"], "No route for /api/nope");
+    }
+
+    #[tokio::test]
+    async fn test_missing_token_is_unauthorized() {
+        let (app, _) = app_with_admin_token().await;
+        let response = app
+            .oneshot(Request::get("/api/test-runs").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[header::WWW_AUTHENTICATE], "Bearer");
+    }
+
+    #[tokio::test]
+    async fn test_health_does_not_require_token() {
+        let (app, _) = app_with_admin_token().await;
+        let (status, body) = call(
+            app,
+            Request::get("/api/health").body(Body::empty()).unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "healthy");
+    }
+
+    #[tokio::test]
+    async fn test_webhooks_authenticate_by_signature() {
+        let (app, mut state) = app_with_admin_token().await;
+        let ping = || {
+            Request::post("/api/webhooks/github")
+                .header("x-github-event", "ping")
+                .header("x-hub-signature-256", "sha256=00")
+                .body(Body::from("{}"))
+                .unwrap()
+        };
+
+        // No bearer token needed, but nothing is accepted until a secret is configured
+        let (status, _) = call(app, ping()).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        state.webhooks.secret = Some("s3cret".to_string());
+        let (status, body) = call(create_app_with_state(state), ping()).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["message"], "Webhook signature does not match");
+    }
+
+    #[tokio::test]
+    async fn test_read_token_cannot_launch_runs() {
+        let (app, state) = app_with_admin_token().await;
+        let (_, secret) = state
+            .tokens
+            .create("dashboard".to_string(), None, TokenScope::Read, None)
+            .await
+            .unwrap();
+
+        let request = Request::get("/api/test-runs")
+            .header(header::AUTHORIZATION, format!("Bearer {secret}"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = call(app.clone(), request).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let request = Request::post("/api/test-runs")
+            .header(header::AUTHORIZATION, format!("Bearer {secret}"))
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"{"name": "x", "image": "alpine", "commands": []}"#,
+            ))
+            .unwrap();
+        let (status, body) = call(app, request).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error_type"], "forbidden");
+        assert_eq!(body["details"]["required_scope"], "run");
+    }
+
+    #[tokio::test]
+    async fn test_token_create_and_revoke() {
+        let request = Request::post("/api/tokens")
+            .header(header::AUTHORIZATION, format!("Bearer {ADMIN_SECRET}"))
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"name": "ci", "scope": "run"}"#))
+            .unwrap();
+
+        let (app, _) = app_with_admin_token().await;
+        let (status, created) = call(app.clone(), request).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(created["scope"], "run");
+        let secret = created["secret"].as_str().unwrap().to_string();
+        assert!(created.get("secret_hash").is_none());
+
+        let (status, tokens) = call(
+            app.clone(),
+            Request::get("/api/tokens")
+                .header(header::AUTHORIZATION, format!("Bearer {ADMIN_SECRET}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(tokens.as_array().unwrap().len(), 2);
+        assert!(tokens[1].get("secret").is_none());
+
+        // A run-scoped token cannot manage tokens
+        let (status, _) = call(
+            app.clone(),
+            Request::get("/api/tokens")
+                .header(header::AUTHORIZATION, format!("Bearer {secret}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::delete(format!("/api/tokens/{}", created["id"].as_str().unwrap()))
+                    .header(header::AUTHORIZATION, format!("Bearer {ADMIN_SECRET}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let (status, body) = call(
+            app,
+            Request::get("/api/test-runs")
+                .header(header::AUTHORIZATION, format!("Bearer {secret}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error_type"], "unauthorized");
+    }
+
+    #[tokio::test]
+    async fn test_user_token_is_limited_to_team_roles() {
+        let (app, state) = app_with_admin_token().await;
+        let user = User {
+            id: uuid::Uuid::new_v4(),
+            name: "carol".to_string(),
+            email: None,
+            subject: None,
+            is_admin: false,
+            created_at: chrono::Utc::now(),
+        };
+        state.store.insert_user(user.clone()).await.unwrap();
+        let (_, secret) = state
+            .tokens
+            .create(
+                "carol-cli".to_string(),
+                Some(user.id),
+                TokenScope::Run,
+                None,
+            )
+            .await
+            .unwrap();
+
+        // Without a team, ad-hoc runs are reserved for admins
+        let request = Request::post("/api/test-runs")
+            .header(header::AUTHORIZATION, format!("Bearer {secret}"))
+            .header("content-type", "application/json")
+            .body(Body::from(
+                r#"{"name": "x", "image": "alpine", "commands": []}"#,
+            ))
+            .unwrap();
+        let (status, body) = call(app, request).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["details"]["actor"], "carol");
+        assert_eq!(body["details"]["user_id"], user.id.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_mutations_are_audited() {
+        let (app, state) = app_with_admin_token().await;
+
+        let request = Request::post("/api/test-definitions")
+            .header(header::AUTHORIZATION, format!("Bearer {ADMIN_SECRET}"))
+            .header("content-type", "application/json")
+            .header("user-agent", "sparktest-cli/1.0")
+            .header("x-request-id", "req-42")
+            .body(Body::from(
+                r#"{"name": "smoke", "image": "alpine", "commands": []}"#,
+            ))
+            .unwrap();
+        let (status, definition) = call(app.clone(), request).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        // Unauthenticated and failed requests are recorded too
+        let request = Request::delete("/api/k8s/jobs/test-run-gone")
+            .body(Body::empty())
+            .unwrap();
+        let (status, _) = call(app.clone(), request).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Reads are not
+        let request = Request::get("/api/test-definitions")
+            .header(header::AUTHORIZATION, format!("Bearer {ADMIN_SECRET}"))
+            .body(Body::empty())
+            .unwrap();
+        call(app.clone(), request).await;
+
+        let events = state.audit.query(&Default::default()).await.unwrap();
+        assert_eq!(events.len(), 2);
+
+        let denied = &events[0];
+        assert_eq!(denied.actor, "unauthenticated");
+        assert_eq!(denied.action, "job.delete");
+        assert_eq!(denied.target_id.as_deref(), Some("test-run-gone"));
+        assert_eq!(denied.outcome, sparktest_core::AuditOutcome::Denied);
+        assert_eq!(denied.message.as_deref(), Some("Missing bearer token"));
+
+        let created = &events[1];
+        assert_eq!(created.actor, "admin");
+        assert_eq!(created.action, "test_definition.create");
+        assert_eq!(
+            created.target_id,
+            definition["id"].as_str().map(str::to_string)
+        );
+        assert_eq!(created.status_code, 201);
+        assert_eq!(created.user_agent.as_deref(), Some("sparktest-cli/1.0"));
+        assert_eq!(created.request_id, "req-42");
+
+        let request = Request::get("/api/audit?outcome=denied")
+            .header(header::AUTHORIZATION, format!("Bearer {ADMIN_SECRET}"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = call(app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["action"], "job.delete");
+    }
+
+    #[tokio::test]
+    async fn test_run_list_is_paged_through_headers() {
+        let (app, state) = app_with_admin_token().await;
+        for name in ["first", "second"] {
+            let run = sparktest_core::TestRun::new(name.into(), "alpine".into(), vec![]);
+            state.store.insert_run(run).await.unwrap();
+        }
+
+        let request = Request::get("/api/test-runs?limit=1&sort=name&order=asc&status=pending")
+            .header(header::AUTHORIZATION, format!("Bearer {ADMIN_SECRET}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-total-count"], "2");
+        let cursor = response.headers()["x-next-cursor"]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let runs: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(runs[0]["name"], "first");
+
+        let request = Request::get(format!(
+            "/api/test-runs?limit=1&sort=name&order=asc&status=pending&cursor={cursor}"
+        ))
+        .header(header::AUTHORIZATION, format!("Bearer {ADMIN_SECRET}"))
+        .body(Body::empty())
+        .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert!(response.headers().get("x-next-cursor").is_none());
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let runs: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(runs[0]["name"], "second");
+
+        let request = Request::get("/api/test-runs?sort=colour")
+            .header(header::AUTHORIZATION, format!("Bearer {ADMIN_SECRET}"))
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = call(app, request).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error_type"], "validation");
+    }
+
+    #[tokio::test]
+    async fn test_metrics_are_exported_without_token() {
+        let (app, _) = app_with_admin_token().await;
+        let (status, _) = send(Request::get("/api/test-runs").body(Body::empty()).unwrap()).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let response = app
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let text = String::from_utf8(body.to_vec()).unwrap();
+        assert!(text.contains(
+            r#"sparktest_http_request_duration_seconds_count{method="GET",route="/api/test-runs",status="200"}"#
+        ), "{text}");
+        assert!(text.contains("sparktest_active_runs"));
+    }
+
+    #[tokio::test]
+    async fn test_events_are_streamed_over_sse() {
+        let (app, state) = app_with_admin_token().await;
+        let request = Request::get("/api/events?suite_id=00000000-0000-0000-0000-000000000001")
+            .header(header::AUTHORIZATION, format!("Bearer {ADMIN_SECRET}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+
+        // Only the second run belongs to the suite asked for
+        let mut other = sparktest_core::TestRun::new("other".into(), "alpine".into(), vec![]);
+        state.events.publish_created(&other);
+        other.suite_id = Some("00000000-0000-0000-0000-000000000001".parse().unwrap());
+        state.events.publish_created(&other);
+
+        let mut body = response.into_body().into_data_stream();
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), body.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let text = String::from_utf8(chunk.to_vec()).unwrap();
+        assert!(text.contains("event: run_created"), "{text}");
+        assert!(text.contains("id: 2"), "{text}");
+        assert!(text.contains(r#""suite_id":"00000000-0000-0000-0000-000000000001""#));
+    }
 }
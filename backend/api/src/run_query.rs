@@ -0,0 +1,650 @@
+use crate::auth::Principal;
+use crate::db::traced;
+use crate::error::ApiError;
+use crate::rbac::Action;
+use crate::state::AppState;
+use crate::store::{RunRow, RUN_COLUMNS};
+use axum::http::HeaderValue;
+use axum::response::{IntoResponse, Json, Response};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sparktest_core::TestRun;
+use sqlx::{PgPool, Postgres, QueryBuilder};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+/// Runs returned when the query gives no `limit`
+pub const DEFAULT_RUN_PAGE_SIZE: usize = 100;
+/// The most runs a single page may hold
+pub const MAX_RUN_PAGE_SIZE: usize = 1000;
+
+/// Header carrying the cursor for the next page; absent on the last page
+pub const NEXT_CURSOR_HEADER: &str = "x-next-cursor";
+/// Header carrying how many runs match the filters across all pages
+pub const TOTAL_COUNT_HEADER: &str = "x-total-count";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunSortField {
+    #[default]
+    CreatedAt,
+    Name,
+    Status,
+    Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Filters, ordering and paging for `GET /api/test-runs`. Every filter given must match.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RunListQuery {
+    /// One status, or several separated by commas
+    pub status: Option<String>,
+    pub definition_id: Option<Uuid>,
+    pub suite_id: Option<Uuid>,
+    pub executor_id: Option<String>,
+    /// A label on the definition or suite the run was started from
+    pub label: Option<String>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    /// Case-insensitive substring of the run name
+    pub q: Option<String>,
+    #[serde(default)]
+    pub sort: RunSortField,
+    #[serde(default)]
+    pub order: SortOrder,
+    pub limit: Option<usize>,
+    /// Taken from the previous page's `X-Next-Cursor` header
+    pub cursor: Option<String>,
+    /// `logs` to include each run's logs, which the list leaves out otherwise
+    pub include: Option<String>,
+}
+
+impl RunListQuery {
+    fn statuses(&self) -> Option<Vec<String>> {
+        self.status.as_deref().map(|statuses| {
+            statuses
+                .split(',')
+                .map(|status| status.trim().to_ascii_lowercase())
+                .collect()
+        })
+    }
+
+    fn includes(&self, field: &str) -> bool {
+        self.include
+            .as_deref()
+            .is_some_and(|include| include.split(',').any(|f| f.trim() == field))
+    }
+
+    fn matches(&self, run: &TestRun, labels: &HashMap<Uuid, Vec<String>>) -> bool {
+        let status = self
+            .statuses()
+            .is_none_or(|statuses| statuses.contains(&run.status.to_ascii_lowercase()));
+        let name = self
+            .q
+            .as_deref()
+            .is_none_or(|q| run.name.to_lowercase().contains(&q.trim().to_lowercase()));
+        let label = self.label.as_deref().is_none_or(|label| {
+            [run.definition_id, run.suite_id]
+                .into_iter()
+                .flatten()
+                .filter_map(|id| labels.get(&id))
+                .any(|labels| labels.iter().any(|l| l == label))
+        });
+
+        status
+            && name
+            && label
+            && self
+                .definition_id
+                .is_none_or(|id| run.definition_id == Some(id))
+            && self.suite_id.is_none_or(|id| run.suite_id == Some(id))
+            && self
+                .executor_id
+                .as_deref()
+                .is_none_or(|id| run.executor_id.as_deref() == Some(id))
+            && self
+                .created_after
+                .is_none_or(|after| run.created_at >= after)
+            && self
+                .created_before
+                .is_none_or(|before| run.created_at < before)
+    }
+}
+
+/// Where a run falls in the chosen order; runs without a duration sort first
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+enum SortKey {
+    Number(Option<i64>),
+    Text(String),
+}
+
+impl SortKey {
+    fn of(run: &TestRun, field: RunSortField) -> Self {
+        match field {
+            RunSortField::CreatedAt => SortKey::Number(Some(run.created_at.timestamp_micros())),
+            RunSortField::Name => SortKey::Text(run.name.to_lowercase()),
+            RunSortField::Status => SortKey::Text(run.status.clone()),
+            RunSortField::Duration => SortKey::Number(run.duration.map(i64::from)),
+        }
+    }
+
+    /// The same order in SQL. Text compares bytewise, like Rust strings, and runs
+    /// without a duration come before a duration of 0.
+    fn column(field: RunSortField) -> &'static str {
+        match field {
+            RunSortField::CreatedAt => "created_at",
+            RunSortField::Name => "lower(name) COLLATE \"C\"",
+            RunSortField::Status => "status COLLATE \"C\"",
+            RunSortField::Duration => "COALESCE(duration::BIGINT, -1)",
+        }
+    }
+
+    fn push_bind(&self, sql: &mut QueryBuilder<'_, Postgres>, field: RunSortField) {
+        match (self, field) {
+            (SortKey::Number(micros), RunSortField::CreatedAt) => {
+                sql.push_bind(micros.and_then(DateTime::<Utc>::from_timestamp_micros))
+            }
+            (SortKey::Number(number), _) => sql.push_bind(number.unwrap_or(-1)),
+            (SortKey::Text(text), _) => sql.push_bind(text.clone()),
+        };
+    }
+
+    fn fits(&self, field: RunSortField) -> bool {
+        matches!(
+            (self, field),
+            (SortKey::Number(Some(_)), RunSortField::CreatedAt)
+                | (SortKey::Number(_), RunSortField::Duration)
+                | (SortKey::Text(_), RunSortField::Name | RunSortField::Status)
+        )
+    }
+}
+
+/// The last run of a page and the order it was in. Encoded as hex so clients
+/// treat it as opaque.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct RunCursor {
+    sort: RunSortField,
+    order: SortOrder,
+    key: SortKey,
+    id: Uuid,
+}
+
+impl RunCursor {
+    fn encode(&self) -> String {
+        hex::encode(serde_json::to_vec(self).expect("cursors serialize to JSON"))
+    }
+
+    fn decode(cursor: &str) -> Result<Self, ApiError> {
+        hex::decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .ok_or_else(|| ApiError::validation("Invalid cursor"))
+    }
+}
+
+/// One page of runs; the body is the runs, paging goes in headers so clients
+/// that expect a plain array keep working
+#[derive(Debug)]
+pub struct RunPage {
+    pub runs: Vec<TestRun>,
+    pub next_cursor: Option<String>,
+    pub total: usize,
+}
+
+impl IntoResponse for RunPage {
+    fn into_response(self) -> Response {
+        let mut response = Json(self.runs).into_response();
+        let headers = response.headers_mut();
+        headers.insert(TOTAL_COUNT_HEADER, HeaderValue::from(self.total));
+        if let Some(cursor) = self.next_cursor {
+            headers.insert(
+                NEXT_CURSOR_HEADER,
+                HeaderValue::from_str(&cursor).expect("cursors are hex"),
+            );
+        }
+        response
+    }
+}
+
+/// The page of runs the principal may view that matches the query
+pub async fn query_runs(
+    state: &AppState,
+    principal: &Principal,
+    query: &RunListQuery,
+) -> Result<RunPage, ApiError> {
+    let limit = query.limit.unwrap_or(DEFAULT_RUN_PAGE_SIZE);
+    if limit == 0 || limit > MAX_RUN_PAGE_SIZE {
+        return Err(ApiError::validation(format!(
+            "limit must be between 1 and {MAX_RUN_PAGE_SIZE}"
+        )));
+    }
+    if let (Some(after), Some(before)) = (query.created_after, query.created_before) {
+        if after >= before {
+            return Err(ApiError::validation(
+                "created_after must be before created_before",
+            ));
+        }
+    }
+    let cursor = query.cursor.as_deref().map(RunCursor::decode).transpose()?;
+    if let Some(cursor) = &cursor {
+        if !cursor.key.fits(cursor.sort) {
+            return Err(ApiError::validation("Invalid cursor"));
+        }
+        if cursor.sort != query.sort || cursor.order != query.order {
+            return Err(ApiError::validation(
+                "The cursor belongs to a different sort order",
+            ));
+        }
+    }
+
+    // Page one further than asked, to know whether there is a next page
+    let (mut page, total) = match &state.db {
+        Some(db) => stored_runs(db, principal, query, cursor.as_ref(), limit + 1).await?,
+        None => held_runs(state, principal, query, cursor.as_ref(), limit + 1).await,
+    };
+
+    let next_cursor = if page.len() > limit {
+        page.truncate(limit);
+        page.last().map(|(key, run)| {
+            RunCursor {
+                sort: query.sort,
+                order: query.order,
+                key: key.clone(),
+                id: run.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    let include_logs = query.includes("logs");
+    let runs = page
+        .into_iter()
+        .map(|(_, mut run)| {
+            if !include_logs {
+                run.logs = None;
+            }
+            state.queue.annotate(&mut run);
+            run
+        })
+        .collect();
+
+    Ok(RunPage {
+        runs,
+        next_cursor,
+        total,
+    })
+}
+
+/// Runs in the order asked for, after the cursor, from the runs the server holds
+async fn held_runs(
+    state: &AppState,
+    principal: &Principal,
+    query: &RunListQuery,
+    cursor: Option<&RunCursor>,
+    limit: usize,
+) -> (Vec<(SortKey, TestRun)>, usize) {
+    let labels = match query.label {
+        Some(_) => run_source_labels(state).await,
+        None => HashMap::new(),
+    };
+
+    let mut runs: Vec<(SortKey, TestRun)> = state
+        .store
+        .list_runs()
+        .await
+        .into_iter()
+        .filter(|run| principal.can(Action::View, &run.into()))
+        .filter(|run| query.matches(run, &labels))
+        .map(|run| (SortKey::of(&run, query.sort), run))
+        .collect();
+    runs.sort_by(|(a, x), (b, y)| (a, x.id).cmp(&(b, y.id)));
+    if query.order == SortOrder::Desc {
+        runs.reverse();
+    }
+    let total = runs.len();
+
+    let start = match cursor {
+        Some(cursor) => runs.partition_point(|(key, run)| {
+            let position = (key, run.id).cmp(&(&cursor.key, cursor.id));
+            match query.order {
+                SortOrder::Asc => position.is_le(),
+                SortOrder::Desc => position.is_ge(),
+            }
+        }),
+        None => 0,
+    };
+    (runs.into_iter().skip(start).take(limit).collect(), total)
+}
+
+/// The same page from `test_runs`, so the indexes on it do the filtering,
+/// searching and ordering
+async fn stored_runs(
+    db: &PgPool,
+    principal: &Principal,
+    query: &RunListQuery,
+    cursor: Option<&RunCursor>,
+    limit: usize,
+) -> Result<(Vec<(SortKey, TestRun)>, usize), sqlx::Error> {
+    let column = SortKey::column(query.sort);
+    let (direction, comparison) = match query.order {
+        SortOrder::Asc => ("ASC", ">"),
+        SortOrder::Desc => ("DESC", "<"),
+    };
+
+    let mut sql =
+        QueryBuilder::<Postgres>::new(format!("SELECT {RUN_COLUMNS} FROM test_runs WHERE TRUE"));
+    push_run_filters(&mut sql, principal, query);
+    if let Some(cursor) = cursor {
+        sql.push(format_args!(" AND ({column}, id) {comparison} ("));
+        cursor.key.push_bind(&mut sql, query.sort);
+        sql.push(", ").push_bind(cursor.id).push(")");
+    }
+    sql.push(format_args!(
+        " ORDER BY {column} {direction}, id {direction} LIMIT "
+    ))
+    .push_bind(limit as i64);
+    let statement = sql.sql().to_string();
+    let rows: Vec<RunRow> = traced(&statement, |_| sql.build_query_as().fetch_all(db)).await?;
+
+    let mut count = QueryBuilder::<Postgres>::new("SELECT COUNT(*) FROM test_runs WHERE TRUE");
+    push_run_filters(&mut count, principal, query);
+    let statement = count.sql().to_string();
+    let (total,): (i64,) = traced(&statement, |_| count.build_query_as().fetch_one(db)).await?;
+
+    let runs = rows
+        .into_iter()
+        .map(|row| {
+            let run = TestRun::from(row);
+            (SortKey::of(&run, query.sort), run)
+        })
+        .collect();
+    Ok((runs, total as usize))
+}
+
+/// The filters of [`RunListQuery::matches`], and the runs the principal may view
+fn push_run_filters(
+    sql: &mut QueryBuilder<'_, Postgres>,
+    principal: &Principal,
+    query: &RunListQuery,
+) {
+    push_visible_runs(sql, principal);
+    if let Some(statuses) = query.statuses() {
+        sql.push(" AND lower(status) = ANY(")
+            .push_bind(statuses)
+            .push(")");
+    }
+    if let Some(id) = query.definition_id {
+        sql.push(" AND test_definition_id = ").push_bind(id);
+    }
+    if let Some(id) = query.suite_id {
+        sql.push(" AND suite_id = ").push_bind(id);
+    }
+    if let Some(id) = &query.executor_id {
+        sql.push(" AND executor_id = ").push_bind(id.clone());
+    }
+    if let Some(label) = &query.label {
+        sql.push(
+            " AND (EXISTS (SELECT 1 FROM test_definitions d \
+             WHERE d.id = test_runs.test_definition_id AND ",
+        )
+        .push_bind(label.clone())
+        .push(
+            " = ANY(d.labels)) OR EXISTS (SELECT 1 FROM test_suites s \
+             WHERE s.id = test_runs.suite_id AND ",
+        )
+        .push_bind(label.clone())
+        .push(" = ANY(s.labels)))");
+    }
+    if let Some(after) = query.created_after {
+        sql.push(" AND created_at >= ").push_bind(after);
+    }
+    if let Some(before) = query.created_before {
+        sql.push(" AND created_at < ").push_bind(before);
+    }
+    if let Some(q) = &query.q {
+        // Matches the trigram index on lower(name)
+        sql.push(" AND lower(name) LIKE ")
+            .push_bind(format!("%{}%", escape_like(&q.trim().to_lowercase())));
+    }
+}
+
+/// Runs [`Principal::can`] view: those of the principal's teams and those of no team
+pub(crate) fn push_visible_runs(sql: &mut QueryBuilder<'_, Postgres>, principal: &Principal) {
+    if !principal.is_admin {
+        let teams: Vec<Uuid> = principal.roles.keys().copied().collect();
+        sql.push(" AND (team_id IS NULL OR team_id = ANY(")
+            .push_bind(teams)
+            .push("))");
+    }
+}
+
+/// `q` is a plain substring, so LIKE wildcards in it match themselves
+fn escape_like(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Labels of every definition and suite, by id
+async fn run_source_labels(state: &AppState) -> HashMap<Uuid, Vec<String>> {
+    let definitions = state
+        .store
+        .list_definitions()
+        .await
+        .into_iter()
+        .map(|definition| (definition.id, definition.labels.unwrap_or_default()));
+    let suites = state
+        .store
+        .list_suites()
+        .await
+        .into_iter()
+        .map(|suite| (suite.id, suite.labels.unwrap_or_default()));
+    definitions.chain(suites).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use crate::store::Store;
+    use sparktest_core::{Role, TestDefinition};
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let cursor = RunCursor {
+            sort: RunSortField::Duration,
+            order: SortOrder::Asc,
+            key: SortKey::Number(None),
+            id: Uuid::new_v4(),
+        };
+        assert_eq!(RunCursor::decode(&cursor.encode()).unwrap(), cursor);
+
+        let cursor = RunCursor {
+            sort: RunSortField::Name,
+            order: SortOrder::Desc,
+            key: SortKey::Text("nightly".to_string()),
+            id: Uuid::new_v4(),
+        };
+        assert_eq!(RunCursor::decode(&cursor.encode()).unwrap(), cursor);
+        assert!(RunCursor::decode("not-a-cursor").is_err());
+    }
+
+    #[test]
+    fn test_runs_without_duration_sort_first() {
+        assert!(SortKey::Number(None) < SortKey::Number(Some(0)));
+        assert!(SortKey::Number(Some(3)) < SortKey::Number(Some(10)));
+    }
+
+    /// Every page of the query, following the cursors
+    async fn all_pages(state: &AppState, principal: &Principal, query: &RunListQuery) -> Vec<Uuid> {
+        let mut query = query.clone();
+        let mut ids = Vec::new();
+        loop {
+            let page = query_runs(state, principal, &query).await.unwrap();
+            ids.extend(page.runs.iter().map(|run| run.id));
+            match page.next_cursor {
+                Some(cursor) => query.cursor = Some(cursor),
+                None => return ids,
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stored_runs_are_listed_like_held_runs() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        // Other tests share the database, so every query searches for this tag
+        let tag = Uuid::new_v4().simple().to_string();
+        let (team, other_team) = (Uuid::new_v4(), Uuid::new_v4());
+        let store = Store::load(db.clone()).await.unwrap();
+        let definition = TestDefinition {
+            id: Uuid::new_v4(),
+            name: format!("{tag} smoke"),
+            description: String::new(),
+            image: "alpine".to_string(),
+            commands: vec!["true".to_string()],
+            created_at: Utc::now(),
+            executor_id: None,
+            variables: None,
+            labels: Some(vec![format!("{tag}-label")]),
+            target: None,
+            team_id: None,
+            resources: None,
+            privileged: false,
+            secrets: Vec::new(),
+            source: None,
+            files: Vec::new(),
+            priority: Default::default(),
+            job_labels: Default::default(),
+            job_annotations: Default::default(),
+        };
+        store.insert_definition(definition.clone()).await.unwrap();
+        let runs = [
+            ("Alpha", "succeeded", Some(3), Some(team), true),
+            ("beta", "failed", None, None, true),
+            ("Gamma 100%", "failed", Some(10), Some(team), false),
+            ("delta_1", "running", Some(3), None, false),
+            ("epsilon", "succeeded", None, Some(other_team), true),
+            ("zeta", "pending", Some(0), None, false),
+        ];
+        let start = Utc::now();
+        for (i, (name, status, duration, team_id, from_definition)) in runs.into_iter().enumerate()
+        {
+            let mut run = TestRun::new(format!("{tag} {name}"), "alpine".to_string(), Vec::new());
+            run.status = status.to_string();
+            run.duration = duration;
+            run.team_id = team_id;
+            run.created_at = start + chrono::Duration::seconds(i as i64 % 4);
+            if from_definition {
+                run.definition_id = Some(definition.id);
+            }
+            store.insert_run(run).await.unwrap();
+        }
+
+        let held = AppState {
+            store: store.clone(),
+            ..AppState::default()
+        };
+        let stored = AppState {
+            db: Some(db),
+            ..held.clone()
+        };
+        let member = Principal {
+            is_admin: false,
+            roles: HashMap::from([(team, Role::Viewer)]),
+            ..Principal::anonymous()
+        };
+        let queries = [
+            RunListQuery::default(),
+            RunListQuery {
+                status: Some("Failed, succeeded".to_string()),
+                ..Default::default()
+            },
+            RunListQuery {
+                label: Some(format!("{tag}-label")),
+                ..Default::default()
+            },
+            RunListQuery {
+                definition_id: Some(definition.id),
+                created_after: Some(start + chrono::Duration::seconds(1)),
+                ..Default::default()
+            },
+        ];
+        for principal in [Principal::anonymous(), member] {
+            for base in &queries {
+                for sort in [
+                    RunSortField::CreatedAt,
+                    RunSortField::Name,
+                    RunSortField::Status,
+                    RunSortField::Duration,
+                ] {
+                    for order in [SortOrder::Asc, SortOrder::Desc] {
+                        let query = RunListQuery {
+                            q: Some(tag.clone()),
+                            sort,
+                            order,
+                            limit: Some(2),
+                            ..base.clone()
+                        };
+                        let expected = all_pages(&held, &principal, &query).await;
+                        assert!(!expected.is_empty());
+                        assert_eq!(
+                            all_pages(&stored, &principal, &query).await,
+                            expected,
+                            "{query:?}"
+                        );
+                        let total = query_runs(&stored, &principal, &query).await.unwrap().total;
+                        assert_eq!(total, expected.len());
+                    }
+                }
+            }
+        }
+
+        // LIKE wildcards in the search are taken literally
+        let query = RunListQuery {
+            q: Some(format!("{tag} gamma 100%")),
+            ..Default::default()
+        };
+        assert_eq!(
+            all_pages(&stored, &Principal::anonymous(), &query)
+                .await
+                .len(),
+            1
+        );
+        let query = RunListQuery {
+            q: Some(format!("{tag} delta_")),
+            ..Default::default()
+        };
+        assert_eq!(
+            all_pages(&stored, &Principal::anonymous(), &query)
+                .await
+                .len(),
+            1
+        );
+        let query = RunListQuery {
+            q: Some(format!("{tag} %a")),
+            ..Default::default()
+        };
+        assert!(all_pages(&stored, &Principal::anonymous(), &query)
+            .await
+            .is_empty());
+
+        for run in store.list_runs().await {
+            if run.name.starts_with(&tag) {
+                store.remove_run(run.id).await.unwrap();
+            }
+        }
+        store.remove_definition(definition.id).await.unwrap();
+    }
+}
@@ -0,0 +1,368 @@
+use crate::backend::JobSpec;
+use crate::commit_status::report_run_status;
+use crate::events::EventKind;
+use crate::metrics::metrics;
+use crate::notifications::{notify_run_finished, notify_suite_finished};
+use crate::secrets::{resolve_run_secrets, Redactor};
+use crate::source::DEFAULT_GIT_IMAGE;
+use crate::state::AppState;
+use crate::telemetry::current_traceparent;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use sparktest_core::{ExecutionTarget, TestRun};
+use std::collections::BTreeMap;
+use tokio::time::{sleep, Duration};
+use tracing::{instrument, warn, Instrument};
+use uuid::Uuid;
+
+/// Tuning for the background task that drives a run through its backend
+#[derive(Debug, Clone)]
+pub struct RunnerConfig {
+    pub poll_interval: Duration,
+    /// Clones a run's Git source before its tests start; subject to the admission policy
+    pub git_image: String,
+}
+
+impl Default for RunnerConfig {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(2),
+            git_image: DEFAULT_GIT_IMAGE.to_string(),
+        }
+    }
+}
+
+impl RunnerConfig {
+    /// `SPARKTEST_GIT_IMAGE` replaces the image that clones Git sources
+    pub fn from_env() -> Self {
+        let mut config = Self::default();
+        if let Ok(image) = std::env::var("SPARKTEST_GIT_IMAGE") {
+            if !image.trim().is_empty() {
+                config.git_image = image.trim().to_string();
+            }
+        }
+        config
+    }
+}
+
+/// What happened to a single job attempt
+enum JobOutcome {
+    Finished { succeeded: bool, logs: Vec<String> },
+    RunDeleted,
+}
+
+/// Launch a stored run in the background
+pub fn spawn_run(state: AppState, run_id: Uuid) {
+    tokio::spawn(
+        async move {
+            if let Err(e) = execute_run(&state, run_id).await {
+                warn!("Run {} did not complete cleanly: {:#}", run_id, e);
+            }
+        }
+        .in_current_span(),
+    );
+}
+
+/// Launch the runs of a suite in the background, either all at once or one
+/// after another in the given order, and notify about the suite once they
+/// have all finished
+pub fn spawn_suite(state: AppState, run_ids: Vec<Uuid>, sequential: bool) {
+    tokio::spawn(
+        async move {
+            if sequential {
+                for run_id in &run_ids {
+                    execute_suite_run(&state, *run_id, &run_ids).await;
+                }
+            } else {
+                let runs: Vec<_> = run_ids
+                    .iter()
+                    .map(|run_id| {
+                        let (state, run_id, run_ids) = (state.clone(), *run_id, run_ids.clone());
+                        tokio::spawn(
+                            async move { execute_suite_run(&state, run_id, &run_ids).await }
+                                .in_current_span(),
+                        )
+                    })
+                    .collect();
+                futures::future::join_all(runs).await;
+            }
+            notify_suite_finished(&state, &run_ids).await;
+        }
+        .in_current_span(),
+    );
+}
+
+/// Put the runs a previous process left waiting back in the queue, where they
+/// take their place by priority and creation time again. A suite's waiting runs
+/// are resumed together, one after another if the suite runs sequentially.
+/// Call before anything else can launch runs.
+pub async fn resume_queued_runs(state: &AppState) -> usize {
+    let mut pending: Vec<TestRun> = state
+        .store
+        .list_runs()
+        .await
+        .into_iter()
+        .filter(|run| run.status == "pending")
+        .collect();
+    pending.sort_by_key(|run| run.created_at);
+
+    let mut suites: BTreeMap<Uuid, Vec<Uuid>> = BTreeMap::new();
+    for run in &pending {
+        match run.suite_id {
+            Some(suite_id) => suites.entry(suite_id).or_default().push(run.id),
+            None => spawn_run(state.clone(), run.id),
+        }
+    }
+    for (suite_id, run_ids) in suites {
+        let sequential = state
+            .store
+            .get_suite(suite_id)
+            .await
+            .is_some_and(|suite| suite.execution_mode == "sequential");
+        spawn_suite(state.clone(), run_ids, sequential);
+    }
+    pending.len()
+}
+
+/// Run one run of a suite, then publish how far the suite has got
+async fn execute_suite_run(state: &AppState, run_id: Uuid, run_ids: &[Uuid]) {
+    if let Err(e) = execute_run(state, run_id).await {
+        warn!("Run {} did not complete cleanly: {:#}", run_id, e);
+    }
+
+    let mut runs = Vec::new();
+    for run_id in run_ids {
+        runs.extend(state.store.get_run(*run_id).await);
+    }
+    let Some(suite_id) = runs.first().and_then(|run| run.suite_id) else {
+        return;
+    };
+    let finished = |status: &[&str]| {
+        runs.iter()
+            .filter(|run| status.contains(&run.status.as_str()))
+            .count()
+    };
+    state.events.publish_suite(
+        suite_id,
+        runs[0].team_id,
+        EventKind::SuiteProgress {
+            finished: finished(&["succeeded", "failed"]),
+            failed: finished(&["failed"]),
+            total: run_ids.len(),
+        },
+    );
+}
+
+/// Run a stored run to completion, retrying failed attempts up to `run.retries`
+/// times, then record the final status, duration and logs on the run
+#[instrument(name = "run", skip(state))]
+pub async fn execute_run(state: &AppState, run_id: Uuid) -> Result<()> {
+    let run = state
+        .store
+        .get_run(run_id)
+        .await
+        .with_context(|| format!("Run {run_id} not found"))?;
+    // Waits for the concurrency limits to leave room; the slot is held until the run finishes
+    let Some(_slot) = state.queue.enter(&run).await else {
+        // Deleted while it was waiting
+        return Ok(());
+    };
+    let started = Utc::now();
+    let _active = metrics().active_run();
+    metrics()
+        .queue_wait
+        .observe((started - run.created_at).num_milliseconds().max(0) as f64 / 1000.0);
+
+    let Some(target) = state.targets.resolve(run.target.as_deref()).await else {
+        let message = format!(
+            "Execution target '{}' no longer exists",
+            run.target.as_deref().unwrap_or_default()
+        );
+        finish_run(state, run_id, false, vec![message.clone()], started).await;
+        return Err(anyhow::anyhow!(message));
+    };
+
+    // A missing secret would leave the pod stuck until the run times out, so
+    // check the references up front; the values are only kept to redact logs
+    let redactor = match resolve_run_secrets(state.backend.as_ref(), &target, &run).await {
+        Ok(values) => Redactor::new(values),
+        Err(e) => {
+            finish_run(state, run_id, false, vec![format!("{e:#}")], started).await;
+            return Err(e);
+        }
+    };
+
+    let files = state.store.files_of(run_id).await;
+    let attempts = run.retries.unwrap_or(0).max(0) + 1;
+    let mut logs = Vec::new();
+    let mut succeeded = false;
+
+    for attempt in 1..=attempts {
+        let mut spec = JobSpec::for_run(&run, &state.runner.git_image);
+        spec.files = files.clone();
+        // Lets the tests' own spans and output be correlated with the run
+        if let Some(traceparent) = current_traceparent() {
+            spec.env.insert("TRACEPARENT".to_string(), traceparent);
+        }
+        if attempt > 1 {
+            // Earlier attempts keep their job around for debugging, so retries need their own name
+            spec.name = format!("{}-retry-{}", spec.name, attempt - 1);
+            logs.push(format!("Retrying (attempt {attempt} of {attempts})"));
+        }
+
+        match run_job(state, &target, run_id, &spec).await {
+            JobOutcome::Finished {
+                succeeded: job_succeeded,
+                logs: job_logs,
+            } => {
+                logs.extend(job_logs);
+                succeeded = job_succeeded;
+            }
+            JobOutcome::RunDeleted => return Ok(()),
+        }
+
+        if succeeded {
+            break;
+        }
+    }
+
+    let logs = redactor.redact_lines(logs);
+    finish_run(state, run_id, succeeded, logs, started).await;
+    Ok(())
+}
+
+/// Create one job, poll it until it finishes or times out, and collect its logs
+async fn run_job(
+    state: &AppState,
+    target: &ExecutionTarget,
+    run_id: Uuid,
+    spec: &JobSpec,
+) -> JobOutcome {
+    if let Err(e) = state.backend.create_job(target, spec).await {
+        return JobOutcome::Finished {
+            succeeded: false,
+            logs: vec![format!("Failed to launch job: {e:#}")],
+        };
+    }
+
+    let updated = state
+        .store
+        .update_run(run_id, |run| {
+            run.status = "running".to_string();
+            run.k8s_job_name = Some(spec.name.clone());
+        })
+        .await;
+    let Some(run) = updated else {
+        // The run was deleted while its job was being created
+        state.backend.delete_job(target, &spec.name).await.ok();
+        return JobOutcome::RunDeleted;
+    };
+    state.events.publish_run(
+        &run,
+        EventKind::StatusChanged {
+            status: run.status.clone(),
+        },
+    );
+    report_run_status(state, &run);
+
+    let deadline = Utc::now() + chrono::Duration::seconds(target.timeout_seconds as i64);
+    let mut timed_out = false;
+    let succeeded = loop {
+        sleep(state.runner.poll_interval).await;
+
+        match state.backend.get_job_status(target, &spec.name).await {
+            Ok(status) if status == "completed" => break true,
+            Ok(status) if status == "failed" => break false,
+            Ok(status) if status == "running" => {
+                let mut started = None;
+                let updated = state
+                    .store
+                    .update_run(run_id, |run| {
+                        if run.container_started.is_none() {
+                            run.container_started = Some(Utc::now());
+                            started = run.container_started;
+                        }
+                    })
+                    .await;
+                if let (Some(run), Some(at)) = (updated, started) {
+                    state.events.publish_run(
+                        &run,
+                        EventKind::LifecycleTimestamp {
+                            field: "container_started",
+                            at,
+                        },
+                    );
+                }
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Failed to poll job '{}': {:#}", spec.name, e),
+        }
+
+        if state.store.get_run(run_id).await.is_none() {
+            return JobOutcome::RunDeleted;
+        }
+        if Utc::now() >= deadline {
+            timed_out = true;
+            break false;
+        }
+    };
+
+    let mut logs: Vec<String> = match state.backend.get_job_logs(target, &spec.name).await {
+        Ok(job_logs) => job_logs.logs.lines().map(str::to_string).collect(),
+        Err(e) => vec![format!("Failed to collect logs: {e:#}")],
+    };
+    if timed_out {
+        logs.push(format!(
+            "Run timed out after {} seconds",
+            target.timeout_seconds
+        ));
+        if state.backend.delete_job(target, &spec.name).await.is_ok() {
+            let run = state.store.get_run(run_id).await;
+            state.events.publish_job_deleted(&spec.name, run.as_ref());
+        }
+    }
+
+    JobOutcome::Finished { succeeded, logs }
+}
+
+pub(crate) async fn finish_run(
+    state: &AppState,
+    run_id: Uuid,
+    succeeded: bool,
+    logs: Vec<String>,
+    started: chrono::DateTime<Utc>,
+) {
+    let now = Utc::now();
+    let updated = state
+        .store
+        .update_run(run_id, |run| {
+            run.duration = Some((now - started).num_seconds() as i32);
+            run.logs = Some(logs);
+            if succeeded {
+                run.status = "succeeded".to_string();
+                run.completed = Some(now);
+            } else {
+                run.status = "failed".to_string();
+                run.failed = Some(now);
+            }
+        })
+        .await;
+    if let Some(run) = updated {
+        metrics().run_finished(&run, (now - started).num_milliseconds() as f64 / 1000.0);
+        state.events.publish_run(
+            &run,
+            EventKind::StatusChanged {
+                status: run.status.clone(),
+            },
+        );
+        state.events.publish_run(
+            &run,
+            EventKind::LifecycleTimestamp {
+                field: if succeeded { "completed" } else { "failed" },
+                at: now,
+            },
+        );
+        report_run_status(state, &run);
+        notify_run_finished(state, &run);
+    }
+}
@@ -0,0 +1,613 @@
+use crate::db::traced;
+use crate::error::ApiError;
+use crate::launch::{launch_definition, launch_suite, RunOrigin};
+use crate::state::AppState;
+use chrono::{DateTime, SubsecRound, Utc};
+use chrono_tz::Tz;
+use croner::Cron;
+use sparktest_core::{Schedule, ScheduleOutcome, TestRun};
+use tokio::time::{sleep, Duration};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Upper bound on the occurrences walked through when catching up after
+/// downtime, so a per-second schedule can't stall the scheduler
+const MAX_CATCH_UP: usize = 100_000;
+
+/// How the scheduler runs
+#[derive(Debug, Clone)]
+pub struct SchedulerConfig {
+    /// How often schedules are checked for due occurrences
+    pub tick: Duration,
+    /// How late an occurrence may still start runs, e.g. after a restart;
+    /// older occurrences are recorded as missed
+    pub misfire_grace: chrono::Duration,
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            tick: Duration::from_secs(10),
+            misfire_grace: chrono::Duration::minutes(5),
+        }
+    }
+}
+
+impl SchedulerConfig {
+    /// Reads `SPARKTEST_SCHEDULE_MISFIRE_GRACE_SECONDS`
+    pub fn from_env() -> Self {
+        let defaults = Self::default();
+        Self {
+            misfire_grace: std::env::var("SPARKTEST_SCHEDULE_MISFIRE_GRACE_SECONDS")
+                .ok()
+                .and_then(|seconds| seconds.parse().ok())
+                .map(chrono::Duration::seconds)
+                .unwrap_or(defaults.misfire_grace),
+            ..defaults
+        }
+    }
+}
+
+/// A schedule's cron expression, evaluated in its time zone
+#[derive(Debug, Clone)]
+pub struct CronSchedule {
+    cron: Cron,
+    timezone: Tz,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str, timezone: &str) -> Result<Self, ApiError> {
+        let fields = expression.split_whitespace().count();
+        if !(5..=6).contains(&fields) {
+            return Err(ApiError::validation(
+                "cron must have five fields (minute hour day-of-month month day-of-week), \
+                 or six with leading seconds",
+            ));
+        }
+        let cron = Cron::new(expression)
+            .with_seconds_optional()
+            .parse()
+            .map_err(|e| {
+                ApiError::validation(format!("Invalid cron expression '{expression}': {e}"))
+            })?;
+        let timezone = timezone
+            .parse()
+            .map_err(|_| ApiError::validation(format!("Unknown time zone '{timezone}'")))?;
+        Ok(Self { cron, timezone })
+    }
+
+    /// The first occurrence strictly after `after`
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let local = after.trunc_subsecs(0).with_timezone(&self.timezone);
+        let next = self.cron.find_next_occurrence(&local, false).ok()?;
+        Some(next.with_timezone(&Utc))
+    }
+
+    /// The latest occurrence after `after` and no later than `until`
+    pub fn latest_between(
+        &self,
+        after: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        let mut latest = None;
+        let mut cursor = after;
+        for _ in 0..MAX_CATCH_UP {
+            match self.next_after(cursor) {
+                Some(next) if next <= until => {
+                    latest = Some(next);
+                    cursor = next;
+                }
+                _ => break,
+            }
+        }
+        latest
+    }
+}
+
+/// Check a schedule before it is stored, filling in its next occurrence
+pub fn prepare_schedule(schedule: &mut Schedule) -> Result<(), ApiError> {
+    if schedule.name.is_empty() {
+        return Err(ApiError::validation("name is required"));
+    }
+    let cron = CronSchedule::parse(&schedule.cron, &schedule.timezone)?;
+    schedule.next_run_at = if schedule.enabled {
+        cron.next_after(Utc::now())
+    } else {
+        None
+    };
+    Ok(())
+}
+
+/// Check schedules for due occurrences until the process exits
+pub async fn run_scheduler(state: AppState) {
+    // Definitions synced from the cluster come back shortly after a restart;
+    // give them a tick before schedules that start them are checked
+    loop {
+        sleep(state.scheduler.tick).await;
+        run_due_schedules(&state, Utc::now()).await;
+    }
+}
+
+/// Handle the latest due occurrence of every enabled schedule. Occurrences
+/// missed while the server was down collapse into one, which only starts runs
+/// within the misfire grace period. Schedules whose definition or suite no
+/// longer exists are disabled.
+pub async fn run_due_schedules(state: &AppState, now: DateTime<Utc>) {
+    for schedule in state.store.list_schedules().await {
+        if !schedule.enabled {
+            continue;
+        }
+        // Stored schedules were validated when they were created
+        let Ok(cron) = CronSchedule::parse(&schedule.cron, &schedule.timezone) else {
+            continue;
+        };
+        let since = schedule
+            .last_fired_at
+            .map_or(schedule.updated_at, |fired| fired.max(schedule.updated_at));
+        let Some(occurrence) = cron.latest_between(since, now) else {
+            continue;
+        };
+
+        // Record the occurrence as handled before starting anything, so a
+        // restart in between cannot start it a second time
+        update_and_save(state, schedule.id, |schedule| {
+            schedule.last_fired_at = Some(occurrence);
+            schedule.next_run_at = cron.next_after(now);
+        })
+        .await;
+
+        let missing = missing_target(state, &schedule).await;
+        let (outcome, error) = if let Some(missing) = &missing {
+            (
+                ScheduleOutcome::Failed,
+                Some(format!(
+                    "{missing} no longer exists, so the schedule was disabled"
+                )),
+            )
+        } else if now - occurrence > state.scheduler.misfire_grace {
+            (
+                ScheduleOutcome::Missed,
+                Some(format!(
+                    "The occurrence at {} passed while the scheduler was not running",
+                    occurrence.to_rfc3339()
+                )),
+            )
+        } else if state.store.has_active_runs(schedule.id).await {
+            (
+                ScheduleOutcome::Skipped,
+                Some("Runs from the previous occurrence are still in progress".to_string()),
+            )
+        } else {
+            match start_schedule(state, &schedule).await {
+                Ok(runs) => {
+                    info!(
+                        "Schedule '{}' started {} run(s) for {}",
+                        schedule.name,
+                        runs.len(),
+                        occurrence.to_rfc3339()
+                    );
+                    (ScheduleOutcome::Started, None)
+                }
+                Err(e) => (ScheduleOutcome::Failed, Some(e.message)),
+            }
+        };
+        if let Some(error) = &error {
+            warn!("Schedule '{}': {}", schedule.name, error);
+        }
+        update_and_save(state, schedule.id, |schedule| {
+            schedule.last_outcome = Some(outcome);
+            schedule.last_error = error;
+            if missing.is_some() {
+                schedule.enabled = false;
+                schedule.next_run_at = None;
+            }
+        })
+        .await;
+    }
+}
+
+/// The definition or suite the schedule starts, if it has been deleted
+async fn missing_target(state: &AppState, schedule: &Schedule) -> Option<String> {
+    if let Some(id) = schedule.test_definition_id {
+        if state.store.get_definition(id).await.is_none() {
+            return Some(format!("Test definition {id}"));
+        }
+    }
+    if let Some(id) = schedule.test_suite_id {
+        if state.store.get_suite(id).await.is_none() {
+            return Some(format!("Test suite {id}"));
+        }
+    }
+    None
+}
+
+async fn update_and_save<F>(state: &AppState, id: Uuid, update: F)
+where
+    F: FnOnce(&mut Schedule),
+{
+    let Some(schedule) = state.store.update_schedule(id, update).await else {
+        return;
+    };
+    if let Err(e) = save_schedule(state, &schedule).await {
+        warn!("Failed to save schedule '{}': {}", schedule.name, e);
+    }
+}
+
+async fn start_schedule(state: &AppState, schedule: &Schedule) -> Result<Vec<TestRun>, ApiError> {
+    let origin = RunOrigin {
+        schedule_id: Some(schedule.id),
+        ..RunOrigin::default()
+    };
+    if let Some(id) = schedule.test_definition_id {
+        let definition = state
+            .store
+            .get_definition(id)
+            .await
+            .ok_or_else(|| ApiError::not_found(format!("Test definition {id} not found")))?;
+        return Ok(vec![launch_definition(state, &definition, origin).await?]);
+    }
+    if let Some(id) = schedule.test_suite_id {
+        let suite = state
+            .store
+            .get_suite(id)
+            .await
+            .ok_or_else(|| ApiError::not_found(format!("Test suite {id} not found")))?;
+        return launch_suite(state, &suite, origin).await;
+    }
+    Ok(Vec::new())
+}
+
+#[derive(sqlx::FromRow)]
+struct ScheduleRow {
+    id: Uuid,
+    name: String,
+    cron: String,
+    timezone: String,
+    test_definition_id: Option<Uuid>,
+    test_suite_id: Option<Uuid>,
+    enabled: bool,
+    team_id: Option<Uuid>,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    last_fired_at: Option<DateTime<Utc>>,
+    last_outcome: Option<String>,
+    last_error: Option<String>,
+    next_run_at: Option<DateTime<Utc>>,
+}
+
+/// Restore the schedules stored in the database by a previous process,
+/// returning how many. Without a database schedules only live in memory.
+pub async fn load_schedules(state: &AppState) -> Result<usize, sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(0);
+    };
+    let rows: Vec<ScheduleRow> = traced(
+        "SELECT id, name, cron, timezone, test_definition_id, test_suite_id, enabled, team_id, \
+         created_at, updated_at, last_fired_at, last_outcome, last_error, next_run_at \
+         FROM schedules",
+        |sql| sqlx::query_as(sql).fetch_all(db),
+    )
+    .await?;
+
+    let count = rows.len();
+    for row in rows {
+        state
+            .store
+            .insert_schedule(Schedule {
+                id: row.id,
+                name: row.name,
+                cron: row.cron,
+                timezone: row.timezone,
+                test_definition_id: row.test_definition_id,
+                test_suite_id: row.test_suite_id,
+                enabled: row.enabled,
+                team_id: row.team_id,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+                last_fired_at: row.last_fired_at,
+                last_outcome: row.last_outcome.as_deref().and_then(parse_outcome),
+                last_error: row.last_error,
+                next_run_at: row.next_run_at,
+            })
+            .await;
+    }
+    Ok(count)
+}
+
+/// Write a schedule to the database, if there is one
+pub async fn save_schedule(state: &AppState, schedule: &Schedule) -> Result<(), sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    traced(
+        "INSERT INTO schedules (id, name, cron, timezone, test_definition_id, test_suite_id, \
+         enabled, team_id, created_at, updated_at, last_fired_at, last_outcome, last_error, \
+         next_run_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14) \
+         ON CONFLICT (id) DO UPDATE SET name = $2, cron = $3, timezone = $4, \
+         test_definition_id = $5, test_suite_id = $6, enabled = $7, team_id = $8, \
+         updated_at = $10, last_fired_at = $11, last_outcome = $12, last_error = $13, \
+         next_run_at = $14",
+        |sql| {
+            sqlx::query(sql)
+                .bind(schedule.id)
+                .bind(&schedule.name)
+                .bind(&schedule.cron)
+                .bind(&schedule.timezone)
+                .bind(schedule.test_definition_id)
+                .bind(schedule.test_suite_id)
+                .bind(schedule.enabled)
+                .bind(schedule.team_id)
+                .bind(schedule.created_at)
+                .bind(schedule.updated_at)
+                .bind(schedule.last_fired_at)
+                .bind(schedule.last_outcome.map(outcome_name))
+                .bind(&schedule.last_error)
+                .bind(schedule.next_run_at)
+                .execute(db)
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+/// Remove a schedule from the database, if there is one
+pub async fn delete_saved_schedule(state: &AppState, id: Uuid) -> Result<(), sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    traced("DELETE FROM schedules WHERE id = $1", |sql| {
+        sqlx::query(sql).bind(id).execute(db)
+    })
+    .await?;
+    Ok(())
+}
+
+fn outcome_name(outcome: ScheduleOutcome) -> &'static str {
+    match outcome {
+        ScheduleOutcome::Started => "started",
+        ScheduleOutcome::Skipped => "skipped",
+        ScheduleOutcome::Missed => "missed",
+        ScheduleOutcome::Failed => "failed",
+    }
+}
+
+fn parse_outcome(name: &str) -> Option<ScheduleOutcome> {
+    match name {
+        "started" => Some(ScheduleOutcome::Started),
+        "skipped" => Some(ScheduleOutcome::Skipped),
+        "missed" => Some(ScheduleOutcome::Missed),
+        "failed" => Some(ScheduleOutcome::Failed),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use crate::fake::{FakeBackend, FakeScript};
+    use sparktest_core::{RunPriority, TestDefinition};
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        timestamp.parse().unwrap()
+    }
+
+    fn scheduler_state() -> AppState {
+        let backend = Arc::new(FakeBackend::new(
+            FakeScript::succeed(&["ok"]).with_duration(Duration::from_millis(20)),
+        ));
+        let mut state = AppState::new(backend);
+        state.runner.poll_interval = Duration::from_millis(5);
+        state
+    }
+
+    async fn nightly_schedule(state: &AppState, created_at: &str) -> Schedule {
+        let definition = TestDefinition {
+            id: Uuid::new_v4(),
+            name: "K6 Performance Load Tests".to_string(),
+            description: String::new(),
+            image: "grafana/k6:latest".to_string(),
+            commands: vec!["k6".to_string(), "run".to_string()],
+            created_at: at(created_at),
+            executor_id: None,
+            variables: None,
+            labels: None,
+            target: None,
+            team_id: None,
+            resources: None,
+            privileged: false,
+            secrets: Vec::new(),
+            source: None,
+            files: Vec::new(),
+            priority: RunPriority::default(),
+            job_labels: BTreeMap::new(),
+            job_annotations: BTreeMap::new(),
+        };
+        state
+            .store
+            .insert_definition(definition.clone())
+            .await
+            .unwrap();
+
+        let schedule = Schedule {
+            id: Uuid::new_v4(),
+            name: "Nightly load test".to_string(),
+            cron: "0 2 * * *".to_string(),
+            timezone: "Europe/Oslo".to_string(),
+            test_definition_id: Some(definition.id),
+            test_suite_id: None,
+            enabled: true,
+            team_id: None,
+            created_at: at(created_at),
+            updated_at: at(created_at),
+            last_fired_at: None,
+            last_outcome: None,
+            last_error: None,
+            next_run_at: None,
+        };
+        state.store.insert_schedule(schedule.clone()).await;
+        schedule
+    }
+
+    async fn runs_of(state: &AppState, schedule_id: Uuid) -> Vec<TestRun> {
+        let mut runs = state.store.list_runs().await;
+        runs.retain(|run| run.schedule_id == Some(schedule_id));
+        runs
+    }
+
+    #[test]
+    fn test_parse_cron() {
+        assert!(CronSchedule::parse("0 2 * * *", "UTC").is_ok());
+        assert!(CronSchedule::parse("30 0 2 * * MON-FRI", "America/New_York").is_ok());
+        assert!(CronSchedule::parse("0 2 * *", "UTC").is_err());
+        assert!(CronSchedule::parse("0 2 * * * * 2027", "UTC").is_err());
+        assert!(CronSchedule::parse("0 25 * * *", "UTC").is_err());
+        assert!(CronSchedule::parse("0 2 * * *", "Mars/Olympus_Mons").is_err());
+    }
+
+    #[test]
+    fn test_next_occurrence_in_time_zone() {
+        let nightly = CronSchedule::parse("0 2 * * *", "Europe/Oslo").unwrap();
+        // 02:00 in Oslo is 01:00 UTC in winter and 00:00 UTC in summer
+        assert_eq!(
+            nightly.next_after(at("2026-01-10T12:00:00Z")),
+            Some(at("2026-01-11T01:00:00Z"))
+        );
+        assert_eq!(
+            nightly.next_after(at("2026-07-10T12:00:00Z")),
+            Some(at("2026-07-11T00:00:00Z"))
+        );
+
+        // Day of week counts from Sunday = 0, as in crontab
+        let weekdays = CronSchedule::parse("0 9 * * 1-5", "UTC").unwrap();
+        assert_eq!(
+            weekdays.next_after(at("2026-10-17T12:00:00Z")), // a Saturday
+            Some(at("2026-10-19T09:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn test_latest_between() {
+        let hourly = CronSchedule::parse("0 * * * *", "UTC").unwrap();
+        assert_eq!(
+            hourly.latest_between(at("2026-10-18T01:30:00Z"), at("2026-10-18T05:10:00Z")),
+            Some(at("2026-10-18T05:00:00Z"))
+        );
+        assert_eq!(
+            hourly.latest_between(at("2026-10-18T05:00:00Z"), at("2026-10-18T05:10:00Z")),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_occurrence_fires_once() {
+        let state = scheduler_state();
+        let schedule = nightly_schedule(&state, "2026-10-17T12:00:00Z").await;
+
+        run_due_schedules(&state, at("2026-10-17T23:59:55Z")).await;
+        assert!(runs_of(&state, schedule.id).await.is_empty());
+
+        run_due_schedules(&state, at("2026-10-18T00:00:05Z")).await;
+        run_due_schedules(&state, at("2026-10-18T00:00:15Z")).await;
+        let runs = runs_of(&state, schedule.id).await;
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].name, "K6 Performance Load Tests");
+
+        let schedule = state.store.get_schedule(schedule.id).await.unwrap();
+        assert_eq!(schedule.last_fired_at, Some(at("2026-10-18T00:00:00Z")));
+        assert_eq!(schedule.last_outcome, Some(ScheduleOutcome::Started));
+        assert_eq!(schedule.next_run_at, Some(at("2026-10-19T00:00:00Z")));
+    }
+
+    #[tokio::test]
+    async fn test_skips_while_previous_runs_are_active() {
+        let state = scheduler_state();
+        let schedule = nightly_schedule(&state, "2026-10-17T12:00:00Z").await;
+        let mut previous = TestRun::new("previous".to_string(), "k6".to_string(), vec![]);
+        previous.schedule_id = Some(schedule.id);
+        previous.status = "running".to_string();
+        state.store.insert_run(previous).await.unwrap();
+
+        run_due_schedules(&state, at("2026-10-18T00:00:05Z")).await;
+
+        assert_eq!(runs_of(&state, schedule.id).await.len(), 1);
+        let schedule = state.store.get_schedule(schedule.id).await.unwrap();
+        assert_eq!(schedule.last_outcome, Some(ScheduleOutcome::Skipped));
+        assert_eq!(schedule.last_fired_at, Some(at("2026-10-18T00:00:00Z")));
+    }
+
+    #[tokio::test]
+    async fn test_missed_occurrences_collapse_and_expire() {
+        let state = scheduler_state();
+        let schedule = nightly_schedule(&state, "2026-10-10T12:00:00Z").await;
+
+        // Down for a week; the last night's occurrence is hours old
+        run_due_schedules(&state, at("2026-10-18T09:00:00Z")).await;
+
+        assert!(runs_of(&state, schedule.id).await.is_empty());
+        let schedule = state.store.get_schedule(schedule.id).await.unwrap();
+        assert_eq!(schedule.last_outcome, Some(ScheduleOutcome::Missed));
+        assert_eq!(schedule.last_fired_at, Some(at("2026-10-18T00:00:00Z")));
+    }
+
+    #[tokio::test]
+    async fn test_schedule_of_deleted_definition_is_disabled() {
+        let state = scheduler_state();
+        let schedule = nightly_schedule(&state, "2026-10-17T12:00:00Z").await;
+        state
+            .store
+            .remove_definition(schedule.test_definition_id.unwrap())
+            .await
+            .unwrap();
+
+        run_due_schedules(&state, at("2026-10-18T00:00:05Z")).await;
+
+        let schedule = state.store.get_schedule(schedule.id).await.unwrap();
+        assert!(!schedule.enabled);
+        assert_eq!(schedule.last_outcome, Some(ScheduleOutcome::Failed));
+        assert!(schedule.last_error.unwrap().contains("no longer exists"));
+        assert!(runs_of(&state, schedule.id).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_restart_does_not_fire_twice() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        let mut state = scheduler_state();
+        state.db = Some(db.clone());
+        let schedule = nightly_schedule(&state, "2026-10-17T12:00:00Z").await;
+        save_schedule(&state, &schedule).await.unwrap();
+        run_due_schedules(&state, at("2026-10-18T00:00:05Z")).await;
+        assert_eq!(runs_of(&state, schedule.id).await.len(), 1);
+
+        // A new process picks the schedule up from the database
+        let mut restarted = scheduler_state();
+        restarted.db = Some(db);
+        for definition in state.store.list_definitions().await {
+            restarted.store.insert_definition(definition).await.unwrap();
+        }
+        load_schedules(&restarted).await.unwrap();
+        let restored = restarted.store.get_schedule(schedule.id).await.unwrap();
+        assert_eq!(restored.last_fired_at, Some(at("2026-10-18T00:00:00Z")));
+        assert_eq!(restored.last_outcome, Some(ScheduleOutcome::Started));
+
+        // Other tests share the database; leave their schedules alone
+        for other in restarted.store.list_schedules().await {
+            if other.id != schedule.id {
+                restarted.store.remove_schedule(other.id).await;
+            }
+        }
+        run_due_schedules(&restarted, at("2026-10-18T00:00:20Z")).await;
+        assert!(runs_of(&restarted, schedule.id).await.is_empty());
+
+        run_due_schedules(&restarted, at("2026-10-19T00:00:05Z")).await;
+        assert_eq!(runs_of(&restarted, schedule.id).await.len(), 1);
+
+        delete_saved_schedule(&restarted, schedule.id)
+            .await
+            .unwrap();
+    }
+}
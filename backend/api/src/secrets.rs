@@ -0,0 +1,259 @@
+use crate::backend::{ExecutionBackend, SecretValue};
+use crate::error::ApiError;
+use anyhow::{Context, Result};
+use sparktest_core::{ExecutionTarget, SecretRef, TestRun};
+use std::collections::HashSet;
+
+/// What secret values are replaced with in logs
+pub const REDACTED: &str = "[REDACTED]";
+
+/// A team's runs may only use Secrets carrying this label with the team's id
+pub const SECRET_TEAM_LABEL: &str = "sparktest.dev/team";
+
+/// Check that secret references can be rendered into a Job and don't clash
+/// with each other or with the plain `variables`
+pub fn validate_secret_refs(
+    refs: &[SecretRef],
+    variables: Option<&serde_json::Value>,
+) -> Result<(), ApiError> {
+    let variable_names: HashSet<&str> = variables
+        .and_then(|v| v.as_object())
+        .map(|vars| vars.keys().map(String::as_str).collect())
+        .unwrap_or_default();
+    let mut env_names = HashSet::new();
+    let mut paths = HashSet::new();
+
+    for secret_ref in refs {
+        let subject = format!("secret '{}' key '{}'", secret_ref.secret, secret_ref.key);
+        if !is_valid_secret_name(&secret_ref.secret) {
+            return Err(ApiError::validation(format!(
+                "'{}' is not a valid Kubernetes Secret name",
+                secret_ref.secret
+            )));
+        }
+        if !is_valid_secret_key(&secret_ref.key) {
+            return Err(ApiError::validation(format!(
+                "'{}' is not a valid Secret key",
+                secret_ref.key
+            )));
+        }
+
+        match (&secret_ref.env, &secret_ref.path) {
+            (Some(env), None) => {
+                if !is_valid_env_name(env) {
+                    return Err(ApiError::validation(format!(
+                        "{subject}: '{env}' is not a valid environment variable name"
+                    )));
+                }
+                if variable_names.contains(env.as_str()) || !env_names.insert(env.as_str()) {
+                    return Err(ApiError::validation(format!(
+                        "{subject}: environment variable '{env}' is set more than once"
+                    )));
+                }
+            }
+            (None, Some(path)) => {
+                if !path.starts_with('/') || path == "/" || path.split('/').any(|p| p == "..") {
+                    return Err(ApiError::validation(format!(
+                        "{subject}: '{path}' must be an absolute file path"
+                    )));
+                }
+                if !paths.insert(path.as_str()) {
+                    return Err(ApiError::validation(format!(
+                        "{subject}: '{path}' is mounted more than once"
+                    )));
+                }
+            }
+            _ => {
+                return Err(ApiError::validation(format!(
+                    "{subject}: set exactly one of 'env' or 'path'"
+                )))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// DNS subdomain: lowercase alphanumerics, `-` and `.`, starting and ending alphanumeric
+fn is_valid_secret_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 253
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '.')
+        && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name.ends_with(|c: char| c.is_ascii_alphanumeric())
+}
+
+fn is_valid_secret_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn is_valid_env_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Read every referenced value from the target, failing on the first missing one
+pub async fn resolve_secret_values(
+    backend: &dyn ExecutionBackend,
+    target: &ExecutionTarget,
+    refs: &[SecretRef],
+) -> Result<Vec<String>> {
+    let secrets = read_secrets(backend, target, refs).await?;
+    Ok(secrets.into_iter().map(|secret| secret.value).collect())
+}
+
+/// Like [`resolve_secret_values`], but also refuses Secrets the run's team may
+/// not use: those without a [`SECRET_TEAM_LABEL`] naming the team. Runs without
+/// a team can only be started by instance admins, so they may use any Secret.
+pub async fn resolve_run_secrets(
+    backend: &dyn ExecutionBackend,
+    target: &ExecutionTarget,
+    run: &TestRun,
+) -> Result<Vec<String>> {
+    let secrets = read_secrets(backend, target, &run.secrets).await?;
+    if let Some(team_id) = run.team_id {
+        let team_id = team_id.to_string();
+        for (secret, secret_ref) in secrets.iter().zip(&run.secrets) {
+            if secret.team.as_deref() != Some(team_id.as_str()) {
+                anyhow::bail!(
+                    "Secret '{}' is not available to team {}: label it {}={}",
+                    secret_ref.secret,
+                    team_id,
+                    SECRET_TEAM_LABEL,
+                    team_id
+                );
+            }
+        }
+    }
+    Ok(secrets.into_iter().map(|secret| secret.value).collect())
+}
+
+async fn read_secrets(
+    backend: &dyn ExecutionBackend,
+    target: &ExecutionTarget,
+    refs: &[SecretRef],
+) -> Result<Vec<SecretValue>> {
+    let mut values = Vec::with_capacity(refs.len());
+    for secret_ref in refs {
+        let value = backend
+            .read_secret(target, &secret_ref.secret, &secret_ref.key)
+            .await
+            .with_context(|| format!("Failed to read secret '{}'", secret_ref.secret))?
+            .with_context(|| {
+                format!(
+                    "Secret '{}' has no key '{}' on target '{}'",
+                    secret_ref.secret, secret_ref.key, target.name
+                )
+            })?;
+        values.push(value);
+    }
+    Ok(values)
+}
+
+/// Replaces known secret values in log output
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    needles: Vec<String>,
+}
+
+impl Redactor {
+    /// Multi-line values such as certificates are also redacted line by line,
+    /// since logs are split into lines before they are stored
+    pub fn new(values: impl IntoIterator<Item = String>) -> Self {
+        let mut needles: Vec<String> = values
+            .into_iter()
+            .flat_map(|value| {
+                let lines: Vec<String> = value
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| line.len() >= 4)
+                    .map(str::to_string)
+                    .collect();
+                std::iter::once(value.trim().to_string()).chain(lines)
+            })
+            .filter(|needle| !needle.is_empty())
+            .collect();
+        // Longest first, so a value is never partially replaced by one of its own lines
+        needles.sort_by_key(|needle| std::cmp::Reverse(needle.len()));
+        needles.dedup();
+        Self { needles }
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        self.needles.iter().fold(text.to_string(), |text, needle| {
+            text.replace(needle, REDACTED)
+        })
+    }
+
+    pub fn redact_lines(&self, lines: Vec<String>) -> Vec<String> {
+        if self.needles.is_empty() {
+            return lines;
+        }
+        lines.iter().map(|line| self.redact(line)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env_ref(secret: &str, key: &str, env: &str) -> SecretRef {
+        SecretRef {
+            secret: secret.to_string(),
+            key: key.to_string(),
+            env: Some(env.to_string()),
+            path: None,
+        }
+    }
+
+    #[test]
+    fn test_validate_secret_refs() {
+        let file_ref = SecretRef {
+            secret: "api-credentials".to_string(),
+            key: "client.pem".to_string(),
+            env: None,
+            path: Some("/etc/certs/client.pem".to_string()),
+        };
+        let token = env_ref("api-credentials", "token", "API_TOKEN");
+        assert!(validate_secret_refs(&[token.clone(), file_ref.clone()], None).is_ok());
+
+        let variables = serde_json::json!({ "API_TOKEN": "plain" });
+        assert!(validate_secret_refs(std::slice::from_ref(&token), Some(&variables)).is_err());
+        assert!(validate_secret_refs(&[token.clone(), token.clone()], None).is_err());
+        assert!(validate_secret_refs(&[env_ref("Bad_Name", "token", "X")], None).is_err());
+        assert!(validate_secret_refs(&[env_ref("creds", "token", "1X")], None).is_err());
+
+        let both = SecretRef {
+            env: Some("TOKEN".to_string()),
+            ..file_ref.clone()
+        };
+        assert!(validate_secret_refs(&[both], None).is_err());
+        let relative = SecretRef {
+            path: Some("certs/client.pem".to_string()),
+            ..file_ref
+        };
+        assert!(validate_secret_refs(&[relative], None).is_err());
+    }
+
+    #[test]
+    fn test_redactor() {
+        let redactor = Redactor::new([
+            "s3cr3t-token".to_string(),
+            "-----BEGIN KEY-----\nMIIEabc\n-----END KEY-----".to_string(),
+        ]);
+        assert_eq!(
+            redactor.redact("Authorization: Bearer s3cr3t-token"),
+            "Authorization: Bearer [REDACTED]"
+        );
+        assert_eq!(redactor.redact("read MIIEabc"), "read [REDACTED]");
+        assert_eq!(redactor.redact("nothing to hide"), "nothing to hide");
+        assert_eq!(
+            Redactor::default().redact_lines(vec!["s3cr3t-token".to_string()]),
+            ["s3cr3t-token"]
+        );
+    }
+}
@@ -0,0 +1,204 @@
+use crate::error::ApiError;
+use sparktest_core::GitSource;
+
+/// Where the shared workspace volume is mounted in every container of a run
+pub const WORKSPACE_DIR: &str = "/workspace";
+
+/// Image of the init container that clones the repository, unless
+/// `SPARKTEST_GIT_IMAGE` names another one
+pub const DEFAULT_GIT_IMAGE: &str = "alpine/git:2.45.2";
+
+/// Clones `$GIT_URL` into `$SPARKTEST_WORKSPACE/src` and checks out `$GIT_REF`.
+/// Branches that only exist on the remote are found through `origin/`. User input
+/// only ever reaches git through environment variables, never through the script.
+pub const CHECKOUT_SCRIPT: &str = r#"set -eu
+dest="$SPARKTEST_WORKSPACE/src"
+git clone --quiet -- "$GIT_URL" "$dest"
+cd "$dest"
+if [ -n "${GIT_REF:-}" ]; then
+  git checkout --quiet --detach "$GIT_REF" -- 2>/dev/null \
+    || git checkout --quiet --detach "origin/$GIT_REF" --
+fi
+git log -1 --format='Checked out %H'
+"#;
+
+/// Environment for [`CHECKOUT_SCRIPT`]
+pub fn checkout_env(source: &GitSource) -> Vec<(String, String)> {
+    vec![
+        ("GIT_URL".to_string(), source.url.clone()),
+        (
+            "GIT_REF".to_string(),
+            source.git_ref.clone().unwrap_or_default(),
+        ),
+        ("SPARKTEST_WORKSPACE".to_string(), WORKSPACE_DIR.to_string()),
+    ]
+}
+
+/// The test container's working directory: the checkout, or a directory inside it
+pub fn working_dir(source: &GitSource) -> String {
+    match source.subdirectory.as_deref().map(|s| s.trim_matches('/')) {
+        Some(subdirectory) if !subdirectory.is_empty() => {
+            format!("{WORKSPACE_DIR}/src/{subdirectory}")
+        }
+        _ => format!("{WORKSPACE_DIR}/src"),
+    }
+}
+
+pub fn validate_source(source: &GitSource) -> Result<(), ApiError> {
+    let url = source.url.as_str();
+    let known_scheme = ["https://", "http://", "ssh://", "git://", "file://"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme));
+    // scp-like syntax, e.g. git@github.com:org/repo.git
+    let scp_like = url
+        .split_once(':')
+        .is_some_and(|(host, path)| host.contains('@') && !path.is_empty());
+    if url.starts_with('-') || url.chars().any(char::is_whitespace) || !(known_scheme || scp_like) {
+        return Err(ApiError::validation(format!(
+            "'{url}' is not a supported Git URL"
+        )));
+    }
+
+    if let Some(git_ref) = &source.git_ref {
+        if git_ref.is_empty()
+            || git_ref.starts_with('-')
+            || git_ref.chars().any(|c| c.is_whitespace() || c.is_control())
+        {
+            return Err(ApiError::validation(format!(
+                "'{git_ref}' is not a valid Git ref"
+            )));
+        }
+    }
+
+    if let Some(subdirectory) = &source.subdirectory {
+        if subdirectory.starts_with('/') || subdirectory.split('/').any(|part| part == "..") {
+            return Err(ApiError::validation(format!(
+                "subdirectory '{subdirectory}' must be a relative path inside the repository"
+            )));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    fn source(url: &str, git_ref: Option<&str>) -> GitSource {
+        GitSource {
+            url: url.to_string(),
+            git_ref: git_ref.map(str::to_string),
+            subdirectory: None,
+        }
+    }
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args([
+                "-c",
+                "user.name=SparkTest",
+                "-c",
+                "user.email=ci@sparktest.dev",
+            ])
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "git {args:?} failed: {output:?}");
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    /// A bare repository with two commits on `main`, a tag and a feature branch
+    fn bare_repo(root: &Path) -> (PathBuf, String) {
+        let work = root.join("work");
+        std::fs::create_dir_all(work.join("e2e")).unwrap();
+        git(&work, &["init", "--quiet", "--initial-branch=main"]);
+        std::fs::write(work.join("e2e/version.txt"), "one").unwrap();
+        git(&work, &["add", "."]);
+        git(&work, &["commit", "--quiet", "-m", "first"]);
+        let first = git(&work, &["rev-parse", "HEAD"]);
+        git(&work, &["tag", "v1"]);
+        std::fs::write(work.join("e2e/version.txt"), "two").unwrap();
+        git(&work, &["commit", "--quiet", "-am", "second"]);
+        git(&work, &["checkout", "--quiet", "-b", "feature", &first]);
+        std::fs::write(work.join("e2e/version.txt"), "feature").unwrap();
+        git(&work, &["commit", "--quiet", "-am", "feature"]);
+        git(&work, &["checkout", "--quiet", "main"]);
+
+        let bare = root.join("repo.git");
+        git(
+            root,
+            &[
+                "clone",
+                "--quiet",
+                "--bare",
+                work.to_str().unwrap(),
+                "repo.git",
+            ],
+        );
+        (bare, first)
+    }
+
+    /// Run the checkout script the way the init container does, with the
+    /// workspace in a temporary directory instead of the volume
+    fn checkout(root: &Path, source: &GitSource) -> Result<String, String> {
+        let workspace = root.join(format!("workspace-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&workspace).unwrap();
+        let output = Command::new("sh")
+            .args(["-c", CHECKOUT_SCRIPT])
+            .envs(checkout_env(source))
+            .env("SPARKTEST_WORKSPACE", &workspace)
+            .output()
+            .unwrap();
+        if !output.status.success() {
+            return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+        }
+        Ok(std::fs::read_to_string(workspace.join("src/e2e/version.txt")).unwrap())
+    }
+
+    #[test]
+    fn test_checkout_script_from_file_url() {
+        let root = std::env::temp_dir().join(format!("sparktest-git-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let (bare, first) = bare_repo(&root);
+        let url = format!("file://{}", bare.display());
+
+        assert_eq!(checkout(&root, &source(&url, None)).unwrap(), "two");
+        assert_eq!(checkout(&root, &source(&url, Some("main"))).unwrap(), "two");
+        assert_eq!(
+            checkout(&root, &source(&url, Some("feature"))).unwrap(),
+            "feature"
+        );
+        assert_eq!(checkout(&root, &source(&url, Some("v1"))).unwrap(), "one");
+        assert_eq!(checkout(&root, &source(&url, Some(&first))).unwrap(), "one");
+        assert!(checkout(&root, &source(&url, Some("missing"))).is_err());
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn test_working_dir() {
+        let mut git_source = source("https://github.com/example/tests", None);
+        assert_eq!(working_dir(&git_source), "/workspace/src");
+        git_source.subdirectory = Some("e2e/api/".to_string());
+        assert_eq!(working_dir(&git_source), "/workspace/src/e2e/api");
+    }
+
+    #[test]
+    fn test_validate_source() {
+        assert!(validate_source(&source("https://github.com/example/tests.git", None)).is_ok());
+        assert!(validate_source(&source("git@github.com:example/tests.git", Some("main"))).is_ok());
+        assert!(validate_source(&source("file:///srv/git/tests.git", None)).is_ok());
+        assert!(validate_source(&source("--upload-pack=touch /tmp/x", None)).is_err());
+        assert!(validate_source(&source("/srv/git/tests.git", None)).is_err());
+        assert!(validate_source(&source("https://github.com/example/tests", Some("-f"))).is_err());
+
+        let escaping = GitSource {
+            subdirectory: Some("../../etc".to_string()),
+            ..source("https://github.com/example/tests", None)
+        };
+        assert!(validate_source(&escaping).is_err());
+    }
+}
@@ -0,0 +1,78 @@
+use crate::audit::AuditLog;
+use crate::auth::{AuthConfig, TokenStore};
+use crate::backend::{ExecutionBackend, KubernetesBackend};
+use crate::commit_status::StatusReportingConfig;
+use crate::encryption::SecretKey;
+use crate::events::EventBus;
+use crate::files::FileLimits;
+use crate::notifications::NotificationConfig;
+use crate::oidc::OidcValidator;
+use crate::policy::AdmissionPolicy;
+use crate::queue::RunQueue;
+use crate::runner::RunnerConfig;
+use crate::scheduler::SchedulerConfig;
+use crate::store::Store;
+use crate::targets::TargetRegistry;
+use crate::webhooks::WebhookConfig;
+use sqlx::PgPool;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Shared state handed to every handler through axum's `State` extractor
+#[derive(Clone)]
+pub struct AppState {
+    pub targets: TargetRegistry,
+    pub backend: Arc<dyn ExecutionBackend>,
+    pub store: Store,
+    pub runner: RunnerConfig,
+    pub tokens: TokenStore,
+    pub auth: AuthConfig,
+    /// Accepts OIDC JWTs as bearer tokens when configured
+    pub oidc: Option<OidcValidator>,
+    pub audit: AuditLog,
+    /// Checked before any run is launched; replaceable at runtime by admins
+    pub policy: Arc<RwLock<AdmissionPolicy>>,
+    pub files: FileLimits,
+    pub webhooks: WebhookConfig,
+    pub reporting: StatusReportingConfig,
+    pub scheduler: SchedulerConfig,
+    pub notifications: NotificationConfig,
+    pub events: EventBus,
+    /// Holds runs back until the concurrency limits leave room for them
+    pub queue: RunQueue,
+    /// PostgreSQL, when the server runs with one; schedules are written through to it
+    pub db: Option<PgPool>,
+    /// Encrypts the credentials written to `db`; they can't be stored without it
+    pub secret_key: Option<SecretKey>,
+}
+
+impl AppState {
+    pub fn new(backend: Arc<dyn ExecutionBackend>) -> Self {
+        Self {
+            targets: TargetRegistry::default(),
+            backend,
+            store: Store::default(),
+            runner: RunnerConfig::default(),
+            tokens: TokenStore::default(),
+            auth: AuthConfig::default(),
+            oidc: None,
+            audit: AuditLog::default(),
+            policy: Arc::default(),
+            files: FileLimits::default(),
+            webhooks: WebhookConfig::default(),
+            reporting: StatusReportingConfig::default(),
+            scheduler: SchedulerConfig::default(),
+            notifications: NotificationConfig::default(),
+            events: EventBus::default(),
+            queue: RunQueue::default(),
+            db: None,
+            secret_key: None,
+        }
+    }
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new(Arc::new(KubernetesBackend::default()))
+    }
+}
@@ -0,0 +1,1385 @@
+use crate::db::traced;
+use chrono::{DateTime, Utc};
+use sparktest_core::{
+    Executor, GitSource, NotificationChannel, NotificationRule, ResourceLimits, Role, RunPriority,
+    RunTrigger, Schedule, SecretRef, StatusReporter, Team, TeamMember, TestDefinition, TestFile,
+    TestRun, TestSuite, TriggerRule, User,
+};
+use sqlx::types::Json;
+use sqlx::PgPool;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::warn;
+use uuid::Uuid;
+
+/// File contents by file name
+type FileContents = BTreeMap<String, Vec<u8>>;
+
+/// State shared by the handlers. Definitions, suites, runs, their files, which runs were
+/// deleted, users and teams are written through to the database when there is one; the
+/// rest is kept in memory.
+#[derive(Clone, Default)]
+pub struct Store {
+    runs: Arc<RwLock<HashMap<Uuid, TestRun>>>,
+    /// Runs deleted through the API, whose Jobs may be collected
+    deleted_runs: Arc<RwLock<HashSet<Uuid>>>,
+    definitions: Arc<RwLock<HashMap<Uuid, TestDefinition>>>,
+    suites: Arc<RwLock<HashMap<Uuid, TestSuite>>>,
+    executors: Arc<RwLock<HashMap<String, Executor>>>,
+    users: Arc<RwLock<HashMap<Uuid, User>>>,
+    teams: Arc<RwLock<HashMap<Uuid, Team>>>,
+    /// Uploaded files by owning definition or run
+    files: Arc<RwLock<HashMap<Uuid, FileContents>>>,
+    trigger_rules: Arc<RwLock<HashMap<Uuid, TriggerRule>>>,
+    status_reporters: Arc<RwLock<HashMap<Uuid, StatusReporter>>>,
+    schedules: Arc<RwLock<HashMap<Uuid, Schedule>>>,
+    notification_channels: Arc<RwLock<HashMap<Uuid, NotificationChannel>>>,
+    notification_rules: Arc<RwLock<HashMap<Uuid, NotificationRule>>>,
+    /// Status of the latest finished run of each definition and suite
+    outcomes: Arc<RwLock<HashMap<Uuid, String>>>,
+    db: Option<PgPool>,
+}
+
+#[derive(sqlx::FromRow)]
+struct DefinitionRow {
+    id: Uuid,
+    name: String,
+    description: Option<String>,
+    image: String,
+    commands: Vec<String>,
+    created_at: DateTime<Utc>,
+    executor_id: Option<String>,
+    variables: Option<serde_json::Value>,
+    labels: Option<Vec<String>>,
+    target: Option<String>,
+    team_id: Option<Uuid>,
+    resources: Option<Json<ResourceLimits>>,
+    privileged: bool,
+    secrets: Json<Vec<SecretRef>>,
+    source: Option<Json<GitSource>>,
+    files: Json<Vec<TestFile>>,
+    job_labels: Json<BTreeMap<String, String>>,
+    job_annotations: Json<BTreeMap<String, String>>,
+    priority: String,
+}
+
+impl From<DefinitionRow> for TestDefinition {
+    fn from(row: DefinitionRow) -> Self {
+        Self {
+            id: row.id,
+            name: row.name,
+            description: row.description.unwrap_or_default(),
+            image: row.image,
+            commands: row.commands,
+            created_at: row.created_at,
+            executor_id: row.executor_id,
+            variables: row.variables,
+            labels: row.labels,
+            target: row.target,
+            team_id: row.team_id,
+            resources: row.resources.map(|resources| resources.0),
+            privileged: row.privileged,
+            secrets: row.secrets.0,
+            source: row.source.map(|source| source.0),
+            files: row.files.0,
+            priority: parse_priority(&row.priority),
+            job_labels: row.job_labels.0,
+            job_annotations: row.job_annotations.0,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct SuiteRow {
+    id: Uuid,
+    name: String,
+    description: Option<String>,
+    test_definition_ids: Vec<Uuid>,
+    created_at: DateTime<Utc>,
+    execution_mode: String,
+    labels: Option<Vec<String>>,
+    team_id: Option<Uuid>,
+}
+
+impl From<SuiteRow> for TestSuite {
+    fn from(row: SuiteRow) -> Self {
+        Self {
+            id: row.id,
+            name: row.name,
+            description: row.description.unwrap_or_default(),
+            test_definition_ids: row.test_definition_ids,
+            created_at: row.created_at,
+            execution_mode: row.execution_mode,
+            labels: row.labels,
+            team_id: row.team_id,
+        }
+    }
+}
+
+/// The columns of a [`RunRow`]
+pub(crate) const RUN_COLUMNS: &str = "id, name, image, command, status, created_at, \
+    test_definition_id, executor_id, suite_id, variables, artifacts, duration, retries, logs, \
+    k8s_job_name, pod_scheduled, container_created, container_started, completed, failed, \
+    target, team_id, schedule_id, resource_uid, triggered_by, resources, privileged, secrets, \
+    source, files, job_labels, job_annotations, trigger, priority";
+
+#[derive(sqlx::FromRow)]
+pub(crate) struct RunRow {
+    id: Uuid,
+    name: String,
+    image: String,
+    command: Vec<String>,
+    status: String,
+    created_at: DateTime<Utc>,
+    test_definition_id: Option<Uuid>,
+    executor_id: Option<String>,
+    suite_id: Option<Uuid>,
+    variables: Option<serde_json::Value>,
+    artifacts: Option<Vec<String>>,
+    duration: Option<i32>,
+    retries: Option<i32>,
+    logs: Option<Vec<String>>,
+    k8s_job_name: Option<String>,
+    pod_scheduled: Option<DateTime<Utc>>,
+    container_created: Option<DateTime<Utc>>,
+    container_started: Option<DateTime<Utc>>,
+    completed: Option<DateTime<Utc>>,
+    failed: Option<DateTime<Utc>>,
+    target: Option<String>,
+    team_id: Option<Uuid>,
+    schedule_id: Option<Uuid>,
+    resource_uid: Option<String>,
+    triggered_by: Option<String>,
+    resources: Option<Json<ResourceLimits>>,
+    privileged: bool,
+    secrets: Json<Vec<SecretRef>>,
+    source: Option<Json<GitSource>>,
+    files: Json<Vec<TestFile>>,
+    job_labels: Json<BTreeMap<String, String>>,
+    job_annotations: Json<BTreeMap<String, String>>,
+    trigger: Option<Json<RunTrigger>>,
+    priority: String,
+}
+
+impl From<RunRow> for TestRun {
+    fn from(row: RunRow) -> Self {
+        let mut run = TestRun::new(row.name, row.image, row.command);
+        run.id = row.id;
+        run.status = row.status;
+        run.created_at = row.created_at;
+        run.definition_id = row.test_definition_id;
+        run.executor_id = row.executor_id;
+        run.suite_id = row.suite_id;
+        run.variables = row.variables;
+        run.artifacts = row.artifacts;
+        run.duration = row.duration;
+        run.retries = row.retries;
+        run.logs = row.logs;
+        run.k8s_job_name = row.k8s_job_name;
+        run.pod_scheduled = row.pod_scheduled;
+        run.container_created = row.container_created;
+        run.container_started = row.container_started;
+        run.completed = row.completed;
+        run.failed = row.failed;
+        run.target = row.target;
+        run.team_id = row.team_id;
+        run.schedule_id = row.schedule_id;
+        run.resource_uid = row.resource_uid;
+        run.triggered_by = row.triggered_by;
+        run.resources = row.resources.map(|resources| resources.0);
+        run.privileged = row.privileged;
+        run.secrets = row.secrets.0;
+        run.source = row.source.map(|source| source.0);
+        run.files = row.files.0;
+        run.job_labels = row.job_labels.0;
+        run.job_annotations = row.job_annotations.0;
+        run.trigger = row.trigger.map(|trigger| trigger.0);
+        run.priority = parse_priority(&row.priority);
+        run
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct UserRow {
+    id: Uuid,
+    name: String,
+    email: Option<String>,
+    subject: Option<String>,
+    is_admin: bool,
+    created_at: DateTime<Utc>,
+}
+
+impl From<UserRow> for User {
+    fn from(row: UserRow) -> Self {
+        Self {
+            id: row.id,
+            name: row.name,
+            email: row.email,
+            subject: row.subject,
+            is_admin: row.is_admin,
+            created_at: row.created_at,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct TeamRow {
+    id: Uuid,
+    name: String,
+    description: Option<String>,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(sqlx::FromRow)]
+struct MemberRow {
+    team_id: Uuid,
+    user_id: Uuid,
+    role: String,
+}
+
+fn parse_role(role: &str) -> Option<Role> {
+    match role {
+        "viewer" => Some(Role::Viewer),
+        "runner" => Some(Role::Runner),
+        "maintainer" => Some(Role::Maintainer),
+        "admin" => Some(Role::Admin),
+        _ => None,
+    }
+}
+
+fn role_name(role: Role) -> &'static str {
+    match role {
+        Role::Viewer => "viewer",
+        Role::Runner => "runner",
+        Role::Maintainer => "maintainer",
+        Role::Admin => "admin",
+    }
+}
+
+/// The `priority` columns only allow these three
+fn parse_priority(priority: &str) -> RunPriority {
+    match priority {
+        "low" => RunPriority::Low,
+        "high" => RunPriority::High,
+        _ => RunPriority::Normal,
+    }
+}
+
+fn priority_name(priority: RunPriority) -> &'static str {
+    match priority {
+        RunPriority::Low => "low",
+        RunPriority::Normal => "normal",
+        RunPriority::High => "high",
+    }
+}
+
+impl Store {
+    /// Load the definitions, suites, runs, their files, users and teams stored by a previous
+    /// process and the runs it deleted, and write every change to them through to the
+    /// database from then on
+    pub async fn load(db: PgPool) -> Result<Self, sqlx::Error> {
+        let definitions: Vec<DefinitionRow> = traced(
+            "SELECT id, name, description, image, commands, created_at, executor_id, variables, \
+             labels, target, team_id, resources, privileged, secrets, source, files, job_labels, \
+             job_annotations, priority FROM test_definitions",
+            |sql| sqlx::query_as(sql).fetch_all(&db),
+        )
+        .await?;
+        let suites: Vec<SuiteRow> = traced(
+            "SELECT id, name, description, test_definition_ids, created_at, execution_mode, \
+             labels, team_id FROM test_suites",
+            |sql| sqlx::query_as(sql).fetch_all(&db),
+        )
+        .await?;
+        let statement = format!("SELECT {RUN_COLUMNS} FROM test_runs");
+        let runs: Vec<RunRow> =
+            traced(&statement, |sql| sqlx::query_as(sql).fetch_all(&db)).await?;
+        let users: Vec<UserRow> = traced(
+            "SELECT id, name, email, subject, is_admin, created_at FROM users",
+            |sql| sqlx::query_as(sql).fetch_all(&db),
+        )
+        .await?;
+        let teams: Vec<TeamRow> = traced(
+            "SELECT id, name, description, created_at FROM teams",
+            |sql| sqlx::query_as(sql).fetch_all(&db),
+        )
+        .await?;
+        let members: Vec<MemberRow> = traced(
+            "SELECT team_id, user_id, role FROM team_members ORDER BY team_id, user_id",
+            |sql| sqlx::query_as(sql).fetch_all(&db),
+        )
+        .await?;
+        let files: Vec<(Uuid, String, Vec<u8>)> =
+            traced("SELECT owner_id, name, content FROM test_files", |sql| {
+                sqlx::query_as(sql).fetch_all(&db)
+            })
+            .await?;
+        let deleted_runs: Vec<Uuid> = traced("SELECT id FROM deleted_runs", |sql| {
+            sqlx::query_scalar(sql).fetch_all(&db)
+        })
+        .await?;
+
+        let store = Self {
+            db: Some(db),
+            ..Self::default()
+        };
+        *store.definitions.write().await = definitions
+            .into_iter()
+            .map(|row| (row.id, row.into()))
+            .collect();
+        *store.suites.write().await = suites.into_iter().map(|row| (row.id, row.into())).collect();
+        *store.runs.write().await = runs.into_iter().map(|row| (row.id, row.into())).collect();
+        *store.deleted_runs.write().await = deleted_runs.into_iter().collect();
+        *store.users.write().await = users.into_iter().map(|row| (row.id, row.into())).collect();
+        let mut teams: HashMap<Uuid, Team> = teams
+            .into_iter()
+            .map(|row| {
+                let team = Team {
+                    id: row.id,
+                    name: row.name,
+                    description: row.description,
+                    members: Vec::new(),
+                    created_at: row.created_at,
+                };
+                (team.id, team)
+            })
+            .collect();
+        for member in members {
+            if let (Some(team), Some(role)) =
+                (teams.get_mut(&member.team_id), parse_role(&member.role))
+            {
+                team.members.push(TeamMember {
+                    user_id: member.user_id,
+                    role,
+                });
+            }
+        }
+        *store.teams.write().await = teams;
+        let mut contents: HashMap<Uuid, FileContents> = HashMap::new();
+        for (owner, name, content) in files {
+            contents.entry(owner).or_default().insert(name, content);
+        }
+        *store.files.write().await = contents;
+        Ok(store)
+    }
+
+    /// List runs, newest first
+    pub async fn list_runs(&self) -> Vec<TestRun> {
+        let mut runs: Vec<TestRun> = self.runs.read().await.values().cloned().collect();
+        runs.sort_by_key(|run| std::cmp::Reverse(run.created_at));
+        runs
+    }
+
+    pub async fn get_run(&self, id: Uuid) -> Option<TestRun> {
+        self.runs.read().await.get(&id).cloned()
+    }
+
+    pub async fn insert_run(&self, run: TestRun) -> Result<(), sqlx::Error> {
+        let mut runs = self.runs.write().await;
+        self.save_run(&run).await?;
+        runs.insert(run.id, run);
+        Ok(())
+    }
+
+    /// Apply an update to a run, returning the updated copy if it still exists.
+    /// Runners can't act on a failed write, so it is logged and the run kept in
+    /// memory; its next update writes the whole run again.
+    pub async fn update_run<F>(&self, id: Uuid, update: F) -> Option<TestRun>
+    where
+        F: FnOnce(&mut TestRun),
+    {
+        let mut runs = self.runs.write().await;
+        let run = runs.get_mut(&id)?;
+        update(run);
+        if let Err(e) = self.save_run(run).await {
+            warn!("Failed to store run {}: {}", id, e);
+        }
+        Some(run.clone())
+    }
+
+    pub async fn remove_run(&self, id: Uuid) -> Result<Option<TestRun>, sqlx::Error> {
+        self.remove_run_if(id, |_| Ok(())).await
+    }
+
+    /// Remove a run if `check` allows it, in the same lookup that finds it
+    pub async fn remove_run_if<F, E>(&self, id: Uuid, check: F) -> Result<Option<TestRun>, E>
+    where
+        F: FnOnce(&TestRun) -> Result<(), E>,
+        E: From<sqlx::Error>,
+    {
+        let run = {
+            let mut runs = self.runs.write().await;
+            let Some(run) = runs.get(&id) else {
+                return Ok(None);
+            };
+            check(run)?;
+            if let Some(db) = &self.db {
+                traced("DELETE FROM test_runs WHERE id = $1", |sql| {
+                    sqlx::query(sql).bind(id).execute(db)
+                })
+                .await?;
+                traced("DELETE FROM test_files WHERE owner_id = $1", |sql| {
+                    sqlx::query(sql).bind(id).execute(db)
+                })
+                .await?;
+                traced(
+                    "INSERT INTO deleted_runs (id) VALUES ($1) ON CONFLICT DO NOTHING",
+                    |sql| sqlx::query(sql).bind(id).execute(db),
+                )
+                .await?;
+            }
+            runs.remove(&id)
+        };
+        self.files.write().await.remove(&id);
+        self.deleted_runs.write().await.insert(id);
+        Ok(run)
+    }
+
+    /// Whether the run was deleted, since the server started or, with PostgreSQL,
+    /// before. Runs the store doesn't know may just predate it, so this is what
+    /// Jobs are collected by.
+    pub async fn was_deleted(&self, id: Uuid) -> bool {
+        self.deleted_runs.read().await.contains(&id)
+    }
+
+    /// List definitions, oldest first
+    pub async fn list_definitions(&self) -> Vec<TestDefinition> {
+        let mut definitions: Vec<TestDefinition> =
+            self.definitions.read().await.values().cloned().collect();
+        definitions.sort_by_key(|definition| definition.created_at);
+        definitions
+    }
+
+    pub async fn get_definition(&self, id: Uuid) -> Option<TestDefinition> {
+        self.definitions.read().await.get(&id).cloned()
+    }
+
+    pub async fn insert_definition(&self, definition: TestDefinition) -> Result<(), sqlx::Error> {
+        let mut definitions = self.definitions.write().await;
+        if let Some(db) = &self.db {
+            traced(
+                "INSERT INTO test_definitions (id, name, description, image, commands, \
+                 created_at, executor_id, variables, labels, target, team_id, resources, \
+                 privileged, secrets, source, files, job_labels, job_annotations, priority) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, \
+                 $17, $18, $19) \
+                 ON CONFLICT (id) DO UPDATE SET name = $2, description = $3, image = $4, \
+                 commands = $5, executor_id = $7, variables = $8, labels = $9, target = $10, \
+                 team_id = $11, resources = $12, privileged = $13, secrets = $14, source = $15, \
+                 files = $16, job_labels = $17, job_annotations = $18, priority = $19",
+                |sql| {
+                    sqlx::query(sql)
+                        .bind(definition.id)
+                        .bind(&definition.name)
+                        .bind(&definition.description)
+                        .bind(&definition.image)
+                        .bind(&definition.commands)
+                        .bind(definition.created_at)
+                        .bind(&definition.executor_id)
+                        .bind(&definition.variables)
+                        .bind(&definition.labels)
+                        .bind(&definition.target)
+                        .bind(definition.team_id)
+                        .bind(definition.resources.as_ref().map(Json))
+                        .bind(definition.privileged)
+                        .bind(Json(&definition.secrets))
+                        .bind(definition.source.as_ref().map(Json))
+                        .bind(Json(&definition.files))
+                        .bind(Json(&definition.job_labels))
+                        .bind(Json(&definition.job_annotations))
+                        .bind(priority_name(definition.priority))
+                        .execute(db)
+                },
+            )
+            .await?;
+        }
+        definitions.insert(definition.id, definition);
+        Ok(())
+    }
+
+    pub async fn remove_definition(&self, id: Uuid) -> Result<Option<TestDefinition>, sqlx::Error> {
+        let mut definitions = self.definitions.write().await;
+        if let Some(db) = &self.db {
+            traced("DELETE FROM test_definitions WHERE id = $1", |sql| {
+                sqlx::query(sql).bind(id).execute(db)
+            })
+            .await?;
+            traced("DELETE FROM test_files WHERE owner_id = $1", |sql| {
+                sqlx::query(sql).bind(id).execute(db)
+            })
+            .await?;
+        }
+        self.files.write().await.remove(&id);
+        Ok(definitions.remove(&id))
+    }
+
+    /// List suites, oldest first
+    pub async fn list_suites(&self) -> Vec<TestSuite> {
+        let mut suites: Vec<TestSuite> = self.suites.read().await.values().cloned().collect();
+        suites.sort_by_key(|suite| suite.created_at);
+        suites
+    }
+
+    pub async fn get_suite(&self, id: Uuid) -> Option<TestSuite> {
+        self.suites.read().await.get(&id).cloned()
+    }
+
+    pub async fn insert_suite(&self, suite: TestSuite) -> Result<(), sqlx::Error> {
+        let mut suites = self.suites.write().await;
+        if let Some(db) = &self.db {
+            traced(
+                "INSERT INTO test_suites (id, name, description, test_definition_ids, \
+                 created_at, execution_mode, labels, team_id) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8) \
+                 ON CONFLICT (id) DO UPDATE SET name = $2, description = $3, \
+                 test_definition_ids = $4, execution_mode = $6, labels = $7, team_id = $8",
+                |sql| {
+                    sqlx::query(sql)
+                        .bind(suite.id)
+                        .bind(&suite.name)
+                        .bind(&suite.description)
+                        .bind(&suite.test_definition_ids)
+                        .bind(suite.created_at)
+                        .bind(&suite.execution_mode)
+                        .bind(&suite.labels)
+                        .bind(suite.team_id)
+                        .execute(db)
+                },
+            )
+            .await?;
+        }
+        suites.insert(suite.id, suite);
+        Ok(())
+    }
+
+    pub async fn remove_suite(&self, id: Uuid) -> Result<Option<TestSuite>, sqlx::Error> {
+        let mut suites = self.suites.write().await;
+        if let Some(db) = &self.db {
+            traced("DELETE FROM test_suites WHERE id = $1", |sql| {
+                sqlx::query(sql).bind(id).execute(db)
+            })
+            .await?;
+        }
+        Ok(suites.remove(&id))
+    }
+
+    /// List trigger rules, oldest first
+    pub async fn list_trigger_rules(&self) -> Vec<TriggerRule> {
+        let mut rules: Vec<TriggerRule> =
+            self.trigger_rules.read().await.values().cloned().collect();
+        rules.sort_by_key(|rule| rule.created_at);
+        rules
+    }
+
+    pub async fn get_trigger_rule(&self, id: Uuid) -> Option<TriggerRule> {
+        self.trigger_rules.read().await.get(&id).cloned()
+    }
+
+    pub async fn insert_trigger_rule(&self, rule: TriggerRule) {
+        self.trigger_rules.write().await.insert(rule.id, rule);
+    }
+
+    pub async fn remove_trigger_rule(&self, id: Uuid) -> Option<TriggerRule> {
+        self.trigger_rules.write().await.remove(&id)
+    }
+
+    /// List status reporters, oldest first
+    pub async fn list_status_reporters(&self) -> Vec<StatusReporter> {
+        let mut reporters: Vec<StatusReporter> = self
+            .status_reporters
+            .read()
+            .await
+            .values()
+            .cloned()
+            .collect();
+        reporters.sort_by_key(|reporter| reporter.created_at);
+        reporters
+    }
+
+    pub async fn get_status_reporter(&self, id: Uuid) -> Option<StatusReporter> {
+        self.status_reporters.read().await.get(&id).cloned()
+    }
+
+    pub async fn insert_status_reporter(&self, reporter: StatusReporter) {
+        self.status_reporters
+            .write()
+            .await
+            .insert(reporter.id, reporter);
+    }
+
+    pub async fn remove_status_reporter(&self, id: Uuid) -> Option<StatusReporter> {
+        self.status_reporters.write().await.remove(&id)
+    }
+
+    /// List schedules, oldest first
+    pub async fn list_schedules(&self) -> Vec<Schedule> {
+        let mut schedules: Vec<Schedule> = self.schedules.read().await.values().cloned().collect();
+        schedules.sort_by_key(|schedule| schedule.created_at);
+        schedules
+    }
+
+    pub async fn get_schedule(&self, id: Uuid) -> Option<Schedule> {
+        self.schedules.read().await.get(&id).cloned()
+    }
+
+    pub async fn insert_schedule(&self, schedule: Schedule) {
+        self.schedules.write().await.insert(schedule.id, schedule);
+    }
+
+    /// Apply an update to a schedule, returning the updated copy if it still exists
+    pub async fn update_schedule<F>(&self, id: Uuid, update: F) -> Option<Schedule>
+    where
+        F: FnOnce(&mut Schedule),
+    {
+        let mut schedules = self.schedules.write().await;
+        let schedule = schedules.get_mut(&id)?;
+        update(schedule);
+        Some(schedule.clone())
+    }
+
+    pub async fn remove_schedule(&self, id: Uuid) -> Option<Schedule> {
+        self.schedules.write().await.remove(&id)
+    }
+
+    /// Whether any run started by the schedule is still pending or running
+    pub async fn has_active_runs(&self, schedule_id: Uuid) -> bool {
+        self.runs.read().await.values().any(|run| {
+            run.schedule_id == Some(schedule_id)
+                && matches!(run.status.as_str(), "pending" | "running")
+        })
+    }
+
+    /// Runs started for a Kubernetes `TestRun` resource, oldest first
+    pub async fn find_runs_by_resource(&self, resource_uid: &str) -> Vec<TestRun> {
+        let mut runs: Vec<TestRun> = self
+            .runs
+            .read()
+            .await
+            .values()
+            .filter(|run| run.resource_uid.as_deref() == Some(resource_uid))
+            .cloned()
+            .collect();
+        runs.sort_by_key(|run| run.created_at);
+        runs
+    }
+
+    /// List notification channels, oldest first
+    pub async fn list_notification_channels(&self) -> Vec<NotificationChannel> {
+        let mut channels: Vec<NotificationChannel> = self
+            .notification_channels
+            .read()
+            .await
+            .values()
+            .cloned()
+            .collect();
+        channels.sort_by_key(|channel| channel.created_at);
+        channels
+    }
+
+    pub async fn get_notification_channel(&self, id: Uuid) -> Option<NotificationChannel> {
+        self.notification_channels.read().await.get(&id).cloned()
+    }
+
+    pub async fn insert_notification_channel(&self, channel: NotificationChannel) {
+        self.notification_channels
+            .write()
+            .await
+            .insert(channel.id, channel);
+    }
+
+    /// Remove a channel along with the rules that send to it
+    pub async fn remove_notification_channel(&self, id: Uuid) -> Option<NotificationChannel> {
+        self.notification_rules
+            .write()
+            .await
+            .retain(|_, rule| rule.channel_id != id);
+        self.notification_channels.write().await.remove(&id)
+    }
+
+    /// List notification rules, oldest first
+    pub async fn list_notification_rules(&self) -> Vec<NotificationRule> {
+        let mut rules: Vec<NotificationRule> = self
+            .notification_rules
+            .read()
+            .await
+            .values()
+            .cloned()
+            .collect();
+        rules.sort_by_key(|rule| rule.created_at);
+        rules
+    }
+
+    pub async fn get_notification_rule(&self, id: Uuid) -> Option<NotificationRule> {
+        self.notification_rules.read().await.get(&id).cloned()
+    }
+
+    pub async fn insert_notification_rule(&self, rule: NotificationRule) {
+        self.notification_rules.write().await.insert(rule.id, rule);
+    }
+
+    pub async fn remove_notification_rule(&self, id: Uuid) -> Option<NotificationRule> {
+        self.notification_rules.write().await.remove(&id)
+    }
+
+    /// Record the status a definition or suite finished with, returning the
+    /// previous one
+    pub async fn record_outcome(&self, id: Uuid, status: &str) -> Option<String> {
+        self.outcomes.write().await.insert(id, status.to_string())
+    }
+
+    /// List executors, ordered by id
+    pub async fn list_executors(&self) -> Vec<Executor> {
+        let mut executors: Vec<Executor> = self.executors.read().await.values().cloned().collect();
+        executors.sort_by(|a, b| a.id.cmp(&b.id));
+        executors
+    }
+
+    pub async fn insert_executor(&self, executor: Executor) {
+        self.executors
+            .write()
+            .await
+            .insert(executor.id.clone(), executor);
+    }
+
+    /// Find the run that launched a job, including its retry jobs, or that adopted it
+    pub async fn find_run_by_job(&self, job_name: &str) -> Option<TestRun> {
+        let runs = self.runs.read().await;
+        runs.values()
+            .find(|run| {
+                run.k8s_job_name.as_deref() == Some(job_name)
+                    || job_name
+                        .strip_prefix(&format!("test-run-{}", run.id))
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with("-retry-"))
+            })
+            .cloned()
+    }
+
+    pub async fn put_file(
+        &self,
+        owner: Uuid,
+        name: String,
+        content: Vec<u8>,
+    ) -> Result<(), sqlx::Error> {
+        let mut files = self.files.write().await;
+        if let Some(db) = &self.db {
+            traced(
+                "INSERT INTO test_files (owner_id, name, content) VALUES ($1, $2, $3) \
+                 ON CONFLICT (owner_id, name) DO UPDATE SET content = $3",
+                |sql| {
+                    sqlx::query(sql)
+                        .bind(owner)
+                        .bind(&name)
+                        .bind(&content)
+                        .execute(db)
+                },
+            )
+            .await?;
+        }
+        files.entry(owner).or_default().insert(name, content);
+        Ok(())
+    }
+
+    pub async fn get_file(&self, owner: Uuid, name: &str) -> Option<Vec<u8>> {
+        self.files.read().await.get(&owner)?.get(name).cloned()
+    }
+
+    pub async fn remove_file(&self, owner: Uuid, name: &str) -> Result<bool, sqlx::Error> {
+        let mut files = self.files.write().await;
+        let Some(contents) = files.get_mut(&owner) else {
+            return Ok(false);
+        };
+        if let Some(db) = &self.db {
+            traced(
+                "DELETE FROM test_files WHERE owner_id = $1 AND name = $2",
+                |sql| sqlx::query(sql).bind(owner).bind(name).execute(db),
+            )
+            .await?;
+        }
+        Ok(contents.remove(name).is_some())
+    }
+
+    /// Every file of a definition or run, by name
+    pub async fn files_of(&self, owner: Uuid) -> FileContents {
+        self.files
+            .read()
+            .await
+            .get(&owner)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Snapshot a definition's files for a run, so later uploads don't change it
+    pub async fn copy_files(&self, from: Uuid, to: Uuid) -> Result<(), sqlx::Error> {
+        let mut files = self.files.write().await;
+        let Some(contents) = files.get(&from).cloned() else {
+            return Ok(());
+        };
+        if let Some(db) = &self.db {
+            traced(
+                "INSERT INTO test_files (owner_id, name, content) \
+                 SELECT $2, name, content FROM test_files WHERE owner_id = $1",
+                |sql| sqlx::query(sql).bind(from).bind(to).execute(db),
+            )
+            .await?;
+        }
+        files.insert(to, contents);
+        Ok(())
+    }
+
+    pub async fn get_executor(&self, id: &str) -> Option<Executor> {
+        self.executors.read().await.get(id).cloned()
+    }
+
+    /// List users, oldest first
+    pub async fn list_users(&self) -> Vec<User> {
+        let mut users: Vec<User> = self.users.read().await.values().cloned().collect();
+        users.sort_by_key(|user| user.created_at);
+        users
+    }
+
+    pub async fn get_user(&self, id: Uuid) -> Option<User> {
+        self.users.read().await.get(&id).cloned()
+    }
+
+    /// Find a user signed in through OIDC by their `sub` claim
+    pub async fn find_user_by_subject(&self, subject: &str) -> Option<User> {
+        self.users
+            .read()
+            .await
+            .values()
+            .find(|user| user.subject.as_deref() == Some(subject))
+            .cloned()
+    }
+
+    pub async fn insert_user(&self, user: User) -> Result<(), sqlx::Error> {
+        let mut users = self.users.write().await;
+        self.save_user(&user).await?;
+        users.insert(user.id, user);
+        Ok(())
+    }
+
+    /// The user signed in through OIDC with `user`'s subject, storing `user` on
+    /// their first sign-in. Emails are unique, so one another user already has
+    /// is left out rather than trusted to be theirs.
+    pub async fn provision_user(&self, mut user: User) -> Result<User, sqlx::Error> {
+        let mut users = self.users.write().await;
+        if let Some(existing) = users
+            .values()
+            .find(|existing| existing.subject.is_some() && existing.subject == user.subject)
+        {
+            return Ok(existing.clone());
+        }
+        if users
+            .values()
+            .any(|existing| existing.email.is_some() && existing.email == user.email)
+        {
+            user.email = None;
+        }
+        self.save_user(&user).await?;
+        users.insert(user.id, user.clone());
+        Ok(user)
+    }
+
+    async fn save_user(&self, user: &User) -> Result<(), sqlx::Error> {
+        let Some(db) = &self.db else {
+            return Ok(());
+        };
+        traced(
+            "INSERT INTO users (id, name, email, subject, is_admin, created_at) \
+             VALUES ($1, $2, $3, $4, $5, $6) \
+             ON CONFLICT (id) DO UPDATE SET name = $2, email = $3, subject = $4, is_admin = $5",
+            |sql| {
+                sqlx::query(sql)
+                    .bind(user.id)
+                    .bind(&user.name)
+                    .bind(&user.email)
+                    .bind(&user.subject)
+                    .bind(user.is_admin)
+                    .bind(user.created_at)
+                    .execute(db)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// List teams, oldest first
+    pub async fn list_teams(&self) -> Vec<Team> {
+        let mut teams: Vec<Team> = self.teams.read().await.values().cloned().collect();
+        teams.sort_by_key(|team| team.created_at);
+        teams
+    }
+
+    pub async fn get_team(&self, id: Uuid) -> Option<Team> {
+        self.teams.read().await.get(&id).cloned()
+    }
+
+    pub async fn find_team_by_name(&self, name: &str) -> Option<Team> {
+        self.teams
+            .read()
+            .await
+            .values()
+            .find(|team| team.name == name)
+            .cloned()
+    }
+
+    /// Store a team along with its members
+    pub async fn insert_team(&self, team: Team) -> Result<(), sqlx::Error> {
+        let mut teams = self.teams.write().await;
+        if let Some(db) = &self.db {
+            let mut tx = db.begin().await?;
+            traced(
+                "INSERT INTO teams (id, name, description, created_at) VALUES ($1, $2, $3, $4) \
+                 ON CONFLICT (id) DO UPDATE SET name = $2, description = $3",
+                |sql| {
+                    sqlx::query(sql)
+                        .bind(team.id)
+                        .bind(&team.name)
+                        .bind(&team.description)
+                        .bind(team.created_at)
+                        .execute(&mut *tx)
+                },
+            )
+            .await?;
+            traced("DELETE FROM team_members WHERE team_id = $1", |sql| {
+                sqlx::query(sql).bind(team.id).execute(&mut *tx)
+            })
+            .await?;
+            for member in &team.members {
+                traced(
+                    "INSERT INTO team_members (team_id, user_id, role) VALUES ($1, $2, $3) \
+                     ON CONFLICT (team_id, user_id) DO UPDATE SET role = $3",
+                    |sql| {
+                        sqlx::query(sql)
+                            .bind(team.id)
+                            .bind(member.user_id)
+                            .bind(role_name(member.role))
+                            .execute(&mut *tx)
+                    },
+                )
+                .await?;
+            }
+            tx.commit().await?;
+        }
+        teams.insert(team.id, team);
+        Ok(())
+    }
+
+    /// Add a member or change their role, returning the updated team
+    pub async fn set_team_member(
+        &self,
+        team_id: Uuid,
+        user_id: Uuid,
+        role: Role,
+    ) -> Result<Option<Team>, sqlx::Error> {
+        let mut teams = self.teams.write().await;
+        let Some(team) = teams.get_mut(&team_id) else {
+            return Ok(None);
+        };
+        if let Some(db) = &self.db {
+            traced(
+                "INSERT INTO team_members (team_id, user_id, role) VALUES ($1, $2, $3) \
+                 ON CONFLICT (team_id, user_id) DO UPDATE SET role = $3",
+                |sql| {
+                    sqlx::query(sql)
+                        .bind(team_id)
+                        .bind(user_id)
+                        .bind(role_name(role))
+                        .execute(db)
+                },
+            )
+            .await?;
+        }
+        match team.members.iter_mut().find(|m| m.user_id == user_id) {
+            Some(member) => member.role = role,
+            None => team.members.push(TeamMember { user_id, role }),
+        }
+        Ok(Some(team.clone()))
+    }
+
+    /// Remove a member, returning the updated team
+    pub async fn remove_team_member(
+        &self,
+        team_id: Uuid,
+        user_id: Uuid,
+    ) -> Result<Option<Team>, sqlx::Error> {
+        let mut teams = self.teams.write().await;
+        let Some(team) = teams.get_mut(&team_id) else {
+            return Ok(None);
+        };
+        if let Some(db) = &self.db {
+            traced(
+                "DELETE FROM team_members WHERE team_id = $1 AND user_id = $2",
+                |sql| sqlx::query(sql).bind(team_id).bind(user_id).execute(db),
+            )
+            .await?;
+        }
+        team.members.retain(|member| member.user_id != user_id);
+        Ok(Some(team.clone()))
+    }
+
+    /// The role a user holds in each team they belong to
+    pub async fn roles_of(&self, user_id: Uuid) -> HashMap<Uuid, Role> {
+        self.teams
+            .read()
+            .await
+            .values()
+            .filter_map(|team| Some((team.id, team.role_of(user_id)?)))
+            .collect()
+    }
+
+    /// Write a run to the database, if there is one. Callers hold the runs lock,
+    /// so writes of the same run land in order.
+    async fn save_run(&self, run: &TestRun) -> Result<(), sqlx::Error> {
+        let Some(db) = &self.db else {
+            return Ok(());
+        };
+        traced(
+            "INSERT INTO test_runs (id, name, image, command, status, created_at, \
+             test_definition_id, executor_id, suite_id, variables, artifacts, duration, retries, \
+             logs, k8s_job_name, pod_scheduled, container_created, container_started, \
+             completed, failed, target, team_id, schedule_id, resource_uid, triggered_by, \
+             resources, privileged, secrets, source, files, job_labels, job_annotations, \
+             trigger, priority) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, \
+             $17, $18, $19, $20, $21, $22, $23, $24, $25, $26, $27, $28, $29, $30, $31, $32, \
+             $33, $34) \
+             ON CONFLICT (id) DO UPDATE SET name = $2, image = $3, command = $4, status = $5, \
+             test_definition_id = $7, executor_id = $8, suite_id = $9, variables = $10, \
+             artifacts = $11, duration = $12, retries = $13, logs = $14, k8s_job_name = $15, \
+             pod_scheduled = $16, container_created = $17, container_started = $18, \
+             completed = $19, failed = $20, target = $21, team_id = $22, schedule_id = $23, \
+             resource_uid = $24, triggered_by = $25, resources = $26, privileged = $27, \
+             secrets = $28, source = $29, files = $30, job_labels = $31, job_annotations = $32, \
+             trigger = $33, priority = $34",
+            |sql| {
+                sqlx::query(sql)
+                    .bind(run.id)
+                    .bind(&run.name)
+                    .bind(&run.image)
+                    .bind(&run.commands)
+                    .bind(&run.status)
+                    .bind(run.created_at)
+                    .bind(run.definition_id)
+                    .bind(&run.executor_id)
+                    .bind(run.suite_id)
+                    .bind(&run.variables)
+                    .bind(&run.artifacts)
+                    .bind(run.duration)
+                    .bind(run.retries)
+                    .bind(&run.logs)
+                    .bind(&run.k8s_job_name)
+                    .bind(run.pod_scheduled)
+                    .bind(run.container_created)
+                    .bind(run.container_started)
+                    .bind(run.completed)
+                    .bind(run.failed)
+                    .bind(&run.target)
+                    .bind(run.team_id)
+                    .bind(run.schedule_id)
+                    .bind(&run.resource_uid)
+                    .bind(&run.triggered_by)
+                    .bind(run.resources.as_ref().map(Json))
+                    .bind(run.privileged)
+                    .bind(Json(&run.secrets))
+                    .bind(run.source.as_ref().map(Json))
+                    .bind(Json(&run.files))
+                    .bind(Json(&run.job_labels))
+                    .bind(Json(&run.job_annotations))
+                    .bind(run.trigger.as_ref().map(Json))
+                    .bind(priority_name(run.priority))
+                    .execute(db)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use sparktest_core::GitEventKind;
+
+    #[tokio::test]
+    async fn test_definitions_suites_and_runs_survive_a_restart() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        let store = Store::load(db.clone()).await.unwrap();
+        let definition = TestDefinition {
+            id: Uuid::new_v4(),
+            name: "Checkout E2E".to_string(),
+            description: "Playwright journeys".to_string(),
+            image: "mcr.microsoft.com/playwright:v1.40.0".to_string(),
+            commands: vec![
+                "npx".to_string(),
+                "playwright".to_string(),
+                "test".to_string(),
+            ],
+            created_at: Utc::now(),
+            executor_id: Some("playwright".to_string()),
+            variables: Some(serde_json::json!({"BASE_URL": "https://staging.example.com"})),
+            labels: Some(vec!["e2e".to_string()]),
+            target: Some("staging".to_string()),
+            team_id: Some(Uuid::new_v4()),
+            resources: Some(ResourceLimits {
+                cpu: Some("2".to_string()),
+                memory: None,
+            }),
+            privileged: true,
+            secrets: vec![SecretRef {
+                secret: "staging-credentials".to_string(),
+                key: "password".to_string(),
+                env: Some("E2E_PASSWORD".to_string()),
+                path: None,
+            }],
+            source: Some(GitSource {
+                url: "https://github.com/acme/shop".to_string(),
+                git_ref: Some("main".to_string()),
+                subdirectory: Some("e2e".to_string()),
+            }),
+            files: Vec::new(),
+            priority: Default::default(),
+            job_labels: BTreeMap::from([("cost-center".to_string(), "checkout".to_string())]),
+            job_annotations: BTreeMap::from([(
+                "sidecar.istio.io/inject".to_string(),
+                "false".to_string(),
+            )]),
+        };
+        store.insert_definition(definition.clone()).await.unwrap();
+        let suite = TestSuite {
+            id: Uuid::new_v4(),
+            name: "Release".to_string(),
+            description: String::new(),
+            test_definition_ids: vec![definition.id],
+            created_at: Utc::now(),
+            execution_mode: "sequential".to_string(),
+            labels: None,
+            team_id: definition.team_id,
+        };
+        store.insert_suite(suite.clone()).await.unwrap();
+        let mut run = TestRun::from_definition(&definition);
+        run.suite_id = Some(suite.id);
+        run.trigger = Some(RunTrigger {
+            provider: "github".to_string(),
+            event: GitEventKind::Push,
+            repository: "acme/shop".to_string(),
+            branch: "main".to_string(),
+            commit_sha: "6dcb09b5b57875f334f61aebed695e2e4193db5e".to_string(),
+            pull_request: None,
+            sender: Some("octocat".to_string()),
+            rule_id: Some(Uuid::new_v4()),
+        });
+        store.insert_run(run.clone()).await.unwrap();
+        store
+            .update_run(run.id, |run| {
+                run.status = "failed".to_string();
+                run.logs = Some(vec!["1 failed".to_string()]);
+                run.k8s_job_name = Some(format!("test-run-{}", run.id));
+            })
+            .await
+            .unwrap();
+
+        // A new process loads them from the database
+        let restarted = Store::load(db.clone()).await.unwrap();
+        let loaded = restarted.get_definition(definition.id).await.unwrap();
+        assert_eq!(loaded.variables, definition.variables);
+        assert_eq!(loaded.executor_id.as_deref(), Some("playwright"));
+        assert_eq!(loaded.target.as_deref(), Some("staging"));
+        assert_eq!(loaded.resources, definition.resources);
+        assert!(loaded.privileged);
+        assert_eq!(loaded.secrets, definition.secrets);
+        assert_eq!(loaded.source, definition.source);
+        assert_eq!(loaded.job_labels, definition.job_labels);
+        assert_eq!(loaded.job_annotations, definition.job_annotations);
+        let loaded = restarted.get_suite(suite.id).await.unwrap();
+        assert_eq!(loaded.test_definition_ids, vec![definition.id]);
+        let loaded = restarted.get_run(run.id).await.unwrap();
+        assert_eq!(loaded.status, "failed");
+        assert_eq!(loaded.logs, Some(vec!["1 failed".to_string()]));
+        assert_eq!(loaded.definition_id, Some(definition.id));
+        assert_eq!(loaded.suite_id, Some(suite.id));
+        assert_eq!(loaded.team_id, definition.team_id);
+        assert_eq!(loaded.resources, definition.resources);
+        assert!(loaded.privileged);
+        assert_eq!(loaded.secrets, definition.secrets);
+        assert_eq!(loaded.source, definition.source);
+        assert_eq!(loaded.job_labels, definition.job_labels);
+        assert_eq!(loaded.job_annotations, definition.job_annotations);
+        assert_eq!(loaded.trigger, run.trigger);
+
+        restarted.remove_run(run.id).await.unwrap();
+        restarted.remove_suite(suite.id).await.unwrap();
+        restarted.remove_definition(definition.id).await.unwrap();
+        let restarted = Store::load(db).await.unwrap();
+        assert!(restarted.get_run(run.id).await.is_none());
+        assert!(restarted.get_suite(suite.id).await.is_none());
+        assert!(restarted.get_definition(definition.id).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_files_survive_a_restart() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        let store = Store::load(db.clone()).await.unwrap();
+        let definition = TestDefinition {
+            id: Uuid::new_v4(),
+            name: "Load test".to_string(),
+            description: String::new(),
+            image: "grafana/k6".to_string(),
+            commands: vec!["k6".to_string(), "run".to_string(), "script.js".to_string()],
+            created_at: Utc::now(),
+            executor_id: None,
+            variables: None,
+            labels: None,
+            target: None,
+            team_id: None,
+            resources: None,
+            privileged: false,
+            secrets: Vec::new(),
+            source: None,
+            files: vec![TestFile {
+                name: "script.js".to_string(),
+                size: 29,
+                content_type: Some("text/javascript".to_string()),
+                sha256: "0a1b".to_string(),
+                uploaded_at: Utc::now(),
+            }],
+            priority: Default::default(),
+            job_labels: BTreeMap::new(),
+            job_annotations: BTreeMap::new(),
+        };
+        store.insert_definition(definition.clone()).await.unwrap();
+        let content = b"export default function () {}".to_vec();
+        store
+            .put_file(definition.id, "script.js".to_string(), content.clone())
+            .await
+            .unwrap();
+        store
+            .put_file(definition.id, "users.csv".to_string(), b"id".to_vec())
+            .await
+            .unwrap();
+        assert!(store.remove_file(definition.id, "users.csv").await.unwrap());
+        let run = TestRun::from_definition(&definition);
+        store.copy_files(definition.id, run.id).await.unwrap();
+        store.insert_run(run.clone()).await.unwrap();
+
+        let restarted = Store::load(db.clone()).await.unwrap();
+        let loaded = restarted.get_definition(definition.id).await.unwrap();
+        assert_eq!(loaded.files, definition.files);
+        assert_eq!(
+            restarted.get_run(run.id).await.unwrap().files,
+            definition.files
+        );
+        assert_eq!(
+            restarted.get_file(definition.id, "script.js").await,
+            Some(content.clone())
+        );
+        assert_eq!(restarted.get_file(definition.id, "users.csv").await, None);
+        assert_eq!(
+            restarted.files_of(run.id).await,
+            BTreeMap::from([("script.js".to_string(), content)])
+        );
+
+        // Deleting the owner deletes its files
+        restarted.remove_run(run.id).await.unwrap();
+        restarted.remove_definition(definition.id).await.unwrap();
+        let restarted = Store::load(db).await.unwrap();
+        assert!(restarted.files_of(run.id).await.is_empty());
+        assert!(restarted.files_of(definition.id).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_users_and_teams_survive_a_restart() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        let store = Store::load(db.clone()).await.unwrap();
+        let user = |name: &str| User {
+            id: Uuid::new_v4(),
+            name: name.to_string(),
+            email: None,
+            subject: None,
+            is_admin: false,
+            created_at: Utc::now(),
+        };
+        let (alice, bob) = (user("alice"), user("bob"));
+        store.insert_user(alice.clone()).await.unwrap();
+        store.insert_user(bob.clone()).await.unwrap();
+        let team = Team {
+            id: Uuid::new_v4(),
+            name: "payments".to_string(),
+            description: Some("Checkout and billing".to_string()),
+            members: vec![TeamMember {
+                user_id: alice.id,
+                role: Role::Admin,
+            }],
+            created_at: Utc::now(),
+        };
+        store.insert_team(team.clone()).await.unwrap();
+        store
+            .set_team_member(team.id, bob.id, Role::Runner)
+            .await
+            .unwrap();
+        store
+            .set_team_member(team.id, alice.id, Role::Maintainer)
+            .await
+            .unwrap();
+
+        // A new process loads them from the database
+        let restarted = Store::load(db.clone()).await.unwrap();
+        assert_eq!(restarted.get_user(bob.id).await.unwrap().name, "bob");
+        let loaded = restarted.get_team(team.id).await.unwrap();
+        assert_eq!(loaded.description, team.description);
+        assert_eq!(loaded.role_of(alice.id), Some(Role::Maintainer));
+        assert_eq!(loaded.role_of(bob.id), Some(Role::Runner));
+
+        restarted.remove_team_member(team.id, bob.id).await.unwrap();
+        let restarted = Store::load(db).await.unwrap();
+        assert_eq!(
+            restarted.get_team(team.id).await.unwrap().role_of(bob.id),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oidc_users_are_found_by_subject_after_a_restart() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        let store = Store::load(db.clone()).await.unwrap();
+        let email = format!("{}@example.com", Uuid::new_v4());
+        let existing = User {
+            id: Uuid::new_v4(),
+            name: "dana".to_string(),
+            email: Some(email.clone()),
+            subject: None,
+            is_admin: false,
+            created_at: Utc::now(),
+        };
+        store.insert_user(existing).await.unwrap();
+        let subject = Uuid::new_v4().to_string();
+        let signed_in = || User {
+            id: Uuid::new_v4(),
+            name: "dana".to_string(),
+            email: Some(email.clone()),
+            subject: Some(subject.clone()),
+            is_admin: false,
+            created_at: Utc::now(),
+        };
+        let provisioned = store.provision_user(signed_in()).await.unwrap();
+        // Another user has the email, so it isn't taken over
+        assert_eq!(provisioned.email, None);
+        let again = store.provision_user(signed_in()).await.unwrap();
+        assert_eq!(again.id, provisioned.id);
+
+        // A new process finds them by their subject
+        let restarted = Store::load(db).await.unwrap();
+        let found = restarted.find_user_by_subject(&subject).await.unwrap();
+        assert_eq!(found.id, provisioned.id);
+    }
+}
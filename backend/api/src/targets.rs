@@ -0,0 +1,235 @@
+use crate::db::traced;
+use crate::k8s::KubeConfig;
+use chrono::{DateTime, Utc};
+use sparktest_core::{ExecutionTarget, TargetConnection};
+use sqlx::types::Json;
+use sqlx::PgPool;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Name of the built-in target used when a definition or run does not pick one
+pub const DEFAULT_TARGET: &str = "default";
+
+/// Registry of the execution targets runs can be scheduled onto. Targets
+/// registered through the API are stored in the database when there is one.
+#[derive(Clone)]
+pub struct TargetRegistry {
+    targets: Arc<RwLock<BTreeMap<String, ExecutionTarget>>>,
+    db: Option<PgPool>,
+}
+
+impl Default for TargetRegistry {
+    fn default() -> Self {
+        let defaults = KubeConfig::default();
+        let target = ExecutionTarget {
+            name: DEFAULT_TARGET.to_string(),
+            description: Some("Auto-detected cluster from the default kubeconfig".to_string()),
+            connection: TargetConnection::Auto,
+            namespace: defaults.namespace,
+            timeout_seconds: defaults.timeout_seconds,
+            max_log_lines: defaults.max_log_lines,
+            created_at: chrono::Utc::now(),
+        };
+
+        Self {
+            targets: Arc::new(RwLock::new(BTreeMap::from([(target.name.clone(), target)]))),
+            db: None,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct TargetRow {
+    name: String,
+    description: Option<String>,
+    connection: Json<TargetConnection>,
+    namespace: String,
+    timeout_seconds: i64,
+    max_log_lines: Option<i64>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<TargetRow> for ExecutionTarget {
+    fn from(row: TargetRow) -> Self {
+        Self {
+            name: row.name,
+            description: row.description,
+            connection: row.connection.0,
+            namespace: row.namespace,
+            timeout_seconds: row.timeout_seconds.max(0) as u64,
+            max_log_lines: row.max_log_lines,
+            created_at: row.created_at,
+        }
+    }
+}
+
+impl TargetRegistry {
+    /// The default target plus every target stored in the database, which
+    /// registrations and removals are written to from then on
+    pub async fn load(db: PgPool) -> Result<Self, sqlx::Error> {
+        let rows: Vec<TargetRow> = traced(
+            "SELECT name, description, connection, namespace, timeout_seconds, max_log_lines, \
+             created_at FROM execution_targets",
+            |sql| sqlx::query_as(sql).fetch_all(&db),
+        )
+        .await?;
+
+        let registry = Self {
+            db: Some(db),
+            ..Self::default()
+        };
+        {
+            let mut targets = registry.targets.write().await;
+            for row in rows {
+                let target = ExecutionTarget::from(row);
+                targets.insert(target.name.clone(), target);
+            }
+        }
+        Ok(registry)
+    }
+
+    /// List all registered targets, ordered by name
+    pub async fn list(&self) -> Vec<ExecutionTarget> {
+        self.targets.read().await.values().cloned().collect()
+    }
+
+    /// Look up a target by name
+    pub async fn get(&self, name: &str) -> Option<ExecutionTarget> {
+        self.targets.read().await.get(name).cloned()
+    }
+
+    /// Resolve an optional target selection, falling back to the default target
+    pub async fn resolve(&self, name: Option<&str>) -> Option<ExecutionTarget> {
+        self.get(name.unwrap_or(DEFAULT_TARGET)).await
+    }
+
+    /// Register a new target; returns `false` if the name is already taken
+    pub async fn register(&self, target: ExecutionTarget) -> Result<bool, sqlx::Error> {
+        let mut targets = self.targets.write().await;
+        if targets.contains_key(&target.name) {
+            return Ok(false);
+        }
+        if let Some(db) = &self.db {
+            traced(
+                "INSERT INTO execution_targets (name, description, connection, namespace, \
+                 timeout_seconds, max_log_lines, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                |sql| {
+                    sqlx::query(sql)
+                        .bind(&target.name)
+                        .bind(&target.description)
+                        .bind(Json(&target.connection))
+                        .bind(&target.namespace)
+                        .bind(target.timeout_seconds as i64)
+                        .bind(target.max_log_lines)
+                        .bind(target.created_at)
+                        .execute(db)
+                },
+            )
+            .await?;
+        }
+        targets.insert(target.name.clone(), target);
+        Ok(true)
+    }
+
+    /// Remove a target by name, returning it if it existed
+    pub async fn remove(&self, name: &str) -> Result<Option<ExecutionTarget>, sqlx::Error> {
+        let mut targets = self.targets.write().await;
+        if let Some(db) = &self.db {
+            traced("DELETE FROM execution_targets WHERE name = $1", |sql| {
+                sqlx::query(sql).bind(name).execute(db)
+            })
+            .await?;
+        }
+        Ok(targets.remove(name))
+    }
+}
+
+/// Target names are used in labels and URLs, so keep them DNS-label shaped
+pub fn is_valid_target_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 63
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use uuid::Uuid;
+
+    fn target(name: &str) -> ExecutionTarget {
+        ExecutionTarget {
+            name: name.to_string(),
+            description: None,
+            connection: TargetConnection::Kubeconfig {
+                context: "k3d-staging".to_string(),
+            },
+            namespace: "sparktest".to_string(),
+            timeout_seconds: 600,
+            max_log_lines: None,
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_default_target_is_registered() {
+        let registry = TargetRegistry::default();
+        let default = registry.resolve(None).await.expect("default target");
+        assert_eq!(default.name, DEFAULT_TARGET);
+        assert_eq!(default.connection, TargetConnection::Auto);
+        assert_eq!(default.namespace, "default");
+    }
+
+    #[tokio::test]
+    async fn test_register_and_remove_target() {
+        let registry = TargetRegistry::default();
+        assert!(registry.register(target("staging")).await.unwrap());
+        assert!(!registry.register(target("staging")).await.unwrap());
+        assert_eq!(registry.list().await.len(), 2);
+
+        let staging = registry.resolve(Some("staging")).await.unwrap();
+        assert_eq!(staging.namespace, "sparktest");
+
+        assert!(registry.remove("staging").await.unwrap().is_some());
+        assert!(registry.get("staging").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_registered_targets_survive_a_restart() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        let name = format!("staging-{}", &Uuid::new_v4().simple().to_string()[..8]);
+        let registry = TargetRegistry::load(db.clone()).await.unwrap();
+        assert!(registry.register(target(&name)).await.unwrap());
+
+        let restarted = TargetRegistry::load(db.clone()).await.unwrap();
+        let staging = restarted.get(&name).await.expect("stored target");
+        assert_eq!(
+            staging.connection,
+            TargetConnection::Kubeconfig {
+                context: "k3d-staging".to_string()
+            }
+        );
+        assert!(restarted.resolve(None).await.is_some());
+
+        restarted.remove(&name).await.unwrap();
+        let restarted = TargetRegistry::load(db).await.unwrap();
+        assert!(restarted.get(&name).await.is_none());
+    }
+
+    #[test]
+    fn test_target_name_validation() {
+        assert!(is_valid_target_name("load-testing"));
+        assert!(is_valid_target_name("staging2"));
+        assert!(!is_valid_target_name(""));
+        assert!(!is_valid_target_name("Staging"));
+        assert!(!is_valid_target_name("-staging"));
+        assert!(!is_valid_target_name("staging_cluster"));
+    }
+}
@@ -0,0 +1,278 @@
+use anyhow::{Context, Result};
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderMap,
+    middleware::Next,
+    response::Response,
+};
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use std::collections::HashMap;
+use tracing::{field, Instrument, Subscriber};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Where traces go; tracing stays local unless an OTLP endpoint is configured
+#[derive(Debug, Clone)]
+pub struct TelemetryConfig {
+    /// Base URL of the collector's OTLP/HTTP receiver; spans are posted to `/v1/traces`
+    pub endpoint: Option<String>,
+    pub protocol: Protocol,
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            protocol: Protocol::HttpBinary,
+            service_name: "sparktest".to_string(),
+        }
+    }
+}
+
+impl TelemetryConfig {
+    /// Read the standard `OTEL_EXPORTER_OTLP_ENDPOINT`,
+    /// `OTEL_EXPORTER_OTLP_PROTOCOL` (`http/protobuf` or `http/json`) and
+    /// `OTEL_SERVICE_NAME` variables
+    pub fn from_env() -> Result<Self> {
+        let mut config = Self {
+            endpoint: std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT")
+                .ok()
+                .map(|endpoint| endpoint.trim().trim_end_matches('/').to_string())
+                .filter(|endpoint| !endpoint.is_empty()),
+            ..Self::default()
+        };
+        if let Ok(protocol) = std::env::var("OTEL_EXPORTER_OTLP_PROTOCOL") {
+            config.protocol = match protocol.trim() {
+                "" | "http/protobuf" => Protocol::HttpBinary,
+                "http/json" => Protocol::HttpJson,
+                other => anyhow::bail!(
+                    "Unsupported OTEL_EXPORTER_OTLP_PROTOCOL '{other}'; use http/protobuf or http/json"
+                ),
+            };
+        }
+        if let Ok(name) = std::env::var("OTEL_SERVICE_NAME") {
+            if !name.trim().is_empty() {
+                config.service_name = name.trim().to_string();
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// A provider exporting spans in batches to the configured collector, or `None`
+/// when no endpoint is set. Shut it down on exit to flush what is buffered.
+pub fn init_tracer_provider(config: &TelemetryConfig) -> Result<Option<TracerProvider>> {
+    let Some(endpoint) = &config.endpoint else {
+        return Ok(None);
+    };
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{endpoint}/v1/traces"))
+        .with_protocol(config.protocol)
+        .build()
+        .context("Failed to create the OTLP span exporter")?;
+
+    Ok(Some(
+        TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                config.service_name.clone(),
+            )]))
+            .build(),
+    ))
+}
+
+/// Layer turning `tracing` spans into OpenTelemetry spans
+pub fn otel_layer<S>(provider: &TracerProvider) -> impl Layer<S>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer("sparktest"))
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+/// Run each request in a server span named after its route, continuing the
+/// caller's trace when it sends a `traceparent` header
+pub async fn trace_http_requests(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|matched| matched.as_str().to_string())
+        .unwrap_or_else(|| request.uri().path().to_string());
+
+    let span = tracing::info_span!(
+        "http_request",
+        otel.name = %format!("{method} {route}"),
+        otel.kind = "server",
+        http.request.method = %method,
+        http.route = %route,
+        http.response.status_code = field::Empty,
+    );
+    span.set_parent(TraceContextPropagator::new().extract(&HeaderExtractor(request.headers())));
+
+    let response = next.run(request).instrument(span.clone()).await;
+    span.record("http.response.status_code", response.status().as_u16());
+    response
+}
+
+/// W3C `traceparent` of the current span, `None` when spans are not exported
+pub fn current_traceparent() -> Option<String> {
+    let mut carrier = HashMap::new();
+    TraceContextPropagator::new().inject_context(&tracing::Span::current().context(), &mut carrier);
+    carrier.remove("traceparent")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Bytes, routing::post, Json, Router};
+    use std::sync::{Arc, Mutex};
+    use tokio::net::TcpListener;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    /// Minimal OTLP/HTTP collector receiving JSON-encoded spans
+    #[derive(Clone, Default)]
+    struct CollectorStub {
+        received: Arc<Mutex<Vec<serde_json::Value>>>,
+    }
+
+    impl CollectorStub {
+        async fn start(&self) -> String {
+            let received = self.received.clone();
+            let app = Router::new().route(
+                "/v1/traces",
+                post(move |body: Bytes| async move {
+                    received
+                        .lock()
+                        .unwrap()
+                        .push(serde_json::from_slice(&body).unwrap());
+                    Json(serde_json::json!({}))
+                }),
+            );
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let address = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+            format!("http://{address}")
+        }
+
+        /// Every exported span as `(name, trace id, parent span id)`
+        fn spans(&self) -> Vec<(String, String, String)> {
+            let received = self.received.lock().unwrap();
+            received
+                .iter()
+                .flat_map(|request| {
+                    request["resourceSpans"]
+                        .as_array()
+                        .cloned()
+                        .unwrap_or_default()
+                })
+                .flat_map(|resource| {
+                    resource["scopeSpans"]
+                        .as_array()
+                        .cloned()
+                        .unwrap_or_default()
+                })
+                .flat_map(|scope| scope["spans"].as_array().cloned().unwrap_or_default())
+                .map(|span| {
+                    let field = |name: &str| span[name].as_str().unwrap_or_default().to_string();
+                    (field("name"), field("traceId"), field("parentSpanId"))
+                })
+                .collect()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_spans_are_exported_and_propagated() {
+        let collector = CollectorStub::default();
+        let config = TelemetryConfig {
+            endpoint: Some(collector.start().await),
+            protocol: Protocol::HttpJson,
+            ..Default::default()
+        };
+        let provider = init_tracer_provider(&config).unwrap().unwrap();
+        let subscriber = tracing_subscriber::registry().with(otel_layer(&provider));
+
+        let traceparent = {
+            let _guard = tracing::subscriber::set_default(subscriber);
+            let span = tracing::info_span!("execute_run");
+            let _entered = span.enter();
+            tracing::info_span!("k8s.create_job").in_scope(|| {});
+            current_traceparent().unwrap()
+        };
+        provider.force_flush();
+
+        let spans = collector.spans();
+        let names: Vec<&str> = spans.iter().map(|(name, _, _)| name.as_str()).collect();
+        assert!(names.contains(&"execute_run"), "{spans:?}");
+        assert!(names.contains(&"k8s.create_job"), "{spans:?}");
+
+        // 00-<trace id>-<span id>-<flags>, naming the run's span
+        let parts: Vec<&str> = traceparent.split('-').collect();
+        assert_eq!(parts.len(), 4);
+        let (_, trace_id, _) = spans
+            .iter()
+            .find(|(name, _, _)| name == "k8s.create_job")
+            .unwrap();
+        assert_eq!(parts[1], trace_id);
+        assert!(spans
+            .iter()
+            .any(|(name, _, parent)| name == "k8s.create_job" && parent == parts[2]));
+
+        assert!(current_traceparent().is_none());
+        provider.shutdown().unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_requests_continue_the_callers_trace() {
+        use axum::{body::Body, middleware, routing::get};
+        use tower::ServiceExt;
+
+        let provider = TracerProvider::builder().build();
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::registry().with(otel_layer(&provider)),
+        );
+
+        let app = Router::new()
+            .route(
+                "/runs/:id",
+                get(|| async { current_traceparent().unwrap() }),
+            )
+            .route_layer(middleware::from_fn(trace_http_requests));
+        let request = axum::http::Request::get("/runs/1")
+            .header(
+                "traceparent",
+                "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            )
+            .body(Body::empty())
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let traceparent = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(traceparent.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert!(!traceparent.contains("00f067aa0ba902b7"));
+    }
+}
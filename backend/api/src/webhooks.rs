@@ -0,0 +1,685 @@
+use crate::auth::constant_time_eq;
+use crate::db::traced;
+use crate::error::ApiError;
+use crate::launch::{launch_definition, launch_suite};
+use crate::policy::glob_match;
+use crate::state::AppState;
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use sparktest_core::{GitEventKind, RunTrigger, TestRun, TriggerRule};
+use uuid::Uuid;
+
+/// The secret configured on the Git host's webhook: GitHub signs deliveries
+/// with it, GitLab sends it as a token
+#[derive(Debug, Clone, Default)]
+pub struct WebhookConfig {
+    pub secret: Option<String>,
+}
+
+impl WebhookConfig {
+    /// Reads `SPARKTEST_WEBHOOK_SECRET`; webhooks are refused without it
+    pub fn from_env() -> Self {
+        Self {
+            secret: std::env::var("SPARKTEST_WEBHOOK_SECRET")
+                .ok()
+                .filter(|secret| !secret.is_empty()),
+        }
+    }
+
+    pub fn secret(&self) -> Result<&str, ApiError> {
+        self.secret.as_deref().ok_or_else(|| {
+            ApiError::forbidden("Webhooks are disabled; set SPARKTEST_WEBHOOK_SECRET")
+        })
+    }
+}
+
+/// Check GitHub's `X-Hub-Signature-256: sha256=<hex HMAC of the body>`
+pub fn verify_github_signature(
+    secret: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<(), ApiError> {
+    let signature = headers
+        .get("x-hub-signature-256")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("sha256="))
+        .and_then(|value| hex::decode(value).ok())
+        .ok_or_else(|| ApiError::unauthorized("Missing or malformed X-Hub-Signature-256 header"))?;
+
+    mac(secret, body)?
+        .verify_slice(&signature)
+        .map_err(|_| ApiError::unauthorized("Webhook signature does not match"))
+}
+
+/// Check GitLab's `X-Gitlab-Token`, which carries the secret itself
+pub fn verify_gitlab_token(secret: &str, headers: &HeaderMap) -> Result<(), ApiError> {
+    let token = headers
+        .get("x-gitlab-token")
+        .and_then(|value| value.to_str().ok())
+        .ok_or_else(|| ApiError::unauthorized("Missing X-Gitlab-Token header"))?;
+    if !constant_time_eq(token, secret) {
+        return Err(ApiError::unauthorized("Webhook token does not match"));
+    }
+    Ok(())
+}
+
+fn mac(secret: &str, body: &[u8]) -> Result<Hmac<Sha256>, ApiError> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|e| ApiError::internal(e.to_string()))?;
+    mac.update(body);
+    Ok(mac)
+}
+
+#[derive(Deserialize)]
+struct GithubRepository {
+    full_name: String,
+}
+
+#[derive(Deserialize)]
+struct GithubUser {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct GithubPush {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    after: String,
+    #[serde(default)]
+    deleted: bool,
+    repository: GithubRepository,
+    sender: Option<GithubUser>,
+}
+
+#[derive(Deserialize)]
+struct GithubPullRequestEvent {
+    action: String,
+    number: u64,
+    pull_request: GithubPullRequest,
+    repository: GithubRepository,
+    sender: Option<GithubUser>,
+}
+
+#[derive(Deserialize)]
+struct GithubPullRequest {
+    head: GithubCommitRef,
+    base: GithubBranchRef,
+}
+
+#[derive(Deserialize)]
+struct GithubCommitRef {
+    sha: String,
+}
+
+#[derive(Deserialize)]
+struct GithubBranchRef {
+    #[serde(rename = "ref")]
+    git_ref: String,
+}
+
+/// Turn a GitHub delivery into a trigger. Events that should not start runs
+/// (pings, tags, deleted branches, closed or relabelled pull requests) give `None`.
+pub fn parse_github_event(event: &str, body: &[u8]) -> Result<Option<RunTrigger>, ApiError> {
+    match event {
+        "push" => {
+            let push: GithubPush = parse_payload(body)?;
+            let Some(branch) = push.git_ref.strip_prefix("refs/heads/") else {
+                return Ok(None);
+            };
+            if push.deleted {
+                return Ok(None);
+            }
+            Ok(Some(RunTrigger {
+                provider: "github".to_string(),
+                event: GitEventKind::Push,
+                repository: push.repository.full_name,
+                branch: branch.to_string(),
+                commit_sha: push.after,
+                pull_request: None,
+                sender: push.sender.map(|user| user.login),
+                rule_id: None,
+            }))
+        }
+        "pull_request" => {
+            let event: GithubPullRequestEvent = parse_payload(body)?;
+            if !matches!(event.action.as_str(), "opened" | "synchronize" | "reopened") {
+                return Ok(None);
+            }
+            Ok(Some(RunTrigger {
+                provider: "github".to_string(),
+                event: GitEventKind::PullRequest,
+                repository: event.repository.full_name,
+                branch: event.pull_request.base.git_ref,
+                commit_sha: event.pull_request.head.sha,
+                pull_request: Some(event.number),
+                sender: event.sender.map(|user| user.login),
+                rule_id: None,
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "object_kind", rename_all = "snake_case")]
+enum GitlabEvent {
+    Push(GitlabPush),
+    MergeRequest(GitlabMergeRequest),
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct GitlabProject {
+    path_with_namespace: String,
+}
+
+#[derive(Deserialize)]
+struct GitlabPush {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    /// Null when the branch was deleted
+    checkout_sha: Option<String>,
+    user_username: Option<String>,
+    project: GitlabProject,
+}
+
+#[derive(Deserialize)]
+struct GitlabMergeRequest {
+    user: Option<GitlabUser>,
+    project: GitlabProject,
+    object_attributes: GitlabMergeRequestAttributes,
+}
+
+#[derive(Deserialize)]
+struct GitlabUser {
+    username: String,
+}
+
+#[derive(Deserialize)]
+struct GitlabMergeRequestAttributes {
+    iid: u64,
+    action: Option<String>,
+    target_branch: String,
+    last_commit: GitlabCommit,
+    /// Only set on updates that pushed new commits
+    oldrev: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct GitlabCommit {
+    id: String,
+}
+
+/// Turn a GitLab delivery into a trigger, like [`parse_github_event`]
+pub fn parse_gitlab_event(body: &[u8]) -> Result<Option<RunTrigger>, ApiError> {
+    match parse_payload(body)? {
+        GitlabEvent::Push(push) => {
+            let (Some(branch), Some(commit_sha)) =
+                (push.git_ref.strip_prefix("refs/heads/"), push.checkout_sha)
+            else {
+                return Ok(None);
+            };
+            Ok(Some(RunTrigger {
+                provider: "gitlab".to_string(),
+                event: GitEventKind::Push,
+                repository: push.project.path_with_namespace,
+                branch: branch.to_string(),
+                commit_sha,
+                pull_request: None,
+                sender: push.user_username,
+                rule_id: None,
+            }))
+        }
+        GitlabEvent::MergeRequest(event) => {
+            let attributes = event.object_attributes;
+            let relevant = match attributes.action.as_deref() {
+                Some("open" | "reopen") => true,
+                Some("update") => attributes.oldrev.is_some(),
+                _ => false,
+            };
+            if !relevant {
+                return Ok(None);
+            }
+            Ok(Some(RunTrigger {
+                provider: "gitlab".to_string(),
+                event: GitEventKind::PullRequest,
+                repository: event.project.path_with_namespace,
+                branch: attributes.target_branch,
+                commit_sha: attributes.last_commit.id,
+                pull_request: Some(attributes.iid),
+                sender: event.user.map(|user| user.username),
+                rule_id: None,
+            }))
+        }
+        GitlabEvent::Other => Ok(None),
+    }
+}
+
+fn parse_payload<'a, T: Deserialize<'a>>(body: &'a [u8]) -> Result<T, ApiError> {
+    serde_json::from_slice(body)
+        .map_err(|e| ApiError::validation(format!("Unexpected webhook payload: {e}")))
+}
+
+/// Repositories are compared case-insensitively, as Git hosts treat them
+pub fn rule_matches(rule: &TriggerRule, trigger: &RunTrigger) -> bool {
+    glob_match(
+        &rule.repository.to_ascii_lowercase(),
+        &trigger.repository.to_ascii_lowercase(),
+    ) && (rule.events.is_empty() || rule.events.contains(&trigger.event))
+        && (rule.branches.is_empty()
+            || rule
+                .branches
+                .iter()
+                .any(|pattern| glob_match(pattern, &trigger.branch)))
+}
+
+/// What a webhook delivery started
+#[derive(Debug, Serialize)]
+pub struct WebhookResponse {
+    /// `None` when the event is ignored
+    pub trigger: Option<RunTrigger>,
+    pub runs: Vec<TestRun>,
+    /// Matching rules that could not start their runs
+    pub errors: Vec<TriggerError>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TriggerError {
+    pub rule_id: Uuid,
+    pub message: String,
+}
+
+/// Start the runs of every rule the trigger matches. One failing rule, for
+/// example one refused by the admission policy, does not stop the others.
+pub async fn dispatch_trigger(state: &AppState, trigger: Option<RunTrigger>) -> WebhookResponse {
+    let mut response = WebhookResponse {
+        trigger: trigger.clone(),
+        runs: Vec::new(),
+        errors: Vec::new(),
+    };
+    let Some(trigger) = trigger else {
+        return response;
+    };
+
+    for rule in state.store.list_trigger_rules().await {
+        if !rule_matches(&rule, &trigger) {
+            continue;
+        }
+        let trigger = RunTrigger {
+            rule_id: Some(rule.id),
+            ..trigger.clone()
+        };
+        match launch_rule(state, &rule, trigger).await {
+            Ok(runs) => response.runs.extend(runs),
+            Err(e) => response.errors.push(TriggerError {
+                rule_id: rule.id,
+                message: e.message,
+            }),
+        }
+    }
+    response
+}
+
+async fn launch_rule(
+    state: &AppState,
+    rule: &TriggerRule,
+    trigger: RunTrigger,
+) -> Result<Vec<TestRun>, ApiError> {
+    if let Some(id) = rule.test_definition_id {
+        let definition = state
+            .store
+            .get_definition(id)
+            .await
+            .ok_or_else(|| ApiError::not_found(format!("Test definition {id} not found")))?;
+        return Ok(vec![
+            launch_definition(state, &definition, trigger.into()).await?,
+        ]);
+    }
+    if let Some(id) = rule.test_suite_id {
+        let suite = state
+            .store
+            .get_suite(id)
+            .await
+            .ok_or_else(|| ApiError::not_found(format!("Test suite {id} not found")))?;
+        return launch_suite(state, &suite, trigger.into()).await;
+    }
+    Ok(Vec::new())
+}
+
+#[derive(sqlx::FromRow)]
+struct TriggerRuleRow {
+    id: Uuid,
+    name: String,
+    repository: String,
+    branches: Vec<String>,
+    events: Vec<String>,
+    test_definition_id: Option<Uuid>,
+    test_suite_id: Option<Uuid>,
+    team_id: Option<Uuid>,
+    created_at: DateTime<Utc>,
+}
+
+/// Restore the trigger rules stored in the database by a previous process,
+/// returning how many. Without a database rules only live in memory.
+pub async fn load_trigger_rules(state: &AppState) -> Result<usize, sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(0);
+    };
+    let rows: Vec<TriggerRuleRow> = traced(
+        "SELECT id, name, repository, branches, events, test_definition_id, test_suite_id, \
+         team_id, created_at FROM trigger_rules",
+        |sql| sqlx::query_as(sql).fetch_all(db),
+    )
+    .await?;
+
+    let count = rows.len();
+    for row in rows {
+        state
+            .store
+            .insert_trigger_rule(TriggerRule {
+                id: row.id,
+                name: row.name,
+                repository: row.repository,
+                branches: row.branches,
+                events: row
+                    .events
+                    .iter()
+                    .filter_map(|event| parse_event_kind(event))
+                    .collect(),
+                test_definition_id: row.test_definition_id,
+                test_suite_id: row.test_suite_id,
+                team_id: row.team_id,
+                created_at: row.created_at,
+            })
+            .await;
+    }
+    Ok(count)
+}
+
+/// Write a trigger rule to the database, if there is one
+pub async fn save_trigger_rule(state: &AppState, rule: &TriggerRule) -> Result<(), sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    let events: Vec<&str> = rule.events.iter().copied().map(event_kind_name).collect();
+    traced(
+        "INSERT INTO trigger_rules (id, name, repository, branches, events, \
+         test_definition_id, test_suite_id, team_id, created_at) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9) \
+         ON CONFLICT (id) DO UPDATE SET name = $2, repository = $3, branches = $4, \
+         events = $5, test_definition_id = $6, test_suite_id = $7, team_id = $8",
+        |sql| {
+            sqlx::query(sql)
+                .bind(rule.id)
+                .bind(&rule.name)
+                .bind(&rule.repository)
+                .bind(&rule.branches)
+                .bind(&events)
+                .bind(rule.test_definition_id)
+                .bind(rule.test_suite_id)
+                .bind(rule.team_id)
+                .bind(rule.created_at)
+                .execute(db)
+        },
+    )
+    .await?;
+    Ok(())
+}
+
+/// Remove a trigger rule from the database, if there is one
+pub async fn delete_saved_trigger_rule(state: &AppState, id: Uuid) -> Result<(), sqlx::Error> {
+    let Some(db) = &state.db else {
+        return Ok(());
+    };
+    traced("DELETE FROM trigger_rules WHERE id = $1", |sql| {
+        sqlx::query(sql).bind(id).execute(db)
+    })
+    .await?;
+    Ok(())
+}
+
+fn event_kind_name(kind: GitEventKind) -> &'static str {
+    match kind {
+        GitEventKind::Push => "push",
+        GitEventKind::PullRequest => "pull_request",
+    }
+}
+
+fn parse_event_kind(name: &str) -> Option<GitEventKind> {
+    match name {
+        "push" => Some(GitEventKind::Push),
+        "pull_request" => Some(GitEventKind::PullRequest),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::test_database;
+    use axum::http::HeaderValue;
+    use chrono::Utc;
+
+    fn sign(secret: &str, body: &[u8]) -> String {
+        let signature = mac(secret, body).unwrap().finalize().into_bytes();
+        format!("sha256={}", hex::encode(signature))
+    }
+
+    #[test]
+    fn test_github_signature() {
+        let body = br#"{"zen":"Keep it logically awesome."}"#;
+        let mut headers = HeaderMap::new();
+        assert!(verify_github_signature("s3cret", &headers, body).is_err());
+
+        headers.insert(
+            "x-hub-signature-256",
+            HeaderValue::from_str(&sign("s3cret", body)).unwrap(),
+        );
+        assert!(verify_github_signature("s3cret", &headers, body).is_ok());
+        assert!(verify_github_signature("other", &headers, body).is_err());
+        assert!(verify_github_signature("s3cret", &headers, b"{}").is_err());
+    }
+
+    #[test]
+    fn test_gitlab_token() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-gitlab-token", HeaderValue::from_static("s3cret"));
+        assert!(verify_gitlab_token("s3cret", &headers).is_ok());
+        assert!(verify_gitlab_token("s3cret2", &headers).is_err());
+        assert!(verify_gitlab_token("s3cret", &HeaderMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_parse_github_events() {
+        let push = serde_json::json!({
+            "ref": "refs/heads/main",
+            "after": "6dcb09b5b57875f334f61aebed695e2e4193db5e",
+            "deleted": false,
+            "repository": { "full_name": "example/app" },
+            "sender": { "login": "octocat" },
+        });
+        let trigger = parse_github_event("push", push.to_string().as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(trigger.event, GitEventKind::Push);
+        assert_eq!(trigger.branch, "main");
+        assert_eq!(
+            trigger.commit_sha,
+            "6dcb09b5b57875f334f61aebed695e2e4193db5e"
+        );
+        assert_eq!(trigger.sender.as_deref(), Some("octocat"));
+
+        let mut tag = push.clone();
+        tag["ref"] = "refs/tags/v1.0.0".into();
+        assert!(parse_github_event("push", tag.to_string().as_bytes())
+            .unwrap()
+            .is_none());
+
+        let pull_request = serde_json::json!({
+            "action": "synchronize",
+            "number": 247,
+            "pull_request": {
+                "head": { "sha": "e5bd3914e2e596debea16f433f57875b5b90bcd6" },
+                "base": { "ref": "main" },
+            },
+            "repository": { "full_name": "example/app" },
+            "sender": { "login": "octocat" },
+        });
+        let trigger = parse_github_event("pull_request", pull_request.to_string().as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(trigger.pull_request, Some(247));
+        assert_eq!(trigger.describe(), "PR #247");
+        assert_eq!(
+            trigger.commit_sha,
+            "e5bd3914e2e596debea16f433f57875b5b90bcd6"
+        );
+
+        let mut closed = pull_request.clone();
+        closed["action"] = "closed".into();
+        assert!(
+            parse_github_event("pull_request", closed.to_string().as_bytes())
+                .unwrap()
+                .is_none()
+        );
+        assert!(parse_github_event("ping", b"{}").unwrap().is_none());
+        assert!(parse_github_event("push", b"{}").is_err());
+    }
+
+    #[test]
+    fn test_parse_gitlab_events() {
+        let push = serde_json::json!({
+            "object_kind": "push",
+            "ref": "refs/heads/release/1.2",
+            "checkout_sha": "da1560886d4f094c3e6c9ef40349f7d38b5d27d7",
+            "user_username": "jsmith",
+            "project": { "path_with_namespace": "group/app" },
+        });
+        let trigger = parse_gitlab_event(push.to_string().as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(trigger.provider, "gitlab");
+        assert_eq!(trigger.branch, "release/1.2");
+
+        let merge_request = serde_json::json!({
+            "object_kind": "merge_request",
+            "user": { "username": "jsmith" },
+            "project": { "path_with_namespace": "group/app" },
+            "object_attributes": {
+                "iid": 12,
+                "action": "update",
+                "target_branch": "main",
+                "last_commit": { "id": "b83d6e391c22777fca1ed3012fce84f633d7fed0" },
+                "oldrev": "da1560886d4f094c3e6c9ef40349f7d38b5d27d7",
+            },
+        });
+        let trigger = parse_gitlab_event(merge_request.to_string().as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(trigger.event, GitEventKind::PullRequest);
+        assert_eq!(trigger.pull_request, Some(12));
+
+        // Title edits don't push commits
+        let mut edited = merge_request.clone();
+        edited["object_attributes"]
+            .as_object_mut()
+            .unwrap()
+            .remove("oldrev");
+        assert!(parse_gitlab_event(edited.to_string().as_bytes())
+            .unwrap()
+            .is_none());
+        assert!(parse_gitlab_event(br#"{"object_kind":"pipeline"}"#)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_rule_matching() {
+        let rule = TriggerRule {
+            id: Uuid::new_v4(),
+            name: "Release branches".to_string(),
+            repository: "Example/*".to_string(),
+            branches: vec!["main".to_string(), "release/*".to_string()],
+            events: vec![GitEventKind::Push],
+            test_definition_id: Some(Uuid::new_v4()),
+            test_suite_id: None,
+            team_id: None,
+            created_at: Utc::now(),
+        };
+        let trigger = RunTrigger {
+            provider: "github".to_string(),
+            event: GitEventKind::Push,
+            repository: "example/app".to_string(),
+            branch: "release/1.2".to_string(),
+            commit_sha: "abc".to_string(),
+            pull_request: None,
+            sender: None,
+            rule_id: None,
+        };
+        assert!(rule_matches(&rule, &trigger));
+
+        let feature = RunTrigger {
+            branch: "feature/login".to_string(),
+            ..trigger.clone()
+        };
+        assert!(!rule_matches(&rule, &feature));
+        let pull_request = RunTrigger {
+            event: GitEventKind::PullRequest,
+            ..trigger.clone()
+        };
+        assert!(!rule_matches(&rule, &pull_request));
+        let other_owner = RunTrigger {
+            repository: "someone/app".to_string(),
+            ..trigger
+        };
+        assert!(!rule_matches(&rule, &other_owner));
+    }
+
+    #[tokio::test]
+    async fn test_trigger_rules_survive_a_restart() {
+        let Some(db) = test_database().await else {
+            return;
+        };
+        let state = AppState {
+            db: Some(db.clone()),
+            ..AppState::default()
+        };
+        let rule = TriggerRule {
+            id: Uuid::new_v4(),
+            name: "Release branches".to_string(),
+            repository: "example/app".to_string(),
+            branches: vec!["release/*".to_string()],
+            events: vec![GitEventKind::Push, GitEventKind::PullRequest],
+            test_definition_id: Some(Uuid::new_v4()),
+            test_suite_id: None,
+            team_id: Some(Uuid::new_v4()),
+            created_at: Utc::now(),
+        };
+        save_trigger_rule(&state, &rule).await.unwrap();
+
+        let restarted = AppState {
+            db: Some(db.clone()),
+            ..AppState::default()
+        };
+        load_trigger_rules(&restarted).await.unwrap();
+        let loaded = restarted.store.get_trigger_rule(rule.id).await.unwrap();
+        assert_eq!(loaded.branches, rule.branches);
+        assert_eq!(loaded.events, rule.events);
+        assert_eq!(loaded.test_definition_id, rule.test_definition_id);
+        assert_eq!(loaded.team_id, rule.team_id);
+
+        delete_saved_trigger_rule(&restarted, rule.id)
+            .await
+            .unwrap();
+        let restarted = AppState {
+            db: Some(db),
+            ..AppState::default()
+        };
+        load_trigger_rules(&restarted).await.unwrap();
+        assert!(restarted.store.get_trigger_rule(rule.id).await.is_none());
+    }
+}
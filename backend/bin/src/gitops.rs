@@ -0,0 +1,398 @@
+//! Keeps SparkTest in step with the `TestDefinition`, `TestSuite` and `TestRun`
+//! resources declared in the cluster
+
+use futures::{future, StreamExt};
+use kube::api::{Api, Patch, PatchParams};
+use kube::core::NamespaceResourceScope;
+use kube::runtime::controller::{Action, Controller};
+use kube::runtime::finalizer::{finalizer, Event};
+use kube::runtime::watcher;
+use kube::{Client, CustomResourceExt, Resource, ResourceExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sparktest_api::{
+    apply_definition, apply_suite, run_status, start_runs, AppState, RunSource, SyncStatus,
+    TestDefinitionResource, TestRunResource, TestRunStatus, TestSuiteResource,
+};
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Removes the definition or suite from SparkTest before its resource goes away
+const FINALIZER: &str = "sparktest.dev/cleanup";
+/// Field manager of the status patches
+const MANAGER: &str = "sparktest";
+/// How soon unfinished test runs and suites waiting on their definitions are looked at again
+const RECHECK: Duration = Duration::from_secs(5);
+/// How soon a resource is retried after the Kubernetes API failed
+const RETRY: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Default)]
+pub struct GitOpsConfig {
+    pub enabled: bool,
+    /// Only watch this namespace; all namespaces when unset
+    pub namespace: Option<String>,
+}
+
+impl GitOpsConfig {
+    /// Off unless `SPARKTEST_GITOPS=true`; `SPARKTEST_GITOPS_NAMESPACE` limits it to one namespace
+    pub fn from_env() -> Self {
+        Self {
+            enabled: std::env::var("SPARKTEST_GITOPS")
+                .map(|v| v.eq_ignore_ascii_case("true") || v == "1")
+                .unwrap_or(false),
+            namespace: std::env::var("SPARKTEST_GITOPS_NAMESPACE")
+                .ok()
+                .filter(|namespace| !namespace.trim().is_empty()),
+        }
+    }
+}
+
+/// The CustomResourceDefinitions as a multi-document YAML stream for `kubectl apply -f -`
+pub fn crd_manifests() -> anyhow::Result<String> {
+    let mut manifests = String::new();
+    for crd in [
+        TestDefinitionResource::crd(),
+        TestSuiteResource::crd(),
+        TestRunResource::crd(),
+    ] {
+        manifests.push_str("---\n");
+        manifests.push_str(&serde_yaml::to_string(&crd)?);
+    }
+    Ok(manifests)
+}
+
+struct Context {
+    state: AppState,
+    client: Client,
+}
+
+#[derive(Debug)]
+struct ReconcileError(anyhow::Error);
+
+impl std::fmt::Display for ReconcileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#}", self.0)
+    }
+}
+
+impl std::error::Error for ReconcileError {}
+
+impl From<kube::Error> for ReconcileError {
+    fn from(error: kube::Error) -> Self {
+        Self(error.into())
+    }
+}
+
+impl From<sqlx::Error> for ReconcileError {
+    fn from(error: sqlx::Error) -> Self {
+        Self(error.into())
+    }
+}
+
+/// Reconcile the three resource kinds until the process exits
+pub async fn run_controllers(state: AppState, client: Client, config: GitOpsConfig) {
+    let context = Arc::new(Context {
+        state,
+        client: client.clone(),
+    });
+    let namespace = config.namespace.as_deref();
+
+    let definitions = Controller::new(
+        watched::<TestDefinitionResource>(&client, namespace),
+        watcher::Config::default(),
+    )
+    .run(reconcile_definition, retry_later, context.clone())
+    .for_each(|result| {
+        log_failure("TestDefinition", result);
+        future::ready(())
+    });
+    let suites = Controller::new(
+        watched::<TestSuiteResource>(&client, namespace),
+        watcher::Config::default(),
+    )
+    .run(reconcile_suite, retry_later, context.clone())
+    .for_each(|result| {
+        log_failure("TestSuite", result);
+        future::ready(())
+    });
+    let runs = Controller::new(
+        watched::<TestRunResource>(&client, namespace),
+        watcher::Config::default(),
+    )
+    .run(reconcile_test_run, retry_later, context)
+    .for_each(|result| {
+        log_failure("TestRun", result);
+        future::ready(())
+    });
+
+    tracing::info!(
+        "Syncing SparkTest resources in {}",
+        namespace.map_or("all namespaces".to_string(), |ns| format!("namespace {ns}"))
+    );
+    futures::join!(definitions, suites, runs);
+}
+
+fn watched<K>(client: &Client, namespace: Option<&str>) -> Api<K>
+where
+    K: Resource<Scope = NamespaceResourceScope>,
+    K::DynamicType: Default,
+{
+    match namespace {
+        Some(namespace) => Api::namespaced(client.clone(), namespace),
+        None => Api::all(client.clone()),
+    }
+}
+
+fn log_failure<T, E: std::fmt::Display>(kind: &str, result: Result<T, E>) {
+    if let Err(e) = result {
+        tracing::warn!("Failed to reconcile {kind}: {e}");
+    }
+}
+
+fn retry_later<K>(_: Arc<K>, error: &ReconcileError, _: Arc<Context>) -> Action {
+    tracing::warn!("Retrying in {}s: {error}", RETRY.as_secs());
+    Action::requeue(RETRY)
+}
+
+/// The API handle for `K` in the namespace of `resource`
+fn namespaced<K>(client: &Client, resource: &impl Resource) -> Api<K>
+where
+    K: Resource<Scope = NamespaceResourceScope>,
+    K::DynamicType: Default,
+{
+    Api::namespaced(client.clone(), &resource.namespace().unwrap_or_default())
+}
+
+/// Definitions and suites take their resource's UID as their id in SparkTest, and
+/// runs record the UID of the test run resource that started them
+fn resource_id<K: Resource>(resource: &K) -> Result<Uuid, ReconcileError> {
+    resource
+        .meta()
+        .uid
+        .as_deref()
+        .and_then(|uid| uid.parse().ok())
+        .ok_or_else(|| ReconcileError(anyhow::anyhow!("Resource has no UID")))
+}
+
+/// Write the status unless it is already what the resource reports
+async fn patch_status<K, S>(
+    api: &Api<K>,
+    resource: &K,
+    current: Option<&S>,
+    status: &S,
+) -> Result<(), ReconcileError>
+where
+    K: Resource + Clone + DeserializeOwned + Debug,
+    S: Serialize + PartialEq,
+{
+    if current == Some(status) {
+        return Ok(());
+    }
+    api.patch_status(
+        &resource.name_any(),
+        &PatchParams::apply(MANAGER),
+        &Patch::Merge(serde_json::json!({ "status": status })),
+    )
+    .await?;
+    Ok(())
+}
+
+fn sync_status<K: Resource>(resource: &K, result: Result<Uuid, String>) -> SyncStatus {
+    let observed_generation = resource.meta().generation;
+    match result {
+        Ok(id) => SyncStatus {
+            id: Some(id.to_string()),
+            synced: true,
+            observed_generation,
+            message: None,
+        },
+        Err(message) => SyncStatus {
+            id: None,
+            synced: false,
+            observed_generation,
+            message: Some(message),
+        },
+    }
+}
+
+async fn reconcile_definition(
+    resource: Arc<TestDefinitionResource>,
+    context: Arc<Context>,
+) -> Result<Action, ReconcileError> {
+    let api = namespaced(&context.client, resource.as_ref());
+    finalizer(&api, FINALIZER, resource, |event| async {
+        match event {
+            Event::Apply(resource) => sync_definition(&api, &context, &resource).await,
+            Event::Cleanup(resource) => {
+                let id = resource_id(resource.as_ref())?;
+                context.state.store.remove_definition(id).await?;
+                Ok(Action::await_change())
+            }
+        }
+    })
+    .await
+    .map_err(|e| ReconcileError(e.into()))
+}
+
+async fn sync_definition(
+    api: &Api<TestDefinitionResource>,
+    context: &Context,
+    resource: &TestDefinitionResource,
+) -> Result<Action, ReconcileError> {
+    let id = resource_id(resource)?;
+    let result = apply_definition(&context.state, id, &resource.name_any(), &resource.spec)
+        .await
+        .map(|definition| definition.id)
+        .map_err(|e| e.message);
+    let status = sync_status(resource, result);
+    patch_status(api, resource, resource.status.as_ref(), &status).await?;
+    Ok(Action::await_change())
+}
+
+async fn reconcile_suite(
+    resource: Arc<TestSuiteResource>,
+    context: Arc<Context>,
+) -> Result<Action, ReconcileError> {
+    let api = namespaced(&context.client, resource.as_ref());
+    finalizer(&api, FINALIZER, resource, |event| async {
+        match event {
+            Event::Apply(resource) => sync_suite(&api, &context, &resource).await,
+            Event::Cleanup(resource) => {
+                let id = resource_id(resource.as_ref())?;
+                context.state.store.remove_suite(id).await?;
+                Ok(Action::await_change())
+            }
+        }
+    })
+    .await
+    .map_err(|e| ReconcileError(e.into()))
+}
+
+async fn sync_suite(
+    api: &Api<TestSuiteResource>,
+    context: &Context,
+    resource: &TestSuiteResource,
+) -> Result<Action, ReconcileError> {
+    let id = resource_id(resource)?;
+    let definitions: Api<TestDefinitionResource> = namespaced(&context.client, resource);
+    let mut definition_ids = Vec::new();
+    let mut waiting = Vec::new();
+    for name in &resource.spec.definitions {
+        match synced_id(definitions.get_opt(name).await?.and_then(|d| d.status)) {
+            Some(id) => definition_ids.push(id),
+            None => waiting.push(name.as_str()),
+        }
+    }
+
+    // The suite isn't told when its definitions change, so look again soon
+    let (result, action) = if waiting.is_empty() {
+        let result = apply_suite(
+            &context.state,
+            id,
+            &resource.name_any(),
+            &resource.spec,
+            definition_ids,
+        )
+        .await
+        .map(|suite| suite.id)
+        .map_err(|e| e.message);
+        (result, Action::await_change())
+    } else {
+        let message = format!(
+            "Waiting for TestDefinition(s) {} to be synced",
+            waiting.join(", ")
+        );
+        (Err(message), Action::requeue(RECHECK))
+    };
+    let status = sync_status(resource, result);
+    patch_status(api, resource, resource.status.as_ref(), &status).await?;
+    Ok(action)
+}
+
+/// Id of a definition or suite once its resource has been synced
+fn synced_id(status: Option<SyncStatus>) -> Option<Uuid> {
+    status.filter(|status| status.synced)?.id?.parse().ok()
+}
+
+async fn reconcile_test_run(
+    resource: Arc<TestRunResource>,
+    context: Arc<Context>,
+) -> Result<Action, ReconcileError> {
+    let current = resource.status.clone().unwrap_or_default();
+    if current.is_finished() {
+        return Ok(Action::await_change());
+    }
+
+    let status = if current.run_ids.is_empty() {
+        let uid = resource_id(resource.as_ref())?.to_string();
+        match resolve_run_source(&context, &resource).await? {
+            Ok(source) => match start_runs(&context.state, &uid, source).await {
+                Ok(runs) => {
+                    let ids: Vec<Uuid> = runs.iter().map(|run| run.id).collect();
+                    run_status(&context.state, &ids).await
+                }
+                Err(e) => failed(e.message),
+            },
+            Err(status) => status,
+        }
+    } else {
+        let ids: Vec<Uuid> = current
+            .run_ids
+            .iter()
+            .filter_map(|id| id.parse().ok())
+            .collect();
+        run_status(&context.state, &ids).await
+    };
+
+    let api = namespaced(&context.client, resource.as_ref());
+    patch_status(&api, resource.as_ref(), resource.status.as_ref(), &status).await?;
+    Ok(if status.is_finished() {
+        Action::await_change()
+    } else {
+        Action::requeue(RECHECK)
+    })
+}
+
+fn failed(message: String) -> TestRunStatus {
+    TestRunStatus {
+        phase: Some("failed".to_string()),
+        message: Some(message),
+        ..TestRunStatus::default()
+    }
+}
+
+/// What the test run asks for, or the status to report while it can't be started
+async fn resolve_run_source(
+    context: &Context,
+    resource: &TestRunResource,
+) -> Result<Result<RunSource, TestRunStatus>, ReconcileError> {
+    let (kind, name, source) = match (&resource.spec.definition, &resource.spec.suite) {
+        (Some(name), None) => {
+            let api: Api<TestDefinitionResource> = namespaced(&context.client, resource);
+            let status = api.get_opt(name).await?.and_then(|d| d.status);
+            (
+                "TestDefinition",
+                name,
+                synced_id(status).map(RunSource::Definition),
+            )
+        }
+        (None, Some(name)) => {
+            let api: Api<TestSuiteResource> = namespaced(&context.client, resource);
+            let status = api.get_opt(name).await?.and_then(|s| s.status);
+            ("TestSuite", name, synced_id(status).map(RunSource::Suite))
+        }
+        _ => {
+            return Ok(Err(failed(
+                "Set exactly one of definition or suite".to_string(),
+            )))
+        }
+    };
+
+    Ok(source.ok_or_else(|| TestRunStatus {
+        phase: Some("pending".to_string()),
+        message: Some(format!("Waiting for {kind} '{name}' to be synced")),
+        ..TestRunStatus::default()
+    }))
+}
@@ -1,7 +1,19 @@
-use sparktest_api::create_app;
+mod gitops;
+
+use anyhow::Context;
+use gitops::{crd_manifests, run_controllers, GitOpsConfig};
+use sparktest_api::{
+    backend_from_env, create_app_with_state, init_tracer_provider, load_admission_policy,
+    otel_layer, run_job_controllers, AdmissionPolicy, AppState, AuditConfig, AuditLog, AuthConfig,
+    FileLimits, OidcConfig, OidcValidator, QueueConfig, RunQueue, RunnerConfig, SchedulerConfig,
+    SecretKey, StatusReportingConfig, Store, TargetRegistry, TelemetryConfig, TokenStore,
+    WebhookConfig,
+};
+use sparktest_core::TokenScope;
 use sqlx::{postgres::PgPoolOptions, sqlite::SqlitePoolOptions, Pool, Postgres, Sqlite};
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
+use tracing::Instrument;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[allow(dead_code)]
@@ -10,19 +22,54 @@ enum DatabasePool {
     Sqlite(Pool<Sqlite>),
 }
 
+/// Name of the admin token registered or generated on start
+const BOOTSTRAP_TOKEN: &str = "bootstrap-admin";
+
+/// Write a secret to a file only the server's user can read
+fn write_secret_file(path: &str, secret: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    #[cfg(unix)]
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(path)?;
+    // `mode` only applies to new files
+    #[cfg(unix)]
+    file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    writeln!(file, "{secret}")
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    // Initialize tracing
+    // `sparktest-bin crds | kubectl apply -f -` installs the custom resources
+    if std::env::args().nth(1).as_deref() == Some("crds") {
+        print!("{}", crd_manifests()?);
+        return Ok(());
+    }
+
+    // Load environment variables
+    dotenvy::dotenv().ok();
+
+    // Initialize tracing, exporting spans over OTLP when a collector is configured
+    let tracer_provider = init_tracer_provider(&TelemetryConfig::from_env()?)?;
     tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG")
-                .unwrap_or_else(|_| "sparktest_bin=debug,tower_http=debug".into()),
+            std::env::var("RUST_LOG").unwrap_or_else(|_| {
+                "sparktest_bin=debug,sparktest_api=info,tower_http=debug".into()
+            }),
         ))
         .with(tracing_subscriber::fmt::layer())
+        .with(tracer_provider.as_ref().map(otel_layer))
         .init();
-
-    // Load environment variables
-    dotenvy::dotenv().ok();
+    if tracer_provider.is_some() {
+        tracing::info!("Exporting traces over OTLP");
+    }
 
     // Get database URL from environment
     let database_url =
@@ -31,7 +78,7 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("Connecting to database: {}", database_url);
 
     // Connect to database based on URL scheme
-    let _pool =
+    let pool =
         if database_url.starts_with("postgresql://") || database_url.starts_with("postgres://") {
             tracing::info!("Using PostgreSQL database");
             let pg_pool = PgPoolOptions::new()
@@ -43,6 +90,7 @@ async fn main() -> anyhow::Result<()> {
             // Run PostgreSQL migrations
             sqlx::migrate!("./migrations")
                 .run(&pg_pool)
+                .instrument(tracing::info_span!("db.migrate", db.system = "postgresql"))
                 .await
                 .expect("Failed to run PostgreSQL migrations");
 
@@ -65,8 +113,166 @@ async fn main() -> anyhow::Result<()> {
             DatabasePool::Sqlite(sqlite_pool)
         };
 
+    // Pick the execution backend (Kubernetes, or Docker/Podman on developer machines)
+    let backend = backend_from_env()?;
+    tracing::info!("Using {} execution backend", backend.name());
+
+    let mut state = AppState::new(backend);
+    // State that has to survive restarts is kept in PostgreSQL; with SQLite it
+    // only lives as long as the process
+    let db = match &pool {
+        DatabasePool::Postgres(pool) => Some(pool.clone()),
+        DatabasePool::Sqlite(_) => None,
+    };
+    state.db = db.clone();
+    state.secret_key = SecretKey::from_env()?;
+    if let Some(db) = &db {
+        state.store = Store::load(db.clone())
+            .await
+            .context("Failed to load definitions, suites and runs")?;
+        state.targets = TargetRegistry::load(db.clone())
+            .await
+            .context("Failed to load execution targets")?;
+        state.tokens = TokenStore::load(db.clone())
+            .await
+            .context("Failed to load API tokens")?;
+    }
+    state.auth = AuthConfig::from_env();
+    state.runner = RunnerConfig::from_env();
+    state.audit = match &db {
+        Some(db) => AuditLog::with_database(AuditConfig::from_env(), db.clone()),
+        None => AuditLog::new(AuditConfig::from_env()),
+    };
+    // A policy replaced through the API takes over from the variables
+    let policy = AdmissionPolicy::from_env()?;
+    *state.policy.write().await = match load_admission_policy(&state)
+        .await
+        .context("Failed to load the admission policy")?
+    {
+        Some(stored) => {
+            if stored != policy {
+                tracing::info!(
+                    "Using the admission policy replaced through the API; the SPARKTEST_* policy variables are ignored"
+                );
+            }
+            stored
+        }
+        None => policy,
+    };
+    state.files = FileLimits::from_env();
+    state.webhooks = WebhookConfig::from_env();
+    state.reporting = StatusReportingConfig::from_env();
+    state.scheduler = SchedulerConfig::from_env();
+    state.queue = RunQueue::new(QueueConfig::from_env());
+    if let Some(oidc) = OidcConfig::from_env()? {
+        tracing::info!("Accepting OIDC tokens issued by {}", oidc.issuer);
+        state.oidc = Some(OidcValidator::new(oidc));
+    }
+
+    // Bootstrap an admin token so the API is usable on first start
+    if !state.auth.enabled {
+        tracing::warn!("API authentication is disabled (SPARKTEST_AUTH_DISABLED)");
+    } else if let Ok(secret) = std::env::var("SPARKTEST_ADMIN_TOKEN") {
+        let token = state
+            .tokens
+            .insert(
+                BOOTSTRAP_TOKEN.to_string(),
+                None,
+                TokenScope::Admin,
+                None,
+                &secret,
+            )
+            .await
+            .context("Failed to register SPARKTEST_ADMIN_TOKEN")?;
+        if token.revoked_at.is_some() {
+            tracing::warn!("SPARKTEST_ADMIN_TOKEN has been revoked; set a new secret");
+        }
+        // Rotating the variable retires the secret it replaced
+        for previous in state.tokens.list().await {
+            if previous.name == BOOTSTRAP_TOKEN && previous.id != token.id {
+                state.tokens.revoke(previous.id).await?;
+            }
+        }
+        tracing::info!("Registered admin token from SPARKTEST_ADMIN_TOKEN");
+    } else if state
+        .tokens
+        .list()
+        .await
+        .iter()
+        .any(|token| token.scope == TokenScope::Admin && token.is_live(chrono::Utc::now()))
+    {
+        tracing::info!("SPARKTEST_ADMIN_TOKEN is not set; using the stored admin tokens");
+    } else {
+        // The secret never goes to the log, which may be shipped elsewhere
+        let path = std::env::var("SPARKTEST_ADMIN_TOKEN_FILE")
+            .unwrap_or_else(|_| "../data/admin-token".to_string());
+        let (_, secret) = state
+            .tokens
+            .create(BOOTSTRAP_TOKEN.to_string(), None, TokenScope::Admin, None)
+            .await?;
+        write_secret_file(&path, &secret).with_context(|| {
+            format!("SPARKTEST_ADMIN_TOKEN is not set and the generated admin token could not be written to {path}")
+        })?;
+        tracing::warn!(
+            "SPARKTEST_ADMIN_TOKEN is not set; wrote a generated admin token to {}",
+            path
+        );
+    }
+
+    // Runs still waiting when the previous process stopped queue up again
+    let resumed = sparktest_api::resume_queued_runs(&state).await;
+    if resumed > 0 {
+        tracing::info!("Resumed {} queued run(s)", resumed);
+    }
+
+    // Start schedules where the previous process left off
+    let restored = sparktest_api::load_schedules(&state)
+        .await
+        .context("Failed to load schedules")?;
+    if restored > 0 {
+        tracing::info!("Restored {} schedule(s)", restored);
+    }
+    tokio::spawn(sparktest_api::run_scheduler(state.clone()));
+
+    let restored = sparktest_api::load_trigger_rules(&state)
+        .await
+        .context("Failed to load trigger rules")?;
+    if restored > 0 {
+        tracing::info!("Restored {} trigger rule(s)", restored);
+    }
+    let restored = sparktest_api::load_status_reporters(&state)
+        .await
+        .context("Failed to load status reporters")?;
+    if restored > 0 {
+        tracing::info!("Restored {} status reporter(s)", restored);
+    }
+    let (channels, rules) = sparktest_api::load_notifications(&state)
+        .await
+        .context("Failed to load notification channels and rules")?;
+    if channels + rules > 0 {
+        tracing::info!(
+            "Restored {} notification channel(s) and {} rule(s)",
+            channels,
+            rules
+        );
+    }
+
+    // Keep runs in step with the Jobs of every target, adopting strays and collecting leftovers
+    if state.backend.name() == "kubernetes" {
+        tokio::spawn(run_job_controllers(state.clone()));
+    }
+
+    // Sync TestDefinition, TestSuite and TestRun resources declared in the cluster
+    let gitops = GitOpsConfig::from_env();
+    if gitops.enabled {
+        let client = kube::Client::try_default()
+            .await
+            .context("SPARKTEST_GITOPS needs access to a Kubernetes cluster")?;
+        tokio::spawn(run_controllers(state.clone(), client, gitops));
+    }
+
     // Create the application
-    let app = create_app();
+    let app = create_app_with_state(state);
 
     // Get port from environment
     let port = std::env::var("PORT")
@@ -80,9 +286,18 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("Server starting on {}", addr);
 
     // Start the server
-    axum::serve(listener, app)
-        .await
-        .expect("Server failed to start");
+    // The audit log records the connecting address
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .expect("Server failed to start");
+
+    // Flush spans still waiting for the next batch
+    if let Some(provider) = tracer_provider {
+        provider.shutdown().ok();
+    }
 
     Ok(())
 }
@@ -8,6 +8,7 @@ pub use models::*;
 mod tests {
     use super::*;
     use chrono::Utc;
+    use std::collections::BTreeMap;
     use uuid::Uuid;
 
     #[tokio::test]
@@ -33,6 +34,21 @@ mod tests {
             container_started: None,
             completed: None,
             failed: None,
+            target: None,
+            team_id: None,
+            resources: None,
+            privileged: false,
+            secrets: Vec::new(),
+            source: None,
+            files: Vec::new(),
+            trigger: None,
+            schedule_id: None,
+            resource_uid: None,
+            triggered_by: None,
+            priority: RunPriority::Normal,
+            job_labels: BTreeMap::new(),
+            job_annotations: BTreeMap::new(),
+            queue_position: None,
         };
 
         assert_eq!(test_run.name, "Test Run");
@@ -52,6 +68,16 @@ mod tests {
             executor_id: Some("executor-1".to_string()),
             labels: Some(vec!["test".to_string()]),
             variables: None,
+            target: Some("staging".to_string()),
+            team_id: None,
+            resources: None,
+            privileged: false,
+            secrets: Vec::new(),
+            source: None,
+            files: Vec::new(),
+            priority: RunPriority::default(),
+            job_labels: BTreeMap::new(),
+            job_annotations: BTreeMap::new(),
         };
 
         assert_eq!(definition.name, "Test Definition");
@@ -70,10 +96,51 @@ mod tests {
             supported_file_types: Some(vec!["json".to_string()]),
             env: None,
             created_at: Utc::now(),
+            team_id: None,
         };
 
         assert_eq!(executor.name, "Test Executor");
         assert!(executor.description.is_some());
         assert_eq!(executor.image, "test:latest");
     }
+
+    #[test]
+    fn test_team_roles() {
+        let user_id = Uuid::new_v4();
+        let team = Team {
+            id: Uuid::new_v4(),
+            name: "payments".to_string(),
+            description: None,
+            members: vec![TeamMember {
+                user_id,
+                role: Role::Runner,
+            }],
+            created_at: Utc::now(),
+        };
+
+        assert_eq!(team.role_of(user_id), Some(Role::Runner));
+        assert_eq!(team.role_of(Uuid::new_v4()), None);
+        assert!(Role::Admin > Role::Maintainer);
+        assert!(Role::Runner > Role::Viewer);
+    }
+
+    #[test]
+    fn test_git_source_accepts_plain_url() {
+        let source: GitSource =
+            serde_json::from_value(serde_json::json!("https://github.com/example/tests")).unwrap();
+        assert_eq!(source.url, "https://github.com/example/tests");
+        assert_eq!(source.git_ref, None);
+
+        let source: GitSource = serde_json::from_value(serde_json::json!({
+            "url": "https://github.com/example/tests",
+            "ref": "v1.2.0",
+            "subdirectory": "e2e"
+        }))
+        .unwrap();
+        assert_eq!(source.git_ref.as_deref(), Some("v1.2.0"));
+        assert_eq!(
+            serde_json::to_value(&source).unwrap()["subdirectory"],
+            "e2e"
+        );
+    }
 }
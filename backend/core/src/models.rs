@@ -1,5 +1,6 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,6 +25,116 @@ pub struct TestRun {
     pub container_started: Option<DateTime<Utc>>,
     pub completed: Option<DateTime<Utc>>,
     pub failed: Option<DateTime<Utc>>,
+    pub target: Option<String>,
+    pub team_id: Option<Uuid>,
+    pub resources: Option<ResourceLimits>,
+    #[serde(default)]
+    pub privileged: bool,
+    #[serde(default)]
+    pub secrets: Vec<SecretRef>,
+    pub source: Option<GitSource>,
+    #[serde(default)]
+    pub files: Vec<TestFile>,
+    /// The Git push or pull request that started the run, if any
+    pub trigger: Option<RunTrigger>,
+    /// The schedule that started the run, if any
+    pub schedule_id: Option<Uuid>,
+    /// UID of the Kubernetes `TestRun` resource that started the run, if any
+    pub resource_uid: Option<String>,
+    /// Who started the run: the user or API token behind the request, or the
+    /// sender of the Git event that triggered it
+    pub triggered_by: Option<String>,
+    #[serde(default)]
+    pub priority: RunPriority,
+    /// Extra labels for the run's Job and pod, next to the ones SparkTest sets
+    #[serde(default)]
+    pub job_labels: BTreeMap<String, String>,
+    /// Extra annotations for the run's Job and pod
+    #[serde(default)]
+    pub job_annotations: BTreeMap<String, String>,
+    /// 1 for the next run to start while the run waits for a free slot; not stored
+    #[serde(default)]
+    pub queue_position: Option<usize>,
+}
+
+impl TestRun {
+    /// A new pending run with no lifecycle information yet
+    pub fn new(name: String, image: String, commands: Vec<String>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            name,
+            image,
+            commands,
+            status: "pending".to_string(),
+            created_at: Utc::now(),
+            definition_id: None,
+            executor_id: None,
+            suite_id: None,
+            variables: None,
+            artifacts: None,
+            duration: None,
+            retries: None,
+            logs: None,
+            k8s_job_name: None,
+            pod_scheduled: None,
+            container_created: None,
+            container_started: None,
+            completed: None,
+            failed: None,
+            target: None,
+            team_id: None,
+            resources: None,
+            privileged: false,
+            secrets: Vec::new(),
+            source: None,
+            files: Vec::new(),
+            trigger: None,
+            schedule_id: None,
+            resource_uid: None,
+            triggered_by: None,
+            priority: RunPriority::default(),
+            job_labels: BTreeMap::new(),
+            job_annotations: BTreeMap::new(),
+            queue_position: None,
+        }
+    }
+
+    /// A new pending run of a definition, inheriting its executor, variables, target
+    /// and container settings
+    pub fn from_definition(definition: &TestDefinition) -> Self {
+        let mut run = Self::new(
+            definition.name.clone(),
+            definition.image.clone(),
+            definition.commands.clone(),
+        );
+        run.definition_id = Some(definition.id);
+        run.executor_id = definition.executor_id.clone();
+        run.variables = definition.variables.clone();
+        run.target = definition.target.clone();
+        run.team_id = definition.team_id;
+        run.resources = definition.resources.clone();
+        run.privileged = definition.privileged;
+        run.secrets = definition.secrets.clone();
+        run.source = definition.source.clone();
+        run.files = definition.files.clone();
+        run.priority = definition.priority;
+        run.job_labels = definition.job_labels.clone();
+        run.job_annotations = definition.job_annotations.clone();
+        run
+    }
+}
+
+/// Order in which waiting runs get a free slot; runs of the same priority
+/// start oldest first
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum RunPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -37,6 +148,101 @@ pub struct TestDefinition {
     pub executor_id: Option<String>,
     pub variables: Option<serde_json::Value>,
     pub labels: Option<Vec<String>>,
+    pub target: Option<String>,
+    pub team_id: Option<Uuid>,
+    pub resources: Option<ResourceLimits>,
+    #[serde(default)]
+    pub privileged: bool,
+    #[serde(default)]
+    pub secrets: Vec<SecretRef>,
+    pub source: Option<GitSource>,
+    #[serde(default)]
+    pub files: Vec<TestFile>,
+    /// Given to the runs started from the definition
+    #[serde(default)]
+    pub priority: RunPriority,
+    /// Extra labels for the Jobs of its runs, e.g. for cost allocation
+    #[serde(default)]
+    pub job_labels: BTreeMap<String, String>,
+    /// Extra annotations for the Jobs of its runs
+    #[serde(default)]
+    pub job_annotations: BTreeMap<String, String>,
+}
+
+/// An uploaded test file (a k6 script, a Postman collection, ...) mounted into
+/// the run's container; the content is served separately
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TestFile {
+    pub name: String,
+    pub size: u64,
+    pub content_type: Option<String>,
+    pub sha256: String,
+    pub uploaded_at: DateTime<Utc>,
+}
+
+/// A Git repository checked out into the run's workspace before the tests start.
+/// Also accepted as a plain URL string, which checks out the default branch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "GitSourceInput")]
+pub struct GitSource {
+    pub url: String,
+    /// Branch, tag or commit SHA; the repository's default branch when unset
+    #[serde(rename = "ref")]
+    pub git_ref: Option<String>,
+    /// Directory inside the repository the tests run from
+    pub subdirectory: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum GitSourceInput {
+    Url(String),
+    Full {
+        url: String,
+        #[serde(rename = "ref", default)]
+        git_ref: Option<String>,
+        #[serde(default)]
+        subdirectory: Option<String>,
+    },
+}
+
+impl From<GitSourceInput> for GitSource {
+    fn from(input: GitSourceInput) -> Self {
+        match input {
+            GitSourceInput::Url(url) => Self {
+                url,
+                git_ref: None,
+                subdirectory: None,
+            },
+            GitSourceInput::Full {
+                url,
+                git_ref,
+                subdirectory,
+            } => Self {
+                url,
+                git_ref,
+                subdirectory,
+            },
+        }
+    }
+}
+
+/// One key of a Kubernetes Secret exposed to a run, either as an environment
+/// variable (`env`) or as a file (`path`). Only the reference is stored; the
+/// value stays in the cluster.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SecretRef {
+    pub secret: String,
+    pub key: String,
+    pub env: Option<String>,
+    pub path: Option<String>,
+}
+
+/// CPU and memory limits for a test container, as Kubernetes quantities ("500m", "1Gi")
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    pub cpu: Option<String>,
+    pub memory: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,6 +255,7 @@ pub struct Executor {
     pub supported_file_types: Option<Vec<String>>,
     pub env: Option<serde_json::Value>,
     pub created_at: DateTime<Utc>,
+    pub team_id: Option<Uuid>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -60,4 +267,334 @@ pub struct TestSuite {
     pub created_at: DateTime<Utc>,
     pub execution_mode: String,
     pub labels: Option<Vec<String>>,
+    pub team_id: Option<Uuid>,
+}
+
+/// A named cluster/namespace pair that runs can be scheduled onto.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExecutionTarget {
+    pub name: String,
+    pub description: Option<String>,
+    pub connection: TargetConnection,
+    pub namespace: String,
+    pub timeout_seconds: u64,
+    pub max_log_lines: Option<i64>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// How the API authenticates against the cluster behind a target.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TargetConnection {
+    /// In-cluster service account first, then the current kubeconfig context
+    Auto,
+    InCluster,
+    Kubeconfig {
+        context: String,
+    },
+}
+
+/// What an API token is allowed to do; each scope includes the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    Read,
+    Run,
+    Admin,
+}
+
+/// An API token as shown by the API; the secret itself is only returned once, at creation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiToken {
+    pub id: Uuid,
+    pub name: String,
+    /// The user the token acts as; tokens without a user are instance-wide service tokens
+    pub user_id: Option<Uuid>,
+    pub scope: TokenScope,
+    pub prefix: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    /// Neither revoked nor expired
+    pub fn is_live(&self, now: DateTime<Utc>) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|at| at > now)
+    }
+}
+
+/// A team member's role; each role includes the permissions of the ones before it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// See the team's definitions, suites, executors and runs
+    Viewer,
+    /// Also launch, cancel and delete the team's runs
+    Runner,
+    /// Also create and change the team's definitions, suites and executors
+    Maintainer,
+    /// Also manage the team's members
+    Admin,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct User {
+    pub id: Uuid,
+    pub name: String,
+    pub email: Option<String>,
+    /// The identity provider's `sub` claim for users signed in through OIDC
+    pub subject: Option<String>,
+    /// Instance administrators can act on every team and manage users, targets and tokens
+    pub is_admin: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TeamMember {
+    pub user_id: Uuid,
+    pub role: Role,
+}
+
+/// A group of users that owns definitions, suites, executors and their runs
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Team {
+    pub id: Uuid,
+    pub name: String,
+    pub description: Option<String>,
+    pub members: Vec<TeamMember>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Team {
+    pub fn role_of(&self, user_id: Uuid) -> Option<Role> {
+        self.members
+            .iter()
+            .find(|member| member.user_id == user_id)
+            .map(|member| member.role)
+    }
+}
+
+/// How a mutating request ended, as recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOutcome {
+    Success,
+    /// Rejected by authentication or authorization
+    Denied,
+    Failed,
+}
+
+/// One entry of the append-only audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub actor: String,
+    pub user_id: Option<Uuid>,
+    pub token_id: Option<Uuid>,
+    /// `<target_type>.<verb>`, e.g. `test_run.create` or `job.delete`
+    pub action: String,
+    pub target_type: String,
+    pub target_id: Option<String>,
+    pub outcome: AuditOutcome,
+    pub status_code: u16,
+    /// The error message for denied and failed requests
+    pub message: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub remote_addr: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: String,
+}
+
+/// Kind of Git event a trigger rule reacts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GitEventKind {
+    Push,
+    PullRequest,
+}
+
+/// Starts a definition or a suite when a repository receives a matching push
+/// or pull request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TriggerRule {
+    pub id: Uuid,
+    pub name: String,
+    /// `owner/repo` as the Git host names it; `*` matches within a path segment
+    pub repository: String,
+    /// Branch patterns, matched against the target branch of pull requests.
+    /// Empty matches every branch.
+    #[serde(default)]
+    pub branches: Vec<String>,
+    /// Empty reacts to every kind of event
+    #[serde(default)]
+    pub events: Vec<GitEventKind>,
+    pub test_definition_id: Option<Uuid>,
+    pub test_suite_id: Option<Uuid>,
+    pub team_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The Git event behind a run, as reported by the Git host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunTrigger {
+    /// `github` or `gitlab`
+    pub provider: String,
+    pub event: GitEventKind,
+    pub repository: String,
+    /// The pushed branch, or the target branch of a pull request
+    pub branch: String,
+    pub commit_sha: String,
+    pub pull_request: Option<u64>,
+    /// Login of whoever pushed or updated the pull request
+    pub sender: Option<String>,
+    pub rule_id: Option<Uuid>,
+}
+
+impl RunTrigger {
+    /// Suffix for the names of runs it starts, e.g. "PR #247" or "main"
+    pub fn describe(&self) -> String {
+        match self.pull_request {
+            Some(number) => format!("PR #{number}"),
+            None => self.branch.clone(),
+        }
+    }
+}
+
+/// Posts the status of triggered runs back to the Git host as commit statuses,
+/// for every repository matching `repository`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatusReporter {
+    pub id: Uuid,
+    pub name: String,
+    /// `github` or `gitlab`; only runs triggered through that provider are reported
+    pub provider: String,
+    /// `owner/repo`; `*` matches within a path segment
+    pub repository: String,
+    /// API base URL, for GitHub Enterprise or self-hosted GitLab
+    pub api_url: Option<String>,
+    /// Name the status is shown under on the commit
+    pub context: String,
+    /// Access token for the Git host's API; accepted on creation, never returned
+    #[serde(default, skip_serializing)]
+    pub token: String,
+    pub team_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Starts a definition or a suite on a cron schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: Uuid,
+    pub name: String,
+    /// Five-field cron expression (minute to day of week), or six fields with
+    /// leading seconds
+    pub cron: String,
+    /// IANA time zone the expression is evaluated in, e.g. `Europe/Oslo`
+    pub timezone: String,
+    pub test_definition_id: Option<Uuid>,
+    pub test_suite_id: Option<Uuid>,
+    pub enabled: bool,
+    pub team_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    /// Occurrences before the schedule was last changed are never started
+    pub updated_at: DateTime<Utc>,
+    /// The most recent occurrence the scheduler has handled, whether or not it
+    /// started runs
+    pub last_fired_at: Option<DateTime<Utc>>,
+    pub last_outcome: Option<ScheduleOutcome>,
+    /// Why the last occurrence did not start runs
+    pub last_error: Option<String>,
+    pub next_run_at: Option<DateTime<Utc>>,
+}
+
+/// What the scheduler did at an occurrence of a schedule
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleOutcome {
+    Started,
+    /// Runs from the previous occurrence were still pending or running
+    Skipped,
+    /// The server was down for longer than the misfire grace period
+    Missed,
+    Failed,
+}
+
+/// Somewhere notifications about finished runs and suites are sent
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationChannel {
+    pub id: Uuid,
+    pub name: String,
+    pub config: ChannelConfig,
+    pub team_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// How a channel delivers its messages
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChannelConfig {
+    /// POSTs the notification as JSON
+    Webhook { url: String },
+    /// A Slack-compatible incoming webhook (Slack, Mattermost, Rocket.Chat, ...)
+    Slack { url: String },
+    Email {
+        smtp_host: String,
+        smtp_port: u16,
+        #[serde(default)]
+        smtp_tls: SmtpTls,
+        username: Option<String>,
+        /// Accepted on creation, never returned
+        #[serde(default, skip_serializing)]
+        password: Option<String>,
+        from: String,
+        to: Vec<String>,
+    },
+}
+
+/// How the connection to an SMTP server is secured
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// Plain connection, for relays on a trusted network
+    None,
+    #[default]
+    #[serde(rename = "starttls")]
+    StartTls,
+    /// TLS from the start, usually on port 465
+    Tls,
+}
+
+/// When a rule sends a notification
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifyOn {
+    #[default]
+    Failure,
+    Success,
+    /// Every finished run or suite
+    Finished,
+    /// The outcome differs from the previous run of the same definition or
+    /// suite; a first outcome counts when it is a failure
+    StatusChange,
+}
+
+/// Sends a notification to a channel when runs of a definition, or a suite as
+/// a whole, finish. A rule naming neither covers every run of its team.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationRule {
+    pub id: Uuid,
+    pub name: String,
+    pub channel_id: Uuid,
+    pub test_definition_id: Option<Uuid>,
+    pub test_suite_id: Option<Uuid>,
+    pub on: NotifyOn,
+    /// Message text with `{{name}}`, `{{status}}`, `{{duration}}`, `{{reason}}`
+    /// and `{{url}}` placeholders; the first line is the email subject
+    pub template: Option<String>,
+    pub team_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
 }
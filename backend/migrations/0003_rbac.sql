@@ -0,0 +1,35 @@
+-- Users, teams and team-scoped ownership for role-based access control
+
+CREATE TABLE users (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    email TEXT UNIQUE,
+    is_admin BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE teams (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    description TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE team_members (
+    team_id UUID NOT NULL REFERENCES teams(id) ON DELETE CASCADE,
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    role TEXT NOT NULL CHECK (role IN ('viewer', 'runner', 'maintainer', 'admin')),
+    PRIMARY KEY (team_id, user_id)
+);
+
+-- Resources without a team stay shared: visible to everyone, changed only by admins
+ALTER TABLE test_executors ADD COLUMN team_id UUID REFERENCES teams(id) ON DELETE SET NULL;
+ALTER TABLE test_definitions ADD COLUMN team_id UUID REFERENCES teams(id) ON DELETE SET NULL;
+ALTER TABLE test_suites ADD COLUMN team_id UUID REFERENCES teams(id) ON DELETE SET NULL;
+ALTER TABLE test_runs ADD COLUMN team_id UUID REFERENCES teams(id) ON DELETE SET NULL;
+ALTER TABLE api_tokens ADD COLUMN user_id UUID REFERENCES users(id) ON DELETE CASCADE;
+
+CREATE INDEX idx_team_members_user_id ON team_members(user_id);
+CREATE INDEX idx_test_definitions_team_id ON test_definitions(team_id);
+CREATE INDEX idx_test_suites_team_id ON test_suites(team_id);
+CREATE INDEX idx_test_runs_team_id ON test_runs(team_id);
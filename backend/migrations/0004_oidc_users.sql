@@ -0,0 +1,3 @@
+-- Users signed in through OIDC are matched by the identity provider's subject claim
+
+ALTER TABLE users ADD COLUMN subject TEXT UNIQUE;
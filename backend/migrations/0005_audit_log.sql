@@ -0,0 +1,27 @@
+-- Append-only audit log of every mutating API request
+-- Rows are never updated; retention is the only thing allowed to delete them
+
+CREATE TABLE audit_log (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    actor TEXT NOT NULL,
+    user_id UUID,
+    token_id UUID,
+    action TEXT NOT NULL,
+    target_type TEXT NOT NULL,
+    target_id TEXT,
+    outcome TEXT NOT NULL CHECK (outcome IN ('success', 'denied', 'failed')),
+    status_code INTEGER NOT NULL,
+    message TEXT,
+    method TEXT NOT NULL,
+    path TEXT NOT NULL,
+    remote_addr TEXT,
+    user_agent TEXT,
+    request_id TEXT NOT NULL
+);
+
+CREATE RULE audit_log_no_update AS ON UPDATE TO audit_log DO INSTEAD NOTHING;
+
+CREATE INDEX idx_audit_log_timestamp ON audit_log(timestamp);
+CREATE INDEX idx_audit_log_actor ON audit_log(actor);
+CREATE INDEX idx_audit_log_target ON audit_log(target_type, target_id);
@@ -0,0 +1,6 @@
+-- Container settings checked by the admission policy before a run is launched
+
+ALTER TABLE test_definitions ADD COLUMN resources JSONB;
+ALTER TABLE test_definitions ADD COLUMN privileged BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE test_runs ADD COLUMN resources JSONB;
+ALTER TABLE test_runs ADD COLUMN privileged BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,4 @@
+-- References to Kubernetes Secret keys; values are never stored
+
+ALTER TABLE test_definitions ADD COLUMN secrets JSONB NOT NULL DEFAULT '[]';
+ALTER TABLE test_runs ADD COLUMN secrets JSONB NOT NULL DEFAULT '[]';
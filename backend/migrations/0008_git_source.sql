@@ -0,0 +1,4 @@
+-- Git repository (url, ref, subdirectory) checked out into the run's workspace
+
+ALTER TABLE test_definitions ADD COLUMN source JSONB;
+ALTER TABLE test_runs ADD COLUMN source JSONB;
@@ -0,0 +1,12 @@
+-- Files uploaded to definitions and runs, mounted into the test container
+
+ALTER TABLE test_definitions ADD COLUMN files JSONB NOT NULL DEFAULT '[]';
+ALTER TABLE test_runs ADD COLUMN files JSONB NOT NULL DEFAULT '[]';
+
+-- Contents live apart from the metadata so listing definitions and runs stays cheap
+CREATE TABLE IF NOT EXISTS test_files (
+    owner_id UUID NOT NULL,
+    name TEXT NOT NULL,
+    content BYTEA NOT NULL,
+    PRIMARY KEY (owner_id, name)
+);
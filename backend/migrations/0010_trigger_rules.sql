@@ -0,0 +1,20 @@
+-- Rules that start definitions or suites from Git pushes and pull requests
+
+CREATE TABLE trigger_rules (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    repository TEXT NOT NULL,
+    branches TEXT[] NOT NULL DEFAULT '{}',
+    events TEXT[] NOT NULL DEFAULT '{}',
+    test_definition_id UUID REFERENCES test_definitions(id) ON DELETE CASCADE,
+    test_suite_id UUID REFERENCES test_suites(id) ON DELETE CASCADE,
+    team_id UUID REFERENCES teams(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK ((test_definition_id IS NULL) <> (test_suite_id IS NULL))
+);
+
+CREATE INDEX idx_trigger_rules_repository ON trigger_rules(lower(repository));
+
+-- The push or pull request behind a run: provider, repository, branch, commit SHA, PR number
+ALTER TABLE test_runs ADD COLUMN trigger JSONB;
+CREATE INDEX idx_test_runs_commit_sha ON test_runs((trigger->>'commit_sha'));
@@ -0,0 +1,13 @@
+-- Git host API credentials for posting commit statuses of triggered runs
+
+CREATE TABLE status_reporters (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    provider TEXT NOT NULL CHECK (provider IN ('github', 'gitlab')),
+    repository TEXT NOT NULL,
+    api_url TEXT,
+    context TEXT NOT NULL DEFAULT 'sparktest',
+    token TEXT NOT NULL,
+    team_id UUID REFERENCES teams(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,25 @@
+-- Cron schedules that start definitions or suites
+
+CREATE TABLE schedules (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    cron TEXT NOT NULL,
+    timezone TEXT NOT NULL DEFAULT 'UTC',
+    test_definition_id UUID REFERENCES test_definitions(id) ON DELETE CASCADE,
+    test_suite_id UUID REFERENCES test_suites(id) ON DELETE CASCADE,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    team_id UUID REFERENCES teams(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_fired_at TIMESTAMPTZ,
+    last_outcome TEXT CHECK (last_outcome IN ('started', 'skipped', 'missed', 'failed')),
+    last_error TEXT,
+    next_run_at TIMESTAMPTZ,
+    CHECK ((test_definition_id IS NULL) <> (test_suite_id IS NULL))
+);
+
+CREATE INDEX idx_schedules_next_run_at ON schedules(next_run_at) WHERE enabled;
+
+-- The schedule that started a run, used to skip occurrences while its runs are active
+ALTER TABLE test_runs ADD COLUMN schedule_id UUID REFERENCES schedules(id) ON DELETE SET NULL;
+CREATE INDEX idx_test_runs_schedule_id ON test_runs(schedule_id);
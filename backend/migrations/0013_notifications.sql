@@ -0,0 +1,26 @@
+-- Where and when to send notifications about finished runs and suites
+
+CREATE TABLE notification_channels (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    -- {"type": "webhook" | "slack" | "email", ...}; the SMTP password is stored here too
+    config JSONB NOT NULL,
+    team_id UUID REFERENCES teams(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE TABLE notification_rules (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    name TEXT NOT NULL,
+    channel_id UUID NOT NULL REFERENCES notification_channels(id) ON DELETE CASCADE,
+    test_definition_id UUID REFERENCES test_definitions(id) ON DELETE CASCADE,
+    test_suite_id UUID REFERENCES test_suites(id) ON DELETE CASCADE,
+    "on" TEXT NOT NULL DEFAULT 'failure'
+        CHECK ("on" IN ('failure', 'success', 'finished', 'status_change')),
+    template TEXT,
+    team_id UUID REFERENCES teams(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    CHECK (test_definition_id IS NULL OR test_suite_id IS NULL)
+);
+
+CREATE INDEX idx_notification_rules_channel_id ON notification_rules(channel_id);
@@ -0,0 +1,12 @@
+-- Keyset pagination, filtering and name search when listing runs
+
+ALTER TABLE test_runs ADD COLUMN suite_id UUID REFERENCES test_suites(id) ON DELETE SET NULL;
+CREATE INDEX idx_test_runs_suite_id ON test_runs(suite_id);
+
+-- The default order, with the id as tie-breaker to match the cursor
+CREATE INDEX idx_test_runs_created_at_id ON test_runs(created_at DESC, id DESC);
+CREATE INDEX idx_test_runs_status_created_at ON test_runs(status, created_at DESC);
+
+-- Case-insensitive substring search on names
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+CREATE INDEX idx_test_runs_name_trgm ON test_runs USING gin (lower(name) gin_trgm_ops);
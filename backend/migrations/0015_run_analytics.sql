@@ -0,0 +1,8 @@
+-- Run analytics group by definition, suite or executor and bucket by creation time
+
+CREATE INDEX idx_test_runs_definition_created_at ON test_runs(test_definition_id, created_at);
+CREATE INDEX idx_test_runs_suite_created_at ON test_runs(suite_id, created_at);
+CREATE INDEX idx_test_runs_executor_created_at ON test_runs(executor_id, created_at);
+-- Only finished runs count towards pass rate and durations
+CREATE INDEX idx_test_runs_finished_created_at ON test_runs(created_at) INCLUDE (status, duration)
+    WHERE status IN ('succeeded', 'failed');
@@ -0,0 +1,10 @@
+-- Runs wait for a free slot in priority order, oldest first within a priority
+
+ALTER TABLE test_definitions ADD COLUMN priority TEXT NOT NULL DEFAULT 'normal'
+    CHECK (priority IN ('low', 'normal', 'high'));
+ALTER TABLE test_runs ADD COLUMN priority TEXT NOT NULL DEFAULT 'normal'
+    CHECK (priority IN ('low', 'normal', 'high'));
+
+-- Waiting runs are the pending ones, started highest priority first
+CREATE INDEX idx_test_runs_pending_queue ON test_runs(priority, created_at)
+    WHERE status = 'pending';
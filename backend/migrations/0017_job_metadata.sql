@@ -0,0 +1,6 @@
+-- Extra labels and annotations for the Jobs of a definition's runs
+
+ALTER TABLE test_definitions ADD COLUMN job_labels JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE test_definitions ADD COLUMN job_annotations JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE test_runs ADD COLUMN job_labels JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE test_runs ADD COLUMN job_annotations JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,12 @@
+-- Execution targets registered through the API; the built-in default target
+-- comes from the server's own kubeconfig and is never stored
+
+CREATE TABLE execution_targets (
+    name TEXT PRIMARY KEY,
+    description TEXT,
+    connection JSONB NOT NULL,
+    namespace TEXT NOT NULL,
+    timeout_seconds BIGINT NOT NULL,
+    max_log_lines BIGINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
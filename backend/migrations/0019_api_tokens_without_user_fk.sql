@@ -0,0 +1,4 @@
+-- API tokens are stored, but users are still kept in memory by the server, so a
+-- personal token's user can't be enforced as a foreign key yet
+
+ALTER TABLE api_tokens DROP CONSTRAINT api_tokens_user_id_fkey;
@@ -0,0 +1,6 @@
+-- Definitions, suites and teams are still kept in memory, so schedules can't
+-- reference their rows; the scheduler disables schedules whose target is gone
+
+ALTER TABLE schedules DROP CONSTRAINT schedules_test_definition_id_fkey;
+ALTER TABLE schedules DROP CONSTRAINT schedules_test_suite_id_fkey;
+ALTER TABLE schedules DROP CONSTRAINT schedules_team_id_fkey;
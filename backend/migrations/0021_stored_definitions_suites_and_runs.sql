@@ -0,0 +1,52 @@
+-- Definitions, suites and runs are written through by the server, so their
+-- tables take every field it keeps. Teams are still kept in memory and
+-- executors are referred to by their API id, which isn't a UUID, so neither
+-- can be enforced as a foreign key; neither can definitions and suites, which
+-- runs outlive.
+
+ALTER TABLE test_definitions DROP CONSTRAINT test_definitions_executor_id_fkey;
+ALTER TABLE test_definitions DROP CONSTRAINT test_definitions_team_id_fkey;
+ALTER TABLE test_suites DROP CONSTRAINT test_suites_team_id_fkey;
+ALTER TABLE test_runs DROP CONSTRAINT test_runs_executor_id_fkey;
+ALTER TABLE test_runs DROP CONSTRAINT test_runs_team_id_fkey;
+ALTER TABLE test_runs DROP CONSTRAINT test_runs_test_definition_id_fkey;
+ALTER TABLE test_runs DROP CONSTRAINT test_runs_suite_id_fkey;
+ALTER TABLE test_runs DROP CONSTRAINT test_runs_schedule_id_fkey;
+
+ALTER TABLE test_definitions ALTER COLUMN executor_id TYPE TEXT;
+ALTER TABLE test_runs ALTER COLUMN executor_id TYPE TEXT;
+
+UPDATE test_definitions SET created_at = NOW() WHERE created_at IS NULL;
+ALTER TABLE test_definitions ALTER COLUMN created_at SET NOT NULL;
+UPDATE test_suites SET created_at = NOW() WHERE created_at IS NULL;
+ALTER TABLE test_suites ALTER COLUMN created_at SET NOT NULL;
+UPDATE test_runs SET created_at = NOW() WHERE created_at IS NULL;
+ALTER TABLE test_runs ALTER COLUMN created_at SET NOT NULL;
+
+-- Runs wait as `pending`; the capitalised statuses were never written by the server
+ALTER TABLE test_runs DROP CONSTRAINT test_runs_status_check;
+UPDATE test_runs SET status = CASE status
+    WHEN 'Running' THEN 'running'
+    WHEN 'Completed' THEN 'succeeded'
+    WHEN 'Failed' THEN 'failed'
+    ELSE status
+END;
+ALTER TABLE test_runs ADD CONSTRAINT test_runs_status_check
+    CHECK (status IN ('pending', 'running', 'succeeded', 'failed'));
+
+ALTER TABLE test_definitions ADD COLUMN variables JSONB;
+ALTER TABLE test_definitions ADD COLUMN target TEXT;
+
+ALTER TABLE test_runs ADD COLUMN variables JSONB;
+ALTER TABLE test_runs ADD COLUMN artifacts TEXT[];
+ALTER TABLE test_runs ADD COLUMN retries INTEGER;
+ALTER TABLE test_runs ADD COLUMN k8s_job_name TEXT;
+ALTER TABLE test_runs ADD COLUMN pod_scheduled TIMESTAMPTZ;
+ALTER TABLE test_runs ADD COLUMN container_created TIMESTAMPTZ;
+ALTER TABLE test_runs ADD COLUMN container_started TIMESTAMPTZ;
+ALTER TABLE test_runs ADD COLUMN completed TIMESTAMPTZ;
+ALTER TABLE test_runs ADD COLUMN failed TIMESTAMPTZ;
+ALTER TABLE test_runs ADD COLUMN target TEXT;
+-- The Kubernetes `TestRun` resource and the user, token or Git sender behind the run
+ALTER TABLE test_runs ADD COLUMN resource_uid TEXT;
+ALTER TABLE test_runs ADD COLUMN triggered_by TEXT;
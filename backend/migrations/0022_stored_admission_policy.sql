@@ -0,0 +1,9 @@
+-- The admission policy as last replaced through the API. It takes over from the
+-- SPARKTEST_* policy variables, which only apply until the policy is replaced.
+
+CREATE TABLE admission_policy (
+    -- A single row
+    id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+    policy JSONB NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,7 @@
+-- Rules outlive the definitions and suites they start, which webhook deliveries
+-- then report as not found, so they can't be cascaded away with them. Like
+-- definitions and suites, rules don't enforce their team as a foreign key.
+
+ALTER TABLE trigger_rules DROP CONSTRAINT trigger_rules_test_definition_id_fkey;
+ALTER TABLE trigger_rules DROP CONSTRAINT trigger_rules_test_suite_id_fkey;
+ALTER TABLE trigger_rules DROP CONSTRAINT trigger_rules_team_id_fkey;
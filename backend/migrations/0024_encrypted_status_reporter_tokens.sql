@@ -0,0 +1,8 @@
+-- Reporter tokens are stored encrypted with SPARKTEST_SECRET_KEY: a nonce
+-- followed by the AES-256-GCM ciphertext. Reporters were only ever kept in
+-- memory, so there are no plaintext tokens to convert. Like definitions and
+-- suites, reporters don't enforce their team as a foreign key.
+
+ALTER TABLE status_reporters DROP COLUMN token;
+ALTER TABLE status_reporters ADD COLUMN encrypted_token BYTEA NOT NULL;
+ALTER TABLE status_reporters DROP CONSTRAINT status_reporters_team_id_fkey;
@@ -0,0 +1,11 @@
+-- Channels and rules are written through by the server. `config` holds
+-- everything but the SMTP password, which is stored encrypted with
+-- SPARKTEST_SECRET_KEY: a nonce followed by the AES-256-GCM ciphertext.
+-- Rules outlive the definitions and suites they cover, and like definitions
+-- and suites, channels and rules don't enforce their team as a foreign key.
+
+ALTER TABLE notification_channels ADD COLUMN encrypted_password BYTEA;
+ALTER TABLE notification_channels DROP CONSTRAINT notification_channels_team_id_fkey;
+ALTER TABLE notification_rules DROP CONSTRAINT notification_rules_test_definition_id_fkey;
+ALTER TABLE notification_rules DROP CONSTRAINT notification_rules_test_suite_id_fkey;
+ALTER TABLE notification_rules DROP CONSTRAINT notification_rules_team_id_fkey;
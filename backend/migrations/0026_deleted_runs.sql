@@ -0,0 +1,8 @@
+-- Runs deleted through the API, so their Jobs are still collected by a server
+-- started after the deletion. Runs the store doesn't know may predate it, so
+-- Jobs are only deleted for runs recorded here.
+
+CREATE TABLE deleted_runs (
+    id UUID PRIMARY KEY,
+    deleted_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -12,16 +12,48 @@ import { StorageService } from "./storage"
 
 const API_BASE = "http://localhost:3001/api"
 
+// The backend requires a bearer token unless it runs with SPARKTEST_AUTH_DISABLED=true.
+// Set NEXT_PUBLIC_SPARKTEST_API_TOKEN, or store one under "sparktest-api-token" in localStorage.
+const API_TOKEN_KEY = "sparktest-api-token"
+
+function apiToken(): string | undefined {
+  if (typeof window !== "undefined") {
+    const stored = localStorage.getItem(API_TOKEN_KEY)
+    if (stored) return stored
+  }
+  return process.env.NEXT_PUBLIC_SPARKTEST_API_TOKEN || undefined
+}
+
+function apiFetch(url: string, init: RequestInit = {}): Promise<Response> {
+  const token = apiToken()
+  const headers = new Headers(init.headers)
+  if (token) headers.set("Authorization", `Bearer ${token}`)
+  return fetch(url, { ...init, headers })
+}
+
+// Convert snake_case to camelCase; createdAt is left empty when the date is invalid
+function toRun(run: any): Run {
+  const { created_at, ...rest } = run
+  let createdAt = ""
+  if (created_at && !isNaN(new Date(created_at).getTime())) {
+    createdAt = new Date(created_at).toISOString()
+  }
+  return {
+    ...rest,
+    createdAt,
+  }
+}
+
 export class ApiStorageService implements StorageService {
   // Test Executors
   async getExecutors(): Promise<Executor[]> {
-    const res = await fetch(`${API_BASE}/test-executors`)
+    const res = await apiFetch(`${API_BASE}/test-executors`)
     if (!res.ok) throw new Error("Failed to fetch executors")
     return await res.json()
   }
 
   async saveExecutor(executor: Executor): Promise<Executor> {
-    const res = await fetch(`${API_BASE}/test-executors`, {
+    const res = await apiFetch(`${API_BASE}/test-executors`, {
       method: "POST",
       headers: { "Content-Type": "application/json" },
       body: JSON.stringify(executor),
@@ -31,7 +63,7 @@ export class ApiStorageService implements StorageService {
   }
 
   async deleteExecutor(id: string): Promise<boolean> {
-    const res = await fetch(`${API_BASE}/test-executors/${id}`, { method: "DELETE" })
+    const res = await apiFetch(`${API_BASE}/test-executors/${id}`, { method: "DELETE" })
     return res.ok
   }
 
@@ -42,13 +74,13 @@ export class ApiStorageService implements StorageService {
 
   // Test Definitions
   async getDefinitions(): Promise<Definition[]> {
-    const res = await fetch(`${API_BASE}/test-definitions`)
+    const res = await apiFetch(`${API_BASE}/test-definitions`)
     if (!res.ok) throw new Error("Failed to fetch definitions")
     return await res.json()
   }
 
   async saveDefinition(def: Definition): Promise<Definition> {
-    const res = await fetch(`${API_BASE}/test-definitions`, {
+    const res = await apiFetch(`${API_BASE}/test-definitions`, {
       method: "POST",
       headers: { "Content-Type": "application/json" },
       body: JSON.stringify(def),
@@ -58,7 +90,7 @@ export class ApiStorageService implements StorageService {
   }
 
   async deleteDefinition(id: string): Promise<boolean> {
-    const res = await fetch(`${API_BASE}/test-definitions/${id}`, { method: "DELETE" })
+    const res = await apiFetch(`${API_BASE}/test-definitions/${id}`, { method: "DELETE" })
     return res.ok
   }
 
@@ -69,29 +101,32 @@ export class ApiStorageService implements StorageService {
 
   // Test Runs
   async getRuns(): Promise<Run[]> {
-    const res = await fetch(`${API_BASE}/test-runs`)
-    if (!res.ok) throw new Error("Failed to fetch runs")
-    const data = await res.json()
-    // Convert snake_case to camelCase, ensure createdAt is valid, filter and sort
-    return data
-      .map((run: any) => {
-        const { created_at, ...rest } = run
-        let createdAt = ""
-        if (created_at && !isNaN(new Date(created_at).getTime())) {
-          createdAt = new Date(created_at).toISOString()
-        }
-        return {
-          ...rest,
-          createdAt,
-        }
-      })
+    // The list leaves logs out and is paged; follow X-Next-Cursor to the last page
+    const runs: any[] = []
+    let cursor: string | null = null
+    do {
+      const params = new URLSearchParams({ limit: "1000" })
+      if (cursor) params.set("cursor", cursor)
+      const res = await apiFetch(`${API_BASE}/test-runs?${params}`)
+      if (!res.ok) throw new Error("Failed to fetch runs")
+      const data = await res.json()
+      runs.push(...data)
+      cursor = res.headers.get("X-Next-Cursor")
+    } while (cursor)
+
+    // Ensure createdAt is valid, filter and sort
+    return runs
+      .map(toRun)
       .filter((run: any) => !!run.createdAt && !isNaN(new Date(run.createdAt).getTime()))
       .sort((a: any, b: any) => new Date(b.createdAt).getTime() - new Date(a.createdAt).getTime())
   }
 
   async getRunById(id: string): Promise<Run | undefined> {
-    const list = await this.getRuns()
-    return list.find((r) => r.id === id)
+    // Unlike the list, a single run comes with its logs
+    const res = await apiFetch(`${API_BASE}/test-runs/${id}`)
+    if (res.status === 404) return undefined
+    if (!res.ok) throw new Error("Failed to fetch run")
+    return toRun(await res.json())
   }
 
   async saveRun(run: Run): Promise<Run> {
@@ -109,7 +144,7 @@ export class ApiStorageService implements StorageService {
     delete payload.definitionId
     delete payload.executorId
 
-    const res = await fetch(url, {
+    const res = await apiFetch(url, {
       method,
       headers: { "Content-Type": "application/json" },
       body: JSON.stringify(payload),
@@ -119,7 +154,7 @@ export class ApiStorageService implements StorageService {
   }
 
   async deleteRun(id: string): Promise<boolean> {
-    const res = await fetch(`${API_BASE}/test-runs/${id}`, { method: "DELETE" })
+    const res = await apiFetch(`${API_BASE}/test-runs/${id}`, { method: "DELETE" })
     return res.ok
   }
 
@@ -166,7 +201,7 @@ export class ApiStorageService implements StorageService {
       test_definition_id: definitionId,
       ...options,
     }
-    const res = await fetch(`${API_BASE}/test-runs`, {
+    const res = await apiFetch(`${API_BASE}/test-runs`, {
       method: "POST",
       headers: { "Content-Type": "application/json" },
       body: JSON.stringify(payload),
@@ -177,7 +212,7 @@ export class ApiStorageService implements StorageService {
 
   // Test Suites
   async getTestSuites(): Promise<TestSuite[]> {
-    const res = await fetch(`${API_BASE}/test-suites`)
+    const res = await apiFetch(`${API_BASE}/test-suites`)
     if (!res.ok) throw new Error("Failed to fetch test suites")
 
     const data = await res.json()
@@ -220,7 +255,7 @@ export class ApiStorageService implements StorageService {
     delete suitePayload.testDefinitionIds
     delete suitePayload.createdAt
 
-    const res = await fetch(url, {
+    const res = await apiFetch(url, {
       method,
       headers: { "Content-Type": "application/json" },
       body: JSON.stringify(suitePayload),
@@ -237,7 +272,7 @@ export class ApiStorageService implements StorageService {
       uuidId = `00000000-0000-0000-0000-${id.padStart(12, "0").substring(0, 12)}`
     }
 
-    const res = await fetch(`${API_BASE}/test-suites/${uuidId}`, { method: "DELETE" })
+    const res = await apiFetch(`${API_BASE}/test-suites/${uuidId}`, { method: "DELETE" })
     return res.ok
   }
 
@@ -248,7 +283,7 @@ export class ApiStorageService implements StorageService {
       uuidId = `00000000-0000-0000-0000-${id.padStart(12, "0").substring(0, 12)}`
     }
 
-    const res = await fetch(`${API_BASE}/test-suites/${uuidId}`)
+    const res = await apiFetch(`${API_BASE}/test-suites/${uuidId}`)
     if (!res.ok) throw new Error("Failed to fetch test suite")
 
     const data = await res.json()
@@ -267,31 +302,31 @@ export class ApiStorageService implements StorageService {
 
   // Kubernetes Integration
   async getKubernetesHealth(): Promise<KubernetesHealth> {
-    const res = await fetch(`${API_BASE}/k8s/health`)
+    const res = await apiFetch(`${API_BASE}/k8s/health`)
     if (!res.ok) throw new Error("Failed to check Kubernetes health")
     return await res.json()
   }
 
   async getTestRunLogs(runId: string): Promise<JobLogs> {
-    const res = await fetch(`${API_BASE}/test-runs/${runId}/logs`)
+    const res = await apiFetch(`${API_BASE}/test-runs/${runId}/logs`)
     if (!res.ok) throw new Error(`Failed to fetch logs for test run ${runId}`)
     return await res.json()
   }
 
   async getJobLogs(jobName: string): Promise<JobLogs> {
-    const res = await fetch(`${API_BASE}/k8s/jobs/${jobName}/logs`)
+    const res = await apiFetch(`${API_BASE}/k8s/jobs/${jobName}/logs`)
     if (!res.ok) throw new Error(`Failed to fetch logs for job ${jobName}`)
     return await res.json()
   }
 
   async getJobStatus(jobName: string): Promise<JobStatus> {
-    const res = await fetch(`${API_BASE}/k8s/jobs/${jobName}/status`)
+    const res = await apiFetch(`${API_BASE}/k8s/jobs/${jobName}/status`)
     if (!res.ok) throw new Error(`Failed to fetch status for job ${jobName}`)
     return await res.json()
   }
 
   async deleteJob(jobName: string): Promise<JobDeleteResponse> {
-    const res = await fetch(`${API_BASE}/k8s/jobs/${jobName}`, { method: "DELETE" })
+    const res = await apiFetch(`${API_BASE}/k8s/jobs/${jobName}`, { method: "DELETE" })
     if (!res.ok) throw new Error(`Failed to delete job ${jobName}`)
     return await res.json()
   }